@@ -0,0 +1,102 @@
+//! Benchmarks of the packets exchanged while discovering and polling, run
+//! with `cargo bench -p bjnp`.
+
+use std::{hint::black_box, net::Ipv4Addr};
+
+use bjnp::{
+    discover, identity, poll,
+    serdes::{Empty, Serialize},
+    Host, Packet, PacketBuilder, PacketHeaderOnly, PacketType, PayloadType,
+};
+use criterion::{criterion_group, criterion_main, Criterion};
+
+/// Poll response of an MX920 reporting a scan job
+const INTERRUPT: &[u8] = &[
+    0x42, 0x4a, 0x4e, 0x50, 0x82, 0x32, 0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x24,
+    0x00, 0x00, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x14, 0x00, 0x00, 0x00, 0x01,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x01, 0x00, 0x01, 0x01, 0x02, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00,
+];
+
+/// `MFG:Canon;CMD:MultiPass 2.1,IVEC;MDL:MX920 series;`
+const IDENTITY: &[u8] = &[
+    0x42, 0x4a, 0x4e, 0x50, 0x82, 0x30, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x34,
+    0x00, 0x34, 0x4d, 0x46, 0x47, 0x3a, 0x43, 0x61, 0x6e, 0x6f, 0x6e, 0x3b, 0x43, 0x4d, 0x44, 0x3a,
+    0x4d, 0x75, 0x6c, 0x74, 0x69, 0x50, 0x61, 0x73, 0x73, 0x20, 0x32, 0x2e, 0x31, 0x2c, 0x49, 0x56,
+    0x45, 0x43, 0x3b, 0x4d, 0x44, 0x4c, 0x3a, 0x4d, 0x58, 0x39, 0x32, 0x30, 0x20, 0x73, 0x65, 0x72,
+    0x69, 0x65, 0x73, 0x3b,
+];
+
+fn discover(c: &mut Criterion) {
+    let command = PacketBuilder::new(PacketType::ScannerCommand, PayloadType::Discover)
+        .build(discover::Request::new());
+    let response = PacketBuilder::new(PacketType::ScannerResponse, PayloadType::Discover).build(
+        discover::Response::new(
+            "00:1e:8f:01:02:03".parse().unwrap(),
+            Ipv4Addr::new(192, 168, 1, 20).into(),
+        ),
+    );
+    let buffer = response.serialize_to_vec();
+
+    c.bench_function("discover command to vec", |b| {
+        b.iter(|| black_box(&command).serialize_to_vec())
+    });
+    c.bench_function("discover command to array", |b| {
+        b.iter(|| black_box(&command).serialize_to_array::<64>())
+    });
+    c.bench_function("discover response to array", |b| {
+        b.iter(|| black_box(&response).serialize_to_array::<64>())
+    });
+    c.bench_function("discover response parse", |b| {
+        b.iter(|| {
+            let packet = PacketHeaderOnly::parse(black_box(&buffer)).unwrap();
+            Packet::<discover::Response>::try_from(packet).unwrap()
+        })
+    });
+}
+
+fn poll(c: &mut Criterion) {
+    let command = PacketBuilder::new(PacketType::ScannerCommand, PayloadType::Poll).build(
+        poll::CommandBuilder::new(poll::PollType::Full)
+            .host(Host::new("scanner-button"))
+            .session_id(7)
+            .datetime(poll::Datetime::new(2023, 1, 1, 12, 0, 0).unwrap())
+            .build()
+            .unwrap(),
+    );
+    let buffer = command.serialize_to_vec();
+
+    c.bench_function("poll command to array", |b| {
+        b.iter(|| black_box(&command).serialize_to_array::<256>())
+    });
+    c.bench_function("poll command parse", |b| {
+        b.iter(|| {
+            let packet = PacketHeaderOnly::parse(black_box(&buffer)).unwrap();
+            Packet::<poll::Command>::try_from(packet).unwrap()
+        })
+    });
+    c.bench_function("poll response parse", |b| {
+        b.iter(|| {
+            let packet = PacketHeaderOnly::parse(black_box(INTERRUPT)).unwrap();
+            Packet::<poll::Response>::try_from(packet).unwrap()
+        })
+    });
+}
+
+fn identity(c: &mut Criterion) {
+    let command =
+        PacketBuilder::new(PacketType::ScannerCommand, PayloadType::GetId).build(Empty);
+
+    c.bench_function("identity command to array", |b| {
+        b.iter(|| black_box(&command).serialize_to_array::<16>())
+    });
+    c.bench_function("identity response parse", |b| {
+        b.iter(|| {
+            let packet = PacketHeaderOnly::parse(black_box(IDENTITY)).unwrap();
+            Packet::<identity::Response>::try_from(packet).unwrap()
+        })
+    });
+}
+
+criterion_group!(benches, discover, poll, identity);
+criterion_main!(benches);
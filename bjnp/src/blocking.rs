@@ -0,0 +1,269 @@
+//! This module contains a blocking client of the scanner channel on top of
+//! [`std::net::UdpSocket`], mirroring the async client in
+//! [`client`](crate::client) for synchronous programs.
+//!
+//! ```no_run
+//! # fn example() -> Result<(), bjnp::Error> {
+//! use bjnp::{blocking::Client, identity};
+//!
+//! let mut client = Client::connect("192.168.1.2:8612".parse().unwrap())?;
+//! let id = client.request(identity::Request::new())?;
+//! println!("{id}");
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{
+    io,
+    net::{SocketAddr, UdpSocket},
+    num::Wrapping,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    client::{build_command, match_response},
+    discover::{self, DiscoveredDevice},
+    identity, Error, ProtocolError, Request,
+};
+
+/// A client of the scanner channel of a single scanner
+#[derive(Debug)]
+pub struct Client {
+    socket: UdpSocket,
+    sequence: Wrapping<u16>,
+    timeout: Duration,
+}
+
+impl Client {
+    /// Time to wait for a response unless changed by [`Client::set_timeout`]
+    pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// Creates a client of the scanner at `addr`, usually on port 8612.
+    pub fn connect(addr: SocketAddr) -> Result<Self, Error> {
+        let socket = UdpSocket::bind(unspecified_for(&addr))?;
+        socket.connect(addr)?;
+        Ok(Self::from_socket(socket))
+    }
+
+    /// Creates a client from a socket already connected to a scanner. The read
+    /// timeout of the socket is overridden on each request.
+    pub fn from_socket(socket: UdpSocket) -> Self {
+        Self {
+            socket,
+            sequence: Wrapping(0),
+            timeout: Self::DEFAULT_TIMEOUT,
+        }
+    }
+
+    #[inline(always)]
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// Sets the time to wait for a response. Panics if `timeout` is zero.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        assert!(!timeout.is_zero(), "timeout must be non-zero");
+        self.timeout = timeout;
+    }
+
+    /// Returns the address of the scanner.
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.peer_addr()
+    }
+
+    /// Resets the sequence number, as expected by scanners when a session
+    /// starts.
+    pub fn reset_sequence(&mut self) {
+        self.sequence = Wrapping(0);
+    }
+
+    /// Sends a command and awaits its response, of the type answering it.
+    /// Packets not answering the command, e.g. late responses to earlier
+    /// commands, are discarded.
+    pub fn request<R: Request>(&mut self, command: R) -> Result<R::Response, Error> {
+        let payload_type = R::PAYLOAD_TYPE;
+        let sequence = self.sequence.0;
+        self.sequence += 1;
+
+        self.socket.send(&build_command(sequence, command))?;
+
+        let deadline = Instant::now() + self.timeout;
+        let mut buffer = vec![0; 65536];
+        loop {
+            // stray packets shouldn't extend the time waited
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(ProtocolError::Timeout(payload_type).into());
+            }
+            self.socket.set_read_timeout(Some(remaining))?;
+
+            let size = match self.socket.recv(&mut buffer) {
+                Ok(size) => size,
+                // platforms differ in the error reported on timeout
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                    ) =>
+                {
+                    return Err(ProtocolError::Timeout(payload_type).into())
+                }
+                Err(e) => return Err(e.into()),
+            };
+            if let Some(resp) = match_response::<R>(&buffer[..size], sequence) {
+                return resp;
+            }
+        }
+    }
+}
+
+/// Broadcasts a discover command to `broadcast`, e.g.
+/// `255.255.255.255:8612`, then asks each device answering within `timeout`
+/// for its identity, one after another. Devices not reporting their identity
+/// within `timeout` are left out. Panics if `timeout` is zero.
+pub fn discover(broadcast: SocketAddr, timeout: Duration) -> Result<Vec<DiscoveredDevice>, Error> {
+    let socket = UdpSocket::bind(unspecified_for(&broadcast))?;
+    socket.set_broadcast(true)?;
+    socket.send_to(&build_command(0, discover::Request::new()), broadcast)?;
+
+    let deadline = Instant::now() + timeout;
+    let mut buffer = vec![0; 65536];
+    let mut responses: Vec<discover::Response> = vec![];
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            break;
+        }
+        socket.set_read_timeout(Some(remaining))?;
+
+        let size = match socket.recv(&mut buffer) {
+            Ok(size) => size,
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) =>
+            {
+                break
+            }
+            Err(e) => return Err(e.into()),
+        };
+        // packets other than discover responses are skipped
+        let resp = match_response::<discover::Request>(&buffer[..size], 0);
+        if let Some(Ok(resp)) = resp {
+            // the same device may answer more than once
+            if !responses
+                .iter()
+                .any(|seen| seen.ip_addr() == resp.ip_addr() && seen.mac_addr() == resp.mac_addr())
+            {
+                responses.push(resp);
+            }
+        }
+    }
+
+    let mut devices = vec![];
+    for resp in responses {
+        let mut client = Client::connect(SocketAddr::new(*resp.ip_addr(), broadcast.port()))?;
+        client.set_timeout(timeout);
+        if let Ok(identity) = client.request(identity::Request::new()) {
+            devices.push(DiscoveredDevice::new(&resp, identity));
+        }
+    }
+    devices.sort_by_key(|device| (*device.ip_addr(), *device.mac_addr()));
+    Ok(devices)
+}
+
+fn unspecified_for(addr: &SocketAddr) -> SocketAddr {
+    if addr.is_ipv4() {
+        ([0; 4], 0).into()
+    } else {
+        ([0; 16], 0).into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+    use crate::{
+        serdes::{Empty, Serialize},
+        PacketBuilder, PacketHeaderOnly, PacketType, PayloadType,
+    };
+
+    #[test]
+    fn request_skips_stray_packets() {
+        let scanner = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut client = Client::connect(scanner.local_addr().unwrap()).unwrap();
+
+        let scanner = thread::spawn(move || {
+            let mut buffer = [0; 1024];
+            let (size, peer) = scanner.recv_from(&mut buffer).unwrap();
+            let command = PacketHeaderOnly::parse(&buffer[..size]).unwrap();
+            for offset in [1, 0] {
+                let response =
+                    PacketBuilder::new(PacketType::ScannerResponse, PayloadType::Discover)
+                        .sequence(command.sequence().wrapping_add(offset))
+                        .build(discover::Response::new(
+                            "00:1e:8f:01:02:03".parse().unwrap(),
+                            "127.0.0.1".parse().unwrap(),
+                        ));
+                scanner.send_to(&response.serialize_to_vec(), peer).unwrap();
+            }
+        });
+        let resp = client.request(discover::Request::new());
+        scanner.join().unwrap();
+        assert!(resp.is_ok());
+    }
+
+    #[test]
+    fn discover_devices() {
+        let scanner = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = scanner.local_addr().unwrap();
+
+        let scanner = thread::spawn(move || {
+            let mut buffer = [0; 1024];
+            for _ in 0..2 {
+                let (size, peer) = scanner.recv_from(&mut buffer).unwrap();
+                let command = PacketHeaderOnly::parse(&buffer[..size]).unwrap();
+                let builder =
+                    PacketBuilder::new(PacketType::ScannerResponse, command.payload_type())
+                        .sequence(command.sequence())
+                        .clone();
+                let response = match command.payload_type() {
+                    PayloadType::Discover => builder
+                        .build(discover::Response::new(
+                            "00:1e:8f:01:02:03".parse().unwrap(),
+                            addr.ip(),
+                        ))
+                        .serialize_to_vec(),
+                    _ => {
+                        let mut response = builder.build(Empty).serialize_to_vec();
+                        let identity = b"\x00\x16MFG:Canon;MDL:Dummy;";
+                        response[15] = identity.len() as u8;
+                        response.extend_from_slice(identity);
+                        response
+                    }
+                };
+                scanner.send_to(&response, peer).unwrap();
+            }
+        });
+        let devices = discover(addr, Duration::from_millis(200)).unwrap();
+        scanner.join().unwrap();
+        assert_eq!(devices.len(), 1);
+        assert_eq!(devices[0].ip_addr(), &addr.ip());
+        assert_eq!(devices[0].identity().get("MDL"), Some("Dummy"));
+    }
+
+    #[test]
+    fn request_times_out() {
+        let scanner = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut client = Client::connect(scanner.local_addr().unwrap()).unwrap();
+        client.set_timeout(Duration::from_millis(10));
+        let resp = client.request(discover::Request::new());
+        assert!(matches!(
+            resp,
+            Err(Error::Protocol(ProtocolError::Timeout(PayloadType::Discover)))
+        ));
+    }
+}
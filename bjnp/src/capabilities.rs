@@ -0,0 +1,265 @@
+//! This module contains the capabilities of a scanner, derived from the
+//! response of a get identity command
+
+use std::fmt::Display;
+
+use thiserror::Error;
+
+use crate::{
+    identity,
+    poll::{FeederType, Interrupt, Source, DPI},
+};
+
+/// A model known to the crate, matched by the prefix of `MDL`
+struct Model {
+    name: &'static str,
+    /// Type of the feeder, if the model has one
+    feeder: Option<FeederType>,
+    /// Highest resolution offered on the panel
+    max_dpi: DPI,
+}
+
+const MODELS: &[Model] = &[
+    Model {
+        name: "MX920",
+        feeder: Some(FeederType::Duplex),
+        max_dpi: DPI::_600,
+    },
+    Model {
+        name: "MX720",
+        feeder: Some(FeederType::Duplex),
+        max_dpi: DPI::_600,
+    },
+    Model {
+        name: "MX520",
+        feeder: Some(FeederType::Simplex),
+        max_dpi: DPI::_600,
+    },
+    Model {
+        name: "MX490",
+        feeder: Some(FeederType::Simplex),
+        max_dpi: DPI::_600,
+    },
+    Model {
+        name: "TR8500",
+        feeder: Some(FeederType::Simplex),
+        max_dpi: DPI::_600,
+    },
+    Model {
+        name: "TR4500",
+        feeder: Some(FeederType::Simplex),
+        max_dpi: DPI::_600,
+    },
+    Model {
+        name: "MG7700",
+        feeder: None,
+        max_dpi: DPI::_600,
+    },
+    Model {
+        name: "MG5700",
+        feeder: None,
+        max_dpi: DPI::_600,
+    },
+    Model {
+        name: "MG3600",
+        feeder: None,
+        max_dpi: DPI::_600,
+    },
+    Model {
+        name: "TS5100",
+        feeder: None,
+        max_dpi: DPI::_600,
+    },
+];
+
+/// Scan parameters a scanner can't honor
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum Unsupported {
+    #[error("{model} has no {requested}")]
+    Source { model: String, requested: Source },
+    #[error("{model} can't scan both sides from the feeder")]
+    Duplex { model: String },
+    #[error("{model} scans at most at {max} DPI, requested {requested} DPI")]
+    Dpi {
+        model: String,
+        requested: DPI,
+        max: DPI,
+    },
+}
+
+/// What a scanner is able to do, used to validate scan parameters before
+/// starting a job.
+///
+/// Capabilities of models missing from the embedded table are unknown, and
+/// every parameter is assumed to be supported.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Capabilities {
+    model: Option<String>,
+    command_sets: Vec<String>,
+    known: bool,
+    feeder: Option<FeederType>,
+    max_dpi: DPI,
+}
+
+impl Capabilities {
+    pub fn from_identity(identity: &identity::Response) -> Self {
+        let model = identity.get("MDL").map(str::to_owned);
+        // e.g. `CMD:MultiPass 2.1,IVEC;`
+        let command_sets = identity
+            .get("CMD")
+            .map(|cmd| {
+                cmd.split(',')
+                    .map(|set| set.trim().to_owned())
+                    .filter(|set| !set.is_empty())
+                    .collect()
+            })
+            .unwrap_or_default();
+        let entry = model
+            .as_deref()
+            .and_then(|model| MODELS.iter().find(|entry| model.starts_with(entry.name)));
+
+        Self {
+            model,
+            command_sets,
+            known: entry.is_some(),
+            feeder: entry.map_or(Some(FeederType::Duplex), |entry| entry.feeder),
+            max_dpi: entry.map_or(DPI::_600, |entry| entry.max_dpi),
+        }
+    }
+
+    /// Model reported in `MDL`
+    #[inline(always)]
+    pub fn model(&self) -> Option<&str> {
+        self.model.as_deref()
+    }
+
+    /// Command sets reported in `CMD`
+    #[inline(always)]
+    pub fn command_sets(&self) -> &[String] {
+        &self.command_sets
+    }
+
+    /// Whether the model is in the embedded table, otherwise the capabilities
+    /// are assumed
+    #[inline(always)]
+    pub fn known(&self) -> bool {
+        self.known
+    }
+
+    pub fn sources(&self) -> Vec<Source> {
+        let mut sources = vec![Source::Flatbed];
+        if self.feeder.is_some() {
+            sources.push(Source::AutoDocumentFeeder);
+        }
+        sources
+    }
+
+    #[inline(always)]
+    pub fn duplex(&self) -> bool {
+        self.feeder == Some(FeederType::Duplex)
+    }
+
+    #[inline(always)]
+    pub fn max_dpi(&self) -> DPI {
+        self.max_dpi
+    }
+
+    /// Checks that the parameters chosen on the panel can be honored. Values
+    /// not recognized by the crate are let through.
+    pub fn check(&self, interrupt: &Interrupt) -> Result<(), Unsupported> {
+        let model = || self.model.clone().unwrap_or_else(|| "scanner".to_owned());
+        if !self.sources().contains(&interrupt.source()) {
+            return Err(Unsupported::Source {
+                model: model(),
+                requested: interrupt.source(),
+            });
+        }
+        if interrupt.source() == Source::AutoDocumentFeeder
+            && interrupt.feeder_type() == Some(FeederType::Duplex)
+            && !self.duplex()
+        {
+            return Err(Unsupported::Duplex { model: model() });
+        }
+        if let Some(requested) = interrupt.dpi().known() {
+            if requested.dpi_value() > self.max_dpi.dpi_value() {
+                return Err(Unsupported::Dpi {
+                    model: model(),
+                    requested,
+                    max: self.max_dpi,
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Display for Capabilities {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("flatbed")?;
+        if let Some(feeder_type) = self.feeder {
+            f.write_fmt(format_args!(", {feeder_type} feeder"))?;
+        }
+        f.write_fmt(format_args!(", up to {} DPI", self.max_dpi))?;
+        if !self.known {
+            f.write_str(" (assumed, unknown model)")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::serdes::Deserialize;
+
+    fn identity(identity: &str) -> identity::Response {
+        let mut buffer = ((identity.len() + 2) as u16).to_be_bytes().to_vec();
+        buffer.extend_from_slice(identity.as_bytes());
+        identity::Response::deserialize(&buffer).unwrap().0
+    }
+
+    fn interrupt(source: u8, feeder_type: u8, dpi: u8) -> Interrupt {
+        let mut buffer = [0; 20];
+        buffer[7] = 0x01; // color mode
+        buffer[8] = source;
+        buffer[9] = feeder_type;
+        buffer[12] = dpi;
+        Interrupt::deserialize(&buffer).unwrap().0
+    }
+
+    #[test]
+    fn known_model() {
+        let capabilities = Capabilities::from_identity(&identity(
+            "MFG:Canon;CMD:MultiPass 2.1,IVEC;MDL:MX490 series;",
+        ));
+        assert!(capabilities.known());
+        assert_eq!(capabilities.command_sets(), ["MultiPass 2.1", "IVEC"]);
+        assert_eq!(
+            capabilities.sources(),
+            [Source::Flatbed, Source::AutoDocumentFeeder]
+        );
+        assert!(!capabilities.duplex());
+        assert_eq!(
+            capabilities.to_string(),
+            "flatbed, simplex feeder, up to 600 DPI"
+        );
+
+        assert_eq!(capabilities.check(&interrupt(0x02, 0x01, 0x03)), Ok(()));
+        assert_eq!(
+            capabilities.check(&interrupt(0x02, 0x02, 0x03)),
+            Err(Unsupported::Duplex {
+                model: "MX490 series".to_owned()
+            })
+        );
+        // unknown DPI values are let through
+        assert_eq!(capabilities.check(&interrupt(0x01, 0x00, 0x05)), Ok(()));
+    }
+
+    #[test]
+    fn unknown_model() {
+        let capabilities = Capabilities::from_identity(&identity("MFG:Canon;MDL:Dummy;"));
+        assert!(!capabilities.known());
+        assert!(capabilities.duplex());
+        assert_eq!(capabilities.check(&interrupt(0x02, 0x02, 0x06)), Ok(()));
+    }
+}
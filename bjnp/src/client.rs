@@ -0,0 +1,66 @@
+//! This module contains an async client of the scanner channel on top of
+//! tokio, sending commands to a scanner and awaiting the matching responses.
+//! The client is only available with the `tokio` feature; see
+//! [`blocking`](crate::blocking) for a client without async runtime.
+//!
+//! ```no_run
+//! # #[cfg(feature = "tokio")]
+//! # async fn example() -> Result<(), bjnp::Error> {
+//! use bjnp::{client::Client, identity};
+//!
+//! let mut client = Client::connect("192.168.1.2:8612".parse().unwrap()).await?;
+//! let id = client.request(identity::Request::new()).await?;
+//! println!("{id}");
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::{
+    serdes::Serialize, Error, Packet, PacketBuilder, PacketHeaderOnly, PacketType, Payload,
+    ProtocolError, Request,
+};
+
+#[cfg(feature = "tokio")]
+mod nonblocking;
+
+#[cfg(feature = "tokio")]
+pub use nonblocking::Client;
+
+/// Serializes a command with the given sequence number.
+pub(crate) fn build_command<C: Payload + Serialize>(sequence: u16, command: C) -> Vec<u8> {
+    PacketBuilder::new(PacketType::ScannerCommand, C::PAYLOAD_TYPE)
+        .sequence(sequence)
+        .build(command)
+        .serialize_to_vec()
+}
+
+/// Decodes `buffer` as the response to the command `R` of `sequence`, or
+/// returns `None` if it doesn't answer the command.
+pub(crate) fn match_response<R: Request>(
+    buffer: &[u8],
+    sequence: u16,
+) -> Option<Result<R::Response, Error>> {
+    let packet = PacketHeaderOnly::parse(buffer).ok()?;
+    if packet.sequence() != sequence {
+        return None;
+    }
+    if packet.packet_type() != PacketType::ScannerResponse
+        || packet.payload_type() != R::PAYLOAD_TYPE
+    {
+        return Some(Err(ProtocolError::UnexpectedResponse {
+            expected: R::PAYLOAD_TYPE,
+            packet_type: packet.packet_type(),
+            payload_type: packet.payload_type(),
+        }
+        .into()));
+    }
+    if packet.error() != 0 && packet.payload_size() == 0 {
+        return Some(Err(ProtocolError::Remote(packet.error()).into()));
+    }
+
+    Some(
+        Packet::<R::Response>::try_from(packet)
+            .map(Packet::payload)
+            .map_err(Error::from),
+    )
+}
@@ -0,0 +1,154 @@
+use std::{io, net::SocketAddr, num::Wrapping, time::Duration};
+
+use tokio::{net::UdpSocket, time::timeout};
+
+use super::{build_command, match_response};
+use crate::{Error, ProtocolError, Request};
+
+/// A client of the scanner channel of a single scanner
+#[derive(Debug)]
+pub struct Client {
+    socket: UdpSocket,
+    sequence: Wrapping<u16>,
+    timeout: Duration,
+}
+
+impl Client {
+    /// Time to wait for a response unless changed by [`Client::set_timeout`]
+    pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// Creates a client of the scanner at `addr`, usually on port 8612.
+    pub async fn connect(addr: SocketAddr) -> Result<Self, Error> {
+        let local: SocketAddr = if addr.is_ipv4() {
+            ([0; 4], 0).into()
+        } else {
+            ([0; 16], 0).into()
+        };
+        let socket = UdpSocket::bind(local).await?;
+        socket.connect(addr).await?;
+        Ok(Self::from_socket(socket))
+    }
+
+    /// Creates a client from a socket already connected to a scanner.
+    pub fn from_socket(socket: UdpSocket) -> Self {
+        Self {
+            socket,
+            sequence: Wrapping(0),
+            timeout: Self::DEFAULT_TIMEOUT,
+        }
+    }
+
+    #[inline(always)]
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    /// Returns the address of the scanner.
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.peer_addr()
+    }
+
+    /// Resets the sequence number, as expected by scanners when a session
+    /// starts.
+    pub fn reset_sequence(&mut self) {
+        self.sequence = Wrapping(0);
+    }
+
+    /// Sends a command and awaits its response, of the type answering it.
+    /// Packets not answering the command, e.g. late responses to earlier
+    /// commands, are discarded.
+    pub async fn request<R: Request>(&mut self, command: R) -> Result<R::Response, Error> {
+        let sequence = self.sequence.0;
+        self.sequence += 1;
+
+        self.socket.send(&build_command(sequence, command)).await?;
+
+        timeout(self.timeout, self.recv::<R>(sequence))
+            .await
+            .map_err(|_| ProtocolError::Timeout(R::PAYLOAD_TYPE))?
+    }
+
+    async fn recv<R: Request>(&self, sequence: u16) -> Result<R::Response, Error> {
+        let mut buffer = vec![0; 65536];
+        loop {
+            let size = self.socket.recv(&mut buffer).await?;
+            if let Some(resp) = match_response::<R>(&buffer[..size], sequence) {
+                return resp;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        discover, serdes::Serialize, PacketBuilder, PacketHeaderOnly, PacketType, PayloadType,
+    };
+
+    async fn pair() -> (Client, UdpSocket) {
+        let scanner = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let client = Client::connect(scanner.local_addr().unwrap())
+            .await
+            .unwrap();
+        (client, scanner)
+    }
+
+    /// Answers a command with a discover response for each offset from its
+    /// sequence.
+    async fn reply(scanner: &UdpSocket, payload_type: PayloadType, offsets: &[u16]) {
+        let mut buffer = [0; 1024];
+        let (size, peer) = scanner.recv_from(&mut buffer).await.unwrap();
+        let command = PacketHeaderOnly::parse(&buffer[..size]).unwrap();
+        for offset in offsets {
+            let response = PacketBuilder::new(PacketType::ScannerResponse, payload_type)
+                .sequence(command.sequence().wrapping_add(*offset))
+                .build(discover::Response::new(
+                    "00:1e:8f:01:02:03".parse().unwrap(),
+                    "127.0.0.1".parse().unwrap(),
+                ));
+            scanner
+                .send_to(&response.serialize_to_vec(), peer)
+                .await
+                .unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn request_skips_stray_packets() {
+        let (mut client, scanner) = pair().await;
+        let (resp, _) = tokio::join!(
+            client.request(discover::Request::new()),
+            reply(&scanner, PayloadType::Discover, &[1, 0])
+        );
+        assert!(resp.is_ok());
+    }
+
+    #[tokio::test]
+    async fn request_rejects_other_payload_type() {
+        let (mut client, scanner) = pair().await;
+        let (resp, _) = tokio::join!(
+            client.request(discover::Request::new()),
+            reply(&scanner, PayloadType::GetId, &[0])
+        );
+        assert!(matches!(
+            resp,
+            Err(Error::Protocol(ProtocolError::UnexpectedResponse { .. }))
+        ));
+    }
+
+    #[tokio::test]
+    async fn request_times_out() {
+        let (mut client, _scanner) = pair().await;
+        client.set_timeout(Duration::from_millis(10));
+        let resp = client.request(discover::Request::new()).await;
+        assert!(matches!(
+            resp,
+            Err(Error::Protocol(ProtocolError::Timeout(PayloadType::Discover)))
+        ));
+    }
+}
@@ -1,19 +1,29 @@
-//! This module contains structs related to the response of a discover command
+//! This module contains structs related to a discover command and its response
 
 use std::{
     fmt::Display,
     mem::size_of,
     net::{IpAddr, Ipv4Addr, Ipv6Addr},
     slice,
+    str::FromStr,
 };
 
+use arrayvec::ArrayVec;
 use memoffset::offset_of;
-
-use crate::serdes::{
-    Deserialize, FormatError, OffsetError, ParseError, Serialize, SizedDeserialize,
+use thiserror::Error;
+
+use crate::{
+    capabilities::Capabilities,
+    identity,
+    json::JsonObject,
+    serdes::{
+        Deserialize, Empty, FixedSize, FormatError, OffsetError, ParseError, Serialize,
+        SizedDeserialize,
+    },
+    Packet, PacketBuilder, PacketHeaderOnly, PacketType, Payload, PayloadType,
 };
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 #[repr(C, packed)]
 pub struct Eui48([u8; 6]);
 
@@ -64,7 +74,7 @@ impl From<[u8; 6]> for Eui48 {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 #[repr(C, packed)]
 pub struct Eui64([u8; 8]);
 
@@ -115,12 +125,29 @@ impl From<[u8; 8]> for Eui64 {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum MacAddr {
     Eui48(Eui48),
     Eui64(Eui64),
 }
 
+impl MacAddr {
+    /// Returns the Organizationally Unique Identifier of the address.
+    pub fn oui(&self) -> [u8; 3] {
+        match self {
+            MacAddr::Eui48(addr) => [addr.0[0], addr.0[1], addr.0[2]],
+            MacAddr::Eui64(addr) => [addr.0[0], addr.0[1], addr.0[2]],
+        }
+    }
+
+    /// Returns the name of the vendor that the address is assigned to, if it
+    /// is in the embedded OUI table.
+    #[cfg(feature = "oui")]
+    pub fn vendor(&self) -> Option<&'static str> {
+        crate::oui::lookup(self.oui())
+    }
+}
+
 impl Display for MacAddr {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -130,6 +157,33 @@ impl Display for MacAddr {
     }
 }
 
+/// Error parsing a [`MacAddr`] from a string
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+#[error("invalid MAC address, expect 6 or 8 hexadecimal octets separated by `:` or `-`")]
+pub struct AddrParseError;
+
+impl FromStr for MacAddr {
+    type Err = AddrParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let octets = s
+            .split([':', '-'])
+            .map(|octet| {
+                if octet.len() == 2 {
+                    u8::from_str_radix(octet, 16).map_err(|_| AddrParseError)
+                } else {
+                    Err(AddrParseError)
+                }
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        match octets.len() {
+            6 => Ok(Eui48(octets.try_into().unwrap()).into()),
+            8 => Ok(Eui64(octets.try_into().unwrap()).into()),
+            _ => Err(AddrParseError),
+        }
+    }
+}
+
 impl From<Eui48> for MacAddr {
     #[inline(always)]
     fn from(value: Eui48) -> Self {
@@ -234,15 +288,84 @@ impl Serialize for IpAddr {
     }
 }
 
+/// Payload of a discover command.
+///
+/// The probe is currently empty on the wire.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Request {}
+
+impl Request {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+
+impl From<Empty> for Request {
+    #[inline(always)]
+    fn from(_: Empty) -> Self {
+        Self::new()
+    }
+}
+
+impl Serialize for Request {
+    #[inline(always)]
+    fn serialize<W>(&self, _writer: &mut W) -> Result<(), std::io::Error>
+    where
+        W: std::io::Write,
+    {
+        Ok(())
+    }
+
+    #[inline(always)]
+    fn size(&self) -> usize {
+        0
+    }
+}
+
+impl FixedSize for Request {
+    const SIZE: usize = 0;
+}
+
+impl Payload for Request {
+    const PAYLOAD_TYPE: PayloadType = PayloadType::Discover;
+}
+
+impl crate::Request for Request {
+    type Response = Response;
+}
+
+impl Deserialize for Request {
+    #[inline(always)]
+    fn deserialize(_buffer: &[u8]) -> Result<(Self, usize), ParseError> {
+        Ok((Self::new(), 0))
+    }
+}
+
+impl Display for Request {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if f.alternate() {
+            return JsonObject::new(f)?.finish();
+        }
+        f.write_str("<empty>")
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Response {
+    /// Bytes of unknown meaning, kept as received to be sent again as is
+    unk_1: [u8; 4],
     mac_addr: MacAddr,
     ip_addr: IpAddr,
 }
 
 impl Response {
     pub fn new(mac_addr: MacAddr, ip_addr: IpAddr) -> Self {
-        Self { mac_addr, ip_addr }
+        Self {
+            unk_1: [0x00, 0x01, 0x08, 0x00],
+            mac_addr,
+            ip_addr,
+        }
     }
 
     #[inline(always)]
@@ -262,14 +385,16 @@ impl Serialize for Response {
         W: std::io::Write,
     {
         let raw_header = RawResponseHeader {
-            unk_1: [0x00, 0x01, 0x08, 0x00],
+            unk_1: self.unk_1,
             mac_len: self.mac_addr.size() as u8,
             ip_len: self.ip_addr.size() as u8,
         };
-        raw_header.serialize(writer)?;
-        self.mac_addr.serialize(writer)?;
-        self.ip_addr.serialize(writer)?;
-        Ok(())
+        // assembled on the stack to be written at once
+        let mut buffer = ArrayVec::<u8, MAX_RESPONSE_SIZE>::new();
+        raw_header.serialize(&mut buffer)?;
+        self.mac_addr.serialize(&mut buffer)?;
+        self.ip_addr.serialize(&mut buffer)?;
+        writer.write_all(&buffer)
     }
 
     fn size(&self) -> usize {
@@ -277,6 +402,10 @@ impl Serialize for Response {
     }
 }
 
+impl Payload for Response {
+    const PAYLOAD_TYPE: PayloadType = PayloadType::Discover;
+}
+
 impl Deserialize for Response {
     fn deserialize(buffer: &[u8]) -> Result<(Response, usize), ParseError> {
         let (raw_header, mut size): (&RawResponseHeader, _) = Deserialize::deserialize(buffer)?;
@@ -293,6 +422,7 @@ impl Deserialize for Response {
                     byte: b,
                     offset: offset_of!(RawResponseHeader, mac_len),
                     message: "invalid MAC address size, can only be 6 or 8",
+                    field: "mac_len".into(),
                 }
                 .into());
             }
@@ -312,17 +442,31 @@ impl Deserialize for Response {
                     byte: b,
                     offset: offset_of!(RawResponseHeader, ip_len),
                     message: "invalid IP address size, can only be 4 or 16",
+                    field: "ip_len".into(),
                 }
                 .into());
             }
         };
         size += next;
-        Ok((Self { mac_addr, ip_addr }, size))
+        Ok((
+            Self {
+                unk_1: raw_header.unk_1,
+                mac_addr,
+                ip_addr,
+            },
+            size,
+        ))
     }
 }
 
 impl Display for Response {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if f.alternate() {
+            let mut obj = JsonObject::new(f)?;
+            obj.string("mac", &self.mac_addr)?;
+            obj.string("ip", &self.ip_addr)?;
+            return obj.finish();
+        }
         f.write_fmt(format_args!(
             "mac={mac} ip={ip}",
             mac = self.mac_addr,
@@ -331,6 +475,88 @@ impl Display for Response {
     }
 }
 
+/// A device which answered a discover command, along with the identity it
+/// reported afterwards
+#[derive(Debug, Clone)]
+pub struct DiscoveredDevice {
+    mac_addr: MacAddr,
+    ip_addr: IpAddr,
+    identity: identity::Response,
+    capabilities: Capabilities,
+}
+
+impl DiscoveredDevice {
+    pub fn new(response: &Response, identity: identity::Response) -> Self {
+        Self {
+            mac_addr: response.mac_addr,
+            ip_addr: response.ip_addr,
+            capabilities: Capabilities::from_identity(&identity),
+            identity,
+        }
+    }
+
+    #[inline(always)]
+    pub fn mac_addr(&self) -> &MacAddr {
+        &self.mac_addr
+    }
+
+    #[inline(always)]
+    pub fn ip_addr(&self) -> &IpAddr {
+        &self.ip_addr
+    }
+
+    #[inline(always)]
+    pub fn identity(&self) -> &identity::Response {
+        &self.identity
+    }
+
+    #[inline(always)]
+    pub fn capabilities(&self) -> &Capabilities {
+        &self.capabilities
+    }
+}
+
+/// Answers discover commands on behalf of a device, e.g. to advertise a
+/// device for testing or to make a device on another network segment
+/// discoverable.
+///
+/// The responder does no I/O by itself: feed it inbound packets and send back
+/// whatever it returns.
+#[derive(Debug, Clone)]
+pub struct Responder {
+    response: Response,
+}
+
+impl Responder {
+    pub fn new(response: Response) -> Self {
+        Self { response }
+    }
+
+    #[inline(always)]
+    pub fn response(&self) -> &Response {
+        &self.response
+    }
+
+    /// Returns the response packet if `buffer` contains a discover command,
+    /// or `None` if it contains any other packet.
+    pub fn respond(&self, buffer: &[u8]) -> Result<Option<Packet<Response>>, ParseError> {
+        let packet = PacketHeaderOnly::parse(buffer)?;
+        if packet.packet_type() != PacketType::ScannerCommand
+            || packet.payload_type() != PayloadType::Discover
+        {
+            return Ok(None);
+        }
+        Ok(Some(
+            PacketBuilder::new(PacketType::ScannerResponse, PayloadType::Discover)
+                .sequence(packet.sequence())
+                .build(self.response.clone()),
+        ))
+    }
+}
+
+/// Size of a response with an EUI-64 and an IPv6 address
+const MAX_RESPONSE_SIZE: usize = size_of::<RawResponseHeader>() + 8 + 16;
+
 #[derive(Debug, Clone)]
 #[repr(C, packed)]
 struct RawResponseHeader {
@@ -363,3 +589,99 @@ impl SizedDeserialize for &RawResponseHeader {
         Ok(&*(buffer.as_ptr() as *const RawResponseHeader))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_mac_addr() {
+        assert_eq!(
+            "00:1e:8f:01:02:ab".parse(),
+            Ok(MacAddr::Eui48([0x00, 0x1e, 0x8f, 0x01, 0x02, 0xab].into()))
+        );
+        assert_eq!(
+            "00-1E-8F-01-02-03-04-05".parse(),
+            Ok(MacAddr::Eui64(
+                [0x00, 0x1e, 0x8f, 0x01, 0x02, 0x03, 0x04, 0x05].into()
+            ))
+        );
+        assert_eq!("00:1e:8f:01:02".parse::<MacAddr>(), Err(AddrParseError));
+        assert_eq!("00:1e:8f:01:02:zz".parse::<MacAddr>(), Err(AddrParseError));
+    }
+
+    #[test]
+    fn respond_to_discover() {
+        let mac: MacAddr = "00:1e:8f:01:02:03".parse().unwrap();
+        let ip: IpAddr = Ipv4Addr::new(192, 168, 1, 2).into();
+        let responder = Responder::new(Response::new(mac, ip));
+
+        let command = PacketBuilder::new(PacketType::ScannerCommand, PayloadType::Discover)
+            .sequence(3)
+            .build(Request::new());
+        assert_eq!(Packet::<Request>::SIZE, 16);
+        let command = command
+            .serialize_to_array::<{ Packet::<Request>::SIZE }>()
+            .unwrap();
+        let packet = responder.respond(&command).unwrap().unwrap();
+        assert_eq!(packet.packet_type(), PacketType::ScannerResponse);
+        assert_eq!(packet.sequence(), 3);
+
+        let ipv6 = packet.map_payload(|response| {
+            Response::new(*response.mac_addr(), "fe80::1".parse().unwrap())
+        });
+        assert_eq!((ipv6.sequence(), ipv6.payload_size()), (3, 6 + 6 + 16));
+        let (header, _) = ipv6.into_parts();
+        let packet = Packet::from_parts(header, Response::new(mac, ip));
+
+        let buffer = packet.serialize_to_vec();
+        assert_eq!(
+            packet.serialize_to_array::<64>().unwrap().as_slice(),
+            buffer
+        );
+        assert_eq!(
+            packet.serialize_to_array::<16>().unwrap_err().kind(),
+            std::io::ErrorKind::WriteZero
+        );
+        let packet: Packet<Response> = PacketHeaderOnly::parse(&buffer)
+            .unwrap()
+            .try_into()
+            .unwrap();
+        assert_eq!(packet.payload_ref().mac_addr(), &mac);
+        assert_eq!(packet.payload_ref().ip_addr(), &ip);
+
+        let truncated = &buffer[..buffer.len() - 4];
+        assert!(PacketHeaderOnly::parse(truncated).is_err());
+        let packet = PacketHeaderOnly::parse_truncated(truncated).unwrap();
+        assert!(packet.is_truncated());
+        assert_eq!(
+            packet.received_payload_size() + 4,
+            packet.payload_size() as usize
+        );
+        assert!(!PacketHeaderOnly::parse_truncated(&buffer)
+            .unwrap()
+            .is_truncated());
+
+        let command = PacketBuilder::new(PacketType::ScannerCommand, PayloadType::GetId)
+            .build(Empty)
+            .serialize_to_vec();
+        assert!(responder.respond(&command).unwrap().is_none());
+    }
+
+    #[test]
+    fn display_json() {
+        let mac: MacAddr = "00:1e:8f:01:02:03".parse().unwrap();
+        let packet = PacketBuilder::new(PacketType::ScannerResponse, PayloadType::Discover)
+            .sequence(3)
+            .build(Response::new(mac, Ipv4Addr::new(192, 168, 1, 2).into()));
+        assert_eq!(
+            format!("{packet:#}"),
+            format!(
+                r#"{{"header":{{"packet_type":"{packet_type}","payload_type":"{payload_type}","error":0,"sequence":3,"job_id":null,"payload_len":{len}}},"payload":{{"mac":"{mac}","ip":"192.168.1.2"}}}}"#,
+                packet_type = PacketType::ScannerResponse,
+                payload_type = PayloadType::Discover,
+                len = packet.payload_size(),
+            )
+        );
+    }
+}
@@ -0,0 +1,71 @@
+//! This module contains the top-level error of the crate, covering failures
+//! when talking to a scanner.
+
+use std::io;
+
+use thiserror::Error;
+
+use crate::{serdes::ParseError, PacketType, PayloadType};
+
+/// Error communicating with a scanner
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("socket error")]
+    Transport(#[from] io::Error),
+    #[error("malformed packet")]
+    Parse(#[from] ParseError),
+    #[error(transparent)]
+    Protocol(#[from] ProtocolError),
+}
+
+/// Well-formed packets violating the protocol
+#[derive(Error, Debug, Clone)]
+pub enum ProtocolError {
+    #[error("timeout awaiting `[{0}]` response")]
+    Timeout(PayloadType),
+    #[error(
+        "unexpected response, expect `[{expected}]`, found `[{packet_type}] [{payload_type}]`"
+    )]
+    UnexpectedResponse {
+        expected: PayloadType,
+        packet_type: PacketType,
+        payload_type: PayloadType,
+    },
+    #[error("scanner returns error code `{0:#02x}`")]
+    Remote(u8),
+}
+
+impl From<Error> for io::Error {
+    fn from(err: Error) -> Self {
+        match err {
+            Error::Transport(err) => err,
+            Error::Parse(err) => err.into(),
+            Error::Protocol(err @ ProtocolError::Timeout(_)) => {
+                io::Error::new(io::ErrorKind::TimedOut, err)
+            }
+            Error::Protocol(err @ ProtocolError::UnexpectedResponse { .. }) => {
+                io::Error::new(io::ErrorKind::InvalidData, err)
+            }
+            Error::Protocol(err @ ProtocolError::Remote(_)) => io::Error::other(err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn into_io_error() {
+        let err: io::Error = Error::from(ProtocolError::Timeout(PayloadType::Poll)).into();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        assert_eq!(err.to_string(), "timeout awaiting `[poll]` response");
+
+        let err: io::Error = Error::from(ParseError::UnexpectedEnd {
+            expected: 16,
+            actual: 4,
+        })
+        .into();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
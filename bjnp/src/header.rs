@@ -4,7 +4,10 @@ use std::{fmt::Display, num::NonZeroU16};
 
 use memoffset::offset_of;
 
-use crate::serdes::{make_u8_field, FormatError, HasRawRepr, OffsetError};
+use crate::{
+    json::JsonObject,
+    serdes::{make_u8_field, FieldError, FormatError, HasRawRepr, OffsetError},
+};
 
 const MAGIC: &[u8; 4] = b"BJNP";
 
@@ -49,11 +52,18 @@ pub(crate) struct Header {
     pub(crate) packet_type: PacketType,
     pub(crate) payload_type: PayloadType,
     pub(crate) error: u8,
+    /// Byte of unknown meaning, kept as received to be sent again as is
+    pub(crate) unk_1: u8,
     pub(crate) sequence: u16,
     pub(crate) job_id: Option<NonZeroU16>,
     pub(crate) payload_size: u32,
 }
 
+impl Header {
+    /// Size of the header on the wire
+    pub(crate) const SIZE: usize = std::mem::size_of::<RawHeader>();
+}
+
 #[doc(hidden)]
 #[derive(Debug, Clone)]
 #[repr(C, packed)]
@@ -70,6 +80,16 @@ pub(crate) struct RawHeader {
 
 impl Display for Header {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if f.alternate() {
+            let mut obj = JsonObject::new(f)?;
+            obj.string("packet_type", &self.packet_type)?;
+            obj.string("payload_type", &self.payload_type)?;
+            obj.raw("error", &self.error)?;
+            obj.raw("sequence", &self.sequence)?;
+            obj.optional("job_id", self.job_id, JsonObject::raw)?;
+            obj.raw("payload_len", &self.payload_size)?;
+            return obj.finish();
+        }
         f.pad("")?;
         f.write_fmt(format_args!(
             "[{}] [{}] error={:#02x} sequence={}",
@@ -94,7 +114,7 @@ impl From<&Header> for RawHeader {
             packet_type: header.packet_type as u8,
             payload_type: header.payload_type as u8,
             error: header.error,
-            unk_1: 0,
+            unk_1: header.unk_1,
             sequence: header.sequence.to_be_bytes(),
             job_id: header
                 .job_id
@@ -114,14 +134,20 @@ impl TryFrom<&RawHeader> for Header {
             return Err(FormatError::InvalidSlice {
                 span: (0..4),
                 message: "magic bytes is not b'BJNP'",
+                field: "magic".into(),
             });
         }
 
-        let packet_type = raw_header.packet_type.try_into()?;
+        let packet_type = raw_header
+            .packet_type
+            .try_into()
+            .offset_by(offset_of!(RawHeader, packet_type))
+            .in_field("packet_type")?;
         let payload_type = raw_header
             .payload_type
             .try_into()
-            .offset_by(offset_of!(RawHeader, payload_type))?;
+            .offset_by(offset_of!(RawHeader, payload_type))
+            .in_field("payload_type")?;
         let sequence = u16::from_be_bytes(raw_header.sequence);
         let job_id = NonZeroU16::new(u16::from_be_bytes(raw_header.job_id));
         let len = u32::from_be_bytes(raw_header.len);
@@ -129,6 +155,7 @@ impl TryFrom<&RawHeader> for Header {
             packet_type,
             payload_type,
             error: raw_header.error,
+            unk_1: raw_header.unk_1,
             sequence,
             job_id,
             payload_size: len,
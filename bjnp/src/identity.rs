@@ -7,42 +7,115 @@ use std::{
     str,
 };
 
-use crate::serdes::{Deserialize, FormatError, OffsetError, ParseError, Serialize};
+use crate::{
+    json::JsonObject,
+    serdes::{Deserialize, Empty, FixedSize, FormatError, OffsetError, ParseError, Serialize},
+    Payload, PayloadType,
+};
+
+/// Payload of a get identity command, empty on the wire.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Request {}
+
+impl Request {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+
+impl From<Empty> for Request {
+    #[inline(always)]
+    fn from(_: Empty) -> Self {
+        Self::new()
+    }
+}
+
+impl Serialize for Request {
+    #[inline(always)]
+    fn serialize<W>(&self, _writer: &mut W) -> Result<(), std::io::Error>
+    where
+        W: std::io::Write,
+    {
+        Ok(())
+    }
+
+    #[inline(always)]
+    fn size(&self) -> usize {
+        0
+    }
+}
+
+impl FixedSize for Request {
+    const SIZE: usize = 0;
+}
+
+impl Payload for Request {
+    const PAYLOAD_TYPE: PayloadType = PayloadType::GetId;
+}
+
+impl crate::Request for Request {
+    type Response = Response;
+}
+
+impl Deserialize for Request {
+    #[inline(always)]
+    fn deserialize(_buffer: &[u8]) -> Result<(Self, usize), ParseError> {
+        Ok((Self::new(), 0))
+    }
+}
+
+impl Display for Request {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if f.alternate() {
+            return JsonObject::new(f)?.finish();
+        }
+        f.write_str("<empty>")
+    }
+}
 
 #[derive(Debug, Clone, PartialEq, Eq)]
-pub struct Response(HashMap<String, String>);
+pub struct Response {
+    map: HashMap<String, String>,
+    /// Identity as received, in the order of the scanner, to be sent again
+    /// as is
+    raw: String,
+}
 
 impl Response {
     pub fn get(&self, key: &str) -> Option<&str> {
-        self.0.get(key).map(String::as_str)
+        self.map.get(key).map(String::as_str)
     }
 
     pub fn contains_key(&self, key: &str) -> bool {
-        self.0.contains_key(key)
-    }
-
-    pub fn iter(&self) -> hash_map::Iter<String, String> {
-        self.0.iter()
+        self.map.contains_key(key)
     }
 
-    fn as_str_len(&self) -> usize {
-        self.0
-            .iter()
-            .map(|(key, value)| key.len() + value.len() + 2)
-            .sum()
+    pub fn iter(&self) -> hash_map::Iter<'_, String, String> {
+        self.map.iter()
     }
 }
 
 impl Display for Response {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.pad("")?;
-        for (key, value) in self.0.iter() {
-            f.write_fmt(format_args!("{}:{};", key, value))?;
+        if f.alternate() {
+            let mut entries: Vec<_> = self.map.iter().collect();
+            entries.sort();
+            let mut obj = JsonObject::new(f)?;
+            for (key, value) in entries {
+                obj.string(key, value)?;
+            }
+            return obj.finish();
         }
-        Ok(())
+        f.pad("")?;
+        f.write_str(&self.raw)
     }
 }
 
+impl Payload for Response {
+    const PAYLOAD_TYPE: PayloadType = PayloadType::GetId;
+}
+
 impl Deserialize for Response {
     fn deserialize(buffer: &[u8]) -> Result<(Self, usize), ParseError> {
         use FormatError::*;
@@ -60,6 +133,7 @@ impl Deserialize for Response {
             return Err(InvalidSlice {
                 span: (0..2),
                 message: "invalid length of identity, should always be >=2",
+                field: "identity_len".into(),
             }
             .into());
         }
@@ -76,12 +150,14 @@ impl Deserialize for Response {
             Some(len) if len > 1 => InvalidSlice {
                 span: (e.valid_up_to()..e.valid_up_to() + len),
                 message: "invalid UTF-8 bytes",
+                field: "identity".into(),
             }
             .into(),
             Some(_) => InvalidByte {
                 byte: buffer[e.valid_up_to() - 1],
                 offset: e.valid_up_to() - 1,
                 message: "invalid UTF-8 byte",
+                field: "identity".into(),
             }
             .into(),
             None => UnexpectedEnd {
@@ -90,13 +166,19 @@ impl Deserialize for Response {
             },
         })?;
 
-        let identity_map = identity
+        let map = identity
             .split_terminator(';')
             .filter_map(|item| item.split_once(':'))
             .map(|(key, value)| (key.to_string(), value.to_string()))
             .collect();
 
-        Ok((Self(identity_map), identity_len + 2))
+        Ok((
+            Self {
+                map,
+                raw: identity.to_owned(),
+            },
+            identity_len + 2,
+        ))
     }
 }
 
@@ -105,18 +187,19 @@ impl Serialize for Response {
     where
         W: std::io::Write,
     {
-        let u16_size: u16 = self.as_str_len().try_into().map_err(|_| {
+        // the length covers itself
+        let u16_size: u16 = self.size().try_into().map_err(|_| {
             std::io::Error::new(
                 std::io::ErrorKind::InvalidInput,
                 "length of identity exceeds maximum limit (u16::MAX)",
             )
         })?;
         writer.write_all(&u16_size.to_be_bytes())?;
-        writer.write_fmt(format_args!("{}", self))
+        writer.write_all(self.raw.as_bytes())
     }
 
     fn size(&self) -> usize {
-        2 + self.as_str_len()
+        2 + self.raw.len()
     }
 }
 
@@ -127,13 +210,12 @@ mod tests {
     #[test]
     fn deserialize() {
         // MFG:Canon;MDL:Dummy;CLS:IMAGE;
-        let response = Response::deserialize(&[
+        let (response, _) = Response::deserialize_strict(&[
             0x00, 0x20, 0x4d, 0x46, 0x47, 0x3a, 0x43, 0x61, 0x6e, 0x6f, 0x6e, 0x3b, 0x4d, 0x44,
             0x4c, 0x3a, 0x44, 0x75, 0x6d, 0x6d, 0x79, 0x3b, 0x43, 0x4c, 0x53, 0x3a, 0x49, 0x4d,
             0x41, 0x47, 0x45, 0x3b,
         ])
-        .unwrap()
-        .0;
+        .unwrap();
         assert_eq!(response.get("MFG"), Some("Canon"));
         assert_eq!(response.get("MDL"), Some("Dummy"));
         assert_eq!(response.get("CLS"), Some("IMAGE"));
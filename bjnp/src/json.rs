@@ -0,0 +1,107 @@
+//! This module contains helpers for the alternate (`{:#}`) display of packets,
+//! which prints a packet as a JSON value instead of the indented text format.
+
+use std::fmt::{self, Display, Formatter, Write};
+
+/// Writes a JSON object field by field.
+pub(crate) struct JsonObject<'a, 'b> {
+    f: &'a mut Formatter<'b>,
+    empty: bool,
+}
+
+impl<'a, 'b> JsonObject<'a, 'b> {
+    pub(crate) fn new(f: &'a mut Formatter<'b>) -> Result<Self, fmt::Error> {
+        f.write_char('{')?;
+        Ok(Self { f, empty: true })
+    }
+
+    fn key(&mut self, key: &str) -> fmt::Result {
+        if !self.empty {
+            self.f.write_char(',')?;
+        }
+        self.empty = false;
+        write_string(self.f, key)?;
+        self.f.write_char(':')
+    }
+
+    /// Writes a field whose value is displayed as a JSON string.
+    pub(crate) fn string<T: Display + ?Sized>(&mut self, key: &str, value: &T) -> fmt::Result {
+        self.key(key)?;
+        write_string(self.f, value)
+    }
+
+    /// Writes a field whose value is already valid JSON when displayed, e.g. a
+    /// number, or a nested packet in alternate form.
+    pub(crate) fn raw<T: Display + ?Sized>(&mut self, key: &str, value: &T) -> fmt::Result {
+        self.key(key)?;
+        self.f.write_fmt(format_args!("{value:#}"))
+    }
+
+    /// Writes a field which is `null` if absent.
+    pub(crate) fn optional<T: Display>(
+        &mut self,
+        key: &str,
+        value: Option<T>,
+        write: impl FnOnce(&mut Self, &str, &T) -> fmt::Result,
+    ) -> fmt::Result {
+        match value {
+            Some(value) => write(self, key, &value),
+            None => self.raw(key, "null"),
+        }
+    }
+
+    pub(crate) fn finish(self) -> fmt::Result {
+        self.f.write_char('}')
+    }
+}
+
+/// Writes `value` as a quoted and escaped JSON string.
+pub(crate) fn write_string<T: Display + ?Sized>(f: &mut Formatter<'_>, value: &T) -> fmt::Result {
+    f.write_char('"')?;
+    write!(Escape(f), "{value}")?;
+    f.write_char('"')
+}
+
+struct Escape<'a, 'b>(&'a mut Formatter<'b>);
+
+impl Write for Escape<'_, '_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            match c {
+                '"' => self.0.write_str("\\\"")?,
+                '\\' => self.0.write_str("\\\\")?,
+                '\n' => self.0.write_str("\\n")?,
+                '\r' => self.0.write_str("\\r")?,
+                '\t' => self.0.write_str("\\t")?,
+                c if c.is_control() => self.0.write_fmt(format_args!("\\u{:04x}", c as u32))?,
+                c => self.0.write_char(c)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Sample;
+
+    impl Display for Sample {
+        fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+            let mut obj = JsonObject::new(f)?;
+            obj.string("name", "a \"quoted\"\nline")?;
+            obj.raw("count", &3)?;
+            obj.optional("missing", None::<u8>, JsonObject::raw)?;
+            obj.finish()
+        }
+    }
+
+    #[test]
+    fn escape_strings() {
+        assert_eq!(
+            Sample.to_string(),
+            r#"{"name":"a \"quoted\"\nline","count":3,"missing":null}"#
+        );
+    }
+}
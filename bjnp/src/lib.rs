@@ -1,10 +1,23 @@
+pub mod blocking;
+pub mod capabilities;
+pub mod client;
 pub mod discover;
+mod error;
 mod header;
 pub mod identity;
+mod json;
+#[cfg(feature = "oui")]
+mod oui;
 pub mod packet;
 pub mod poll;
+pub mod print;
 pub mod serdes;
+#[cfg(feature = "test-vectors")]
+pub mod test_vectors;
+pub mod validate;
 
+// Packets are displayed in an indented text format by default, on a single
+// line with `{:-}`, and as JSON with `{:#}`.
 const DISPLAY_INDENT: usize = 4;
 macro_rules! write_nested {
     ($f: expr, $obj: expr) => {{
@@ -24,4 +37,8 @@ macro_rules! write_nested {
 }
 pub(crate) use write_nested;
 
-pub use crate::{packet::*, poll::command::Host};
+pub use crate::{
+    error::{Error, ProtocolError},
+    packet::*,
+    poll::command::Host,
+};
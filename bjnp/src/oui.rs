@@ -0,0 +1,51 @@
+//! This module contains an embedded table of Organizationally Unique
+//! Identifiers (OUI) for the vendors likely to be found answering on the BJNP
+//! port.
+
+/// Sorted by OUI to allow binary searching.
+const OUI_TABLE: &[([u8; 3], &str)] = &[
+    ([0x00, 0x00, 0x48], "Seiko Epson Corporation"),
+    ([0x00, 0x00, 0x85], "Canon Inc."),
+    ([0x00, 0x1b, 0xa9], "Brother Industries, Ltd."),
+    ([0x00, 0x1e, 0x8f], "Canon Inc."),
+    ([0x00, 0x26, 0xab], "Seiko Epson Corporation"),
+    ([0x00, 0x80, 0x77], "Brother Industries, Ltd."),
+    ([0x00, 0xbb, 0xc1], "Canon Inc."),
+    ([0x18, 0x0c, 0xac], "Canon Inc."),
+    ([0x2c, 0x9e, 0xfc], "Canon Inc."),
+    ([0x30, 0x05, 0x5c], "Brother Industries, Ltd."),
+    ([0x60, 0x12, 0x8b], "Canon Inc."),
+    ([0x64, 0xeb, 0x8c], "Seiko Epson Corporation"),
+    ([0x74, 0xbf, 0xc0], "Canon Inc."),
+    ([0x84, 0xba, 0x3b], "Canon Inc."),
+    ([0x88, 0x87, 0x17], "Canon Inc."),
+    ([0x9c, 0x32, 0xce], "Canon Inc."),
+    ([0xb8, 0x27, 0xeb], "Raspberry Pi Foundation"),
+    ([0xdc, 0xa6, 0x32], "Raspberry Pi Trading Ltd"),
+    ([0xf4, 0xa9, 0x97], "Canon Inc."),
+    ([0xf8, 0x0d, 0x60], "Canon Inc."),
+];
+
+/// Looks up the vendor name of an OUI.
+pub(crate) fn lookup(oui: [u8; 3]) -> Option<&'static str> {
+    OUI_TABLE
+        .binary_search_by_key(&oui, |(key, _)| *key)
+        .ok()
+        .map(|idx| OUI_TABLE[idx].1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn table_is_sorted() {
+        assert!(OUI_TABLE.windows(2).all(|w| w[0].0 < w[1].0));
+    }
+
+    #[test]
+    fn lookup_canon() {
+        assert_eq!(lookup([0x00, 0x1e, 0x8f]), Some("Canon Inc."));
+        assert_eq!(lookup([0x00, 0x1e, 0x90]), None);
+    }
+}
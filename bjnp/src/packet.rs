@@ -1,14 +1,37 @@
 //! This module contains implementation of a generic BJNP packet.
 
-use std::{fmt::Display, num::NonZeroU16};
+use std::{
+    cmp,
+    fmt::Display,
+    io::{self, IoSlice},
+    num::NonZeroU16,
+};
+
+use arrayvec::ArrayVec;
 
 pub use crate::header::{PacketType, PayloadType};
 use crate::{
     header::Header,
-    serdes::{Deserialize, ParseError, Serialize},
+    json::JsonObject,
+    serdes::{Deserialize, FixedSize, OffsetError, ParseError, Serialize},
     write_nested,
 };
 
+/// Size of the header on the wire, before the payload of every packet
+pub const HEADER_SIZE: usize = Header::SIZE;
+
+/// Payload of packets of a single payload type, so that the payload type of a
+/// packet can't mismatch its payload.
+pub trait Payload {
+    const PAYLOAD_TYPE: PayloadType;
+}
+
+/// Payload of a command, answered by a response of a single type, so that a
+/// response can't be parsed as another type.
+pub trait Request: Payload + Serialize {
+    type Response: Payload + Deserialize;
+}
+
 #[derive(Debug, Clone)]
 pub struct Packet<T> {
     header: Header,
@@ -55,6 +78,86 @@ impl<T> Packet<T> {
     pub fn payload(self) -> T {
         self.payload
     }
+
+    /// Takes the packet apart into its header and its payload.
+    #[inline(always)]
+    pub fn into_parts(self) -> (HeaderInfo, T) {
+        (HeaderInfo(self.header), self.payload)
+    }
+
+    /// Replaces the payload by what `f` makes of it, keeping the header.
+    pub fn map_payload<U: Serialize>(self, f: impl FnOnce(T) -> U) -> Packet<U> {
+        let (header, payload) = self.into_parts();
+        Packet::from_parts(header, f(payload))
+    }
+
+    /// Builds a packet of `payload` with the same header.
+    pub fn with_payload<U: Serialize>(&self, payload: U) -> Packet<U> {
+        Packet::from_parts(HeaderInfo(self.header.clone()), payload)
+    }
+}
+
+/// Header of a packet taken apart by [`Packet::into_parts`], to be put back
+/// together with a payload by [`Packet::from_parts`].
+#[derive(Debug, Clone)]
+pub struct HeaderInfo(Header);
+
+impl HeaderInfo {
+    #[inline(always)]
+    pub fn packet_type(&self) -> PacketType {
+        self.0.packet_type
+    }
+
+    #[inline(always)]
+    pub fn payload_type(&self) -> PayloadType {
+        self.0.payload_type
+    }
+
+    #[inline(always)]
+    pub fn error(&self) -> u8 {
+        self.0.error
+    }
+
+    #[inline(always)]
+    pub fn sequence(&self) -> u16 {
+        self.0.sequence
+    }
+
+    #[inline(always)]
+    pub fn job_id(&self) -> Option<NonZeroU16> {
+        self.0.job_id
+    }
+}
+
+impl<T> Packet<T>
+where
+    T: Serialize,
+{
+    /// Builds a packet of `payload` with `header`, whose payload size is that
+    /// of `payload`.
+    pub fn from_parts(header: HeaderInfo, payload: T) -> Self {
+        let mut header = header.0;
+        header.payload_size = payload.size() as u32;
+        Self { header, payload }
+    }
+
+    /// Serializes the header and the payload apart, to be sent by a single
+    /// vectored write without concatenating them first. The payload is lent
+    /// if it is held as is, see [`Serialize::wire_bytes`], and serialized on
+    /// the stack otherwise.
+    ///
+    /// # Errors
+    /// [`io::ErrorKind::WriteZero`] if the payload is serialized and is larger
+    /// than `N` bytes.
+    pub fn serialize_vectored<const N: usize>(&self) -> Result<VectoredPacket<'_, N>, io::Error> {
+        let mut header = [0; Header::SIZE];
+        self.header.serialize(&mut header.as_mut_slice())?;
+        let payload = match self.payload.wire_bytes() {
+            Some(bytes) => VectoredPayload::Lent(bytes),
+            None => VectoredPayload::Serialized(self.payload.serialize_to_array()?),
+        };
+        Ok(VectoredPacket { header, payload })
+    }
 }
 
 impl<T> Serialize for Packet<T>
@@ -75,11 +178,24 @@ where
     }
 }
 
+impl<T> FixedSize for Packet<T>
+where
+    T: FixedSize,
+{
+    const SIZE: usize = Header::SIZE + T::SIZE;
+}
+
 impl<T> Display for Packet<T>
 where
     T: Display,
 {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if f.alternate() {
+            let mut obj = JsonObject::new(f)?;
+            obj.raw("header", &self.header)?;
+            obj.raw("payload", &self.payload)?;
+            return obj.finish();
+        }
         f.pad("")?;
         f.write_fmt(format_args!("{}", self.header))?;
         write_nested!(f, self.payload)?;
@@ -87,6 +203,39 @@ where
     }
 }
 
+/// A packet serialized as its header and its payload apart, see
+/// [`Packet::serialize_vectored`].
+#[derive(Debug, Clone)]
+pub struct VectoredPacket<'a, const N: usize> {
+    header: [u8; Header::SIZE],
+    payload: VectoredPayload<'a, N>,
+}
+
+#[derive(Debug, Clone)]
+enum VectoredPayload<'a, const N: usize> {
+    Lent(&'a [u8]),
+    Serialized(ArrayVec<u8, N>),
+}
+
+impl<'a, const N: usize> VectoredPacket<'a, N> {
+    /// Slices to write, the header then the payload
+    pub fn io_slices(&self) -> [IoSlice<'_>; 2] {
+        [IoSlice::new(&self.header), IoSlice::new(self.payload())]
+    }
+
+    /// Concatenates the header and the payload, e.g. to log them.
+    pub fn to_vec(&self) -> Vec<u8> {
+        [&self.header, self.payload()].concat()
+    }
+
+    fn payload(&self) -> &[u8] {
+        match &self.payload {
+            VectoredPayload::Lent(bytes) => bytes,
+            VectoredPayload::Serialized(bytes) => bytes,
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct PacketBuilder {
     packet_type: PacketType,
@@ -142,6 +291,7 @@ impl PacketBuilder {
             packet_type: self.packet_type,
             payload_type: self.payload_type,
             error: self.error.unwrap_or(0),
+            unk_1: 0,
             sequence: self.sequence.unwrap_or(0),
             job_id: self.job_id,
             payload_size: payload.size() as u32,
@@ -170,6 +320,19 @@ impl<'buf> PacketHeaderOnly<'buf> {
         Ok(Self { header, payload })
     }
 
+    /// Parses a packet as [`PacketHeaderOnly::parse`], but keeps the payload
+    /// received so far if the buffer is shorter than the header says, e.g.
+    /// for a datagram truncated on the way. See
+    /// [`PacketHeaderOnly::is_truncated`].
+    pub fn parse_truncated(buffer: &'buf [u8]) -> Result<Self, ParseError> {
+        let (header, offset) = Header::deserialize(buffer)?;
+        let end = cmp::min(offset + header.payload_size as usize, buffer.len());
+        Ok(Self {
+            header,
+            payload: &buffer[offset..end],
+        })
+    }
+
     #[inline(always)]
     pub fn packet_type(&self) -> PacketType {
         self.header.packet_type
@@ -199,6 +362,32 @@ impl<'buf> PacketHeaderOnly<'buf> {
     pub fn payload_size(&self) -> u32 {
         self.header.payload_size
     }
+
+    /// Returns the size of the payload actually received, less than
+    /// [`PacketHeaderOnly::payload_size`] if the packet is truncated.
+    #[inline(always)]
+    pub fn received_payload_size(&self) -> usize {
+        self.payload.len()
+    }
+
+    #[inline(always)]
+    pub fn is_truncated(&self) -> bool {
+        self.payload.len() < self.header.payload_size as usize
+    }
+
+    /// Parses the payload with `parse` rather than [`Deserialize`], for
+    /// payloads whose layout is only known at runtime.
+    pub fn parse_payload<T>(
+        self,
+        parse: impl FnOnce(&[u8]) -> Result<(T, usize), ParseError>,
+    ) -> Result<Packet<T>, ParseError> {
+        // offsets in errors are relative to the start of the packet
+        let (payload, _) = parse(self.payload).offset_by(Header::SIZE)?;
+        Ok(Packet {
+            header: self.header,
+            payload,
+        })
+    }
 }
 
 impl<'buf> Display for PacketHeaderOnly<'buf> {
@@ -214,10 +403,6 @@ where
     type Error = ParseError;
 
     fn try_from(packet: PacketHeaderOnly<'buf>) -> Result<Self, Self::Error> {
-        let (payload, _) = T::deserialize(packet.payload)?;
-        Ok(Self {
-            header: packet.header,
-            payload,
-        })
+        packet.parse_payload(T::deserialize)
     }
 }
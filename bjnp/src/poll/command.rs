@@ -1,15 +1,17 @@
 //! This module contains structs related to the command of a poll request.
 
-use std::{convert::Infallible, fmt::Display, mem::transmute, slice};
+use std::{fmt::Display, mem::transmute, slice};
 
 use memoffset::span_of;
-use time::{
-    format_description::FormatItem, macros::format_description, parsing::Parsed, PrimitiveDateTime,
-};
 
-use crate::serdes::{
-    deserialized_into, make_wider_field, Deserialize, FormatError, HasRawRepr, OffsetError,
-    ParseError, Serialize,
+use super::datetime::{self, Datetime};
+use crate::{
+    json::JsonObject,
+    serdes::{
+        deserialized_into, make_wider_field, Deserialize, FieldError, FormatError, HasRawRepr,
+        OffsetError, ParseError, Serialize,
+    },
+    Payload, PayloadType, Request,
 };
 
 make_wider_field! {
@@ -27,12 +29,12 @@ make_wider_field! {
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Clone, Copy)]
 #[repr(align(2))]
 pub struct Host([u8; Host::MAX_HOST_LENGTH]);
 
 impl Host {
-    const MAX_HOST_LENGTH: usize = 64;
+    pub(crate) const MAX_HOST_LENGTH: usize = 64;
 
     pub fn new<T: AsRef<str>>(host: T) -> Self {
         // alignment = 2
@@ -76,10 +78,22 @@ impl Host {
         }
 
         // SAFETY: u16_buffer has alignment 2, same as host
-        let u8_buffer = unsafe { transmute(u16_buffer) };
+        let u8_buffer = unsafe {
+            transmute::<[u16; Self::MAX_HOST_LENGTH / 2], [u8; Self::MAX_HOST_LENGTH]>(u16_buffer)
+        };
         Self(u8_buffer)
     }
 
+    #[inline(always)]
+    pub(crate) fn from_raw(raw: [u8; Self::MAX_HOST_LENGTH]) -> Self {
+        Self(raw)
+    }
+
+    #[inline(always)]
+    pub(crate) fn to_raw(self) -> [u8; Self::MAX_HOST_LENGTH] {
+        self.0
+    }
+
     pub fn into_buf(self) -> [u16; Self::MAX_HOST_LENGTH / 2] {
         // SAFETY: alignment of self is 2, same as u16
         let mut u16_buffer: [u16; Self::MAX_HOST_LENGTH / 2] = unsafe { transmute(self.0) };
@@ -108,8 +122,20 @@ impl Display for Host {
             *c = u16::from_be(*c);
         }
 
+        // Host is padded with zeros, which are not part of the name
+        let len = u16_buffer
+            .iter()
+            .position(|&c| c == 0)
+            .unwrap_or(u16_buffer.len());
+
         // Host could contain invalid codepoint, so we use lossy decoding to display it
-        String::from_utf16_lossy(&u16_buffer).fmt(f)
+        String::from_utf16_lossy(&u16_buffer[..len]).fmt(f)
+    }
+}
+
+impl std::fmt::Debug for Host {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("Host").field(&self.to_string()).finish()
     }
 }
 
@@ -162,7 +188,7 @@ impl Command {
         }
     }
 
-    pub fn datetime(&self) -> Option<&PrimitiveDateTime> {
+    pub fn datetime(&self) -> Option<&Datetime> {
         use InnerCommand::*;
         match &self.0 {
             Full(command) => Some(&command.datetime),
@@ -197,6 +223,14 @@ impl Serialize for Command {
     }
 }
 
+impl Payload for Command {
+    const PAYLOAD_TYPE: PayloadType = PayloadType::Poll;
+}
+
+impl Request for Command {
+    type Response = super::Response;
+}
+
 impl Deserialize for Command {
     fn deserialize(buffer: &[u8]) -> Result<(Self, usize), ParseError> {
         use PollType::*;
@@ -207,7 +241,7 @@ impl Deserialize for Command {
         })?;
         // NOPANIC: poll_type == &[u8; 2]
         let poll_type = u16::from_be_bytes(poll_type.try_into().unwrap());
-        let poll_type: PollType = poll_type.try_into()?;
+        let poll_type: PollType = poll_type.try_into().in_field("poll_type")?;
         let buffer = &buffer[2..];
 
         let deserialize_result = match poll_type {
@@ -226,6 +260,23 @@ impl Deserialize for Command {
 impl Display for Command {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         use InnerCommand::*;
+        if f.alternate() {
+            let mut obj = JsonObject::new(f)?;
+            obj.string("poll_type", &self.poll_type())?;
+            if let Some(session_id) = self.session_id() {
+                obj.raw("session_id", &session_id)?;
+            }
+            if let Some(host) = self.host() {
+                obj.string("host", host)?;
+            }
+            if let Some(action_id) = self.action_id() {
+                obj.raw("action_id", &action_id)?;
+            }
+            if let Some(datetime) = self.datetime() {
+                obj.string("datetime", datetime)?;
+            }
+            return obj.finish();
+        }
         f.pad("")?;
         match &self.0 {
             Empty(_) => f.write_fmt(format_args!("empty")),
@@ -256,12 +307,10 @@ impl From<&EmptyCommand> for RawEmptyCommand {
     }
 }
 
-impl TryFrom<&RawEmptyCommand> for EmptyCommand {
-    type Error = Infallible;
-
+impl From<&RawEmptyCommand> for EmptyCommand {
     #[inline(always)]
-    fn try_from(_: &RawEmptyCommand) -> Result<Self, Self::Error> {
-        Ok(EmptyCommand)
+    fn from(_: &RawEmptyCommand) -> Self {
+        EmptyCommand
     }
 }
 
@@ -282,6 +331,8 @@ impl From<EmptyCommand> for Command {
 #[derive(Debug, Clone)]
 struct HostOnlyCommand {
     host: Host,
+    /// Bytes of unknown meaning, kept as received to be sent again as is
+    unk_1: [u8; 4],
 }
 
 #[derive(Debug, Clone)]
@@ -301,19 +352,18 @@ impl From<&HostOnlyCommand> for RawHostOnlyCommand {
         Self {
             pad_1: [0; 6],
             host: command.host.0,
-            unk_1: [0; 4],
+            unk_1: command.unk_1,
         }
     }
 }
 
-impl TryFrom<&RawHostOnlyCommand> for HostOnlyCommand {
-    type Error = Infallible;
-
-    fn try_from(raw_command: &RawHostOnlyCommand) -> Result<Self, Self::Error> {
+impl From<&RawHostOnlyCommand> for HostOnlyCommand {
+    fn from(raw_command: &RawHostOnlyCommand) -> Self {
         // We don't check validity of host string, downstream use could be lossy
-        Ok(Self {
+        Self {
             host: Host(raw_command.host),
-        })
+            unk_1: raw_command.unk_1,
+        }
     }
 }
 
@@ -333,7 +383,11 @@ impl From<HostOnlyCommand> for Command {
 struct FullCommand {
     session_id: u32,
     host: Host,
-    datetime: PrimitiveDateTime,
+    datetime: Datetime,
+    /// Bytes of unknown meaning, kept as received to be sent again as is
+    unk_1: [u8; 4],
+    unk_2: [u8; 20],
+    unk_3: [u8; 4],
 }
 
 #[derive(Debug, Clone)]
@@ -353,30 +407,16 @@ impl HasRawRepr for FullCommand {
     type Repr = RawFullCommand;
 }
 
-impl RawFullCommand {
-    const DATETIME_FORMAT: &'static [FormatItem<'static>] =
-        format_description!("[year][month][day][hour][minute][second]");
-}
-
 impl From<&FullCommand> for RawFullCommand {
     fn from(command: &FullCommand) -> Self {
-        let mut datetime = [0; 14];
-        command
-            .datetime
-            .format_into(
-                &mut datetime.as_mut_slice(),
-                RawFullCommand::DATETIME_FORMAT,
-            )
-            .unwrap();
-
         Self {
             pad_1: [0; 2],
             session_id: command.session_id.to_be_bytes(),
             host: command.host.0,
-            unk_1: [0x00, 0x00, 0x00, 0x14],
-            unk_2: [0; 20],
-            unk_3: [0x00, 0x00, 0x00, 0x10],
-            datetime,
+            unk_1: command.unk_1,
+            unk_2: command.unk_2,
+            unk_3: command.unk_3,
+            datetime: datetime::to_digits(&command.datetime),
             pad_2: [0; 2],
         }
     }
@@ -386,19 +426,20 @@ impl TryFrom<&RawFullCommand> for FullCommand {
     type Error = FormatError;
 
     fn try_from(raw_command: &RawFullCommand) -> Result<Self, Self::Error> {
-        let mut parser = Parsed::new();
-        parser
-            .parse_items(&raw_command.datetime, RawFullCommand::DATETIME_FORMAT)
-            .map_err(|_| FormatError::InvalidSlice {
+        let datetime = datetime::from_digits(&raw_command.datetime).ok_or_else(|| {
+            FormatError::InvalidSlice {
                 span: span_of!(RawFullCommand, datetime),
                 message: "invalid datetime string",
-            })?;
-        // if `parse_items` succeeds, it is sufficient to construct `PrimitiveDateTime`
-        let datetime = parser.try_into().unwrap();
+                field: "datetime".into(),
+            }
+        })?;
         Ok(Self {
             session_id: u32::from_be_bytes(raw_command.session_id),
             host: Host(raw_command.host),
             datetime,
+            unk_1: raw_command.unk_1,
+            unk_2: raw_command.unk_2,
+            unk_3: raw_command.unk_3,
         })
     }
 }
@@ -423,6 +464,9 @@ struct ResetCommand {
     session_id: u32,
     host: Host,
     action_id: u32,
+    /// Bytes of unknown meaning, kept as received to be sent again as is
+    unk_1: [u8; 4],
+    unk_2: [u8; 20],
 }
 
 #[derive(Debug, Clone)]
@@ -446,22 +490,22 @@ impl From<&ResetCommand> for RawResetCommand {
             pad_1: [0; 2],
             session_id: command.session_id.to_be_bytes(),
             host: command.host.0,
-            unk_1: [0x00, 0x00, 0x00, 0x14],
+            unk_1: command.unk_1,
             action_id: command.action_id.to_be_bytes(),
-            unk_2: [0; 20],
+            unk_2: command.unk_2,
         }
     }
 }
 
-impl TryFrom<&RawResetCommand> for ResetCommand {
-    type Error = Infallible;
-
-    fn try_from(raw_command: &RawResetCommand) -> Result<Self, Self::Error> {
-        Ok(Self {
+impl From<&RawResetCommand> for ResetCommand {
+    fn from(raw_command: &RawResetCommand) -> Self {
+        Self {
             session_id: u32::from_be_bytes(raw_command.session_id),
             host: Host(raw_command.host),
             action_id: u32::from_be_bytes(raw_command.action_id),
-        })
+            unk_1: raw_command.unk_1,
+            unk_2: raw_command.unk_2,
+        }
     }
 }
 
@@ -486,7 +530,7 @@ pub struct CommandBuilder {
     session_id: Option<u32>,
     host: Option<Host>,
     action_id: Option<u32>,
-    datetime: Option<PrimitiveDateTime>,
+    datetime: Option<Datetime>,
 }
 
 impl CommandBuilder {
@@ -520,7 +564,7 @@ impl CommandBuilder {
         self
     }
 
-    pub fn datetime(&mut self, datetime: PrimitiveDateTime) -> &mut Self {
+    pub fn datetime(&mut self, datetime: Datetime) -> &mut Self {
         self.datetime = Some(datetime);
         self
     }
@@ -529,17 +573,26 @@ impl CommandBuilder {
         use PollType::*;
         Some(match self.poll_type {
             Empty => EmptyCommand.into(),
-            HostOnly => HostOnlyCommand { host: self.host? }.into(),
+            HostOnly => HostOnlyCommand {
+                host: self.host?,
+                unk_1: [0; 4],
+            }
+            .into(),
             Full => FullCommand {
                 session_id: self.session_id?,
                 host: self.host?,
                 datetime: self.datetime?,
+                unk_1: [0x00, 0x00, 0x00, 0x14],
+                unk_2: [0; 20],
+                unk_3: [0x00, 0x00, 0x00, 0x10],
             }
             .into(),
             Reset => ResetCommand {
                 session_id: self.session_id?,
                 host: self.host?,
                 action_id: self.action_id?,
+                unk_1: [0x00, 0x00, 0x00, 0x14],
+                unk_2: [0; 20],
             }
             .into(),
         })
@@ -0,0 +1,193 @@
+//! This module contains the date and time sent in a full poll, formatted as
+//! `YYYYMMDDhhmmss` on the wire. With the `time` feature, it converts from and
+//! into [`time::PrimitiveDateTime`].
+
+use std::fmt::Display;
+
+/// Date and time without time zone
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Datetime {
+    year: u16,
+    month: u8,
+    day: u8,
+    hour: u8,
+    minute: u8,
+    second: u8,
+}
+
+impl Datetime {
+    /// Creates a datetime, or returns `None` if any of the fields is out of
+    /// range.
+    pub fn new(year: u16, month: u8, day: u8, hour: u8, minute: u8, second: u8) -> Option<Self> {
+        let valid = year <= 9999
+            && (1..=12).contains(&month)
+            && (1..=days_in_month(year, month)).contains(&day)
+            && hour < 24
+            && minute < 60
+            && second < 60;
+        valid.then_some(Self {
+            year,
+            month,
+            day,
+            hour,
+            minute,
+            second,
+        })
+    }
+
+    #[inline(always)]
+    pub fn year(&self) -> u16 {
+        self.year
+    }
+
+    #[inline(always)]
+    pub fn month(&self) -> u8 {
+        self.month
+    }
+
+    #[inline(always)]
+    pub fn day(&self) -> u8 {
+        self.day
+    }
+
+    #[inline(always)]
+    pub fn hour(&self) -> u8 {
+        self.hour
+    }
+
+    #[inline(always)]
+    pub fn minute(&self) -> u8 {
+        self.minute
+    }
+
+    #[inline(always)]
+    pub fn second(&self) -> u8 {
+        self.second
+    }
+}
+
+fn days_in_month(year: u16, month: u8) -> u8 {
+    let leap = year.is_multiple_of(4) && (!year.is_multiple_of(100) || year.is_multiple_of(400));
+    match month {
+        2 if leap => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+impl Display for Datetime {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_fmt(format_args!(
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        ))
+    }
+}
+
+/// A [`time::PrimitiveDateTime`] whose year doesn't fit in the 4 digits sent
+/// to scanners
+#[cfg(feature = "time")]
+#[derive(thiserror::Error, Debug, Clone, Copy, PartialEq, Eq)]
+#[error("year {0} is out of range of a poll datetime")]
+pub struct YearOutOfRange(pub i32);
+
+#[cfg(feature = "time")]
+impl TryFrom<time::PrimitiveDateTime> for Datetime {
+    type Error = YearOutOfRange;
+
+    fn try_from(datetime: time::PrimitiveDateTime) -> Result<Self, Self::Error> {
+        let year = u16::try_from(datetime.year())
+            .ok()
+            .filter(|year| *year <= 9999)
+            .ok_or(YearOutOfRange(datetime.year()))?;
+        // the other fields are in range of any valid `PrimitiveDateTime`
+        Ok(Self::new(
+            year,
+            datetime.month().into(),
+            datetime.day(),
+            datetime.hour(),
+            datetime.minute(),
+            datetime.second(),
+        )
+        .unwrap())
+    }
+}
+
+#[cfg(feature = "time")]
+impl From<Datetime> for time::PrimitiveDateTime {
+    fn from(datetime: Datetime) -> Self {
+        // `Datetime::new` checks the same ranges as `time`
+        let date = time::Date::from_calendar_date(
+            datetime.year.into(),
+            datetime.month.try_into().unwrap(),
+            datetime.day,
+        )
+        .unwrap();
+        let time = time::Time::from_hms(datetime.hour, datetime.minute, datetime.second).unwrap();
+        Self::new(date, time)
+    }
+}
+
+pub(crate) fn to_digits(datetime: &Datetime) -> [u8; 14] {
+    let mut digits = [0; 14];
+    let formatted = format!(
+        "{:04}{:02}{:02}{:02}{:02}{:02}",
+        datetime.year,
+        datetime.month,
+        datetime.day,
+        datetime.hour,
+        datetime.minute,
+        datetime.second
+    );
+    digits.copy_from_slice(formatted.as_bytes());
+    digits
+}
+
+pub(crate) fn from_digits(digits: &[u8; 14]) -> Option<Datetime> {
+    if !digits.iter().all(u8::is_ascii_digit) {
+        return None;
+    }
+    let field = |range: std::ops::Range<usize>| -> Option<u16> {
+        std::str::from_utf8(&digits[range]).ok()?.parse().ok()
+    };
+    Datetime::new(
+        field(0..4)?,
+        field(4..6)? as u8,
+        field(6..8)? as u8,
+        field(8..10)? as u8,
+        field(10..12)? as u8,
+        field(12..14)? as u8,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn digits_roundtrip() {
+        let datetime = from_digits(b"20230131235958").unwrap();
+        assert_eq!(&to_digits(&datetime), b"20230131235958");
+        assert!(from_digits(b"20231331235958").is_none());
+        assert!(from_digits(b"20230229235958").is_none());
+        assert!(from_digits(b"2023013123595x").is_none());
+    }
+
+    #[cfg(feature = "time")]
+    #[test]
+    fn time_conversions() {
+        use time::macros::datetime;
+
+        let datetime = Datetime::try_from(datetime!(2024-02-29 23:59:58)).unwrap();
+        assert_eq!(&to_digits(&datetime), b"20240229235958");
+        assert_eq!(
+            time::PrimitiveDateTime::from(datetime),
+            datetime!(2024-02-29 23:59:58)
+        );
+        assert_eq!(
+            Datetime::try_from(datetime!(-0001-01-01 00:00:00)),
+            Err(YearOutOfRange(-1))
+        );
+    }
+}
@@ -0,0 +1,138 @@
+use std::time::Duration;
+
+use futures_util::{stream, Stream};
+use time::{OffsetDateTime, PrimitiveDateTime, UtcOffset};
+use tokio::time::sleep;
+
+use super::{CommandBuilder, Host, Interrupt, PollType, StatusFlags};
+use crate::{client::Client, discover, Error};
+
+/// Time between consecutive polls
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+struct Events {
+    client: Client,
+    host: Host,
+    /// Offset of the datetime sent in polls, shown by the scanner
+    offset: UtcOffset,
+    /// Session of the host registration, or `None` if the host needs to be
+    /// registered
+    session_id: Option<u32>,
+    started: bool,
+}
+
+impl Events {
+    async fn next(&mut self) -> Result<Option<Interrupt>, Error> {
+        if self.started {
+            sleep(POLL_INTERVAL).await;
+        }
+        self.started = true;
+
+        let session_id = match self.session_id {
+            Some(session_id) => session_id,
+            None => self.register().await?,
+        };
+
+        let now = OffsetDateTime::now_utc().to_offset(self.offset);
+        let command = CommandBuilder::new(PollType::Full)
+            .host(self.host)
+            .session_id(session_id)
+            .datetime(
+                PrimitiveDateTime::new(now.date(), now.time())
+                    .try_into()
+                    .expect("current year fits in 4 digits"),
+            )
+            .build()
+            .unwrap();
+        let resp = self.client.request(command).await?;
+        if let Some(session_id) = resp.session_id() {
+            self.session_id = Some(session_id);
+        }
+        if !resp.status_flags().contains(StatusFlags::INTERRUPT) {
+            return Ok(None);
+        }
+
+        // acknowledge the job so that it is not reported again
+        let command = CommandBuilder::new(PollType::Reset)
+            .host(self.host)
+            .session_id(session_id)
+            .action_id(resp.action_id().unwrap_or(0))
+            .build()
+            .unwrap();
+        self.client.request(command).await?;
+
+        Ok(resp.interrupt().cloned())
+    }
+
+    async fn register(&mut self) -> Result<u32, Error> {
+        self.client.reset_sequence();
+        self.client.request(discover::Request::new()).await?;
+
+        let command = CommandBuilder::new(PollType::HostOnly)
+            .host(self.host)
+            .build()
+            .unwrap();
+        let resp = self.client.request(command).await?;
+        // a registration answered without a session is retried by the next poll
+        let session_id = resp.session_id().unwrap_or(0);
+        self.session_id = Some(session_id);
+        Ok(session_id)
+    }
+}
+
+/// Registers `host` on the scanner and yields the scan jobs started on the
+/// scanner for the host, i.e. the scan button presses.
+///
+/// The stream handles the whole session with the scanner: it detects the
+/// scanner, registers the host, polls every second, which also keeps the host
+/// registered, and acknowledges each job so that it is only yielded once. An
+/// error is yielded whenever the scanner doesn't respond properly, after which
+/// the session is started over. The stream never ends, so stop polling it to
+/// stop listening.
+///
+/// The polls carry the current time at `offset`, usually the local offset.
+/// Looking it up with [`UtcOffset::current_local_offset`] fails once the
+/// process has several threads, so look it up before starting the runtime.
+///
+/// ```no_run
+/// # async fn example(offset: time::UtcOffset) -> Result<(), bjnp::Error> {
+/// use bjnp::{client::Client, poll, Host};
+/// use futures_util::{pin_mut, StreamExt};
+///
+/// let client = Client::connect("192.168.1.2:8612".parse().unwrap()).await?;
+/// let events = poll::events(client, Host::new("my-pc"), offset);
+/// pin_mut!(events);
+/// while let Some(event) = events.next().await {
+///     match event {
+///         Ok(interrupt) => println!("scan requested: {interrupt}"),
+///         Err(e) => eprintln!("{e}"),
+///     }
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub fn events(
+    client: Client,
+    host: Host,
+    offset: UtcOffset,
+) -> impl Stream<Item = Result<Interrupt, Error>> {
+    let events = Events {
+        client,
+        host,
+        offset,
+        session_id: None,
+        started: false,
+    };
+    stream::unfold(events, |mut events| async move {
+        loop {
+            match events.next().await {
+                Ok(Some(interrupt)) => return Some((Ok(interrupt), events)),
+                Ok(None) => {}
+                Err(e) => {
+                    events.session_id = None;
+                    return Some((Err(e), events));
+                }
+            }
+        }
+    })
+}
@@ -1,5 +1,13 @@
 pub mod command;
+mod datetime;
+#[cfg(feature = "tokio")]
+mod events;
 pub mod response;
 
 pub use command::*;
+pub use datetime::Datetime;
+#[cfg(feature = "time")]
+pub use datetime::YearOutOfRange;
+#[cfg(feature = "tokio")]
+pub use events::events;
 pub use response::*;
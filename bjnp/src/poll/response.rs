@@ -1,10 +1,13 @@
 //! This module contains structs related to the response of a poll request
 
-use std::fmt::Display;
+use std::{fmt::Display, mem::size_of, str::FromStr};
+
+use memoffset::offset_of;
 
 use crate::{
-    serdes::{make_u8_field, FormatError, HasRawRepr},
-    write_nested,
+    json::JsonObject,
+    serdes::{make_u8_field, FieldError, FormatError, HasRawRepr, OffsetError, ParseError},
+    write_nested, Payload, PayloadType,
 };
 
 make_u8_field! {
@@ -91,6 +94,29 @@ make_u8_field! {
     }
 }
 
+impl Size {
+    /// Width and height of the page in millimetres, in portrait orientation.
+    /// The scanner picks the area itself for [`Size::Auto`], which has no
+    /// dimensions.
+    pub fn dimensions_mm(&self) -> Option<(f64, f64)> {
+        match self {
+            Size::A4 => Some((210.0, 297.0)),
+            Size::Letter => Some((215.9, 279.4)),
+            Size::_10x15 => Some((101.6, 152.4)),
+            Size::_13x18 => Some((127.0, 177.8)),
+            Size::Auto => None,
+        }
+    }
+
+    /// Width and height of the page in pixels when scanned at `dpi`, rounded
+    /// to the nearest pixel.
+    pub fn dimensions_px(&self, dpi: DPI) -> Option<(u32, u32)> {
+        let to_px = |mm: f64| (mm / 25.4 * f64::from(dpi.dpi_value())).round() as u32;
+        self.dimensions_mm()
+            .map(|(width, height)| (to_px(width), to_px(height)))
+    }
+}
+
 impl DPI {
     pub fn dpi_value(&self) -> u32 {
         match self {
@@ -102,20 +128,80 @@ impl DPI {
     }
 }
 
+/// A field of an interrupt that is kept even if its value is not recognized.
+///
+/// Newer models report values that are not known yet, so decoding falls back
+/// to keeping the raw byte instead of failing the whole interrupt.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lenient<T> {
+    Known(T),
+    Unknown(u8),
+}
+
+impl<T> Lenient<T> {
+    #[inline(always)]
+    pub fn known(self) -> Option<T> {
+        match self {
+            Lenient::Known(value) => Some(value),
+            Lenient::Unknown(_) => None,
+        }
+    }
+}
+
+impl<T> From<u8> for Lenient<T>
+where
+    T: TryFrom<u8>,
+{
+    fn from(value: u8) -> Self {
+        T::try_from(value)
+            .map(Lenient::Known)
+            .unwrap_or(Lenient::Unknown(value))
+    }
+}
+
+impl<T> From<Lenient<T>> for u8
+where
+    T: Into<u8>,
+{
+    fn from(value: Lenient<T>) -> Self {
+        match value {
+            Lenient::Known(value) => value.into(),
+            Lenient::Unknown(byte) => byte,
+        }
+    }
+}
+
+impl<T> Display for Lenient<T>
+where
+    T: Display,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Lenient::Known(value) => value.fmt(f),
+            Lenient::Unknown(byte) => f.write_fmt(format_args!("unknown({byte:#04x})")),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Interrupt {
     color_mode: ColorMode,
-    size: Size,
-    format: Format,
-    dpi: DPI,
+    size: Lenient<Size>,
+    format: Lenient<Format>,
+    dpi: Lenient<DPI>,
     source: Source,
     feeder_type: Option<FeederType>,
     feeder_orientation: Option<FeederOrientation>,
+    /// Bytes of unknown meaning at the offsets of the MX920, kept as received
+    /// to be sent again as is
+    unk_1: [u8; 7],
+    unk_4: [u8; 3],
+    unk_5: [u8; 3],
 }
 
 /// Interrupt layout for MX920
 #[doc(hidden)]
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy)]
 #[repr(C, packed)]
 pub struct RawInterrupt {
     unk_1: [u8; 7],
@@ -137,17 +223,17 @@ impl Interrupt {
     }
 
     #[inline(always)]
-    pub fn size(&self) -> Size {
+    pub fn size(&self) -> Lenient<Size> {
         self.size
     }
 
     #[inline(always)]
-    pub fn format(&self) -> Format {
+    pub fn format(&self) -> Lenient<Format> {
         self.format
     }
 
     #[inline(always)]
-    pub fn dpi(&self) -> DPI {
+    pub fn dpi(&self) -> Lenient<DPI> {
         self.dpi
     }
 
@@ -174,50 +260,230 @@ impl HasRawRepr for Interrupt {
 impl From<&Interrupt> for RawInterrupt {
     fn from(interrupt: &Interrupt) -> Self {
         Self {
-            unk_1: [0; 7],
+            unk_1: interrupt.unk_1,
             color_mode: interrupt.color_mode as u8,
             source: interrupt.source as u8,
             feeder_type: interrupt.feeder_type.map(|v| v as u8).unwrap_or(0),
-            size: interrupt.size as u8,
-            format: interrupt.format as u8,
-            dpi: interrupt.dpi as u8,
-            unk_4: [0; 3],
+            size: interrupt.size.into(),
+            format: interrupt.format.into(),
+            dpi: interrupt.dpi.into(),
+            unk_4: interrupt.unk_4,
             feeder_orientation: interrupt.feeder_orientation.map(|v| v as u8).unwrap_or(0),
-            unk_5: [0; 3],
+            unk_5: interrupt.unk_5,
         }
     }
 }
 
-impl TryFrom<&RawInterrupt> for Interrupt {
-    type Error = FormatError;
+/// A field of an interrupt
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum InterruptField {
+    ColorMode,
+    Source,
+    FeederType,
+    Size,
+    Format,
+    Dpi,
+    FeederOrientation,
+}
 
-    fn try_from(raw_interrupt: &RawInterrupt) -> Result<Self, Self::Error> {
-        let feeder_type = if raw_interrupt.feeder_type != 0 {
-            Some(raw_interrupt.feeder_type.try_into()?)
+impl InterruptField {
+    pub const ALL: [Self; 7] = [
+        Self::ColorMode,
+        Self::Source,
+        Self::FeederType,
+        Self::Size,
+        Self::Format,
+        Self::Dpi,
+        Self::FeederOrientation,
+    ];
+
+    /// Name of the field, as in the path of parse errors
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::ColorMode => "color_mode",
+            Self::Source => "source",
+            Self::FeederType => "feeder_type",
+            Self::Size => "size",
+            Self::Format => "format",
+            Self::Dpi => "dpi",
+            Self::FeederOrientation => "feeder_orientation",
+        }
+    }
+}
+
+impl Display for InterruptField {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.pad(self.name())
+    }
+}
+
+impl FromStr for InterruptField {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|field| field.name() == s)
+            .ok_or_else(|| format!("unknown interrupt field `{s}`"))
+    }
+}
+
+/// A byte of a field which a model uses for a value encoded differently by
+/// the MX920
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ValueMapping {
+    pub field: InterruptField,
+    /// Byte sent by the model
+    pub from: u8,
+    /// Byte of the same value sent by the MX920
+    pub to: u8,
+}
+
+/// Offsets of the fields of an interrupt and encodings of their values,
+/// which differ between models
+///
+/// Offsets are relative to the start of the interrupt in a poll response.
+/// Fields at offsets past the end of the interrupt read as 0.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InterruptLayout {
+    pub color_mode: usize,
+    pub source: usize,
+    pub feeder_type: usize,
+    pub size: usize,
+    pub format: usize,
+    pub dpi: usize,
+    pub feeder_orientation: usize,
+    /// Bytes translated before decoding
+    pub mappings: Vec<ValueMapping>,
+}
+
+impl InterruptLayout {
+    /// Layout of MX920, which is the layout of [`RawInterrupt`]
+    pub const MX920: Self = Self {
+        color_mode: 7,
+        source: 8,
+        feeder_type: 9,
+        size: 10,
+        format: 11,
+        dpi: 12,
+        feeder_orientation: 16,
+        mappings: Vec::new(),
+    };
+
+    /// Length of an interrupt, past which offsets read as 0
+    pub const LEN: usize = size_of::<RawInterrupt>();
+
+    pub fn offset(&self, field: InterruptField) -> usize {
+        match field {
+            InterruptField::ColorMode => self.color_mode,
+            InterruptField::Source => self.source,
+            InterruptField::FeederType => self.feeder_type,
+            InterruptField::Size => self.size,
+            InterruptField::Format => self.format,
+            InterruptField::Dpi => self.dpi,
+            InterruptField::FeederOrientation => self.feeder_orientation,
+        }
+    }
+
+    /// Decodes the bytes of an interrupt laid out as described.
+    pub fn decode(&self, bytes: &[u8]) -> Result<Interrupt, FormatError> {
+        use InterruptField::*;
+
+        let feeder_type = if self.byte(bytes, FeederType) != 0 {
+            Some(self.field(bytes, FeederType)?)
         } else {
             None
         };
 
-        let feeder_orientation = if raw_interrupt.feeder_orientation != 0 {
-            Some(raw_interrupt.feeder_orientation.try_into()?)
+        let feeder_orientation = if self.byte(bytes, FeederOrientation) != 0 {
+            Some(self.field(bytes, FeederOrientation)?)
         } else {
             None
         };
 
-        Ok(Self {
-            color_mode: raw_interrupt.color_mode.try_into()?,
-            source: raw_interrupt.source.try_into()?,
+        Ok(Interrupt {
+            color_mode: self.field(bytes, ColorMode)?,
+            source: self.field(bytes, Source)?,
             feeder_type,
-            size: raw_interrupt.size.try_into()?,
-            format: raw_interrupt.format.try_into()?,
-            dpi: raw_interrupt.dpi.try_into()?,
+            size: self.byte(bytes, Size).into(),
+            format: self.byte(bytes, Format).into(),
+            dpi: self.byte(bytes, Dpi).into(),
             feeder_orientation,
+            unk_1: unknown(bytes, offset_of!(RawInterrupt, unk_1)),
+            unk_4: unknown(bytes, offset_of!(RawInterrupt, unk_4)),
+            unk_5: unknown(bytes, offset_of!(RawInterrupt, unk_5)),
         })
     }
+
+    /// Byte of `field` translated to the encoding of the MX920
+    fn byte(&self, bytes: &[u8], field: InterruptField) -> u8 {
+        let byte = bytes.get(self.offset(field)).copied().unwrap_or(0);
+        self.mappings
+            .iter()
+            .find(|mapping| mapping.field == field && mapping.from == byte)
+            .map_or(byte, |mapping| mapping.to)
+    }
+
+    fn field<T>(&self, bytes: &[u8], field: InterruptField) -> Result<T, FormatError>
+    where
+        T: TryFrom<u8, Error = FormatError>,
+    {
+        // errors point at the byte sent by the model
+        self.byte(bytes, field)
+            .try_into()
+            .offset_by(self.offset(field))
+            .in_field(field.name())
+    }
+}
+
+/// Bytes of a field of unknown meaning at `offset`, which read as 0 past the
+/// end of `bytes`
+fn unknown<const N: usize>(bytes: &[u8], offset: usize) -> [u8; N] {
+    let mut unknown = [0; N];
+    for (i, byte) in unknown.iter_mut().enumerate() {
+        *byte = bytes.get(offset + i).copied().unwrap_or(0);
+    }
+    unknown
+}
+
+impl Default for InterruptLayout {
+    fn default() -> Self {
+        Self::MX920
+    }
+}
+
+impl RawInterrupt {
+    fn as_bytes(&self) -> &[u8; InterruptLayout::LEN] {
+        // SAFETY: RawInterrupt is packed bytes without padding
+        unsafe { &*(self as *const Self as *const [u8; InterruptLayout::LEN]) }
+    }
+}
+
+impl TryFrom<&RawInterrupt> for Interrupt {
+    type Error = FormatError;
+
+    fn try_from(raw_interrupt: &RawInterrupt) -> Result<Self, Self::Error> {
+        InterruptLayout::MX920.decode(raw_interrupt.as_bytes())
+    }
 }
 
 impl Display for Interrupt {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if f.alternate() {
+            let mut obj = JsonObject::new(f)?;
+            obj.string("color_mode", &self.color_mode)?;
+            obj.string("size", &self.size)?;
+            obj.string("source", &self.source)?;
+            obj.string("format", &self.format)?;
+            obj.string("dpi", &self.dpi)?;
+            obj.optional("feeder_type", self.feeder_type, JsonObject::string)?;
+            obj.optional(
+                "feeder_orientation",
+                self.feeder_orientation,
+                JsonObject::string,
+            )?;
+            return obj.finish();
+        }
         f.pad("")?;
         f.write_fmt(format_args!(
             "interrupt: color_mode={} size={} source={} format={} dpi={}",
@@ -233,6 +499,39 @@ impl Display for Interrupt {
     }
 }
 
+/// Flags of the status of a poll response. Bits other than the known flags
+/// report conditions of the scanner, e.g. cover open or paper jam.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct StatusFlags(u32);
+
+impl StatusFlags {
+    /// A scan job is pending, the response carries its action id and
+    /// interrupt in place of the session
+    pub const INTERRUPT: Self = Self(0x8000);
+
+    /// Flags known to this crate
+    pub const ALL: Self = Self::INTERRUPT;
+
+    pub const fn from_bits(bits: u32) -> Self {
+        Self(bits)
+    }
+
+    pub const fn bits(self) -> u32 {
+        self.0
+    }
+
+    /// Whether all the flags of `other` are set
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// The bits which are not flags known to this crate, reporting the
+    /// condition of the scanner
+    pub const fn condition(self) -> u32 {
+        self.0 & !Self::ALL.0
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Response {
     status: u32,
@@ -257,6 +556,10 @@ impl Response {
         self.status
     }
 
+    pub fn status_flags(&self) -> StatusFlags {
+        StatusFlags::from_bits(self.status)
+    }
+
     pub fn session_id(&self) -> Option<u32> {
         self.session_id
     }
@@ -274,15 +577,35 @@ impl HasRawRepr for Response {
     type Repr = RawResponse;
 }
 
-impl TryFrom<&RawResponse> for Response {
-    type Error = FormatError;
+impl Response {
+    /// Deserializes a response whose interrupt is laid out as `layout`, for
+    /// models which differ from [`InterruptLayout::MX920`].
+    pub fn deserialize_with_layout(
+        buffer: &[u8],
+        layout: &InterruptLayout,
+    ) -> Result<(Self, usize), ParseError> {
+        const SIZE: usize = size_of::<RawResponse>();
+        if buffer.len() < SIZE {
+            return Err(ParseError::UnexpectedEnd {
+                expected: SIZE,
+                actual: buffer.len(),
+            });
+        }
+        // SAFETY: buffer holds at least SIZE bytes, and RawResponse is packed
+        // bytes without alignment requirement
+        let raw_response = unsafe { &*(buffer.as_ptr() as *const RawResponse) };
+        Ok((Self::decode(raw_response, layout)?, SIZE))
+    }
 
-    fn try_from(raw_response: &RawResponse) -> Result<Self, Self::Error> {
+    fn decode(raw_response: &RawResponse, layout: &InterruptLayout) -> Result<Self, FormatError> {
         let status = u32::from_be_bytes(raw_response.status);
-        if status & 0x00008000 != 0 {
+        if StatusFlags::from_bits(status).contains(StatusFlags::INTERRUPT) {
             // interrupted
             let action_id = u32::from_be_bytes(raw_response.action_id);
-            let interrupt = (&raw_response.interrupt).try_into()?;
+            let interrupt = layout
+                .decode(raw_response.interrupt.as_bytes())
+                .offset_by(offset_of!(RawResponse, interrupt))
+                .in_field("interrupt")?;
             Ok(Self {
                 status,
                 session_id: None,
@@ -301,8 +624,28 @@ impl TryFrom<&RawResponse> for Response {
     }
 }
 
+impl TryFrom<&RawResponse> for Response {
+    type Error = FormatError;
+
+    fn try_from(raw_response: &RawResponse) -> Result<Self, Self::Error> {
+        Self::decode(raw_response, &InterruptLayout::MX920)
+    }
+}
+
+impl Payload for Response {
+    const PAYLOAD_TYPE: PayloadType = PayloadType::Poll;
+}
+
 impl Display for Response {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if f.alternate() {
+            let mut obj = JsonObject::new(f)?;
+            obj.raw("status", &self.status)?;
+            obj.optional("session_id", self.session_id, JsonObject::raw)?;
+            obj.optional("action_id", self.action_id, JsonObject::raw)?;
+            obj.optional("interrupt", self.interrupt.as_ref(), JsonObject::raw)?;
+            return obj.finish();
+        }
         f.pad("")?;
         f.write_fmt(format_args!("status={:#08x}", self.status))?;
         if let Some(session_id) = self.session_id.as_ref() {
@@ -317,3 +660,101 @@ impl Display for Response {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::serdes::{Deserialize, ParseError};
+
+    #[test]
+    fn deserialize_unknown_fields() {
+        let bytes = [
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x03, // unk_1
+            0x01, // color mode
+            0x01, // source
+            0x00, // feeder type
+            0x0c, // size
+            0x03, // format
+            0x7f, // dpi
+            0x00, 0x01, 0x00, // unk_4
+            0x00, // feeder orientation
+            0x00, 0x00, 0x02, // unk_5
+        ];
+        // unknown bytes are sent again as received
+        let (interrupt, _) = Interrupt::deserialize_strict(&bytes).unwrap();
+        assert_eq!(interrupt.size(), Lenient::Unknown(0x0c));
+        assert_eq!(interrupt.format(), Lenient::Known(Format::Pdf));
+        assert_eq!(interrupt.dpi(), Lenient::Unknown(0x7f));
+        assert_eq!(u8::from(interrupt.dpi()), 0x7f);
+    }
+
+    #[test]
+    fn page_dimensions() {
+        assert_eq!(Size::Letter.dimensions_mm(), Some((215.9, 279.4)));
+        assert_eq!(Size::Letter.dimensions_px(DPI::_300), Some((2550, 3300)));
+        assert_eq!(Size::A4.dimensions_px(DPI::_75), Some((620, 877)));
+        assert_eq!(Size::Auto.dimensions_px(DPI::_600), None);
+    }
+
+    #[test]
+    fn deserialize_with_layout() {
+        let mut buffer = [0; 36];
+        buffer[2] = 0x80; // interrupted
+        buffer[16 + 3] = 0x02; // color mode
+        buffer[16 + 4] = 0x02; // source
+        buffer[16 + 5] = 0x02; // feeder type
+        buffer[16 + 6] = 0x03; // dpi
+        buffer[16 + 10] = 0x0c; // size
+        let layout = InterruptLayout {
+            color_mode: 3,
+            source: 4,
+            feeder_type: 5,
+            dpi: 6,
+            mappings: vec![ValueMapping {
+                field: InterruptField::Size,
+                from: 0x0c,
+                to: 0x0b,
+            }],
+            ..InterruptLayout::MX920
+        };
+        let (response, size) = Response::deserialize_with_layout(&buffer, &layout).unwrap();
+        assert_eq!(size, 36);
+        let interrupt = response.interrupt().unwrap();
+        assert_eq!(interrupt.color_mode(), ColorMode::Mono);
+        assert_eq!(interrupt.source(), Source::AutoDocumentFeeder);
+        assert_eq!(interrupt.feeder_type(), Some(FeederType::Duplex));
+        assert_eq!(interrupt.dpi(), Lenient::Known(DPI::_300));
+        assert_eq!(interrupt.size(), Lenient::Known(Size::Auto));
+    }
+
+    #[test]
+    fn error_field_path() {
+        let mut buffer = [0; 36];
+        buffer[2] = 0x80; // interrupted
+        buffer[16 + 7] = 0x01; // color mode
+        buffer[16 + 8] = 0x01; // source
+        buffer[16 + 9] = 0x07; // feeder type
+        let err = Response::deserialize(&buffer).unwrap_err();
+        let ParseError::InvalidFormat(err) = err else {
+            panic!("unexpected error {err}");
+        };
+        assert_eq!(err.field().segments(), ["interrupt", "feeder_type"]);
+        assert_eq!(
+            err.to_string(),
+            "invalid byte `0x7` at offset 25 in `interrupt.feeder_type`: unknown feeder type"
+        );
+    }
+
+    #[test]
+    fn status_flags() {
+        let mut buffer = [0; 36];
+        buffer[2] = 0x40; // scanner condition
+        buffer[3] = 0x40; // scanner condition
+        buffer[7] = 0x07; // session
+        let (response, _) = Response::deserialize(&buffer).unwrap();
+        let flags = response.status_flags();
+        assert!(!flags.contains(StatusFlags::INTERRUPT));
+        assert_eq!(flags.condition(), 0x4040);
+        assert_eq!(response.session_id(), Some(7));
+    }
+}
@@ -0,0 +1,299 @@
+//! This module contains structs related to the payloads of the print channel,
+//! i.e. packets of type [`PacketType::PrinterCommand`] and
+//! [`PacketType::PrinterResponse`].
+//!
+//! A print job is started by sending [`JobDetails`] with
+//! [`PayloadType::JobDetails`]. The printer assigns a job id in the header of
+//! the response, which is then used to send printer data as [`Data`] with
+//! [`PayloadType::Write`], and finally to close the job with
+//! [`PayloadType::Close`]. The status of the printer can be queried at any time
+//! with [`PayloadType::Read`], which is answered with a [`Status`].
+//!
+//! [`PacketType::PrinterCommand`]: crate::PacketType::PrinterCommand
+//! [`PacketType::PrinterResponse`]: crate::PacketType::PrinterResponse
+//! [`PayloadType::JobDetails`]: crate::PayloadType::JobDetails
+//! [`PayloadType::Write`]: crate::PayloadType::Write
+//! [`PayloadType::Close`]: crate::PayloadType::Close
+//! [`PayloadType::Read`]: crate::PayloadType::Read
+
+use std::{collections::hash_map, fmt::Display};
+
+use crate::{
+    identity,
+    json::JsonObject,
+    serdes::{Deserialize, HasRawRepr, ParseError, Serialize},
+    Host, Payload, PayloadType,
+};
+
+const MAX_TITLE_LENGTH: usize = 256;
+
+/// Payload to start a print job
+#[derive(Debug, Clone)]
+pub struct JobDetails {
+    host: Host,
+    user: Host,
+    title: String,
+    /// Bytes of unknown meaning, kept as received to be sent again as is
+    unk_1: [u8; 8],
+}
+
+#[doc(hidden)]
+#[derive(Debug, Clone)]
+#[repr(C, packed)]
+pub struct RawJobDetails {
+    unk_1: [u8; 8],
+    host: [u8; Host::MAX_HOST_LENGTH],
+    user: [u8; Host::MAX_HOST_LENGTH],
+    title: [u8; MAX_TITLE_LENGTH],
+}
+
+impl JobDetails {
+    /// Creates job details. `title` is truncated if its UTF-16 encoding
+    /// exceeds 256 bytes.
+    pub fn new<T: Into<String>>(host: Host, user: Host, title: T) -> Self {
+        let mut title = title.into();
+        let mut len = 0;
+        if let Some((idx, _)) = title.char_indices().find(|(_, c)| {
+            len += c.len_utf16();
+            len > MAX_TITLE_LENGTH / 2
+        }) {
+            title.truncate(idx);
+        }
+        Self {
+            host,
+            user,
+            title,
+            unk_1: [0; 8],
+        }
+    }
+
+    #[inline(always)]
+    pub fn host(&self) -> &Host {
+        &self.host
+    }
+
+    #[inline(always)]
+    pub fn user(&self) -> &Host {
+        &self.user
+    }
+
+    #[inline(always)]
+    pub fn title(&self) -> &str {
+        &self.title
+    }
+}
+
+impl Payload for JobDetails {
+    const PAYLOAD_TYPE: PayloadType = PayloadType::JobDetails;
+}
+
+impl HasRawRepr for JobDetails {
+    type Repr = RawJobDetails;
+}
+
+impl From<&JobDetails> for RawJobDetails {
+    fn from(details: &JobDetails) -> Self {
+        let mut title = [0; MAX_TITLE_LENGTH];
+        // `JobDetails::new` guarantees the title fits
+        for (dst, c) in title.chunks_exact_mut(2).zip(details.title.encode_utf16()) {
+            // it is always big endian on the wire
+            dst.copy_from_slice(&c.to_be_bytes());
+        }
+
+        Self {
+            unk_1: details.unk_1,
+            host: details.host.to_raw(),
+            user: details.user.to_raw(),
+            title,
+        }
+    }
+}
+
+impl From<&RawJobDetails> for JobDetails {
+    fn from(raw_details: &RawJobDetails) -> Self {
+        let title: Vec<_> = raw_details
+            .title
+            .chunks_exact(2)
+            .map(|c| u16::from_be_bytes([c[0], c[1]]))
+            .take_while(|&c| c != 0)
+            .collect();
+
+        // Title could contain invalid codepoint, so we use lossy decoding
+        Self {
+            host: Host::from_raw(raw_details.host),
+            user: Host::from_raw(raw_details.user),
+            title: String::from_utf16_lossy(&title),
+            unk_1: raw_details.unk_1,
+        }
+    }
+}
+
+impl Display for JobDetails {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if f.alternate() {
+            let mut obj = JsonObject::new(f)?;
+            obj.string("host", &self.host)?;
+            obj.string("user", &self.user)?;
+            obj.string("title", &self.title)?;
+            return obj.finish();
+        }
+        f.pad("")?;
+        f.write_fmt(format_args!(
+            "host={} user={} title={}",
+            self.host, self.user, self.title
+        ))
+    }
+}
+
+/// Payload carrying raw printer data of a print job
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Data(Vec<u8>);
+
+impl Data {
+    #[inline(always)]
+    pub fn new(data: Vec<u8>) -> Self {
+        Self(data)
+    }
+
+    #[inline(always)]
+    pub fn as_slice(&self) -> &[u8] {
+        &self.0
+    }
+
+    #[inline(always)]
+    pub fn into_inner(self) -> Vec<u8> {
+        self.0
+    }
+}
+
+impl Serialize for Data {
+    fn serialize<W>(&self, writer: &mut W) -> Result<(), std::io::Error>
+    where
+        W: std::io::Write,
+    {
+        writer.write_all(&self.0)
+    }
+
+    #[inline(always)]
+    fn size(&self) -> usize {
+        self.0.len()
+    }
+
+    #[inline(always)]
+    fn wire_bytes(&self) -> Option<&[u8]> {
+        Some(&self.0)
+    }
+}
+
+impl Payload for Data {
+    const PAYLOAD_TYPE: PayloadType = PayloadType::Write;
+}
+
+impl Deserialize for Data {
+    fn deserialize(buffer: &[u8]) -> Result<(Self, usize), ParseError> {
+        Ok((Self(buffer.to_vec()), buffer.len()))
+    }
+}
+
+impl Display for Data {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if f.alternate() {
+            let mut obj = JsonObject::new(f)?;
+            obj.raw("length", &self.0.len())?;
+            return obj.finish();
+        }
+        f.pad("")?;
+        f.write_fmt(format_args!("<{} bytes>", self.0.len()))
+    }
+}
+
+/// Response to a status query, in the same `KEY:VALUE;` format as an identity
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Status(identity::Response);
+
+impl Status {
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key)
+    }
+
+    pub fn iter(&self) -> hash_map::Iter<'_, String, String> {
+        self.0.iter()
+    }
+}
+
+impl Serialize for Status {
+    fn serialize<W>(&self, writer: &mut W) -> Result<(), std::io::Error>
+    where
+        W: std::io::Write,
+    {
+        self.0.serialize(writer)
+    }
+
+    fn size(&self) -> usize {
+        self.0.size()
+    }
+}
+
+impl Payload for Status {
+    const PAYLOAD_TYPE: PayloadType = PayloadType::Read;
+}
+
+impl Deserialize for Status {
+    fn deserialize(buffer: &[u8]) -> Result<(Self, usize), ParseError> {
+        identity::Response::deserialize(buffer).map(|(status, size)| (Self(status), size))
+    }
+}
+
+impl Display for Status {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{PacketBuilder, PacketType, PayloadType};
+
+    #[test]
+    fn job_details_roundtrip() {
+        let details = JobDetails::new(Host::new("host"), Host::new("user"), "title");
+        let buffer = details.serialize_to_vec();
+        assert_eq!(buffer.len(), 8 + 64 + 64 + 256);
+
+        let (details, _) = JobDetails::deserialize(&buffer).unwrap();
+        assert_eq!(details.host().to_string(), "host");
+        assert_eq!(details.user().to_string(), "user");
+        assert_eq!(details.title(), "title");
+
+        let mut buffer = buffer;
+        buffer[3] = 0x2a;
+        let (details, _) = JobDetails::deserialize(&buffer).unwrap();
+        assert_eq!(details.serialize_to_vec(), buffer);
+    }
+
+    #[test]
+    fn job_details_truncate_title() {
+        let details = JobDetails::new(Host::new("host"), Host::new("user"), "a".repeat(200));
+        assert_eq!(details.title().len(), MAX_TITLE_LENGTH / 2);
+    }
+
+    #[test]
+    fn vectored_packets() {
+        // data is lent however large
+        let data = PacketBuilder::new(PacketType::PrinterCommand, PayloadType::Write)
+            .build(Data::new(vec![0x1b; 4096]));
+        let vectored = data.serialize_vectored::<0>().unwrap();
+        let [header, payload] = vectored.io_slices();
+        assert_eq!((header.len(), payload.len()), (16, 4096));
+        assert_eq!(vectored.to_vec(), data.serialize_to_vec());
+
+        let details =
+            PacketBuilder::new(PacketType::PrinterCommand, PayloadType::JobDetails).build(
+                JobDetails::new(Host::new("host"), Host::new("user"), "title"),
+            );
+        assert!(details.serialize_vectored::<64>().is_err());
+        let vectored = details.serialize_vectored::<512>().unwrap();
+        assert_eq!(vectored.to_vec(), details.serialize_to_vec());
+    }
+}
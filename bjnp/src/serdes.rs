@@ -18,24 +18,80 @@ use std::{
     slice,
 };
 
+use arrayvec::ArrayVec;
 use thiserror::Error;
 
+/// Path of a field within a nested payload, e.g. `interrupt.feeder_type`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FieldPath(Vec<&'static str>);
+
+impl FieldPath {
+    #[inline(always)]
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Field names from the outermost to the innermost
+    #[inline(always)]
+    pub fn segments(&self) -> &[&'static str] {
+        &self.0
+    }
+}
+
+impl From<&'static str> for FieldPath {
+    fn from(field: &'static str) -> Self {
+        Self(vec![field])
+    }
+}
+
+impl Display for FieldPath {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0.join("."))
+    }
+}
+
+fn describe_field(field: &FieldPath) -> String {
+    if field.is_empty() {
+        String::new()
+    } else {
+        format!(" in `{field}`")
+    }
+}
+
 /// Error pertaining only the format
 #[derive(Error, Debug, Clone)]
 pub enum FormatError {
-    #[error("invalid byte `{byte:#02x}` at offset {offset}: {message}")]
+    #[error("invalid byte `{byte:#02x}` at offset {offset}{}: {message}", describe_field(.field))]
     InvalidByte {
         byte: u8,
         offset: usize,
         message: &'static str,
+        field: FieldPath,
     },
-    #[error("invalid byte slice at offset ({}..{}): {message}", .span.start, .span.end)]
+    #[error(
+        "invalid byte slice at offset ({}..{}){}: {message}",
+        .span.start,
+        .span.end,
+        describe_field(.field)
+    )]
     InvalidSlice {
         span: Range<usize>,
         message: &'static str,
+        field: FieldPath,
     },
 }
 
+impl FormatError {
+    /// Field containing the invalid bytes, empty if unknown
+    pub fn field(&self) -> &FieldPath {
+        match self {
+            FormatError::InvalidByte { field, .. } | FormatError::InvalidSlice { field, .. } => {
+                field
+            }
+        }
+    }
+}
+
 /// Error pertaining format and mismatching size
 #[derive(Error, Debug, Clone)]
 pub enum ParseError {
@@ -45,6 +101,12 @@ pub enum ParseError {
     UnexpectedEnd { expected: usize, actual: usize },
 }
 
+impl From<ParseError> for io::Error {
+    fn from(err: ParseError) -> Self {
+        io::Error::new(io::ErrorKind::InvalidData, err)
+    }
+}
+
 impl From<Infallible> for FormatError {
     fn from(x: Infallible) -> FormatError {
         match x {}
@@ -87,7 +149,7 @@ impl OffsetError for ParseError {
             InvalidFormat(err) => InvalidFormat(err.offset_by(offset)),
             UnexpectedEnd { expected, actual } => UnexpectedEnd {
                 expected: expected + offset,
-                actual,
+                actual: actual + offset,
             },
         }
     }
@@ -103,6 +165,46 @@ where
     }
 }
 
+#[doc(hidden)]
+pub trait FieldError {
+    /// Marks the error as coming from within `field` of the enclosing struct.
+    fn in_field(self, field: &'static str) -> Self;
+}
+
+#[doc(hidden)]
+impl FieldError for FormatError {
+    fn in_field(mut self, name: &'static str) -> Self {
+        use FormatError::*;
+        match &mut self {
+            InvalidByte { field, .. } | InvalidSlice { field, .. } => {
+                field.0.insert(0, name);
+            }
+        }
+        self
+    }
+}
+
+#[doc(hidden)]
+impl FieldError for ParseError {
+    fn in_field(self, field: &'static str) -> Self {
+        use ParseError::*;
+        match self {
+            InvalidFormat(err) => InvalidFormat(err.in_field(field)),
+            err @ UnexpectedEnd { .. } => err,
+        }
+    }
+}
+
+#[doc(hidden)]
+impl<T, U> FieldError for Result<U, T>
+where
+    T: FieldError,
+{
+    fn in_field(self, field: &'static str) -> Self {
+        self.map_err(|err| err.in_field(field))
+    }
+}
+
 /// Link a struct to its raw representation, allowing auto implementation of
 /// [`Serialize`] and [`Deserialize`].
 ///
@@ -168,6 +270,7 @@ where
 ///             Err(InvalidSlice {
 ///                 span: (0..4),
 ///                 message: "magic bytes not `0x01020304`",
+///                 field: Default::default(),
 ///             })
 ///         }
 ///     }
@@ -211,12 +314,30 @@ pub trait Serialize {
 
     fn size(&self) -> usize;
 
+    /// Returns the serialization if `self` holds it as is, lent to vectored
+    /// writes instead of copied. `None` by default.
+    #[inline(always)]
+    fn wire_bytes(&self) -> Option<&[u8]> {
+        None
+    }
+
     fn serialize_to_vec(&self) -> Vec<u8> {
         let mut buffer = Vec::with_capacity(self.size());
         // NOPANIC: write to an allocated vector should never fail
         self.serialize(&mut buffer).unwrap();
         buffer
     }
+
+    /// Serializes into a buffer on the stack, avoiding the allocation of
+    /// [`Serialize::serialize_to_vec`] for packets sent repeatedly.
+    ///
+    /// # Errors
+    /// [`io::ErrorKind::WriteZero`] if `self` is larger than `N` bytes.
+    fn serialize_to_array<const N: usize>(&self) -> Result<ArrayVec<u8, N>, io::Error> {
+        let mut buffer = ArrayVec::new();
+        self.serialize(&mut buffer)?;
+        Ok(buffer)
+    }
 }
 
 impl<T> Serialize for T
@@ -246,8 +367,110 @@ where
     }
 }
 
+/// Serialization of a size known at compile time, for sizing the buffer of
+/// [`Serialize::serialize_to_array`] exactly instead of large enough.
+pub trait FixedSize: Serialize {
+    /// Size of the serialization, the same as [`Serialize::size`]
+    const SIZE: usize;
+}
+
+impl<T> FixedSize for T
+where
+    T: HasRawRepr,
+    T::Repr: for<'a> From<&'a T>,
+{
+    const SIZE: usize = size_of::<T::Repr>();
+}
+
 pub trait Deserialize: Sized {
     fn deserialize(buffer: &[u8]) -> Result<(Self, usize), ParseError>;
+
+    /// Deserializes as [`Deserialize::deserialize`], then checks that
+    /// serializing the result gives back the bytes consumed, so that tests
+    /// catch fields modeled incorrectly.
+    fn deserialize_strict(buffer: &[u8]) -> Result<(Self, usize), StrictError>
+    where
+        Self: Serialize,
+    {
+        let (obj, size) = Self::deserialize(buffer)?;
+        diff(&buffer[..size], &obj.serialize_to_vec())?;
+        Ok((obj, size))
+    }
+}
+
+/// Bytes differing between a buffer and the serialization of what was parsed
+/// from it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mismatch {
+    pub expected: Vec<u8>,
+    pub actual: Vec<u8>,
+    /// Spans of differing bytes, the last one running to the end of the
+    /// longer buffer if their sizes differ
+    pub spans: Vec<Range<usize>>,
+}
+
+impl Display for Mismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let hex = |bytes: &[u8], span: &Range<usize>| {
+            let bytes = bytes.get(span.start..span.end.min(bytes.len()));
+            match bytes.unwrap_or_default() {
+                [] => "<none>".to_owned(),
+                bytes => bytes
+                    .iter()
+                    .map(|b| format!("{b:02x}"))
+                    .collect::<Vec<_>>()
+                    .join(" "),
+            }
+        };
+        f.write_str("differs after serializing again")?;
+        for (i, span) in self.spans.iter().enumerate() {
+            f.write_fmt(format_args!(
+                "{} at ({}..{}): {} became {}",
+                if i == 0 { "" } else { "," },
+                span.start,
+                span.end,
+                hex(&self.expected, span),
+                hex(&self.actual, span)
+            ))?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Mismatch {}
+
+/// Compares a buffer with the serialization of what was parsed from it.
+///
+/// # Errors
+/// [`Mismatch`] with the differing spans if `expected` and `actual` differ.
+pub fn diff(expected: &[u8], actual: &[u8]) -> Result<(), Mismatch> {
+    let mut spans: Vec<Range<usize>> = vec![];
+    for i in 0..expected.len().max(actual.len()) {
+        if expected.get(i) == actual.get(i) {
+            continue;
+        }
+        match spans.last_mut() {
+            Some(span) if span.end == i => span.end += 1,
+            _ => spans.push(i..i + 1),
+        }
+    }
+    if spans.is_empty() {
+        return Ok(());
+    }
+    Err(Mismatch {
+        expected: expected.to_vec(),
+        actual: actual.to_vec(),
+        spans,
+    })
+}
+
+/// Error of [`Deserialize::deserialize_strict`]
+#[derive(Error, Debug, Clone)]
+pub enum StrictError {
+    #[error("couldn't parse")]
+    Parse(#[from] ParseError),
+    #[error(transparent)]
+    Mismatch(#[from] Mismatch),
 }
 
 pub(crate) fn deserialized_into<T, U: From<T>>((obj, size): (T, usize)) -> (U, usize) {
@@ -298,6 +521,9 @@ pub struct Empty;
 
 impl Display for Empty {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if f.alternate() {
+            return f.write_str("null");
+        }
         f.write_str("<empty>")
     }
 }
@@ -317,6 +543,10 @@ impl Serialize for Empty {
     }
 }
 
+impl FixedSize for Empty {
+    const SIZE: usize = 0;
+}
+
 impl Deserialize for Empty {
     #[inline(always)]
     fn deserialize(_buffer: &[u8]) -> Result<(Self, usize), ParseError> {
@@ -350,6 +580,13 @@ macro_rules! make_u8_field {
             )+
         }
 
+        impl From<$field> for u8 {
+            #[inline(always)]
+            fn from(value: $field) -> Self {
+                value as u8
+            }
+        }
+
         impl TryFrom<u8> for $field {
             type Error = crate::serdes::FormatError;
 
@@ -360,7 +597,8 @@ macro_rules! make_u8_field {
                     _ => Err(crate::serdes::FormatError::InvalidByte {
                         byte: value,
                         offset: 0,
-                        message: concat!("unknown ", $field_name)
+                        message: concat!("unknown ", $field_name),
+                        field: Default::default(),
                     })
                 }
             }
@@ -414,7 +652,8 @@ macro_rules! make_wider_field {
                     $($value => Ok($variant), )+
                     _ => Err(crate::serdes::FormatError::InvalidSlice {
                         span: (0..::std::mem::size_of::<$type_name>()),
-                        message: concat!("unknown ", $field_name)
+                        message: concat!("unknown ", $field_name),
+                        field: Default::default(),
                     })
                 }
             }
@@ -0,0 +1,185 @@
+//! This module contains a loader of packet captures, used as regression tests
+//! of parsing.
+//!
+//! Each capture is a `.hex` file holding a single packet as hex bytes. Lines
+//! starting with `#` are comments, and leading offsets like `0010:` are
+//! skipped, so that hex dumps from `scanner-button contribute-trace` reports
+//! can be pasted as is:
+//!
+//! ```text
+//! # MX920 series, poll response with an interrupt
+//! 0000: 42 4a 4e 50 82 32 00 00 00 03 00 00 00 00 00 24
+//! 0010: 00 00 80 00 00 00 00 00 00 00 00 14 00 00 00 01
+//! ```
+
+use std::{
+    fmt::Display,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use thiserror::Error;
+
+use crate::{
+    discover,
+    header::Header,
+    identity, poll, print,
+    serdes::{self, Deserialize, Empty, Mismatch, ParseError, Serialize},
+    Packet, PacketHeaderOnly, PacketType, PayloadType,
+};
+
+/// A packet loaded from a capture
+#[derive(Debug, Clone)]
+pub struct Vector {
+    pub path: PathBuf,
+    pub bytes: Vec<u8>,
+}
+
+/// Failure to round trip a packet
+#[derive(Error, Debug, Clone)]
+pub enum VectorError {
+    #[error("couldn't parse packet")]
+    Parse(#[from] ParseError),
+    #[error(transparent)]
+    NotExact(#[from] Mismatch),
+    #[error("payload of {size} bytes parsed as {parsed}, differs after reserializing: {reparsed}")]
+    Mismatch {
+        size: u32,
+        parsed: String,
+        reparsed: String,
+    },
+}
+
+/// Loads the captures in `dir`, sorted by path.
+pub fn load(dir: &Path) -> io::Result<Vec<Vector>> {
+    let mut vectors = vec![];
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().is_none_or(|ext| ext != "hex") {
+            continue;
+        }
+        let bytes = parse_hex(&fs::read_to_string(&path)?).map_err(|message| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: {message}", path.display()),
+            )
+        })?;
+        vectors.push(Vector { path, bytes });
+    }
+    vectors.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(vectors)
+}
+
+fn parse_hex(text: &str) -> Result<Vec<u8>, String> {
+    let mut bytes = vec![];
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.starts_with('#') {
+            continue;
+        }
+        let line = match line.split_once(':') {
+            Some((_offset, rest)) => rest,
+            None => line,
+        };
+        for byte in line.split_whitespace() {
+            bytes.push(
+                u8::from_str_radix(byte, 16)
+                    .map_err(|_| format!("invalid byte `{byte}` on line {}", i + 1))?,
+            );
+        }
+    }
+    Ok(bytes)
+}
+
+/// Parses a packet with the payload its header announces, then checks that
+/// serializing it again yields the same bytes and parsing those the same
+/// packet. Payloads without a serializer, and packets of unknown kinds, are
+/// only parsed.
+pub fn round_trip(buffer: &[u8]) -> Result<(), VectorError> {
+    use PacketType::*;
+    use PayloadType::*;
+
+    let packet = PacketHeaderOnly::parse(buffer)?;
+    match (packet.packet_type(), packet.payload_type()) {
+        (ScannerCommand, Discover) => reparse::<discover::Request>(packet, buffer),
+        (ScannerResponse, Discover) => reparse::<discover::Response>(packet, buffer),
+        (ScannerCommand | PrinterCommand, GetId) => reparse::<Empty>(packet, buffer),
+        (ScannerResponse | PrinterResponse, GetId) => reparse::<identity::Response>(packet, buffer),
+        (ScannerCommand, Poll) => reparse::<poll::Command>(packet, buffer),
+        (ScannerResponse, Poll) => Packet::<poll::Response>::try_from(packet)
+            .map(|_| ())
+            .map_err(Into::into),
+        (PrinterCommand, Write) => reparse::<print::Data>(packet, buffer),
+        (PrinterResponse, Read) => reparse::<print::Status>(packet, buffer),
+        _ => Ok(()),
+    }
+}
+
+fn reparse<T>(packet: PacketHeaderOnly, buffer: &[u8]) -> Result<(), VectorError>
+where
+    T: Serialize + Deserialize + Display,
+{
+    let size = packet.payload_size();
+    let parsed = Packet::<T>::try_from(packet)?;
+    let expected = &buffer[..Header::SIZE + size as usize];
+    let buffer = parsed.serialize_to_vec();
+    serdes::diff(expected, &buffer)?;
+    let reparsed = Packet::<T>::try_from(PacketHeaderOnly::parse(&buffer)?)?;
+
+    // compared in JSON, which is sorted and covers the header
+    let (parsed, reparsed) = (format!("{parsed:#}"), format!("{reparsed:#}"));
+    if parsed != reparsed {
+        return Err(VectorError::Mismatch {
+            size,
+            parsed,
+            reparsed,
+        });
+    }
+    Ok(())
+}
+
+/// Round trips every capture in `dir`, panicking with the failures.
+pub fn assert_round_trip(dir: impl AsRef<Path>) {
+    let dir = dir.as_ref();
+    let vectors = load(dir).unwrap_or_else(|e| panic!("couldn't load {}: {e}", dir.display()));
+    assert!(!vectors.is_empty(), "no captures in {}", dir.display());
+
+    let failures: Vec<_> = vectors
+        .iter()
+        .filter_map(|vector| {
+            round_trip(&vector.bytes)
+                .err()
+                .map(|e| format!("{}: {e}", vector.path.display()))
+        })
+        .collect();
+    assert!(
+        failures.is_empty(),
+        "{} of {} captures failed:\n{}",
+        failures.len(),
+        vectors.len(),
+        failures.join("\n")
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hex_dump_lines() {
+        let text = "# comment\n0000: 42 4a\n4e 50\n\n";
+        assert_eq!(parse_hex(text), Ok(vec![0x42, 0x4a, 0x4e, 0x50]));
+        assert!(parse_hex("0000: 4g").is_err());
+    }
+
+    #[test]
+    fn mismatching_spans() {
+        assert!(serdes::diff(&[1, 2, 3], &[1, 2, 3]).is_ok());
+        let mismatch = serdes::diff(&[1, 2, 3, 4, 5], &[1, 0, 3, 0]).unwrap_err();
+        assert_eq!(mismatch.spans, [1..2, 3..5]);
+        assert_eq!(
+            mismatch.to_string(),
+            "differs after serializing again at (1..2): 02 became 00, at (3..5): 04 05 became 00"
+        );
+    }
+}
@@ -0,0 +1,408 @@
+//! This module contains a linter of packets, checking them against what is
+//! known of the protocol beyond what parsing requires, for tools inspecting
+//! captured traffic.
+//!
+//! ```
+//! use bjnp::validate::{validate, Severity};
+//!
+//! let diagnostics = validate(b"BJNP\x02\x01\x00\x00\x00\x00\x00\x00\x00\x00\x00\x04");
+//! assert_eq!(diagnostics[0].severity, Severity::Error);
+//! assert_eq!(diagnostics[0].span, 16..20);
+//! ```
+
+use std::{fmt::Display, ops::Range};
+
+use crate::{
+    discover,
+    header::Header,
+    identity, poll, print,
+    serdes::{self, Empty, FormatError, ParseError, Serialize},
+    Packet, PacketHeaderOnly, PacketType, PayloadType,
+};
+
+const MAGIC: &[u8; 4] = b"BJNP";
+
+/// Values seen so far in fields of unknown meaning, as the packet type, the
+/// payload type, the poll type of poll commands if any, the span within the
+/// payload and the values
+type KnownValues = (
+    PacketType,
+    PayloadType,
+    Option<u8>,
+    Range<usize>,
+    &'static [&'static [u8]],
+);
+
+const KNOWN_VALUES: &[KnownValues] = &[
+    (
+        PacketType::ScannerResponse,
+        PayloadType::Discover,
+        None,
+        0..4,
+        &[&[0x00, 0x01, 0x08, 0x00]],
+    ),
+    (
+        PacketType::ScannerResponse,
+        PayloadType::Poll,
+        None,
+        8..12,
+        // 0x14 along with interrupts
+        &[&[0x00, 0x00, 0x00, 0x00], &[0x00, 0x00, 0x00, 0x14]],
+    ),
+    (
+        PacketType::ScannerCommand,
+        PayloadType::Poll,
+        Some(0x02),
+        72..76,
+        &[&[0x00, 0x00, 0x00, 0x14]],
+    ),
+    (
+        PacketType::ScannerCommand,
+        PayloadType::Poll,
+        Some(0x02),
+        76..96,
+        &[&[0; 20]],
+    ),
+    (
+        PacketType::ScannerCommand,
+        PayloadType::Poll,
+        Some(0x02),
+        96..100,
+        &[&[0x00, 0x00, 0x00, 0x10]],
+    ),
+    (
+        PacketType::ScannerCommand,
+        PayloadType::Poll,
+        Some(0x05),
+        72..76,
+        &[&[0x00, 0x00, 0x00, 0x14]],
+    ),
+    (
+        PacketType::ScannerCommand,
+        PayloadType::Poll,
+        Some(0x05),
+        80..100,
+        &[&[0; 20]],
+    ),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Severity {
+    /// Unusual, but parsed
+    Warning,
+    /// Not a valid packet
+    Error,
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.pad(match self {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        })
+    }
+}
+
+/// A finding about a packet
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// Bytes the finding is about, from the start of the packet
+    pub span: Range<usize>,
+    pub message: String,
+}
+
+impl Diagnostic {
+    fn new(severity: Severity, span: Range<usize>, message: impl Into<String>) -> Self {
+        Self {
+            severity,
+            span,
+            message: message.into(),
+        }
+    }
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_fmt(format_args!(
+            "{} at ({}..{}): {}",
+            self.severity, self.span.start, self.span.end, self.message
+        ))
+    }
+}
+
+/// Checks the magic, the combination of packet type and payload type, the
+/// payload size against the size of the buffer, the payload, that serializing
+/// the packet again gives back its bytes, and fields of unknown meaning
+/// against the values they are known to hold. Returns the
+/// findings in the order of the checks, empty for a packet looking as
+/// expected.
+pub fn validate(buffer: &[u8]) -> Vec<Diagnostic> {
+    use Severity::*;
+
+    let mut diagnostics = vec![];
+    if buffer.len() < Header::SIZE {
+        diagnostics.push(Diagnostic::new(
+            Error,
+            0..buffer.len(),
+            format!(
+                "{} bytes, shorter than the header of {} bytes",
+                buffer.len(),
+                Header::SIZE
+            ),
+        ));
+        return diagnostics;
+    }
+    if &buffer[0..4] != MAGIC {
+        diagnostics.push(Diagnostic::new(Error, 0..4, "magic bytes are not b'BJNP'"));
+        return diagnostics;
+    }
+
+    let packet_type = PacketType::try_from(buffer[4]).ok();
+    if packet_type.is_none() {
+        diagnostics.push(Diagnostic::new(
+            Error,
+            4..5,
+            format!("unknown packet type {:#04x}", buffer[4]),
+        ));
+    }
+    let payload_type = PayloadType::try_from(buffer[5]).ok();
+    if payload_type.is_none() {
+        diagnostics.push(Diagnostic::new(
+            Error,
+            5..6,
+            format!("unknown payload type {:#04x}", buffer[5]),
+        ));
+    }
+    if let (Some(packet_type), Some(payload_type)) = (packet_type, payload_type) {
+        let scanner = matches!(
+            packet_type,
+            PacketType::ScannerCommand | PacketType::ScannerResponse
+        );
+        if matches!(payload_type, PayloadType::Poll | PayloadType::StartScan) && !scanner {
+            diagnostics.push(Diagnostic::new(
+                Warning,
+                4..6,
+                format!("{payload_type} is only known in scanner packets, not {packet_type}"),
+            ));
+        }
+        let command = matches!(
+            packet_type,
+            PacketType::ScannerCommand | PacketType::PrinterCommand
+        );
+        if command && buffer[6] != 0 {
+            diagnostics.push(Diagnostic::new(
+                Warning,
+                6..7,
+                format!("error {:#04x} in a command", buffer[6]),
+            ));
+        }
+    }
+    if buffer[7] != 0 {
+        diagnostics.push(Diagnostic::new(
+            Warning,
+            7..8,
+            format!(
+                "unknown byte is {:#04x}, 0x00 in all traffic seen",
+                buffer[7]
+            ),
+        ));
+    }
+
+    // NOPANIC: the buffer holds a header
+    let payload_size = u32::from_be_bytes(buffer[12..16].try_into().unwrap()) as usize;
+    let received = buffer.len() - Header::SIZE;
+    if received < payload_size {
+        diagnostics.push(Diagnostic::new(
+            Error,
+            Header::SIZE..Header::SIZE + payload_size,
+            format!("payload of {payload_size} bytes truncated to {received} bytes"),
+        ));
+        return diagnostics;
+    }
+    if received > payload_size {
+        diagnostics.push(Diagnostic::new(
+            Warning,
+            Header::SIZE + payload_size..buffer.len(),
+            format!(
+                "{} bytes after the payload of {payload_size} bytes",
+                received - payload_size
+            ),
+        ));
+    }
+
+    let (Some(packet_type), Some(payload_type)) = (packet_type, payload_type) else {
+        return diagnostics;
+    };
+    let packet = &buffer[..Header::SIZE + payload_size];
+    let payload = &packet[Header::SIZE..];
+    match reserialize(packet_type, payload_type, packet) {
+        Ok(Some(reserialized)) => {
+            if let Err(mismatch) = serdes::diff(packet, &reserialized) {
+                for span in mismatch.spans {
+                    let bytes = &packet[span.start..span.end.min(packet.len())];
+                    diagnostics.push(Diagnostic::new(
+                        Warning,
+                        span,
+                        format!("{bytes:02x?} aren't kept when serializing the packet again"),
+                    ));
+                }
+            }
+        }
+        Ok(None) => {}
+        Err(e) => {
+            diagnostics.push(parse_diagnostic(e, buffer.len()));
+            return diagnostics;
+        }
+    }
+    for (known_packet_type, known_payload_type, poll_type, span, values) in KNOWN_VALUES {
+        let applies = *known_packet_type == packet_type
+            && *known_payload_type == payload_type
+            && poll_type.is_none_or(|poll_type| payload.get(1) == Some(&poll_type));
+        let Some(bytes) = payload.get(span.clone()).filter(|_| applies) else {
+            continue;
+        };
+        if !values.contains(&bytes) {
+            diagnostics.push(Diagnostic::new(
+                Warning,
+                Header::SIZE + span.start..Header::SIZE + span.end,
+                format!("unknown field is {bytes:02x?}, unlike in all traffic seen"),
+            ));
+        }
+    }
+    diagnostics
+}
+
+/// Parses the payload of `buffer` as its header announces, for the payloads
+/// known to this crate, and serializes the packet again for the payloads
+/// with a serializer.
+fn reserialize(
+    packet_type: PacketType,
+    payload_type: PayloadType,
+    buffer: &[u8],
+) -> Result<Option<Vec<u8>>, ParseError> {
+    use PacketType::*;
+    use PayloadType::*;
+
+    fn again<T>(packet: Result<Packet<T>, ParseError>) -> Result<Option<Vec<u8>>, ParseError>
+    where
+        T: Serialize,
+    {
+        packet.map(|packet| Some(packet.serialize_to_vec()))
+    }
+
+    let packet = PacketHeaderOnly::parse(buffer)?;
+    match (packet_type, payload_type) {
+        (ScannerCommand, Discover) => again(Packet::<discover::Request>::try_from(packet)),
+        (ScannerResponse, Discover) => again(Packet::<discover::Response>::try_from(packet)),
+        (ScannerCommand | PrinterCommand, GetId) => again(Packet::<Empty>::try_from(packet)),
+        (ScannerResponse | PrinterResponse, GetId) => {
+            again(Packet::<identity::Response>::try_from(packet))
+        }
+        (ScannerCommand, Poll) => again(Packet::<poll::Command>::try_from(packet)),
+        (ScannerResponse, Poll) => Packet::<poll::Response>::try_from(packet).map(|_| None),
+        (PrinterCommand, Write) => again(Packet::<print::Data>::try_from(packet)),
+        (PrinterResponse, Read) => again(Packet::<print::Status>::try_from(packet)),
+        _ => Ok(None),
+    }
+}
+
+fn parse_diagnostic(error: ParseError, len: usize) -> Diagnostic {
+    match error {
+        ParseError::InvalidFormat(e) => {
+            let span = match &e {
+                FormatError::InvalidByte { offset, .. } => *offset..*offset + 1,
+                FormatError::InvalidSlice { span, .. } => span.clone(),
+            };
+            Diagnostic::new(Severity::Error, span, e.to_string())
+        }
+        ParseError::UnexpectedEnd { expected, actual } => Diagnostic::new(
+            Severity::Error,
+            Header::SIZE..len,
+            format!(
+                "payload of {} bytes, shorter than the {} bytes expected",
+                actual.saturating_sub(Header::SIZE),
+                expected.saturating_sub(Header::SIZE)
+            ),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{serdes::Serialize, Host, PacketBuilder};
+
+    fn reset_command() -> poll::Command {
+        poll::CommandBuilder::new(poll::PollType::Reset)
+            .session_id(1)
+            .host(Host::new("host"))
+            .action_id(2)
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn known_packets_are_clean() {
+        let discover = PacketBuilder::new(PacketType::ScannerCommand, PayloadType::Discover)
+            .build(discover::Request::new())
+            .serialize_to_vec();
+        assert_eq!(validate(&discover), []);
+
+        let response = discover::Response::new(
+            "00:1e:8f:01:02:03".parse().unwrap(),
+            "192.168.1.2".parse().unwrap(),
+        );
+        let discovered = PacketBuilder::new(PacketType::ScannerResponse, PayloadType::Discover)
+            .build(response)
+            .serialize_to_vec();
+        assert_eq!(validate(&discovered), []);
+
+        let reset = PacketBuilder::new(PacketType::ScannerCommand, PayloadType::Poll)
+            .build(reset_command())
+            .serialize_to_vec();
+        assert_eq!(validate(&reset), []);
+    }
+
+    #[test]
+    fn unusual_packets() {
+        assert_eq!(validate(b"BJNP")[0].span, 0..4);
+        assert_eq!(
+            validate(b"BJMP\x02\x01\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00")[0].message,
+            "magic bytes are not b'BJNP'"
+        );
+
+        let mut reset = PacketBuilder::new(PacketType::ScannerCommand, PayloadType::Poll)
+            .build(reset_command())
+            .serialize_to_vec();
+        reset[7] = 0x01;
+        reset[16 + 75] = 0x15;
+        reset.push(0);
+        let diagnostics = validate(&reset);
+        let spans: Vec<_> = diagnostics.iter().map(|d| d.span.clone()).collect();
+        assert_eq!(spans, [7..8, 116..117, 88..92]);
+        assert!(diagnostics
+            .iter()
+            .all(|diagnostic| diagnostic.severity == Severity::Warning));
+
+        let poll = b"BJNP\x01\x32\x00\x00\x00\x00\x00\x00\x00\x00\x00\x02\x00\x00";
+        assert_eq!(validate(poll)[0].span, 4..6);
+        let poll = b"BJNP\x02\x32\x00\x00\x00\x00\x00\x00\x00\x00\x00\x02\x00\x07";
+        let diagnostics = validate(poll);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].severity, Severity::Error);
+        assert_eq!(diagnostics[0].span.start, 16);
+
+        let mut empty = PacketBuilder::new(PacketType::ScannerCommand, PayloadType::Poll)
+            .build(
+                poll::CommandBuilder::new(poll::PollType::Empty)
+                    .build()
+                    .unwrap(),
+            )
+            .serialize_to_vec();
+        empty[28] = 0x01;
+        let diagnostics = validate(&empty);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].span, 28..29);
+    }
+}
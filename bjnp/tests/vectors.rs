@@ -0,0 +1,7 @@
+//! Round trips the packet captures in `tests/vectors`. Add a capture of a new
+//! model there to keep it parsing.
+
+#[test]
+fn captures() {
+    bjnp::test_vectors::assert_round_trip(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/vectors"));
+}
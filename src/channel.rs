@@ -1,36 +1,211 @@
 use std::{
-    fmt::Display,
-    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    collections::HashMap,
+    fmt::{self, Display},
+    io::{self, IoSlice},
+    net::SocketAddr,
     num::Wrapping,
-    sync::Arc,
+    sync::{Arc, Mutex},
 };
 
-use anyhow::{ensure, Context};
+use anyhow::{anyhow, ensure, Context};
 use bjnp::{
-    serdes::{Deserialize, Serialize},
-    Packet, PacketBuilder, PacketHeaderOnly, PayloadType,
+    serdes::{Deserialize, ParseError, Serialize},
+    Packet, PacketBuilder, PacketHeaderOnly, PacketType, Payload, PayloadType, Request,
 };
-use log::{debug, trace};
-use pretty_hex::PrettyHex;
-use tokio::net::UdpSocket;
+use socket2::SockRef;
+use thiserror::Error;
+use tokio::{
+    io::Interest,
+    net::UdpSocket,
+    sync::{mpsc::UnboundedSender, oneshot},
+    task::JoinHandle,
+    time::{timeout, Duration, Instant},
+};
+use tracing::{debug, trace, warn};
+
+use crate::{
+    dump::{self, Direction},
+    utils::{hex_dump, parse_error, unspecified_for, SocketOptions},
+};
+
+/// Size of the buffer payloads of commands are serialized into, larger than a
+/// full poll
+const MAX_COMMAND_SIZE: usize = 256;
+/// Lower bound of timeouts derived from round trip times, as recommended by
+/// RFC 6298
+const MIN_TIMEOUT: Duration = Duration::from_secs(1);
+/// Time a response is remembered to discard duplicates of it
+const DUPLICATE_WINDOW: Duration = Duration::from_secs(2);
+
+#[derive(Error, Debug)]
+pub enum ChannelError {
+    #[error(
+        "unexpected response from {peer}: expect `[{expected}]`, found `[{packet_type}] \
+         [{payload_type}]`"
+    )]
+    UnexpectedResponse {
+        peer: SocketAddr,
+        expected: PayloadType,
+        packet_type: PacketType,
+        payload_type: PayloadType,
+    },
+    #[error(
+        "truncated response from {peer}: received {received} bytes of payload out of {expected}"
+    )]
+    Truncated {
+        peer: SocketAddr,
+        expected: u32,
+        received: usize,
+    },
+}
+
+/// Counters of the commands sent through a [`Channel`], along with round trip
+/// times smoothed as in RFC 6298
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ChannelStats {
+    pub sent: u64,
+    pub received: u64,
+    /// Commands given up before their response arrived, mostly on timeouts
+    pub timeouts: u64,
+    /// Round trip time of the last response
+    pub last_rtt: Option<Duration>,
+    /// Smoothed round trip time
+    pub srtt: Option<Duration>,
+    rttvar: Duration,
+    /// Timeouts since the last response, each doubling the timeout
+    backoff: u32,
+}
+
+impl ChannelStats {
+    fn sample(&mut self, rtt: Duration) {
+        self.received += 1;
+        self.last_rtt = Some(rtt);
+        self.backoff = 0;
+        self.srtt = Some(match self.srtt {
+            None => {
+                self.rttvar = rtt / 2;
+                rtt
+            }
+            Some(srtt) => {
+                self.rttvar = self.rttvar * 3 / 4 + srtt.abs_diff(rtt) / 4;
+                srtt * 7 / 8 + rtt / 8
+            }
+        });
+    }
+
+    fn timed_out(&mut self) {
+        self.timeouts += 1;
+        self.backoff = self.backoff.saturating_add(1);
+    }
+
+    /// Returns the time to wait for a response, derived from round trip times
+    /// so far and at most `max`, which is also the timeout until the first
+    /// response.
+    pub fn timeout(&self, max: Duration) -> Duration {
+        let Some(srtt) = self.srtt else {
+            return max;
+        };
+        let rto = (srtt + self.rttvar * 4).max(MIN_TIMEOUT);
+        rto.saturating_mul(1 << self.backoff.min(16)).min(max)
+    }
+}
+
+impl Display for ChannelStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} sent, {} received, {} timed out",
+            self.sent, self.received, self.timeouts
+        )?;
+        if let (Some(rtt), Some(srtt)) = (self.last_rtt, self.srtt) {
+            write!(
+                f,
+                ", rtt {:.2} ms (smoothed {:.2} ms)",
+                rtt.as_secs_f64() * 1000.0,
+                srtt.as_secs_f64() * 1000.0
+            )?;
+        }
+        Ok(())
+    }
+}
+
+type Routes = HashMap<(SocketAddr, u16), oneshot::Sender<Vec<u8>>>;
+
+/// Receives a copy of the commands sent and responses received by a
+/// [`Channel`] along with the time they were, see [`Channel::set_tap`]
+pub type Tap = UnboundedSender<(Instant, Direction, Vec<u8>)>;
+
+/// Responses recently routed, by peer and sequence number. A scanner may answer
+/// a command more than once, and a copy arriving after the sequence number is
+/// reused, e.g. by [`Channel::reset_sequence`], would otherwise be taken as the
+/// response to the new command.
+#[derive(Debug, Default)]
+struct Delivered(HashMap<(SocketAddr, u16), (Instant, Vec<u8>)>);
+
+impl Delivered {
+    /// Returns whether `buffer` repeats a response delivered within
+    /// [`DUPLICATE_WINDOW`].
+    fn is_duplicate(&self, peer: SocketAddr, sequence: u16, buffer: &[u8], now: Instant) -> bool {
+        self.0
+            .get(&(peer, sequence))
+            .is_some_and(|(at, delivered)| now - *at < DUPLICATE_WINDOW && delivered == buffer)
+    }
+
+    fn record(&mut self, peer: SocketAddr, sequence: u16, buffer: &[u8], now: Instant) {
+        self.0.retain(|_, (at, _)| now - *at < DUPLICATE_WINDOW);
+        self.0.insert((peer, sequence), (now, buffer.to_vec()));
+    }
+}
+
+/// Receives on a socket shared by one or more channels, routing each inbound
+/// packet to the request awaiting it by peer address and sequence number.
+#[derive(Debug, Clone)]
+pub struct Dispatcher(Arc<DispatcherInner>);
 
 #[derive(Debug)]
-pub struct Channel {
+struct DispatcherInner {
     socket: Arc<UdpSocket>,
-    sequence: Wrapping<u16>,
+    connected: bool,
+    routes: Arc<Mutex<Routes>>,
+    // sequences are allocated per peer so that requests to the same peer never
+    // await the same sequence number
+    sequences: Mutex<HashMap<SocketAddr, Wrapping<u16>>>,
+    task: JoinHandle<()>,
 }
 
-impl Channel {
-    pub async fn new(addr: SocketAddr) -> anyhow::Result<Self> {
-        const IPV4_ANY: IpAddr = IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0));
-        const IPV6_ANY: IpAddr = IpAddr::V6(Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 0));
+impl Drop for DispatcherInner {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+impl Dispatcher {
+    pub async fn bind(local: SocketAddr, options: &SocketOptions) -> anyhow::Result<Self> {
+        let socket = UdpSocket::bind(local)
+            .await
+            .with_context(|| format!("couldn't bind to {local}"))?;
+        debug!("binded shared socket to {local}");
+        options
+            .apply(&socket)
+            .with_context(|| format!("couldn't set socket options {options:?} on {local}"))?;
+
+        Ok(Self::spawn(socket, local, false))
+    }
 
-        let local_ip = if addr.is_ipv4() { IPV4_ANY } else { IPV6_ANY };
-        let local = SocketAddr::new(local_ip, 0);
+    /// Creates a dispatcher over a socket connected to `addr`, dedicated to a
+    /// single channel.
+    async fn connect(
+        addr: SocketAddr,
+        local: SocketAddr,
+        options: &SocketOptions,
+    ) -> anyhow::Result<Self> {
         let socket = UdpSocket::bind(local)
             .await
             .with_context(|| format!("couldn't bind to {local}"))?;
         debug!("binded socket to {local}");
+        options
+            .apply(&socket)
+            .with_context(|| format!("couldn't set socket options {options:?} on {local}"))?;
 
         socket
             .connect(addr)
@@ -38,69 +213,509 @@ impl Channel {
             .with_context(|| format!("couldn't connect to remote socket {addr}"))?;
         debug!("connected socket to {addr}");
 
-        Ok(Self {
-            socket: Arc::new(socket),
-            sequence: Wrapping(0),
-        })
+        Ok(Self::spawn(socket, local, true))
     }
 
-    pub async fn send<T: Serialize + Display>(
-        &mut self,
-        payload_type: PayloadType,
+    fn spawn(socket: UdpSocket, local: SocketAddr, connected: bool) -> Self {
+        let socket = Arc::new(socket);
+        let routes = Arc::new(Mutex::new(Routes::new()));
+        let task = tokio::spawn({
+            let socket = socket.clone();
+            let routes = routes.clone();
+            async move {
+                let mut buffer = [0; 65536];
+                let mut delivered = Delivered::default();
+                loop {
+                    let (size, peer) = match socket.recv_from(&mut buffer).await {
+                        Ok(received) => received,
+                        Err(e) => {
+                            warn!("error receiving packet at {local}: {e}");
+                            continue;
+                        }
+                    };
+                    let buffer = &buffer[..size];
+                    trace!(
+                        "inbound packet from {peer}: {buffer:?}",
+                        buffer = hex_dump(buffer)
+                    );
+                    dump::packet(Direction::Inbound, peer, buffer);
+                    // truncated packets are still routed, failing the request
+                    // with a dedicated error rather than timing out
+                    let Ok(packet) = PacketHeaderOnly::parse_truncated(buffer) else {
+                        debug!("discarded malformed packet from {peer}");
+                        continue;
+                    };
+                    let sequence = packet.sequence();
+                    let now = Instant::now();
+                    if delivered.is_duplicate(peer, sequence, buffer, now) {
+                        debug!("discarded duplicate response from {peer}: {packet}");
+                        continue;
+                    }
+                    let route = routes.lock().unwrap().remove(&(peer, sequence));
+                    match route {
+                        Some(sender) => {
+                            delivered.record(peer, sequence, buffer, now);
+                            // the request could have been given up
+                            let _ = sender.send(buffer.to_vec());
+                        }
+                        None => debug!("discarded stray packet from {peer}: {packet}"),
+                    }
+                }
+            }
+        });
+
+        Self(Arc::new(DispatcherInner {
+            socket,
+            connected,
+            routes,
+            sequences: Mutex::new(HashMap::new()),
+            task,
+        }))
+    }
+
+    /// Creates a channel to `addr` over the shared socket.
+    pub fn channel(&self, addr: SocketAddr) -> Channel {
+        Channel {
+            dispatcher: self.clone(),
+            peer: addr,
+            pending: None,
+            stats: Default::default(),
+            tap: None,
+        }
+    }
+
+    fn next_sequence(&self, peer: SocketAddr) -> u16 {
+        let mut sequences = self.0.sequences.lock().unwrap();
+        let sequence = sequences.entry(peer).or_insert(Wrapping(0));
+        let next = sequence.0;
+        *sequence += 1;
+        trace!("sequence to {peer}: {sequence}");
+        next
+    }
+
+    fn reset_sequence(&self, peer: SocketAddr) {
+        trace!("sequence to {peer} reset to 0");
+        self.0.sequences.lock().unwrap().remove(&peer);
+    }
+
+    fn expect(&self, peer: SocketAddr, sequence: u16) -> oneshot::Receiver<Vec<u8>> {
+        let (sender, receiver) = oneshot::channel();
+        self.0
+            .routes
+            .lock()
+            .unwrap()
+            .insert((peer, sequence), sender);
+        receiver
+    }
+
+    fn forget(&self, peer: SocketAddr, sequence: u16) {
+        self.0.routes.lock().unwrap().remove(&(peer, sequence));
+    }
+
+    /// Sends the datagram made of `slices` to `peer` by a single vectored
+    /// write.
+    async fn send_vectored_to(
+        &self,
+        slices: &[IoSlice<'_>],
+        peer: SocketAddr,
+    ) -> io::Result<usize> {
+        dump::packet_vectored(Direction::Outbound, peer, slices);
+        let socket = &self.0.socket;
+        let addr = peer.into();
+        loop {
+            socket.writable().await?;
+            let sent = socket.try_io(Interest::WRITABLE, || {
+                let socket = SockRef::from(&**socket);
+                if self.0.connected {
+                    socket.send_vectored(slices)
+                } else {
+                    socket.send_to_vectored(slices, &addr)
+                }
+            });
+            match sent {
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => continue,
+                result => return result,
+            }
+        }
+    }
+}
+
+/// A channel to a peer, which allows any number of requests in flight through
+/// [`Channel::submit`], or a request at a time through [`Channel::roundtrip`]
+/// and its variants.
+#[derive(Debug)]
+pub struct Channel {
+    dispatcher: Dispatcher,
+    peer: SocketAddr,
+    /// The request sent by [`Channel::send`] awaiting response
+    pending: Option<InFlight>,
+    stats: Arc<Mutex<ChannelStats>>,
+    tap: Option<Tap>,
+}
+
+impl Channel {
+    pub async fn new(addr: SocketAddr, options: &SocketOptions) -> anyhow::Result<Self> {
+        Self::bind(addr, unspecified_for(&addr), options).await
+    }
+
+    /// Creates a channel to `addr` from a specific local address, where port 0
+    /// picks an ephemeral port.
+    pub async fn bind(
+        addr: SocketAddr,
+        local: SocketAddr,
+        options: &SocketOptions,
+    ) -> anyhow::Result<Self> {
+        Ok(Dispatcher::connect(addr, local, options)
+            .await?
+            .channel(addr))
+    }
+
+    /// Sends a command without waiting for the responses to previous ones. The
+    /// response is received through the returned [`InFlight`].
+    pub async fn submit<T: Payload + Serialize + Display>(
+        &self,
         payload: T,
-    ) -> anyhow::Result<()> {
-        let peer = self.socket.peer_addr().unwrap();
+    ) -> anyhow::Result<InFlight> {
+        let payload_type = T::PAYLOAD_TYPE;
+        let peer = self.peer;
+        let sequence = self.dispatcher.next_sequence(peer);
+        // route the response before sending, in case it arrives immediately
+        let in_flight = InFlight {
+            dispatcher: self.dispatcher.clone(),
+            peer,
+            sequence,
+            payload_type,
+            receiver: self.dispatcher.expect(peer, sequence),
+            stats: self.stats.clone(),
+            sent_at: Instant::now(),
+            answered: false,
+            tap: self.tap.clone(),
+        };
 
-        let command = PacketBuilder::new(bjnp::PacketType::ScannerCommand, payload_type)
-            .sequence(self.sequence.0)
+        let command = PacketBuilder::new(PacketType::ScannerCommand, payload_type)
+            .sequence(sequence)
             .build(payload);
         debug!("sending {payload_type} command to {peer}: {command:-}",);
 
-        let buffer = command.serialize_to_vec();
+        let packet = command
+            .serialize_vectored::<MAX_COMMAND_SIZE>()
+            .with_context(|| format!("command `[{payload_type}]` is too large"))?;
         trace!(
             "outbound packet to {peer}: {buffer:?}",
-            buffer = buffer.hex_dump()
+            buffer = hex_dump(&packet.to_vec())
         );
 
-        self.socket
-            .send(buffer.as_slice())
+        self.dispatcher
+            .send_vectored_to(&packet.io_slices(), peer)
             .await
             .with_context(|| format!("couldn't send packet `[{payload_type}]` to {peer}"))?;
+        self.stats.lock().unwrap().sent += 1;
+        if let Some(tap) = &self.tap {
+            let _ = tap.send((Instant::now(), Direction::Outbound, packet.to_vec()));
+        }
 
-        self.sequence += 1;
-        trace!("sequence to {peer}: {sequence}", sequence = self.sequence);
+        Ok(in_flight)
+    }
 
+    /// Sends a command, whose response is then received by
+    /// [`Channel::recv_with`].
+    /// The response to any previous command not yet received is discarded.
+    pub async fn send<T: Payload + Serialize + Display>(
+        &mut self,
+        payload: T,
+    ) -> anyhow::Result<()> {
+        self.pending = Some(self.submit(payload).await?);
         Ok(())
     }
 
-    pub async fn recv<T: Deserialize + Display>(&self) -> anyhow::Result<T> {
-        let peer = self.socket.peer_addr().unwrap();
+    /// Receives the response to the last command sent by [`Channel::send`],
+    /// parsing the payload with `parse`.
+    pub async fn recv_with<T: Display>(
+        &mut self,
+        parse: impl FnOnce(&[u8]) -> Result<(T, usize), ParseError>,
+    ) -> anyhow::Result<T> {
+        let peer = self.peer;
+        self.pending
+            .take()
+            .ok_or_else(|| anyhow!("no request to {peer} awaiting response"))?
+            .recv_with(parse)
+            .await
+    }
+
+    /// Sends a command and receives its response, of the type answering it.
+    pub async fn roundtrip<R>(&mut self, request: R) -> anyhow::Result<R::Response>
+    where
+        R: Request + Display,
+        R::Response: Display,
+    {
+        self.roundtrip_with(request, R::Response::deserialize).await
+    }
 
-        let mut buffer = [0; 65536];
-        let size = self.socket.recv(&mut buffer).await?;
-        let buffer = &buffer[..size];
-        trace!(
-            "inbound packet from {peer}: {buffer:?}",
-            buffer = buffer.hex_dump()
-        );
-        let packet = PacketHeaderOnly::parse(buffer)?;
-        trace!("inbound packet {packet}");
+    /// Sends a command and receives its response as [`Channel::roundtrip`],
+    /// parsing the payload with `parse`.
+    pub async fn roundtrip_with<R>(
+        &mut self,
+        request: R,
+        parse: impl FnOnce(&[u8]) -> Result<(R::Response, usize), ParseError>,
+    ) -> anyhow::Result<R::Response>
+    where
+        R: Request + Display,
+        R::Response: Display,
+    {
+        self.send(request).await?;
+        self.recv_with(parse).await
+    }
+
+    /// Sends a command and receives its response as [`Channel::roundtrip`],
+    /// waiting at most `max_waiting` for each, and sending the command again
+    /// up to `retries` times if the response doesn't arrive in time. Timeouts
+    /// keep [`Elapsed`] in the chain of the error.
+    ///
+    /// [`Elapsed`]: tokio::time::error::Elapsed
+    pub async fn roundtrip_with_timeout<R>(
+        &mut self,
+        request: R,
+        max_waiting: Duration,
+        retries: u32,
+    ) -> anyhow::Result<R::Response>
+    where
+        R: Request + Display + Clone,
+        R::Response: Display,
+    {
+        self.roundtrip_parsed_with_timeout(request, R::Response::deserialize, max_waiting, retries)
+            .await
+    }
+
+    /// Sends a command and receives its response as
+    /// [`Channel::roundtrip_with_timeout`], parsing the payload with `parse`.
+    pub async fn roundtrip_parsed_with_timeout<R>(
+        &mut self,
+        request: R,
+        parse: impl Fn(&[u8]) -> Result<(R::Response, usize), ParseError>,
+        max_waiting: Duration,
+        retries: u32,
+    ) -> anyhow::Result<R::Response>
+    where
+        R: Request + Display + Clone,
+        R::Response: Display,
+    {
+        let payload_type = R::PAYLOAD_TYPE;
+        let mut attempt = 0;
+        loop {
+            timeout(max_waiting, self.send(request.clone()))
+                .await
+                .with_context(|| format!("timeout when sending {payload_type} command"))??;
+            match timeout(max_waiting, self.recv_with(&parse)).await {
+                Ok(response) => return response,
+                Err(e) if attempt == retries => {
+                    return Err(anyhow::Error::new(e)
+                        .context(format!("timeout awaiting {payload_type} response")));
+                }
+                Err(_) => {
+                    attempt += 1;
+                    debug!(
+                        "no {payload_type} response from {peer}, sending again ({attempt}/{retries})",
+                        peer = self.peer
+                    );
+                }
+            }
+        }
+    }
+
+    pub fn peer(&self) -> SocketAddr {
+        self.peer
+    }
+
+    /// Returns the counters of the commands sent through this channel so far.
+    pub fn stats(&self) -> ChannelStats {
+        *self.stats.lock().unwrap()
+    }
+
+    /// Returns the time to wait for the response to the next command, see
+    /// [`ChannelStats::timeout`].
+    pub fn timeout(&self, max: Duration) -> Duration {
+        self.stats.lock().unwrap().timeout(max)
+    }
+
+    /// Sends a copy of every command sent and every response received through
+    /// this channel from now on to `tap`. Stray packets are not copied.
+    pub fn set_tap(&mut self, tap: Tap) {
+        self.tap = Some(tap);
+    }
+
+    /// Resets the sequence number. Sequence numbers of a channel over a shared
+    /// socket are shared with other channels to the same peer and are not
+    /// affected.
+    pub fn reset_sequence(&mut self) {
+        if self.dispatcher.0.connected {
+            self.dispatcher.reset_sequence(self.peer);
+        }
+    }
+}
+
+/// A command sent through a [`Channel`] awaiting its response
+#[derive(Debug)]
+pub struct InFlight {
+    dispatcher: Dispatcher,
+    peer: SocketAddr,
+    sequence: u16,
+    payload_type: PayloadType,
+    receiver: oneshot::Receiver<Vec<u8>>,
+    stats: Arc<Mutex<ChannelStats>>,
+    sent_at: Instant,
+    answered: bool,
+    tap: Option<Tap>,
+}
+
+impl InFlight {
+    /// Receives the response, which must be a scanner response of the same
+    /// payload type as the command.
+    pub async fn recv<T: Deserialize + Display>(self) -> anyhow::Result<T> {
+        self.recv_with(T::deserialize).await
+    }
+
+    /// Receives the response as [`InFlight::recv`], parsing the payload with
+    /// `parse`.
+    pub async fn recv_with<T: Display>(
+        mut self,
+        parse: impl FnOnce(&[u8]) -> Result<(T, usize), ParseError>,
+    ) -> anyhow::Result<T> {
+        let peer = self.peer;
+        let buffer = (&mut self.receiver)
+            .await
+            .context("socket is no longer receiving")?;
+        self.answered = true;
+        self.stats.lock().unwrap().sample(self.sent_at.elapsed());
+        if let Some(tap) = &self.tap {
+            let _ = tap.send((Instant::now(), Direction::Inbound, buffer.clone()));
+        }
+
+        let packet =
+            PacketHeaderOnly::parse_truncated(&buffer).map_err(|e| parse_error(e, &buffer))?;
+        if packet.is_truncated() {
+            return Err(ChannelError::Truncated {
+                peer,
+                expected: packet.payload_size(),
+                received: packet.received_payload_size(),
+            }
+            .into());
+        }
+        trace!("inbound packet {packet:#}");
+        if packet.packet_type() != PacketType::ScannerResponse
+            || packet.payload_type() != self.payload_type
+        {
+            return Err(ChannelError::UnexpectedResponse {
+                peer,
+                expected: self.payload_type,
+                packet_type: packet.packet_type(),
+                payload_type: packet.payload_type(),
+            }
+            .into());
+        }
         ensure!(
             packet.error() == 0 || packet.payload_size() > 0,
             "remote peer {peer} returns error code `{err:#02x}`",
             err = packet.error()
         );
 
-        let packet = Packet::<T>::try_from(packet)?;
+        let packet: Packet<T> = packet
+            .parse_payload(parse)
+            .map_err(|e| parse_error(e, &buffer))?;
         debug!(
             "decoded {payload_type} response: {packet:-}",
             payload_type = packet.payload_type()
         );
         Ok(packet.payload())
     }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        // the response could still arrive after a timeout
+        self.dispatcher.forget(self.peer, self.sequence);
+        if !self.answered {
+            self.stats.lock().unwrap().timed_out();
+        }
+    }
+}
 
-    pub fn reset_sequence(&mut self) {
-        trace!("sequence reset to 0");
-        self.sequence = Wrapping(0);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bjnp::discover::{self, Responder};
+    use tokio::time::error::Elapsed;
+
+    #[test]
+    fn timeout_follows_rtt() {
+        let max = Duration::from_secs(5);
+        let mut stats = ChannelStats::default();
+        assert_eq!(stats.timeout(max), max);
+
+        stats.sample(Duration::from_millis(400));
+        // 400 ms + 4 * 200 ms
+        assert_eq!(stats.timeout(max), Duration::from_millis(1200));
+        for _ in 0..50 {
+            stats.sample(Duration::from_millis(2));
+        }
+        assert_eq!(stats.timeout(max), MIN_TIMEOUT);
+
+        stats.timed_out();
+        stats.timed_out();
+        assert_eq!(stats.timeout(max), MIN_TIMEOUT * 4);
+        stats.timed_out();
+        assert_eq!(stats.timeout(max), max);
+        stats.sample(Duration::from_millis(2));
+        assert_eq!(stats.timeout(max), MIN_TIMEOUT);
+        assert_eq!(stats.timeouts, 3);
+        assert_eq!(stats.received, 52);
+    }
+
+    #[test]
+    fn duplicates_within_window() {
+        let peer: SocketAddr = "192.168.1.20:8612".parse().unwrap();
+        let now = Instant::now();
+        let mut delivered = Delivered::default();
+        delivered.record(peer, 0, b"response", now);
+        assert!(delivered.is_duplicate(peer, 0, b"response", now));
+        assert!(!delivered.is_duplicate(peer, 0, b"other response", now));
+        assert!(!delivered.is_duplicate(peer, 1, b"response", now));
+        assert!(!delivered.is_duplicate(peer, 0, b"response", now + DUPLICATE_WINDOW));
+
+        delivered.record(peer, 1, b"response", now + DUPLICATE_WINDOW);
+        assert_eq!(delivered.0.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn roundtrip_sends_again_unanswered_commands() {
+        let scanner = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = scanner.local_addr().unwrap();
+        let responder = Responder::new(discover::Response::new(
+            "00:1e:8f:01:02:03".parse().unwrap(),
+            addr.ip(),
+        ));
+        tokio::spawn(async move {
+            let mut buffer = [0; 1024];
+            // the first command is lost
+            scanner.recv_from(&mut buffer).await.unwrap();
+            let (size, peer) = scanner.recv_from(&mut buffer).await.unwrap();
+            let response = responder.respond(&buffer[..size]).unwrap().unwrap();
+            scanner
+                .send_to(&response.serialize_to_vec(), peer)
+                .await
+                .unwrap();
+        });
+
+        let mut channel = Channel::new(addr, &SocketOptions::default()).await.unwrap();
+        let max_waiting = Duration::from_millis(200);
+        let response = channel
+            .roundtrip_with_timeout(discover::Request::new(), max_waiting, 1)
+            .await
+            .unwrap();
+        assert_eq!(*response.ip_addr(), addr.ip());
+        let e = channel
+            .roundtrip_with_timeout(discover::Request::new(), max_waiting, 0)
+            .await
+            .unwrap_err();
+        assert!(e.chain().any(|cause| cause.is::<Elapsed>()));
     }
 }
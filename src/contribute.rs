@@ -0,0 +1,259 @@
+//! Collects a report about a scanner for bug reports on unsupported models:
+//! the model, and every packet exchanged while discovering, identifying and
+//! polling it, with MAC addresses and serial numbers redacted.
+
+use std::{
+    fmt::Display,
+    fs,
+    io::{self, Write},
+    net::SocketAddr,
+    path::PathBuf,
+};
+
+use anyhow::{anyhow, Context};
+use bjnp::{
+    capabilities::Capabilities, discover, identity, poll, serdes::Deserialize, validate, Host,
+    Packet, PacketHeaderOnly, Request,
+};
+use time::{OffsetDateTime, PrimitiveDateTime};
+use tokio::{
+    sync::mpsc::{unbounded_channel, UnboundedReceiver},
+    time::{sleep, Duration, Instant},
+};
+use tracing::{debug, warn};
+
+use crate::{channel::Channel, dump::Direction, redact, utils::SocketOptions};
+
+/// Host registered on the scanner while tracing, in place of the name of
+/// this machine
+const TRACE_HOST: &str = "scanner-button";
+
+#[derive(Debug)]
+pub struct ContributeConfig {
+    pub scanner_addr: SocketAddr,
+    pub socket_options: SocketOptions,
+    pub max_waiting: Duration,
+    /// Number of full polls after registering
+    pub polls: u32,
+    pub output: PathBuf,
+}
+
+/// A packet as sent or received, after redaction
+#[derive(Debug)]
+struct Record {
+    at: Duration,
+    direction: Direction,
+    bytes: Vec<u8>,
+    decoded: String,
+}
+
+/// Exchanges packets with a scanner, recording all of them.
+struct Recorder {
+    channel: Channel,
+    tapped: UnboundedReceiver<(Instant, Direction, Vec<u8>)>,
+    max_waiting: Duration,
+    started: Instant,
+    records: Vec<Record>,
+}
+
+impl Recorder {
+    fn record(&mut self, at: Instant, direction: Direction, bytes: Vec<u8>, decoded: String) {
+        self.records.push(Record {
+            at: at - self.started,
+            direction,
+            bytes,
+            decoded,
+        });
+    }
+
+    async fn exchange<R>(&mut self, request: R) -> anyhow::Result<R::Response>
+    where
+        R: Request + Deserialize + Display + Clone,
+        R::Response: Display,
+    {
+        let result = self
+            .channel
+            .roundtrip_with_timeout(request, self.max_waiting, 0)
+            .await;
+        while let Ok((at, direction, bytes)) = self.tapped.try_recv() {
+            let decoded = match (direction, &result) {
+                (Direction::Outbound, _) => decode::<R>(&bytes),
+                (Direction::Inbound, Ok(_)) => decode::<R::Response>(&bytes),
+                (Direction::Inbound, Err(e)) => format!("<{e:#}>"),
+            };
+            let bytes = match direction {
+                Direction::Outbound => bytes,
+                Direction::Inbound => redact::packet(&bytes),
+            };
+            self.record(at, direction, bytes, decoded);
+        }
+        result
+    }
+}
+
+/// Describes a packet of `T` as logged.
+fn decode<T: Deserialize + Display>(bytes: &[u8]) -> String {
+    let packet = PacketHeaderOnly::parse(bytes)
+        .map_err(anyhow::Error::from)
+        .and_then(|packet| Ok(Packet::<T>::try_from(packet)?));
+    match packet {
+        Ok(packet) => format!("{packet:-}"),
+        Err(e) => format!("<{e:#}>"),
+    }
+}
+
+/// Discovers, identifies and polls the scanner, then writes the report even
+/// if a step failed, which is the most useful report.
+pub async fn contribute_trace(config: ContributeConfig) -> anyhow::Result<()> {
+    debug!("loaded contribute-trace config {config:?}");
+
+    let (tap, tapped) = unbounded_channel();
+    let mut channel = Channel::new(config.scanner_addr, &config.socket_options).await?;
+    channel.set_tap(tap);
+    let mut recorder = Recorder {
+        channel,
+        tapped,
+        max_waiting: config.max_waiting,
+        started: Instant::now(),
+        records: vec![],
+    };
+    let mut identity = None;
+    let result = trace(&mut recorder, &config, &mut identity).await;
+    if let Err(e) = &result {
+        warn!("{e:#}");
+    }
+
+    let report = report(&recorder.records, identity.as_ref(), result.err());
+    fs::write(&config.output, report)
+        .with_context(|| format!("couldn't write report to {}", config.output.display()))?;
+    writeln!(
+        io::stdout(),
+        "wrote report to {}, please review it before attaching it to an issue",
+        config.output.display()
+    )
+    .context("failed to write to stdout")?;
+    Ok(())
+}
+
+async fn trace(
+    recorder: &mut Recorder,
+    config: &ContributeConfig,
+    identity: &mut Option<identity::Response>,
+) -> anyhow::Result<()> {
+    recorder.exchange(discover::Request::new()).await?;
+    *identity = Some(recorder.exchange(identity::Request::new()).await?);
+
+    let host = Host::new(TRACE_HOST);
+    let command = poll::CommandBuilder::new(poll::PollType::HostOnly)
+        .host(host)
+        .build()
+        .unwrap();
+    let resp = recorder.exchange(command).await?;
+    let mut session_id = resp
+        .session_id()
+        .ok_or_else(|| anyhow!("unexpected interrupt during host registration"))?;
+    writeln!(
+        io::stdout(),
+        "registered as `{TRACE_HOST}`, select it on the scanner and press the scan button \
+        to include a scan job"
+    )
+    .context("failed to write to stdout")?;
+
+    for _ in 0..config.polls {
+        sleep(Duration::from_secs(1)).await;
+        // UTC rather than local time, which would reveal the time zone
+        let now = OffsetDateTime::now_utc();
+        let command = poll::CommandBuilder::new(poll::PollType::Full)
+            .host(host)
+            .session_id(session_id)
+            .datetime(
+                PrimitiveDateTime::new(now.date(), now.time())
+                    .try_into()
+                    .expect("current year fits in 4 digits"),
+            )
+            .build()
+            .unwrap();
+        let resp = recorder.exchange(command).await?;
+        if let Some(id) = resp.session_id() {
+            session_id = id;
+        }
+        if resp.status_flags().contains(poll::StatusFlags::INTERRUPT) {
+            let command = poll::CommandBuilder::new(poll::PollType::Reset)
+                .host(host)
+                .session_id(session_id)
+                .action_id(resp.action_id().unwrap_or(0))
+                .build()
+                .unwrap();
+            recorder.exchange(command).await?;
+        }
+    }
+
+    // remove `TRACE_HOST` from the scanner
+    let command = poll::CommandBuilder::new(poll::PollType::Empty)
+        .build()
+        .unwrap();
+    recorder.exchange(command).await?;
+    Ok(())
+}
+
+fn report(
+    records: &[Record],
+    identity: Option<&identity::Response>,
+    error: Option<anyhow::Error>,
+) -> String {
+    let mut report = String::new();
+    let mut line = |text: String| {
+        report.push_str(&text);
+        report.push('\n');
+    };
+
+    line(format!(
+        "# scanner-button {} trace report",
+        env!("CARGO_PKG_VERSION")
+    ));
+    line(format!("os: {}", std::env::consts::OS));
+    match identity {
+        Some(identity) => {
+            line(format!(
+                "model: {}",
+                identity.get("MDL").unwrap_or("<unknown>")
+            ));
+            let mut entries: Vec<_> = identity.iter().collect();
+            entries.sort();
+            for (key, value) in entries {
+                line(format!("identity: {key}={value}"));
+            }
+            line(format!(
+                "capabilities: {}",
+                Capabilities::from_identity(identity)
+            ));
+        }
+        None => line("model: <unknown>".to_owned()),
+    }
+    if let Some(error) = error {
+        line(format!("error: {error:#}"));
+    }
+
+    line(String::new());
+    line("## packets".to_owned());
+    for record in records {
+        let arrow = match record.direction {
+            Direction::Outbound => "->",
+            Direction::Inbound => "<-",
+        };
+        line(format!(
+            "\n[+{:.3}s] {arrow} {}",
+            record.at.as_secs_f64(),
+            record.decoded
+        ));
+        // unusual fields, as comments once pasted into test vectors
+        for diagnostic in validate::validate(&record.bytes) {
+            line(format!("# {diagnostic}"));
+        }
+        for (i, chunk) in record.bytes.chunks(16).enumerate() {
+            let hex: Vec<_> = chunk.iter().map(|b| format!("{b:02x}")).collect();
+            line(format!("{:04x}: {}", i * 16, hex.join(" ")));
+        }
+    }
+    report
+}
@@ -0,0 +1,244 @@
+//! Writes a report when the program panics, with the panic and its backtrace,
+//! the last state of each listener and the last datagrams exchanged, so that
+//! rare panics triggered by scanners can be reported with enough detail to
+//! reproduce them. MAC addresses and serial numbers are masked in datagrams,
+//! and with `--redact` the names of hosts as well.
+
+use std::{
+    backtrace::Backtrace,
+    collections::{BTreeMap, VecDeque},
+    fs,
+    io::IoSlice,
+    net::SocketAddr,
+    panic::{self, PanicHookInfo},
+    path::PathBuf,
+    process,
+    sync::{Mutex, MutexGuard, TryLockError},
+};
+
+use time::OffsetDateTime;
+use tokio::task::JoinError;
+
+use crate::{dump::Direction, redact};
+
+/// Exit status after a panic, `EX_SOFTWARE` from `sysexits.h`, so that
+/// supervisors tell crashes apart from errors (1)
+pub const EXIT_CODE: i32 = 70;
+
+/// Number of datagrams kept for the report
+const KEPT_PACKETS: usize = 32;
+
+/// Number of bytes kept of each datagram, enough for the commands and
+/// responses of the protocol but not for the data of jobs
+const KEPT_BYTES: usize = 512;
+
+#[derive(Debug)]
+struct Packet {
+    at: OffsetDateTime,
+    direction: Direction,
+    peer: SocketAddr,
+    /// Length of the whole datagram
+    len: usize,
+    bytes: [u8; KEPT_BYTES],
+}
+
+impl Packet {
+    /// Copies the first [`KEPT_BYTES`] of the datagram made of `slices`,
+    /// without allocating.
+    fn new(
+        at: OffsetDateTime,
+        direction: Direction,
+        peer: SocketAddr,
+        slices: &[IoSlice<'_>],
+    ) -> Self {
+        let mut packet = Packet {
+            at,
+            direction,
+            peer,
+            len: 0,
+            bytes: [0; KEPT_BYTES],
+        };
+        for slice in slices {
+            let start = packet.len.min(KEPT_BYTES);
+            let n = slice.len().min(KEPT_BYTES - start);
+            packet.bytes[start..start + n].copy_from_slice(&slice[..n]);
+            packet.len += slice.len();
+        }
+        packet
+    }
+
+    fn kept(&self) -> &[u8] {
+        &self.bytes[..self.len.min(KEPT_BYTES)]
+    }
+}
+
+/// Last datagrams exchanged, oldest first
+static PACKETS: Mutex<VecDeque<Packet>> = Mutex::new(VecDeque::new());
+
+/// Last state of each listener by scanner and host
+static STATES: Mutex<BTreeMap<(SocketAddr, String), String>> = Mutex::new(BTreeMap::new());
+
+/// Writes a report into `dir` on panic from now on, before the panic is
+/// handled as it was, e.g. printed by the default hook.
+pub fn install(dir: PathBuf) {
+    let previous = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        let report = report(info, &Backtrace::force_capture());
+        let now = OffsetDateTime::now_utc();
+        let path = dir.join(format!(
+            "scanner-button-crash-{}-{}.txt",
+            now.unix_timestamp(),
+            process::id()
+        ));
+        match fs::write(&path, &report) {
+            Ok(()) => eprintln!(
+                "crash report written to {}, review it before attaching it to an issue",
+                path.display()
+            ),
+            Err(e) => eprintln!(
+                "couldn't write crash report to {}: {e}\n{report}",
+                path.display()
+            ),
+        }
+        previous(info);
+    }));
+}
+
+/// Returns whether `error` stems from a task which panicked.
+pub fn is_panic(error: &anyhow::Error) -> bool {
+    error.chain().any(|cause| {
+        cause
+            .downcast_ref::<JoinError>()
+            .is_some_and(JoinError::is_panic)
+    })
+}
+
+/// Keeps the start of a datagram exchanged with `peer` for the report.
+pub fn packet(direction: Direction, peer: SocketAddr, slices: &[IoSlice<'_>]) {
+    let packet = Packet::new(OffsetDateTime::now_utc(), direction, peer, slices);
+    keep(&mut PACKETS.lock().unwrap(), packet);
+}
+
+fn keep(packets: &mut VecDeque<Packet>, packet: Packet) {
+    if packets.len() == KEPT_PACKETS {
+        packets.pop_front();
+    }
+    packets.push_back(packet);
+}
+
+/// Keeps the state of the listener of `host` on `scanner` for the report.
+pub fn state(scanner: SocketAddr, host: &str, state: String) {
+    STATES
+        .lock()
+        .unwrap()
+        .insert((scanner, host.to_owned()), state);
+}
+
+/// Locks `mutex` unless the panicking thread holds it, which would never
+/// release it.
+fn try_lock<T>(mutex: &Mutex<T>) -> Option<MutexGuard<'_, T>> {
+    match mutex.try_lock() {
+        Ok(guard) => Some(guard),
+        Err(TryLockError::Poisoned(e)) => Some(e.into_inner()),
+        Err(TryLockError::WouldBlock) => None,
+    }
+}
+
+fn report(info: &PanicHookInfo<'_>, backtrace: &Backtrace) -> String {
+    let mut report = String::new();
+    let mut line = |text: String| {
+        report.push_str(&text);
+        report.push('\n');
+    };
+
+    line(format!(
+        "# scanner-button {} crash report",
+        env!("CARGO_PKG_VERSION")
+    ));
+    line(format!("os: {}", std::env::consts::OS));
+    line(format!("panic: {}", redact::line(&info.to_string())));
+    line(String::new());
+    line("## backtrace".to_owned());
+    line(backtrace.to_string());
+
+    line("## listeners".to_owned());
+    match try_lock(&STATES) {
+        Some(states) => {
+            for ((scanner, host), state) in states.iter() {
+                line(redact::line(&format!("{host} on {scanner}: {state}")).into_owned());
+            }
+        }
+        None => line("<unavailable>".to_owned()),
+    }
+
+    line(String::new());
+    line("## packets".to_owned());
+    match try_lock(&PACKETS) {
+        Some(packets) => {
+            for packet in packets.iter() {
+                let arrow = match packet.direction {
+                    Direction::Outbound => "->",
+                    Direction::Inbound => "<-",
+                };
+                line(format!("\n[{}] {arrow} {}", packet.at, packet.peer));
+                let bytes = redact::packet(&redact::logged_packet(packet.kept()));
+                for (i, chunk) in bytes.chunks(16).enumerate() {
+                    let hex: Vec<_> = chunk.iter().map(|b| format!("{b:02x}")).collect();
+                    line(format!("{:04x}: {}", i * 16, hex.join(" ")));
+                }
+                if packet.len > KEPT_BYTES {
+                    line(format!("... {} more bytes", packet.len - KEPT_BYTES));
+                }
+            }
+        }
+        None => line("<unavailable>".to_owned()),
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_last_packets() {
+        let mut packets = VecDeque::new();
+        for i in 0..KEPT_PACKETS + 2 {
+            let packet = Packet::new(
+                OffsetDateTime::UNIX_EPOCH,
+                Direction::Inbound,
+                "192.168.1.20:8612".parse().unwrap(),
+                &[IoSlice::new(&[i as u8])],
+            );
+            keep(&mut packets, packet);
+        }
+        assert_eq!(packets.len(), KEPT_PACKETS);
+        assert_eq!(packets.front().unwrap().kept(), [2]);
+        assert_eq!(packets.back().unwrap().kept(), [KEPT_PACKETS as u8 + 1]);
+    }
+
+    #[test]
+    fn keeps_start_of_vectored_packets() {
+        let header = [1; 16];
+        let payload = [2; KEPT_BYTES];
+        let packet = Packet::new(
+            OffsetDateTime::UNIX_EPOCH,
+            Direction::Outbound,
+            "192.168.1.20:8612".parse().unwrap(),
+            &[IoSlice::new(&header), IoSlice::new(&payload)],
+        );
+        assert_eq!(packet.len, 16 + KEPT_BYTES);
+        assert_eq!(packet.kept()[..16], header);
+        assert_eq!(packet.kept()[16..], payload[..KEPT_BYTES - 16]);
+    }
+
+    #[tokio::test]
+    async fn detects_panicked_task() {
+        use anyhow::Context;
+
+        let joined = tokio::spawn(async { panic!("boom") }).await;
+        let e = joined.context("failed to join task").unwrap_err();
+        assert!(is_panic(&e.context("listener stopped")));
+        assert!(!is_panic(&anyhow::anyhow!("socket closed")));
+    }
+}
@@ -0,0 +1,187 @@
+//! Captures every datagram sent or received into a directory, independently
+//! of the verbosity of logs, for replaying or inspecting the exchanges later.
+//!
+//! With [`DumpFormat::Files`], each datagram is written as is to its own file
+//! named after the time, a counter, the direction and the peer, e.g.
+//! `20261016T195645.767208Z-000001-out-192.168.1.20_8612.bin`.
+//!
+//! With [`DumpFormat::Stream`], datagrams are appended to `packets.bin` as
+//! records of, in big endian,
+//! - the time in microseconds since the Unix epoch (`u64`),
+//! - the direction, 0 for inbound and 1 for outbound (`u8`),
+//! - the length of the peer address (`u8`) followed by the address as text,
+//! - the length of the datagram (`u32`) followed by the datagram.
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, IoSlice, Write},
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::{Mutex, OnceLock},
+};
+
+use clap::ValueEnum;
+use time::OffsetDateTime;
+use tracing::warn;
+
+use crate::crash;
+
+/// Name of the stream in the dump directory
+const STREAM_NAME: &str = "packets.bin";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum DumpFormat {
+    /// A file per datagram
+    Files,
+    /// A single append-only file of length-prefixed records
+    Stream,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Inbound = 0,
+    Outbound = 1,
+}
+
+#[derive(Debug)]
+enum Sink {
+    Files { dir: PathBuf, count: Mutex<u64> },
+    Stream(Mutex<File>),
+}
+
+static SINK: OnceLock<Sink> = OnceLock::new();
+
+/// Dumps datagrams into `dir` from now on, creating it if needed.
+pub fn init(dir: &Path, format: DumpFormat) -> io::Result<()> {
+    fs::create_dir_all(dir)?;
+    let sink = match format {
+        DumpFormat::Files => Sink::Files {
+            dir: dir.to_owned(),
+            count: Mutex::new(0),
+        },
+        DumpFormat::Stream => Sink::Stream(Mutex::new(
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(dir.join(STREAM_NAME))?,
+        )),
+    };
+    SINK.set(sink)
+        .map_err(|_| io::Error::other("packets are already dumped"))
+}
+
+/// Dumps a datagram exchanged with `peer`, if dumping is enabled, and keeps
+/// it for crash reports.
+pub fn packet(direction: Direction, peer: SocketAddr, buffer: &[u8]) {
+    packet_vectored(direction, peer, &[IoSlice::new(buffer)]);
+}
+
+/// Dumps a datagram sent to `peer` by a vectored write as [`packet`]. The
+/// slices are only concatenated into a stream record.
+pub fn packet_vectored(direction: Direction, peer: SocketAddr, slices: &[IoSlice<'_>]) {
+    if let Some(sink) = SINK.get() {
+        let at = OffsetDateTime::now_utc();
+        if let Err(e) = sink.write(at, direction, peer, slices) {
+            warn!("couldn't dump packet with {peer}: {e}");
+        }
+    }
+    crash::packet(direction, peer, slices);
+}
+
+impl Sink {
+    fn write(
+        &self,
+        at: OffsetDateTime,
+        direction: Direction,
+        peer: SocketAddr,
+        slices: &[IoSlice<'_>],
+    ) -> io::Result<()> {
+        match self {
+            Sink::Files { dir, count } => {
+                let n = {
+                    let mut count = count.lock().unwrap();
+                    *count += 1;
+                    *count
+                };
+                let mut file = File::create(dir.join(file_name(at, n, direction, peer)))?;
+                slices.iter().try_for_each(|slice| file.write_all(slice))
+            }
+            Sink::Stream(file) => file
+                .lock()
+                .unwrap()
+                .write_all(&record(at, direction, peer, slices)),
+        }
+    }
+}
+
+fn file_name(at: OffsetDateTime, n: u64, direction: Direction, peer: SocketAddr) -> String {
+    let direction = match direction {
+        Direction::Inbound => "in",
+        Direction::Outbound => "out",
+    };
+    // `:` and `%` aren't allowed in file names on Windows
+    let peer: String = peer
+        .to_string()
+        .chars()
+        .filter(|c| !matches!(c, '[' | ']'))
+        .map(|c| if matches!(c, ':' | '%') { '_' } else { c })
+        .collect();
+    format!(
+        "{:04}{:02}{:02}T{:02}{:02}{:02}.{:06}Z-{n:06}-{direction}-{peer}.bin",
+        at.year(),
+        at.month() as u8,
+        at.day(),
+        at.hour(),
+        at.minute(),
+        at.second(),
+        at.microsecond(),
+    )
+}
+
+fn record(
+    at: OffsetDateTime,
+    direction: Direction,
+    peer: SocketAddr,
+    slices: &[IoSlice<'_>],
+) -> Vec<u8> {
+    let micros = (at.unix_timestamp_nanos() / 1000) as u64;
+    let peer = peer.to_string();
+    let len: usize = slices.iter().map(|slice| slice.len()).sum();
+    let mut record = Vec::with_capacity(8 + 1 + 1 + peer.len() + 4 + len);
+    record.extend_from_slice(&micros.to_be_bytes());
+    record.push(direction as u8);
+    record.push(peer.len() as u8);
+    record.extend_from_slice(peer.as_bytes());
+    record.extend_from_slice(&(len as u32).to_be_bytes());
+    for slice in slices {
+        record.extend_from_slice(slice);
+    }
+    record
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dump_names_and_records() {
+        let at = OffsetDateTime::from_unix_timestamp_nanos(1_792_180_605_767_208_000).unwrap();
+        let peer: SocketAddr = "[fe80::1%2]:8612".parse().unwrap();
+        assert_eq!(
+            file_name(at, 1, Direction::Outbound, peer),
+            "20261016T195645.767208Z-000001-out-fe80__1_2_8612.bin"
+        );
+
+        let peer: SocketAddr = "192.168.1.20:8612".parse().unwrap();
+        let record = record(
+            at,
+            Direction::Inbound,
+            peer,
+            &[IoSlice::new(b"BJ"), IoSlice::new(b"NP")],
+        );
+        assert_eq!(record[..8], 1_792_180_605_767_208u64.to_be_bytes());
+        assert_eq!(record[8..10], [0, 17]);
+        assert_eq!(&record[10..27], b"192.168.1.20:8612");
+        assert_eq!(record[27..], *b"\x00\x00\x00\x04BJNP");
+    }
+}
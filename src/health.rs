@@ -0,0 +1,48 @@
+//! Checks the health of listeners from their state file, so that container
+//! health checks can run the same binary as the listener.
+
+use std::path::Path;
+
+use time::OffsetDateTime;
+
+use crate::state::StateStore;
+
+/// Prints the current outcome of polls of every listener recorded in the state
+/// file at `path`, and returns whether all of them last polled their scanner
+/// successfully, and recently enough if they are polling.
+pub fn check(path: &Path) -> anyhow::Result<bool> {
+    let listeners = StateStore::load(path)?.all();
+    if listeners.is_empty() {
+        println!("no listener recorded in {}", path.display());
+        return Ok(false);
+    }
+
+    let now = OffsetDateTime::now_utc().unix_timestamp();
+    let mut healthy = true;
+    for (scanner, host, state) in listeners {
+        if let Some(elapsed) = state.overdue(now) {
+            healthy = false;
+            println!("{host} on {scanner}: stalled, last polled successfully {elapsed}s ago");
+            continue;
+        }
+        match state.history.last() {
+            Some(last) if last.error.is_none() => {
+                let status = last.status.unwrap_or_default();
+                println!(
+                    "{host} on {scanner}: polling, status {status:#x} since {}",
+                    last.at
+                );
+            }
+            Some(last) => {
+                healthy = false;
+                let error = last.error.as_deref().unwrap_or_default();
+                println!("{host} on {scanner}: failing since {}: {error}", last.at);
+            }
+            None => {
+                healthy = false;
+                println!("{host} on {scanner}: not polled yet");
+            }
+        }
+    }
+    Ok(healthy)
+}
@@ -0,0 +1,103 @@
+//! Advisory locks preventing several processes from listening on the same
+//! scanner, which would fight over session ids and steal each other's scan
+//! jobs.
+//!
+//! Scanners are identified by their MAC address, so that the same scanner is
+//! recognized behind different addresses.
+
+use std::{
+    collections::HashMap,
+    fs::{self, File, OpenOptions, TryLockError},
+    io::Write,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use bjnp::discover::MacAddr;
+use thiserror::Error;
+use tracing::{debug, warn};
+
+#[derive(Error, Debug)]
+#[error("scanner {mac_addr} is already listened on by {owner}, pass `--force` to listen anyway")]
+pub struct AlreadyLocked {
+    mac_addr: MacAddr,
+    owner: String,
+}
+
+/// Locks held by this process, shared by all its listeners
+#[derive(Debug, Clone)]
+pub struct ScannerLocks {
+    force: bool,
+    held: Arc<Mutex<HashMap<MacAddr, File>>>,
+}
+
+impl ScannerLocks {
+    /// Creates the locks, or a no-op if `force` is set.
+    pub fn new(force: bool) -> Self {
+        Self {
+            force,
+            held: Arc::default(),
+        }
+    }
+
+    /// Locks the scanner for this process, failing with [`AlreadyLocked`] if
+    /// another process holds the lock. Locking is best effort, a lock file
+    /// which can't be opened is only logged.
+    pub fn acquire(&self, mac_addr: &MacAddr) -> Result<(), AlreadyLocked> {
+        if self.force {
+            return Ok(());
+        }
+        let mut held = self.held.lock().unwrap();
+        if held.contains_key(mac_addr) {
+            return Ok(());
+        }
+
+        let path = lock_path(mac_addr);
+        let mut file = match OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+        {
+            Ok(file) => file,
+            Err(e) => {
+                warn!("couldn't open lock file {}: {e}", path.display());
+                return Ok(());
+            }
+        };
+        match file.try_lock() {
+            Ok(()) => {
+                debug!("locked scanner {mac_addr} with {}", path.display());
+                // the process id only helps to identify the owner
+                let _ = file
+                    .set_len(0)
+                    .and_then(|_| writeln!(file, "{}", std::process::id()));
+                held.insert(*mac_addr, file);
+                Ok(())
+            }
+            Err(TryLockError::WouldBlock) => {
+                let owner = fs::read_to_string(&path)
+                    .ok()
+                    .and_then(|pid| pid.trim().parse::<u32>().ok())
+                    .map_or_else(
+                        || "another process".to_owned(),
+                        |pid| format!("process {pid}"),
+                    );
+                Err(AlreadyLocked {
+                    mac_addr: *mac_addr,
+                    owner,
+                })
+            }
+            Err(TryLockError::Error(e)) => {
+                warn!("couldn't lock {}: {e}", path.display());
+                Ok(())
+            }
+        }
+    }
+}
+
+fn lock_path(mac_addr: &MacAddr) -> PathBuf {
+    let name = mac_addr.to_string().replace(':', "-");
+    std::env::temp_dir().join(format!("scanner-button-{name}.lock"))
+}
@@ -0,0 +1,194 @@
+//! A log file rotated by size or period, keeping a bounded number of rotated
+//! files next to it, for long running listeners without a system journal.
+//!
+//! Rotated files are named after the log file with a number appended, the
+//! most recent being `.1`, e.g. `scanner-button.log.1`.
+
+use std::{
+    ffi::OsString,
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use clap::ValueEnum;
+use tracing_subscriber::fmt::MakeWriter;
+
+/// Period after which the log file is rotated
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Period {
+    Never,
+    Hourly,
+    Daily,
+}
+
+impl Period {
+    /// Number of the period `time` falls in, in UTC
+    fn index(self, time: SystemTime) -> u64 {
+        let secs = time
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs())
+            .unwrap_or(0);
+        match self {
+            Period::Never => 0,
+            Period::Hourly => secs / 3600,
+            Period::Daily => secs / 86400,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Rotation {
+    /// Size in bytes beyond which the file is rotated, if any
+    pub max_size: Option<u64>,
+    pub period: Period,
+    /// Number of rotated files kept
+    pub keep: usize,
+}
+
+#[derive(Debug)]
+struct Current {
+    /// `None` once rotated, until reopened by the next write
+    file: Option<File>,
+    size: u64,
+    period: u64,
+}
+
+/// A log file appended to by every event, rotated as configured
+#[derive(Debug)]
+pub struct RotatingFile {
+    path: PathBuf,
+    rotation: Rotation,
+    current: Mutex<Current>,
+}
+
+impl RotatingFile {
+    /// Opens the log file at `path` for appending, continuing its period if
+    /// it exists.
+    pub fn open(path: &Path, rotation: Rotation) -> io::Result<Self> {
+        let file = append(path)?;
+        let metadata = file.metadata()?;
+        let modified = metadata.modified().unwrap_or_else(|_| SystemTime::now());
+        Ok(Self {
+            path: path.to_owned(),
+            rotation,
+            current: Mutex::new(Current {
+                file: Some(file),
+                size: metadata.len(),
+                period: rotation.period.index(modified),
+            }),
+        })
+    }
+
+    fn numbered(&self, n: usize) -> PathBuf {
+        let mut path = OsString::from(&self.path);
+        path.push(format!(".{n}"));
+        path.into()
+    }
+
+    /// Shifts the rotated files, dropping the oldest, so that the next write
+    /// starts a new file.
+    fn rotate(&self, current: &mut Current) -> io::Result<()> {
+        if self.rotation.keep == 0 {
+            if let Some(file) = &current.file {
+                file.set_len(0)?;
+            }
+        } else {
+            // closed before renaming, which fails on Windows while it is open
+            current.file = None;
+            match fs::remove_file(self.numbered(self.rotation.keep)) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+            for n in (1..self.rotation.keep).rev() {
+                let from = self.numbered(n);
+                if from.exists() {
+                    fs::rename(&from, self.numbered(n + 1))?;
+                }
+            }
+            fs::rename(&self.path, self.numbered(1))?;
+        }
+        current.size = 0;
+        Ok(())
+    }
+
+    fn write_at(&self, buf: &[u8], now: SystemTime) -> io::Result<usize> {
+        let mut current = self.current.lock().unwrap();
+        let period = self.rotation.period.index(now);
+        let full = self
+            .rotation
+            .max_size
+            .is_some_and(|max_size| current.size + buf.len() as u64 > max_size);
+        if current.size > 0 && (full || period != current.period) {
+            self.rotate(&mut current)?;
+        }
+        current.period = period;
+        let file = match current.file.take() {
+            Some(file) => file,
+            None => append(&self.path)?,
+        };
+        current.file.insert(file).write_all(buf)?;
+        current.size += buf.len() as u64;
+        Ok(buf.len())
+    }
+}
+
+fn append(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+impl Write for &RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.write_at(buf, SystemTime::now())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.current.lock().unwrap().file {
+            Some(file) => file.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl<'a> MakeWriter<'a> for RotatingFile {
+    type Writer = &'a RotatingFile;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn rotate_by_size_and_period() {
+        let dir = std::env::temp_dir().join(format!("scanner-button-log-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("scanner-button.log");
+        let rotation = Rotation {
+            max_size: Some(8),
+            period: Period::Daily,
+            keep: 2,
+        };
+        let log = RotatingFile::open(&path, rotation).unwrap();
+        let day = UNIX_EPOCH + Duration::from_secs(86400 * 10_000);
+        for line in ["a\n", "bbbbbb\n", "c\n", "d\n", "e\n"] {
+            log.write_at(line.as_bytes(), day).unwrap();
+        }
+        log.write_at(b"f\n", day + Duration::from_secs(86400))
+            .unwrap();
+
+        let read = |path: PathBuf| fs::read_to_string(path).unwrap();
+        assert_eq!(read(path.clone()), "f\n");
+        assert_eq!(read(log.numbered(1)), "c\nd\ne\n");
+        assert_eq!(read(log.numbered(2)), "bbbbbb\n");
+        assert!(!log.numbered(3).exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
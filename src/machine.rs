@@ -0,0 +1,235 @@
+//! State machine of a listener, as a pure function from a state and what
+//! happened in it to the next state, so that every transition can be tested
+//! without a scanner. The listener in [`crate::poll`] drives it, talking to the
+//! scanner in each state and carrying out the actions of transitions.
+
+use std::{
+    cmp,
+    fmt::{self, Display},
+};
+
+use tokio::time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum State {
+    Init,
+    Poll,
+    /// Waiting for the given delay before retrying initialization
+    Backoff(Duration),
+    /// Waiting for the given delay until active hours
+    Quiet(Duration),
+    /// Scanner answering discover commands but not polls, as in deep sleep,
+    /// probed at a fixed interval rather than backing off
+    Asleep,
+}
+
+/// What happened to a listener in its current state
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Input {
+    /// The work of the state is done
+    Succeeded,
+    /// The work of the state failed, with `asleep` set if the scanner answered
+    /// discover commands but timed out afterwards
+    Failed { asleep: bool },
+    /// Active hours start in the given delay
+    OutsideActiveHours(Duration),
+    /// The system resumed from suspend
+    Resumed,
+    /// The network configuration of the system changed
+    NetworkChanged,
+}
+
+/// Notable transition of a listener, logged and reported to
+/// `ListenConfig::events`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    /// Polling after initializing
+    Connected,
+    /// Polling failed, initializing again
+    Disconnected,
+    /// Polling again after backing off
+    Reconnected,
+    /// Scanner answering discover commands but not polls
+    Asleep,
+    /// Polling again after the scanner slept
+    WokeUp,
+    /// Polling paused for the given delay until active hours
+    Paused(Duration),
+    /// Polling again as active hours started
+    Unpaused,
+}
+
+impl Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Connected => write!(f, "connected"),
+            Self::Disconnected => write!(f, "disconnected"),
+            Self::Reconnected => write!(f, "reconnected"),
+            Self::Asleep => write!(f, "scanner asleep"),
+            Self::WokeUp => write!(f, "scanner woke up"),
+            Self::Paused(dur) => write!(f, "paused for {}s until active hours", dur.as_secs()),
+            Self::Unpaused => write!(f, "active hours started"),
+        }
+    }
+}
+
+/// Delays before initializing again after failures, from `initial` growing by
+/// `factor` up to `maximum`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Backoff {
+    pub initial: Duration,
+    pub factor: f32,
+    pub maximum: Duration,
+}
+
+/// Returns the state following `input` in `state`, along with the action of
+/// the transition if notable.
+pub fn next(state: State, input: Input, backoff: &Backoff) -> (State, Option<Action>) {
+    match (state, input) {
+        (State::Init, Input::Succeeded) => (State::Poll, Some(Action::Connected)),
+        (State::Poll, Input::Succeeded) => (State::Poll, None),
+        (State::Backoff(_), Input::Succeeded) => (State::Poll, Some(Action::Reconnected)),
+        // the registration may have expired meanwhile
+        (State::Quiet(_), Input::Succeeded) => (State::Init, Some(Action::Unpaused)),
+        (State::Asleep, Input::Succeeded) => (State::Poll, Some(Action::WokeUp)),
+
+        // a scanner in deep sleep answers discover commands but not polls
+        (State::Asleep, Input::Failed { asleep: true }) => (State::Asleep, None),
+        (State::Init | State::Backoff(_) | State::Quiet(_), Input::Failed { asleep: true }) => {
+            (State::Asleep, Some(Action::Asleep))
+        }
+        (State::Init, Input::Failed { .. }) => (State::Backoff(backoff.initial), None),
+        (State::Poll, Input::Failed { .. }) => (State::Init, Some(Action::Disconnected)),
+        (State::Backoff(dur), Input::Failed { .. }) => {
+            let dur = cmp::min(backoff.maximum, dur.mul_f32(backoff.factor));
+            (State::Backoff(dur), None)
+        }
+        (State::Quiet(_), Input::Failed { .. }) => (State::Init, None),
+        // not even answering discover commands
+        (State::Asleep, Input::Failed { .. }) => (State::Backoff(backoff.initial), None),
+
+        // the delay left, rather than the whole delay again after an interruption
+        (State::Quiet(_), Input::OutsideActiveHours(dur)) => (State::Quiet(dur), None),
+        (_, Input::OutsideActiveHours(dur)) => (State::Quiet(dur), Some(Action::Paused(dur))),
+        // whatever was awaited before suspend is stale
+        (_, Input::Resumed) => (State::Init, None),
+        // retry right away instead of waiting out the backoff
+        (State::Backoff(_), Input::NetworkChanged) => (State::Init, None),
+        (_, Input::NetworkChanged) => (state, None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BACKOFF: Backoff = Backoff {
+        initial: Duration::from_secs(1),
+        factor: 2.0,
+        maximum: Duration::from_secs(5),
+    };
+
+    const STATES: [State; 5] = [
+        State::Init,
+        State::Poll,
+        State::Backoff(Duration::from_secs(2)),
+        State::Quiet(Duration::from_secs(60)),
+        State::Asleep,
+    ];
+
+    const INPUTS: [Input; 6] = [
+        Input::Succeeded,
+        Input::Failed { asleep: false },
+        Input::Failed { asleep: true },
+        Input::OutsideActiveHours(Duration::from_secs(30)),
+        Input::Resumed,
+        Input::NetworkChanged,
+    ];
+
+    #[test]
+    fn backoff_grows_up_to_maximum() {
+        let failed = Input::Failed { asleep: false };
+        let mut state = State::Init;
+        let mut backoffs = vec![];
+        for _ in 0..5 {
+            state = next(state, failed, &BACKOFF).0;
+            match state {
+                State::Backoff(dur) => backoffs.push(dur.as_secs()),
+                state => panic!("backing off expected, got {state:?}"),
+            }
+        }
+        assert_eq!(backoffs, [1, 2, 4, 5, 5]);
+        assert_eq!(
+            next(state, Input::Succeeded, &BACKOFF),
+            (State::Poll, Some(Action::Reconnected))
+        );
+    }
+
+    #[test]
+    fn sleeping_scanner_probed_until_silent() {
+        let asleep = Input::Failed { asleep: true };
+        assert_eq!(
+            next(State::Poll, asleep, &BACKOFF),
+            (State::Init, Some(Action::Disconnected))
+        );
+        assert_eq!(
+            next(State::Init, asleep, &BACKOFF),
+            (State::Asleep, Some(Action::Asleep))
+        );
+        // reported once
+        assert_eq!(next(State::Asleep, asleep, &BACKOFF), (State::Asleep, None));
+        assert_eq!(
+            next(State::Asleep, Input::Failed { asleep: false }, &BACKOFF),
+            (State::Backoff(BACKOFF.initial), None)
+        );
+        assert_eq!(
+            next(State::Asleep, Input::Succeeded, &BACKOFF),
+            (State::Poll, Some(Action::WokeUp))
+        );
+    }
+
+    #[test]
+    fn every_transition() {
+        for state in STATES {
+            for input in INPUTS {
+                let (next_state, action) = next(state, input, &BACKOFF);
+                let transition = format!("{state:?} on {input:?}: {next_state:?}, {action:?}");
+                match input {
+                    Input::Succeeded => {
+                        assert!(
+                            matches!(next_state, State::Poll | State::Init),
+                            "{transition}"
+                        );
+                        assert_eq!(action.is_none(), state == State::Poll, "{transition}");
+                    }
+                    Input::Failed { .. } => {
+                        assert_ne!(next_state, State::Poll, "{transition}");
+                        assert_eq!(
+                            action == Some(Action::Disconnected),
+                            state == State::Poll,
+                            "{transition}"
+                        );
+                    }
+                    Input::OutsideActiveHours(dur) => match state {
+                        State::Quiet(_) => {
+                            assert_eq!((next_state, action), (State::Quiet(dur), None))
+                        }
+                        _ => assert_eq!(
+                            (next_state, action),
+                            (State::Quiet(dur), Some(Action::Paused(dur))),
+                            "{transition}"
+                        ),
+                    },
+                    Input::Resumed => assert_eq!((next_state, action), (State::Init, None)),
+                    Input::NetworkChanged => {
+                        assert_eq!(action, None, "{transition}");
+                        match state {
+                            State::Backoff(_) => assert_eq!(next_state, State::Init),
+                            _ => assert_eq!(next_state, state, "{transition}"),
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
@@ -1,18 +1,69 @@
 mod channel;
+mod contribute;
+mod crash;
+mod dump;
+mod health;
+mod lock;
+mod logfile;
+mod machine;
+#[cfg(feature = "tui")]
+mod monitor;
+mod network;
+#[cfg(feature = "notify")]
+mod notify;
+mod ping;
+#[cfg(target_os = "linux")]
+mod pktinfo;
 mod poll;
+mod privilege;
+mod proxy;
+mod quirks;
+mod redact;
+mod resolve;
 mod scan;
+mod schedule;
+mod selftest;
+mod service;
+mod setup;
+mod state;
+mod status;
+mod suspend;
+mod targets;
+mod title;
 mod utils;
+mod wol;
 
 use std::{
     cmp,
+    collections::HashSet,
+    env,
     ffi::OsString,
-    io,
-    net::{SocketAddr, ToSocketAddrs},
+    io::{self, IsTerminal},
+    iter,
+    net::{IpAddr, Ipv6Addr, SocketAddr, SocketAddrV6, ToSocketAddrs},
+    panic,
+    path::PathBuf,
+    process,
+    time::Duration,
 };
 
-use bjnp::Host;
+use anyhow::{anyhow, ensure, Context};
+use bjnp::{discover::MacAddr, Host};
 use clap::{Args, Parser, Subcommand};
+use encoding_rs::Encoding;
 use gethostname::gethostname;
+use time::UtcOffset;
+use tokio::sync::mpsc;
+use tracing_subscriber::{
+    filter::{LevelFilter, Targets},
+    fmt,
+    layer::SubscriberExt,
+    util::SubscriberInitExt,
+    Layer,
+};
+
+/// Bytes in a megabyte of `--log-max-size`
+const MEGABYTE: u64 = 1024 * 1024;
 
 #[derive(Parser)]
 #[command(author, version)]
@@ -31,6 +82,85 @@ struct Cli {
     )]
     max_waiting: u64,
 
+    /// TTL (IPv4) or hop limit (IPv6) of packets sent to scanners
+    #[arg(
+        global = true,
+        long,
+        value_name = "HOPS",
+        value_parser = clap::value_parser!(u32).range(1..=255),
+        display_order = 10
+    )]
+    ttl: Option<u32>,
+
+    /// DSCP value of packets sent to scanners (IPv4 only)
+    #[arg(
+        global = true,
+        long,
+        value_parser = clap::value_parser!(u8).range(0..64),
+        display_order = 11
+    )]
+    dscp: Option<u8>,
+
+    /// Also write messages to FILE, at the same verbosity even with `--quiet`
+    #[arg(global = true, long, value_name = "FILE", display_order = 12)]
+    log_file: Option<PathBuf>,
+
+    /// Rotate the log file once it would exceed SIZE megabytes
+    #[arg(
+        global = true,
+        long,
+        value_name = "SIZE",
+        default_value_t = 10,
+        value_parser = clap::value_parser!(u64).range(1..=u64::MAX / MEGABYTE),
+        display_order = 12
+    )]
+    log_max_size: u64,
+
+    /// Also rotate the log file every hour or day (in UTC)
+    #[arg(
+        global = true,
+        long,
+        value_name = "PERIOD",
+        default_value = "never",
+        display_order = 12
+    )]
+    log_rotate: logfile::Period,
+
+    /// Number of rotated log files to keep
+    #[arg(
+        global = true,
+        long,
+        value_name = "N",
+        default_value_t = 5,
+        display_order = 12
+    )]
+    log_keep: usize,
+
+    /// Mask host names, serial numbers and MAC addresses in logs, to share
+    /// them in bug reports
+    #[arg(global = true, long, display_order = 12)]
+    redact: bool,
+
+    /// Write every packet sent or received to DIR, whatever the verbosity
+    #[arg(global = true, long, value_name = "DIR", display_order = 13)]
+    dump_packets: Option<PathBuf>,
+
+    /// Write a file per packet, or append them all to DIR/packets.bin
+    #[arg(
+        global = true,
+        long,
+        value_name = "FORMAT",
+        default_value = "files",
+        display_order = 13
+    )]
+    dump_format: dump::DumpFormat,
+
+    /// Directory to write a report into if the program crashes, with the
+    /// state of listeners and the last packets [default: the temporary
+    /// directory]
+    #[arg(global = true, long, value_name = "DIR", display_order = 13)]
+    crash_dir: Option<PathBuf>,
+
     /// Verbosity of messages (use `-v`, `-vv`, `-vvv`... to increase verbosity)
     #[arg(
         global = true,
@@ -51,13 +181,31 @@ struct Cli {
 #[derive(Subcommand)]
 enum Commands {
     /// Listens on a scanner for scan button press and execute a command
-    Listen(Listen),
+    Listen(Box<Listen>),
     /// Scans for Canon multi-function printers in the LAN
-    Scan,
+    Scan(Scan),
+    /// Forwards BJNP traffic to a scanner on another network segment
+    Proxy(Proxy),
+    /// Measures round trip time and packet loss to a scanner
+    Ping(Ping),
+    /// Shows scanners, listeners, scan jobs and traffic in a dashboard
+    #[cfg(feature = "tui")]
+    Monitor(Monitor),
+    /// Collects a report about a scanner with redacted packet dumps, for
+    /// adding support of its model
+    ContributeTrace(ContributeTrace),
+    /// Exits with status 0 if every listener recorded in a state file last
+    /// polled its scanner successfully and recently, or 1 otherwise
+    Healthcheck(Healthcheck),
+    /// Finds a scanner and writes a configuration for listening on it,
+    /// asking along the way
+    Setup(Setup),
+    /// Installs a systemd unit running `listen`, for the system or the user
+    InstallService(InstallService),
 }
 
 static COMMAND_LONG_HELP: &str = "\
-Command to execute when scan button is pressed
+Command to execute when scan button is pressed, registered as `--hostname`. Only host entries from `--targets` are registered without it
 
 The configuration reported by the printer is passed to the executed command by environment variables:
   SCANNER_COLOR_MODE = COLOR | MONO
@@ -66,7 +214,12 @@ The configuration reported by the printer is passed to the executed command by e
   SCANNER_DPI        = 75 | 150 | 300 | 600
   SCANNER_SOURCE     = FLATBED | FEEDER
   SCANNER_ADF_TYPE   = SIMPLEX | DUPLEX
-  SCANNER_ADF_ORIENT = PORTRAIT | LANDSCAPE\
+  SCANNER_ADF_ORIENT = PORTRAIT | LANDSCAPE
+  SCANNER_TARGET     = <name of the host entry selected on the scanner>
+  SCANNER_JOB_ID     = <number of the scan job, shared by the batches of pages of a feeder job>
+  SCANNER_PAGE_BATCH = <number of the batch of pages within the job, from 1>
+
+Values of SCANNER_PAGE, SCANNER_FORMAT and SCANNER_DPI unknown to this program are passed as raw bytes in hex (e.g. 0x0c).\
 ";
 #[derive(Args)]
 struct Listen {
@@ -75,15 +228,56 @@ struct Listen {
         short,
         long,
         value_name = "ADDR",
-        value_parser = parse_addr,
+        value_parser = parse_scanner,
         display_order = 1
     )]
-    scanner: SocketAddr,
+    scanner: resolve::ScannerAddr,
 
-    /// Name of the host to be displayed on the scanner
-    #[arg(long, default_value_os_t = gethostname(), display_order = 2)]
+    /// Local address to send from, optionally with a fixed source port
+    #[arg(
+        long,
+        value_name = "ADDR[:PORT]",
+        value_parser = parse_bind,
+        display_order = 2
+    )]
+    bind: Option<SocketAddr>,
+
+    /// Name of the host to be displayed in the "Scan to PC" menu of the
+    /// scanner, where `{user}`, `{host}` and `{scanner}` are replaced by the
+    /// user name, the name of this machine and the IP address of the scanner
+    #[arg(
+        long,
+        visible_alias = "display-name",
+        default_value_os_t = gethostname(),
+        display_order = 2
+    )]
     hostname: OsString,
 
+    /// Encoding of a host name which isn't UTF-8, e.g. `shift_jis` [default:
+    /// the encoding of the locale]
+    #[arg(
+        long,
+        value_name = "LABEL",
+        value_parser = parse_encoding,
+        display_order = 2
+    )]
+    hostname_encoding: Option<&'static Encoding>,
+
+    /// Refuse to listen unless the model reported by the scanner matches
+    /// PATTERN, where `*` stands for any characters, e.g. `MX920*`
+    #[arg(long, value_name = "PATTERN", display_order = 3)]
+    expect_model: Option<String>,
+
+    /// Delay in seconds before the first retry of a failed connection
+    #[arg(
+        long,
+        value_name = "SECS",
+        default_value_t = 5,
+        value_parser = clap::value_parser!(u64).range(1..),
+        display_order = 4
+    )]
+    backoff_initial: u64,
+
     /// Exponential factor of backing off for retrying connection
     #[arg(
         long,
@@ -94,7 +288,7 @@ struct Listen {
     )]
     backoff_factor: f32,
 
-    /// Maximum max_waiting in seconds of backing off for retrying connection
+    /// Maximum delay in seconds of backing off for retrying connection
     #[arg(
         long,
         value_name = "SECS",
@@ -104,15 +298,412 @@ struct Listen {
     )]
     backoff_maximum: u64,
 
-    /// Command to execute when scan button is pressed
-    #[arg(long_help = COMMAND_LONG_HELP)]
-    command: OsString,
+    /// Interval in seconds to renew the registration of the host on the
+    /// scanner, which keeps it on the panel of scanners dropping idle hosts.
+    /// Defaults to the quirks of the model, or 600
+    #[arg(
+        long,
+        alias = "register-interval",
+        value_name = "SECS",
+        value_parser = clap::value_parser!(u64).range(1..),
+        display_order = 6
+    )]
+    keepalive_interval: Option<u64>,
+
+    /// Additional host entry to register on the scanner, executing COMMAND
+    /// (without arguments) when the entry is selected on the scanner. NAME
+    /// accepts the same placeholders as `--hostname`
+    #[arg(
+        long,
+        value_name = "NAME=COMMAND",
+        value_parser = parse_target,
+        display_order = 7
+    )]
+    target: Vec<(String, OsString)>,
+
+    /// TOML file of additional host entries to register on the scanner, each
+    /// executing its own command with arguments when selected on the scanner
+    #[arg(long, value_name = "FILE", display_order = 7)]
+    targets: Option<PathBuf>,
+
+    /// Ignore scan jobs arriving within SECS seconds after the last launched
+    /// command, against scanners repeating the same job
+    #[arg(
+        long,
+        value_name = "SECS",
+        value_parser = clap::value_parser!(u64).range(1..),
+        display_order = 8
+    )]
+    cooldown: Option<u64>,
+
+    /// Maximum time in seconds between batches of pages from the feeder to
+    /// count them as the same job in SCANNER_JOB_ID
+    #[arg(
+        long,
+        value_name = "SECS",
+        default_value_t = 60,
+        value_parser = clap::value_parser!(u64).range(1..),
+        display_order = 8
+    )]
+    adf_window: u64,
+
+    /// Only report scan button presses without resetting the job on the
+    /// scanner, leaving it to be serviced by another program
+    #[arg(long, display_order = 8)]
+    no_reset: bool,
+
+    /// Only poll the scanner between these local times of the day, like
+    /// `08:00-22:00` or `07:00-09:00,18:00-23:30`, pausing in quiet hours
+    #[arg(long, value_name = "RANGES", display_order = 8)]
+    active_hours: Option<schedule::ActiveHours>,
+
+    /// Send a Wake-on-LAN packet to the scanner when it doesn't answer, for
+    /// scanners powering their network interface down while idle. Its MAC
+    /// address is learned once discovered, and kept in `--state-file`
+    #[arg(long, display_order = 8)]
+    wol: bool,
+
+    /// MAC address to wake until the scanner is discovered
+    #[arg(long, value_name = "MAC", requires = "wol", display_order = 8)]
+    wol_mac: Option<MacAddr>,
+
+    /// TOML file mapping status codes reported by the scanner to actions
+    #[arg(long, value_name = "FILE", display_order = 9)]
+    status_rules: Option<PathBuf>,
+
+    /// File to keep the session and backoff of listeners across restarts, so
+    /// that a restarted listener resumes polling without registering again.
+    /// Also keeps the last changes of the status reported by each scanner
+    #[arg(long, value_name = "FILE", display_order = 9)]
+    state_file: Option<PathBuf>,
+
+    /// Listen even if another process is listening on the same scanner
+    #[arg(long, display_order = 9)]
+    force: bool,
+
+    /// User to switch to after binding sockets, by name or id (Unix only)
+    #[arg(long, value_name = "USER", display_order = 9)]
+    user: Option<String>,
+
+    /// Show the state of listeners in the terminal title, and notify of scan
+    /// jobs in terminals supporting OSC 9
+    #[arg(long, display_order = 9)]
+    title: bool,
+
+    /// Post a desktop notification for each scan job, to the Notification
+    /// Center on macOS or through `notify-send` elsewhere
+    #[cfg(feature = "notify")]
+    #[arg(long, display_order = 9)]
+    notify: bool,
+
+    /// Run as a launchd job on macOS: log with timestamps on stderr, which
+    /// launchd writes to a file
+    #[arg(long, conflicts_with = "title", display_order = 9)]
+    launchd: bool,
+
+    /// Group to switch to after binding sockets, by name or id (Unix only)
+    /// [default: the primary group of `--user`]
+    #[arg(long, value_name = "GROUP", display_order = 9)]
+    group: Option<String>,
+
+    /// Check the socket, the scanner, a cycle of registering and polling for
+    /// each host entry and the commands they launch, without running them nor
+    /// resetting scan jobs, then print a TOML report and exit, with status 1
+    /// if any check failed
+    #[arg(long, display_order = 9)]
+    self_test: bool,
+
+    /// Command to execute when scan button is pressed, registered as
+    /// `--hostname`. Only host entries from `--targets` are registered
+    /// without it
+    #[arg(long_help = COMMAND_LONG_HELP, required_unless_present = "targets")]
+    command: Option<OsString>,
 
     /// Arguments to the command if any
     args: Vec<OsString>,
 }
 
+#[derive(Args)]
+struct Scan {
+    /// Print each device as soon as it answers instead of sorted by address
+    /// after waiting
+    #[arg(long, display_order = 1)]
+    stream: bool,
+
+    /// Stop as soon as N devices have answered instead of waiting for
+    /// `--max-waiting` seconds
+    #[arg(
+        long,
+        value_name = "N",
+        value_parser = clap::value_parser!(u64).range(1..),
+        display_order = 2
+    )]
+    expect: Option<u64>,
+
+    /// Stop as soon as a device has answered, same as `--expect 1`
+    #[arg(long, conflicts_with = "expect", display_order = 2)]
+    first: bool,
+
+    /// Print only the IP address, the address with port or the MAC address
+    /// of each device, one per line without labels or colors
+    #[arg(long, value_name = "VALUE", value_enum, display_order = 3)]
+    quiet_output: Option<scan::QuietOutput>,
+
+    /// Number of discover commands broadcast on each interface, with
+    /// per-interface statistics of the responses shown with `-v`
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 1,
+        value_parser = clap::value_parser!(u16).range(1..),
+        display_order = 4
+    )]
+    probes: u16,
+
+    /// Interval in milliseconds between discover commands
+    #[arg(
+        long,
+        value_name = "MILLIS",
+        default_value_t = 500,
+        value_parser = clap::value_parser!(u64).range(1..),
+        display_order = 5
+    )]
+    probe_interval: u64,
+
+    /// Maximum number of devices inquired for their identity at once
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 16,
+        value_parser = clap::value_parser!(u32).range(1..),
+        display_order = 6
+    )]
+    max_inquiries: u32,
+
+    /// Time in milliseconds a device is given to report its identity
+    #[arg(
+        long,
+        value_name = "MILLIS",
+        default_value_t = 2000,
+        value_parser = clap::value_parser!(u64).range(1..),
+        display_order = 7
+    )]
+    inquiry_timeout: u64,
+
+    /// Broadcast on every IPv4 interface from a single socket, attributing
+    /// responses to interfaces by IP_PKTINFO (Linux only)
+    #[arg(long, display_order = 8)]
+    shared_socket: bool,
+
+    /// Also broadcast on virtual interfaces (docker, veth, tun and tap) and
+    /// interfaces down, which are skipped by default
+    #[arg(long, display_order = 9)]
+    all_interfaces: bool,
+
+    /// Look up the host name of each device by its IP address (PTR record)
+    #[arg(long, display_order = 10)]
+    resolve_names: bool,
+}
+
+#[derive(Args)]
+struct Setup {
+    /// File to write the host entry and its command to, as given to `listen
+    /// --targets`
+    #[arg(
+        short,
+        long,
+        value_name = "FILE",
+        default_value = "scanner-button.toml",
+        display_order = 1
+    )]
+    output: PathBuf,
+
+    /// File to write the systemd unit running the listener to, if asked for
+    #[arg(
+        long,
+        value_name = "FILE",
+        default_value = "scanner-button.service",
+        display_order = 2
+    )]
+    unit: PathBuf,
+}
+
+#[derive(Args)]
+struct InstallService {
+    /// Install a unit of the user into `~/.config/systemd/user`, running
+    /// while the user is logged in, instead of a unit of the system into
+    /// `/etc/systemd/system`
+    #[arg(long, display_order = 1)]
+    user: bool,
+
+    /// Name of the unit, without `.service`
+    #[arg(
+        long,
+        value_name = "NAME",
+        default_value = "scanner-button",
+        display_order = 2
+    )]
+    name: String,
+
+    /// Enable and start the unit once installed
+    #[arg(long, display_order = 3)]
+    enable: bool,
+
+    /// Replace an installed unit of the same name
+    #[arg(long, display_order = 3)]
+    force: bool,
+
+    /// Options and command of `listen` run by the unit, e.g. `--scanner
+    /// 192.168.1.20:8612 --targets scanner-button.toml`, where relative paths
+    /// are resolved against the current directory
+    #[arg(last = true, required = true, value_name = "LISTEN ARGS")]
+    listen: Vec<OsString>,
+}
+
+#[derive(Args)]
+struct Healthcheck {
+    /// State file of the listeners, as given to `listen --state-file`
+    #[arg(long, value_name = "FILE", display_order = 1)]
+    state_file: PathBuf,
+}
+
+#[derive(Args)]
+struct Proxy {
+    /// The address of the remote scanner
+    #[arg(
+        short,
+        long,
+        value_name = "ADDR",
+        value_parser = parse_addr,
+        display_order = 1
+    )]
+    scanner: SocketAddr,
+
+    /// Local address to listen on for BJNP traffic
+    #[arg(
+        long,
+        value_name = "ADDR",
+        default_value = "0.0.0.0:8612",
+        value_parser = parse_addr,
+        display_order = 2
+    )]
+    bind: SocketAddr,
+
+    /// IP address advertised to clients in discover responses [default: the
+    /// local address used to reach each client]
+    #[arg(long, value_name = "IP", display_order = 4)]
+    advertise: Option<IpAddr>,
+
+    /// Answer discover commands locally with this MAC address instead of
+    /// forwarding them to the scanner
+    #[arg(long, value_name = "MAC", display_order = 5)]
+    mac: Option<MacAddr>,
+}
+
+#[derive(Args)]
+struct Ping {
+    /// The address of the scanner
+    #[arg(
+        short,
+        long,
+        value_name = "ADDR",
+        value_parser = parse_addr,
+        display_order = 1
+    )]
+    scanner: SocketAddr,
+
+    /// Stop after sending N commands [default: until interrupted]
+    #[arg(
+        short,
+        long,
+        value_name = "N",
+        value_parser = clap::value_parser!(u64).range(1..),
+        display_order = 2
+    )]
+    count: Option<u64>,
+
+    /// Interval in milliseconds between commands
+    #[arg(
+        long,
+        value_name = "MILLIS",
+        default_value_t = 1000,
+        value_parser = clap::value_parser!(u64).range(1..),
+        display_order = 3
+    )]
+    interval: u64,
+
+    /// Send empty polls instead of discover commands
+    #[arg(long, display_order = 4)]
+    poll: bool,
+}
+
+#[derive(Args)]
+struct ContributeTrace {
+    /// The address of the scanner
+    #[arg(
+        short,
+        long,
+        value_name = "ADDR",
+        value_parser = parse_addr,
+        display_order = 1
+    )]
+    scanner: SocketAddr,
+
+    /// File to write the report to
+    #[arg(
+        short,
+        long,
+        value_name = "FILE",
+        default_value = "scanner-button-trace.txt",
+        display_order = 2
+    )]
+    output: PathBuf,
+
+    /// Number of polls after registering, one per second. Press the scan
+    /// button meanwhile to include a scan job in the report
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 5,
+        value_parser = clap::value_parser!(u32).range(1..),
+        display_order = 3
+    )]
+    polls: u32,
+}
+
+#[cfg(feature = "tui")]
+#[derive(Args)]
+struct Monitor {
+    /// The address of a scanner to listen on, showing its state and scan jobs
+    #[arg(
+        short,
+        long,
+        value_name = "ADDR",
+        value_parser = parse_scanner,
+        display_order = 1
+    )]
+    scanner: Vec<resolve::ScannerAddr>,
+
+    /// Name of the host to be displayed on the scanners listened on
+    #[arg(long, default_value_os_t = gethostname(), display_order = 2)]
+    hostname: OsString,
+
+    /// Interval in seconds between discover broadcasts
+    #[arg(
+        long,
+        value_name = "SECS",
+        default_value_t = 5,
+        value_parser = clap::value_parser!(u64).range(1..),
+        display_order = 3
+    )]
+    scan_interval: u64,
+}
+
 fn parse_addr(s: &str) -> Result<SocketAddr, io::Error> {
+    if s.contains('%') {
+        return parse_scoped_addr(s);
+    }
+
     let mut addrs = s.to_socket_addrs()?;
     // NOPANIC: if the former call succeeds, there is at least one address
     // (actually exactly one according to the current implementation)
@@ -121,6 +712,52 @@ fn parse_addr(s: &str) -> Result<SocketAddr, io::Error> {
         .expect("successful `to_socket_addrs()` call should produces exactly one address"))
 }
 
+/// Parses the address of a scanner, keeping the host name it is given by to
+/// resolve it again later.
+fn parse_scanner(s: &str) -> Result<resolve::ScannerAddr, io::Error> {
+    let addr = parse_addr(s)?;
+    let name = (!s.contains('%') && s.parse::<SocketAddr>().is_err()).then(|| s.to_owned());
+    Ok(resolve::ScannerAddr { addr, name })
+}
+
+/// Parses a scoped IPv6 address like `fe80::1%eth0:8612` or
+/// `[fe80::1%eth0]:8612`, where the zone is an interface name or index.
+fn parse_scoped_addr(s: &str) -> Result<SocketAddr, io::Error> {
+    let invalid = || {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("`{s}` is not a scoped IPv6 address with port"),
+        )
+    };
+
+    let (ip, rest) = s.split_once('%').ok_or_else(invalid)?;
+    let (zone, port) = rest.rsplit_once(':').ok_or_else(invalid)?;
+    let ip: Ipv6Addr = ip.trim_start_matches('[').parse().map_err(|_| invalid())?;
+    let zone = zone.trim_end_matches(']');
+    let port: u16 = port.parse().map_err(|_| invalid())?;
+    let scope_id = utils::scope_id(zone)?;
+    Ok(SocketAddrV6::new(ip, port, 0, scope_id).into())
+}
+
+fn parse_bind(s: &str) -> Result<SocketAddr, String> {
+    s.parse()
+        .or_else(|_| s.parse::<IpAddr>().map(|ip| SocketAddr::new(ip, 0)))
+        .map_err(|_| format!("`{s}` is not an IP address with an optional port"))
+}
+
+fn parse_encoding(s: &str) -> Result<&'static Encoding, String> {
+    Encoding::for_label(s.as_bytes()).ok_or_else(|| format!("`{s}` is not a known encoding"))
+}
+
+fn parse_target(s: &str) -> Result<(String, OsString), String> {
+    match s.split_once('=') {
+        Some((name, command)) if !name.is_empty() && !command.is_empty() => {
+            Ok((name.to_string(), command.into()))
+        }
+        _ => Err(format!("`{s}` is not in the form of `NAME=COMMAND`")),
+    }
+}
+
 fn parse_factor(s: &str) -> Result<f32, String> {
     let factor: f32 = s.parse().map_err(|_| format!("`{s}` is not a number"))?;
     if factor.is_finite() && matches!(factor.partial_cmp(&1.0f32), Some(cmp::Ordering::Greater)) {
@@ -131,14 +768,104 @@ fn parse_factor(s: &str) -> Result<f32, String> {
 }
 
 fn main() -> anyhow::Result<()> {
+    // the panic unwinds through `run` first, e.g. restoring the terminal of
+    // `monitor`, before exiting with a distinct status
+    match panic::catch_unwind(run) {
+        Ok(Err(e)) if crash::is_panic(&e) => {
+            eprintln!("Error: {e:?}");
+            process::exit(crash::EXIT_CODE)
+        }
+        Ok(result) => result,
+        Err(_) => process::exit(crash::EXIT_CODE),
+    }
+}
+
+fn run() -> anyhow::Result<()> {
+    // looking up the local offset fails once the process has threads, so it
+    // is captured before the runtime starts
+    let local_offset = UtcOffset::current_local_offset().ok();
     let cli = Cli::parse();
 
-    stderrlog::new()
-        .modules([module_path!(), "bjnp"])
-        .quiet(cli.quiet)
-        .verbosity(cli.verbose as usize + 1)
-        .init()
-        .unwrap();
+    // the dashboard shows logs itself instead of stderr
+    #[cfg(feature = "tui")]
+    let dashboard = matches!(cli.command, Commands::Monitor(_));
+    #[cfg(not(feature = "tui"))]
+    let dashboard = false;
+    if cli.redact {
+        redact::enable();
+        redact::add_hostname(&utils::decode_hostname(&gethostname(), None));
+    }
+    let level = match cli.verbose {
+        0 => LevelFilter::WARN,
+        1 => LevelFilter::INFO,
+        2 => LevelFilter::DEBUG,
+        _ => LevelFilter::TRACE,
+    };
+    // dependencies are left out, including those logging through `log`
+    let targets = |level| {
+        Targets::new()
+            .with_target(module_path!(), level)
+            .with_target("bjnp", level)
+    };
+    let stderr = (!dashboard).then(|| {
+        let stderr = fmt::layer()
+            .with_writer(redact::Redacting(io::stderr))
+            .with_ansi(io::stderr().is_terminal())
+            .with_target(false);
+        let stderr_targets = targets(if cli.quiet { LevelFilter::OFF } else { level });
+        // unlike terminals and the journal, files written by launchd have no
+        // timestamps of their own
+        let launchd = matches!(&cli.command, Commands::Listen(args) if args.launchd);
+        if launchd {
+            stderr.with_filter(stderr_targets).boxed()
+        } else {
+            stderr.without_time().with_filter(stderr_targets).boxed()
+        }
+    });
+    #[cfg(feature = "tui")]
+    let (dashboard_layer, dashboard_logs) = if dashboard {
+        let (layer, logs) = monitor::DashboardLayer::new(local_offset);
+        // packets are logged at debug level
+        let dashboard_level = match (cli.quiet, cli.verbose) {
+            (true, _) => LevelFilter::OFF,
+            (false, 0) => LevelFilter::DEBUG,
+            (false, _) => LevelFilter::TRACE,
+        };
+        (Some(layer.with_filter(targets(dashboard_level))), Some(logs))
+    } else {
+        (None, None)
+    };
+    #[cfg(not(feature = "tui"))]
+    let dashboard_layer: Option<tracing_subscriber::layer::Identity> = None;
+    let file = cli
+        .log_file
+        .as_deref()
+        .map(|path| {
+            let rotation = logfile::Rotation {
+                max_size: Some(cli.log_max_size * MEGABYTE),
+                period: cli.log_rotate,
+                keep: cli.log_keep,
+            };
+            logfile::RotatingFile::open(path, rotation)
+                .with_context(|| format!("couldn't open log file {}", path.display()))
+        })
+        .transpose()?
+        .map(|file| {
+            fmt::layer()
+                .with_writer(redact::Redacting(file))
+                .with_ansi(false)
+                .with_filter(targets(level))
+        });
+    tracing_subscriber::registry()
+        .with(stderr)
+        .with(dashboard_layer)
+        .with(file)
+        .init();
+    crash::install(cli.crash_dir.clone().unwrap_or_else(env::temp_dir));
+    if let Some(dir) = cli.dump_packets.as_deref() {
+        dump::init(dir, cli.dump_format)
+            .with_context(|| format!("couldn't dump packets into {}", dir.display()))?;
+    }
 
     let rt = tokio::runtime::Builder::new_current_thread()
         .enable_all()
@@ -147,18 +874,278 @@ fn main() -> anyhow::Result<()> {
         .build()
         .unwrap();
 
+    let socket_options = utils::SocketOptions {
+        ttl: cli.ttl,
+        dscp: cli.dscp,
+    };
+
     match cli.command {
         Commands::Listen(args) => {
+            let machine = utils::decode_hostname(&gethostname(), args.hostname_encoding);
+            let user = env::var("USER")
+                .or_else(|_| env::var("USERNAME"))
+                .unwrap_or_default();
+            let scanner = args.scanner.addr.ip().to_string();
+            let expand = |name: &str| {
+                Host::new(utils::expand_template(
+                    name,
+                    &[("user", &user), ("host", &machine), ("scanner", &scanner)],
+                ))
+            };
+
+            let mut events = vec![];
+            let title = args.title.then(|| {
+                let (sender, receiver) = mpsc::unbounded_channel();
+                events.push(sender);
+                receiver
+            });
+            #[cfg(feature = "notify")]
+            let notify = args.notify.then(|| {
+                let (sender, receiver) = mpsc::unbounded_channel();
+                events.push(sender);
+                receiver
+            });
             let config = poll::ListenConfig {
-                scanner_addr: args.scanner,
-                hostname: Host::new(args.hostname.to_string_lossy()),
+                scanner_addr: args.scanner.addr,
+                scanner_name: args.scanner.name,
+                bind_addr: args.bind,
+                socket_options,
+                hostname: expand(&utils::decode_hostname(
+                    &args.hostname,
+                    args.hostname_encoding,
+                )),
                 initial_max_waiting: cli.max_waiting,
+                backoff_initial: Duration::from_secs(args.backoff_initial),
                 backoff_factor: args.backoff_factor,
-                backoff_maximum: args.backoff_maximum,
-                command: (args.command, args.args),
+                backoff_maximum: Duration::from_secs(args.backoff_maximum),
+                keepalive_interval: args.keepalive_interval.map(Duration::from_secs),
+                reset: !args.no_reset,
+                cooldown: args.cooldown.map(Duration::from_secs),
+                adf_window: Duration::from_secs(args.adf_window),
+                status_rules: args
+                    .status_rules
+                    .as_deref()
+                    .map(status::StatusRules::load)
+                    .transpose()?
+                    .unwrap_or_default(),
+                command: args.command.zip(Some(args.args)),
+                events,
+                expect_model: args.expect_model,
+                active_hours: args.active_hours,
+                wol: args.wol,
+                wol_mac: args.wol_mac,
+                local_offset,
+            };
+            let file_targets = args
+                .targets
+                .as_deref()
+                .map(targets::Targets::load)
+                .transpose()?
+                .unwrap_or_default()
+                .into_iter()
+                .map(|target| {
+                    let mut command = target.command.into_iter().map(OsString::from);
+                    let program = command.next().unwrap();
+                    (target.name, program, command.collect())
+                });
+            let targets = args
+                .target
+                .into_iter()
+                .map(|(name, command)| (name, command, vec![]))
+                .chain(file_targets)
+                .map(|(name, command, args)| poll::ListenConfig {
+                    hostname: expand(&name),
+                    command: Some((command, args)),
+                    ..config.clone()
+                });
+            let configs: Vec<_> = iter::once(config.clone())
+                .filter(|config| config.command.is_some())
+                .chain(targets)
+                .collect();
+            ensure!(!configs.is_empty(), "no host entry to register");
+            let mut hostnames = HashSet::new();
+            for config in configs.iter() {
+                redact::add_hostname(&config.hostname.to_string());
+                ensure!(
+                    hostnames.insert(config.hostname.to_string()),
+                    "host entry `{}` is registered more than once",
+                    config.hostname
+                );
+            }
+            if args.self_test {
+                if !rt.block_on(selftest::run(configs))? {
+                    process::exit(1);
+                }
+                return Ok(());
+            }
+            let store = args
+                .state_file
+                .as_deref()
+                .map(state::StateStore::load)
+                .transpose()?;
+            let locks = lock::ScannerLocks::new(args.force);
+            let credentials = (args.user.is_some() || args.group.is_some())
+                .then(|| {
+                    privilege::Credentials::resolve(args.user.as_deref(), args.group.as_deref())
+                })
+                .transpose()?;
+            poll::check_local_offset(local_offset);
+            rt.block_on(async {
+                if let Some(title) = title {
+                    tokio::spawn(title::show(title, local_offset));
+                }
+                #[cfg(feature = "notify")]
+                if let Some(notify) = notify {
+                    tokio::spawn(notify::show(notify));
+                }
+                poll::listen(configs, store, locks, credentials).await
+            })
+        }
+        Commands::Scan(args) => {
+            let config = scan::ScanConfig {
+                max_waiting: Duration::from_secs(cli.max_waiting),
+                socket_options,
+                expect: if args.first {
+                    Some(1)
+                } else {
+                    args.expect.map(|n| n as usize)
+                },
+                probes: args.probes,
+                probe_interval: Duration::from_millis(args.probe_interval),
+                max_inquiries: args.max_inquiries as usize,
+                inquiry_timeout: Duration::from_millis(args.inquiry_timeout),
+                shared_socket: args.shared_socket,
+                all_interfaces: args.all_interfaces,
+                resolve_names: args.resolve_names,
+            };
+            let output = scan::ScanOutput {
+                stream: args.stream,
+                quiet_output: args.quiet_output,
+            };
+            rt.block_on(scan::print_scan(config, output))
+        }
+        Commands::Proxy(args) => {
+            let config = proxy::ProxyConfig {
+                scanner_addr: args.scanner,
+                bind_addr: args.bind,
+                socket_options,
+                advertise_ip: args.advertise,
+                mac_addr: args.mac,
+            };
+            rt.block_on(proxy::proxy(config))
+        }
+        Commands::Ping(args) => {
+            let config = ping::PingConfig {
+                scanner_addr: args.scanner,
+                socket_options,
+                count: args.count,
+                interval: Duration::from_millis(args.interval),
+                max_waiting: Duration::from_secs(cli.max_waiting),
+                poll: args.poll,
+            };
+            rt.block_on(ping::ping(config))
+        }
+        Commands::ContributeTrace(args) => {
+            let config = contribute::ContributeConfig {
+                scanner_addr: args.scanner,
+                socket_options,
+                max_waiting: Duration::from_secs(cli.max_waiting),
+                polls: args.polls,
+                output: args.output,
+            };
+            rt.block_on(contribute::contribute_trace(config))
+        }
+        Commands::Healthcheck(args) => {
+            if !health::check(&args.state_file)? {
+                process::exit(1);
+            }
+            Ok(())
+        }
+        Commands::Setup(args) => {
+            let config = setup::SetupConfig {
+                scan: scan::ScanConfig {
+                    max_waiting: Duration::from_secs(cli.max_waiting),
+                    socket_options,
+                    expect: None,
+                    probes: 1,
+                    probe_interval: Duration::from_millis(500),
+                    max_inquiries: 16,
+                    inquiry_timeout: Duration::from_secs(2),
+                    shared_socket: false,
+                    all_interfaces: false,
+                    resolve_names: true,
+                },
+                output: args.output,
+                unit: args.unit,
+            };
+            rt.block_on(setup::setup(config))
+        }
+        Commands::InstallService(args) => {
+            let listen = iter::once(OsString::from("listen")).chain(args.listen);
+            let listen = listen
+                .map(|arg| {
+                    arg.into_string()
+                        .map_err(|arg| anyhow!("{arg:?} isn't UTF-8"))
+                })
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            // rejected now rather than by the service once started
+            let parsed = Cli::try_parse_from(
+                iter::once("scanner-button").chain(listen.iter().map(String::as_str)),
+            )
+            .unwrap_or_else(|e| e.exit());
+            if let Commands::Listen(args) = parsed.command {
+                if let Some(targets) = args.targets.as_deref() {
+                    targets::Targets::load(targets)?;
+                }
+            }
+            service::install(service::InstallConfig {
+                name: args.name,
+                user: args.user,
+                enable: args.enable,
+                force: args.force,
+                listen,
+            })
+        }
+        #[cfg(feature = "tui")]
+        Commands::Monitor(args) => {
+            let hostname = Host::new(utils::decode_hostname(&args.hostname, None));
+            redact::add_hostname(&hostname.to_string());
+            let listeners = args
+                .scanner
+                .into_iter()
+                .map(|scanner| poll::ListenConfig {
+                    scanner_addr: scanner.addr,
+                    scanner_name: scanner.name,
+                    bind_addr: None,
+                    socket_options,
+                    hostname,
+                    initial_max_waiting: cli.max_waiting,
+                    backoff_initial: Duration::from_secs(5),
+                    backoff_factor: 2.0,
+                    backoff_maximum: Duration::from_secs(60),
+                    keepalive_interval: None,
+                    // cancel jobs so that the scanner doesn't wait for them
+                    reset: true,
+                    cooldown: None,
+                    adf_window: Duration::from_secs(60),
+                    status_rules: Default::default(),
+                    command: None,
+                    events: vec![],
+                    expect_model: None,
+                    active_hours: None,
+                    wol: false,
+                    wol_mac: None,
+                    local_offset,
+                })
+                .collect();
+            let config = monitor::MonitorConfig {
+                listeners,
+                scan_interval: Duration::from_secs(args.scan_interval),
+                socket_options,
+                logs: dashboard_logs.unwrap(),
+                local_offset,
             };
-            rt.block_on(poll::listen(config))
+            rt.block_on(monitor::monitor(config))
         }
-        Commands::Scan => rt.block_on(scan::scan(cli.max_waiting)),
     }
 }
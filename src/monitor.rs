@@ -0,0 +1,395 @@
+//! Dashboard in the terminal showing the scanners answering on the network,
+//! the state of listeners, recent scan jobs and the packets exchanged, for
+//! finding out why pressing the scan button does nothing.
+
+use std::{
+    collections::{BTreeMap, VecDeque},
+    fmt::{self, Write},
+    net::{IpAddr, SocketAddr},
+    thread,
+};
+
+use anyhow::Context;
+use bjnp::discover::MacAddr;
+use ratatui::{
+    crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers},
+    layout::{Constraint, Layout},
+    style::{Modifier, Style},
+    widgets::{Block, List, Paragraph, Row, Table},
+    DefaultTerminal, Frame,
+};
+use time::UtcOffset;
+use tokio::{
+    sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
+    task::JoinSet,
+    time::{interval, Duration, Instant},
+};
+use tokio_stream::{StreamExt, StreamMap};
+use tracing::{
+    debug, error,
+    field::{Field, Visit},
+    Subscriber,
+};
+use tracing_subscriber::{layer, Layer};
+
+use crate::{
+    crash,
+    lock::ScannerLocks,
+    poll::{self, ListenConfig, ListenerEvent},
+    redact,
+    scan::{broadcast_scan, broadcast_targets},
+    utils::{clock, SocketOptions},
+};
+
+/// Number of scan jobs kept on the dashboard
+const MAX_JOBS: usize = 100;
+
+/// Number of log lines kept on the dashboard
+const MAX_TRAFFIC: usize = 1000;
+
+/// Interval between redraws of the dashboard
+const REFRESH_INTERVAL: Duration = Duration::from_millis(250);
+
+#[derive(Debug)]
+pub struct MonitorConfig {
+    /// Scanners to listen on, showing their state and scan jobs
+    pub listeners: Vec<ListenConfig>,
+    pub scan_interval: Duration,
+    pub socket_options: SocketOptions,
+    /// Logs shown as traffic, sent by [`DashboardLayer`]
+    pub logs: UnboundedReceiver<String>,
+    /// Offset of the local time zone captured at startup, or `None` if it
+    /// couldn't be determined
+    pub local_offset: Option<UtcOffset>,
+}
+
+/// Forwards logs of this program to the dashboard instead of stderr, as a
+/// layer of the same subscriber as the log file
+pub struct DashboardLayer {
+    local_offset: Option<UtcOffset>,
+    sender: UnboundedSender<String>,
+}
+
+impl DashboardLayer {
+    /// Returns the layer along with the logs it forwards, for
+    /// [`MonitorConfig::logs`].
+    pub fn new(local_offset: Option<UtcOffset>) -> (Self, UnboundedReceiver<String>) {
+        let (sender, logs) = unbounded_channel();
+        (
+            Self {
+                local_offset,
+                sender,
+            },
+            logs,
+        )
+    }
+}
+
+impl<S: Subscriber> Layer<S> for DashboardLayer {
+    fn on_event(&self, event: &tracing::Event<'_>, _ctx: layer::Context<'_, S>) {
+        let mut line = format!(
+            "{} {} -",
+            clock(self.local_offset),
+            event.metadata().level()
+        );
+        event.record(&mut LineVisitor(&mut line));
+        let _ = self.sender.send(redact::line(&line).into_owned());
+    }
+}
+
+/// Appends the message and the other fields of an event to a line
+struct LineVisitor<'a>(&'a mut String);
+
+impl Visit for LineVisitor<'_> {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        let _ = match field.name() {
+            "message" => write!(self.0, " {value:?}"),
+            name => write!(self.0, " {name}={value:?}"),
+        };
+    }
+}
+
+/// A scanner which answered the discover broadcast
+#[derive(Debug)]
+struct Scanner {
+    mac_addr: MacAddr,
+    interface: String,
+    last_seen: Instant,
+    rtt: Option<Duration>,
+}
+
+#[derive(Debug, Default)]
+struct Dashboard {
+    scanners: BTreeMap<IpAddr, Scanner>,
+    /// State of each listener by scanner and host name
+    listeners: BTreeMap<(SocketAddr, String), String>,
+    jobs: VecDeque<String>,
+    traffic: VecDeque<String>,
+}
+
+impl Dashboard {
+    fn push_traffic(&mut self, log: String) {
+        // hex dumps span several lines
+        self.traffic.extend(log.lines().map(str::to_owned));
+        while self.traffic.len() > MAX_TRAFFIC {
+            self.traffic.pop_front();
+        }
+    }
+
+    fn draw(&self, frame: &mut Frame) {
+        let [top, jobs, traffic] = Layout::vertical([
+            Constraint::Length(8),
+            Constraint::Length(7),
+            Constraint::Min(5),
+        ])
+        .areas(frame.area());
+        let [scanners, listeners] =
+            Layout::horizontal([Constraint::Percentage(55), Constraint::Percentage(45)]).areas(top);
+        let header = Style::default().add_modifier(Modifier::BOLD);
+
+        let rows = self.scanners.iter().map(|(ip_addr, scanner)| {
+            Row::new([
+                ip_addr.to_string(),
+                scanner.mac_addr.to_string(),
+                scanner.interface.clone(),
+                format!("{}s ago", scanner.last_seen.elapsed().as_secs()),
+                scanner
+                    .rtt
+                    .map(|rtt| format!("{:.1} ms", rtt.as_secs_f64() * 1000.0))
+                    .unwrap_or_default(),
+            ])
+        });
+        let table = Table::new(
+            rows,
+            [
+                Constraint::Fill(1),
+                Constraint::Length(17),
+                Constraint::Length(9),
+                Constraint::Length(7),
+                Constraint::Length(8),
+            ],
+        )
+        .header(Row::new(["ADDRESS", "MAC", "INTERFACE", "SEEN", "RTT"]).style(header))
+        .block(Block::bordered().title(" Scanners "));
+        frame.render_widget(table, scanners);
+
+        let rows = self.listeners.iter().map(|((scanner, host), state)| {
+            Row::new([scanner.ip().to_string(), host.clone(), state.clone()])
+        });
+        let table = Table::new(
+            rows,
+            [
+                Constraint::Length(15),
+                Constraint::Fill(2),
+                Constraint::Fill(3),
+            ],
+        )
+        .header(Row::new(["SCANNER", "HOST", "STATE"]).style(header))
+        .block(Block::bordered().title(" Listeners "));
+        frame.render_widget(table, listeners);
+
+        let visible = jobs.height.saturating_sub(2) as usize;
+        let list = List::new(self.jobs.iter().rev().take(visible).map(String::as_str))
+            .block(Block::bordered().title(" Scan jobs (newest first) "));
+        frame.render_widget(list, jobs);
+
+        let visible = traffic.height.saturating_sub(2) as usize;
+        let lines: Vec<_> = self
+            .traffic
+            .iter()
+            .skip(self.traffic.len().saturating_sub(visible))
+            .map(|line| line.as_str().into())
+            .collect();
+        let paragraph =
+            Paragraph::new(lines).block(Block::bordered().title(" Traffic (q to quit) "));
+        frame.render_widget(paragraph, traffic);
+    }
+}
+
+/// Shows the dashboard until `q`, `Esc` or Ctrl-C is pressed.
+pub async fn monitor(config: MonitorConfig) -> anyhow::Result<()> {
+    debug!("loaded monitor config {config:?}");
+
+    let mut terminal = ratatui::init();
+    let _restore = RestoreTerminal;
+    run(&mut terminal, config).await
+}
+
+/// Restores the terminal once dropped, also when unwinding from a panic
+struct RestoreTerminal;
+
+impl Drop for RestoreTerminal {
+    fn drop(&mut self) {
+        ratatui::restore();
+    }
+}
+
+async fn run(
+    terminal: &mut DefaultTerminal,
+    mut config: MonitorConfig,
+) -> anyhow::Result<()> {
+    let mut dashboard = Dashboard::default();
+
+    // listeners are run as with `listen`, reporting to the dashboard
+    let (sender, mut events) = unbounded_channel();
+    let mut listeners = config.listeners;
+    for listener in listeners.iter_mut() {
+        listener.events.push(sender.clone());
+        dashboard.listeners.insert(
+            (listener.scanner_addr, listener.hostname.to_string()),
+            "starting".to_owned(),
+        );
+    }
+    let mut listening = JoinSet::new();
+    if !listeners.is_empty() {
+        listening.spawn(poll::listen(
+            listeners,
+            None,
+            ScannerLocks::new(false),
+            None,
+        ));
+    }
+
+    // discover commands are broadcast every `scan_interval` for as long as
+    // the dashboard is shown
+    let mut broadcasts = JoinSet::new();
+    let mut discovered = broadcast_targets(false)?
+        .into_iter()
+        .map(|(name, ip, broadcast)| {
+            let receiver = broadcast_scan(
+                &mut broadcasts,
+                name.clone(),
+                ip,
+                broadcast,
+                config.socket_options,
+                (None, config.scan_interval),
+            );
+            (name, receiver)
+        })
+        .collect::<StreamMap<_, _>>();
+
+    let (sender, mut keys) = unbounded_channel();
+    thread::spawn(move || {
+        while let Ok(event) = event::read() {
+            if sender.send(event).is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut ticks = interval(REFRESH_INTERVAL);
+    loop {
+        tokio::select! {
+            _ = ticks.tick() => {
+                terminal
+                    .draw(|frame| dashboard.draw(frame))
+                    .context("failed to draw the dashboard")?;
+            },
+            Some(Event::Key(key)) = keys.recv() => {
+                let ctrl_c =
+                    key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL);
+                if key.kind == KeyEventKind::Press
+                    && (ctrl_c || matches!(key.code, KeyCode::Char('q') | KeyCode::Esc))
+                {
+                    break;
+                }
+            },
+            Some((interface, resp)) = discovered.next() => match resp {
+                Ok((resp, rtt)) => {
+                    let scanner = Scanner {
+                        mac_addr: *resp.mac_addr(),
+                        interface,
+                        last_seen: Instant::now(),
+                        rtt,
+                    };
+                    dashboard.scanners.insert(*resp.ip_addr(), scanner);
+                }
+                Err(e) => error!("socket on {interface}: {e:?}"),
+            },
+            Some(joined) = broadcasts.join_next() => {
+                if let Err(e) = joined.context("failed to join task").and_then(std::convert::identity) {
+                    if crash::is_panic(&e) {
+                        return Err(e);
+                    }
+                    error!("socket error: {e:?}");
+                }
+            },
+            Some(event) = events.recv() => match event {
+                ListenerEvent::State { scanner, host, state } => {
+                    dashboard.listeners.insert((scanner, host.to_string()), state);
+                }
+                ListenerEvent::Interrupt { scanner, host, interrupt } => {
+                    let now = clock(config.local_offset);
+                    dashboard.jobs.push_back(format!("{now} {scanner} {host}: {interrupt}"));
+                    if dashboard.jobs.len() > MAX_JOBS {
+                        dashboard.jobs.pop_front();
+                    }
+                }
+                // the state following the transition is reported as well
+                ListenerEvent::Transition { .. } => {}
+            },
+            Some(joined) = listening.join_next() => {
+                let e = match joined.context("failed to join task").and_then(std::convert::identity) {
+                    Ok(()) => continue,
+                    Err(e) if crash::is_panic(&e) => return Err(e),
+                    Err(e) => e,
+                };
+                error!("{e:#}");
+                for state in dashboard.listeners.values_mut() {
+                    *state = format!("stopped: {e}");
+                }
+            },
+            Some(log) = config.logs.recv() => dashboard.push_traffic(log),
+        }
+    }
+    broadcasts.shutdown().await;
+    listening.shutdown().await;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use ratatui::{backend::TestBackend, Terminal};
+
+    use super::*;
+
+    #[test]
+    fn draw_dashboard() {
+        let mut dashboard = Dashboard::default();
+        dashboard.scanners.insert(
+            "192.168.1.20".parse().unwrap(),
+            Scanner {
+                mac_addr: "00:1e:8f:01:02:03".parse().unwrap(),
+                interface: "eth0".to_owned(),
+                last_seen: Instant::now(),
+                rtt: Some(Duration::from_micros(1500)),
+            },
+        );
+        dashboard.listeners.insert(
+            (
+                "192.168.1.20:8612".parse().unwrap(),
+                "Youmu-Desktop".to_owned(),
+            ),
+            "polling (session 7)".to_owned(),
+        );
+        dashboard.push_traffic("DEBUG - first\nDEBUG - second".to_owned());
+
+        let mut terminal = Terminal::new(TestBackend::new(120, 24)).unwrap();
+        terminal.draw(|frame| dashboard.draw(frame)).unwrap();
+        let screen: String = terminal
+            .backend()
+            .buffer()
+            .content()
+            .chunks(120)
+            .map(|line| line.iter().map(|cell| cell.symbol()).collect::<String>() + "\n")
+            .collect();
+        for text in [
+            "00:1e:8f:01:02:03",
+            "1.5 ms",
+            "polling (session 7)",
+            "DEBUG - second",
+        ] {
+            assert!(screen.contains(text), "`{text}` not shown");
+        }
+    }
+}
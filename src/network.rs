@@ -0,0 +1,265 @@
+//! Detects changes to network interfaces, so that listeners in backoff retry
+//! as soon as a network comes up instead of waiting out the backoff.
+//!
+//! Changes are subscribed through netlink on Linux and through a routing
+//! socket on macOS, where the system configuration daemon reports them, and
+//! are not detected on other platforms.
+
+use tokio::sync::watch;
+
+/// Spawns the detection of network changes, notified through the receiver
+/// whenever an interface comes up or gains an address.
+pub fn watch() -> watch::Receiver<()> {
+    let (sender, receiver) = watch::channel(());
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    tokio::spawn(async move {
+        #[cfg(target_os = "linux")]
+        let result = forward(netlink::subscribe, netlink::has_new_network, sender).await;
+        #[cfg(target_os = "macos")]
+        let result = forward(route::subscribe, route::has_new_network, sender).await;
+        if let Err(e) = result {
+            tracing::warn!("couldn't watch for network changes: {e}");
+        }
+    });
+    #[cfg(not(any(target_os = "linux", target_os = "macos")))]
+    {
+        tracing::debug!("network changes are not detected on this platform");
+        drop(sender);
+    }
+    receiver
+}
+
+/// Reads the messages of the socket opened by `subscribe`, notifying
+/// `sender` of those reporting a new network.
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+async fn forward(
+    subscribe: fn() -> std::io::Result<std::os::fd::OwnedFd>,
+    has_new_network: fn(&[u8]) -> bool,
+    sender: watch::Sender<()>,
+) -> std::io::Result<()> {
+    let socket = tokio::io::unix::AsyncFd::new(subscribe()?)?;
+    let mut buffer = vec![0; 65536];
+    loop {
+        let mut guard = socket.readable().await?;
+        let size = match guard.try_io(|socket| recv(socket.get_ref(), &mut buffer)) {
+            Ok(result) => result?,
+            Err(_would_block) => continue,
+        };
+        if has_new_network(&buffer[..size]) {
+            tracing::debug!("network interfaces changed");
+            if sender.send(()).is_err() {
+                return Ok(());
+            }
+        }
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+fn recv(socket: &std::os::fd::OwnedFd, buffer: &mut [u8]) -> std::io::Result<usize> {
+    use std::os::fd::AsRawFd;
+
+    // SAFETY: buffer is valid for writes of its length
+    let size = unsafe {
+        libc::recv(
+            socket.as_raw_fd(),
+            buffer.as_mut_ptr() as *mut libc::c_void,
+            buffer.len(),
+            0,
+        )
+    };
+    if size < 0 {
+        Err(std::io::Error::last_os_error())
+    } else {
+        Ok(size as usize)
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod netlink {
+    use std::{
+        io, mem,
+        os::fd::{AsRawFd, FromRawFd, OwnedFd},
+    };
+
+    const HEADER_SIZE: usize = mem::size_of::<libc::nlmsghdr>();
+
+    /// Opens a netlink socket receiving changes of links and addresses.
+    pub(super) fn subscribe() -> io::Result<OwnedFd> {
+        // SAFETY: plain syscall, the returned descriptor is owned below
+        let fd = unsafe {
+            libc::socket(
+                libc::AF_NETLINK,
+                libc::SOCK_RAW | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC,
+                libc::NETLINK_ROUTE,
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: fd is a valid descriptor not owned elsewhere
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+
+        // SAFETY: sockaddr_nl is plain old data
+        let mut addr: libc::sockaddr_nl = unsafe { mem::zeroed() };
+        addr.nl_family = libc::AF_NETLINK as u16;
+        addr.nl_groups =
+            (libc::RTMGRP_LINK | libc::RTMGRP_IPV4_IFADDR | libc::RTMGRP_IPV6_IFADDR) as u32;
+        // SAFETY: addr is a valid sockaddr_nl of the given size
+        let ret = unsafe {
+            libc::bind(
+                fd.as_raw_fd(),
+                &addr as *const libc::sockaddr_nl as *const libc::sockaddr,
+                mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+            )
+        };
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(fd)
+    }
+
+    /// Returns whether any message reports a link coming up or a new address.
+    pub(super) fn has_new_network(mut buffer: &[u8]) -> bool {
+        let mut found = false;
+        while buffer.len() >= HEADER_SIZE {
+            // NOPANIC: buffer holds at least a header
+            let len = u32::from_ne_bytes(buffer[0..4].try_into().unwrap()) as usize;
+            let kind = u16::from_ne_bytes(buffer[4..6].try_into().unwrap());
+            if len < HEADER_SIZE || len > buffer.len() {
+                break;
+            }
+            let payload = &buffer[HEADER_SIZE..len];
+            found |= match kind {
+                libc::RTM_NEWADDR => true,
+                // struct ifinfomsg { family: u8, pad: u8, type: u16, index: i32, flags: u32, .. }
+                libc::RTM_NEWLINK => payload.get(8..12).is_some_and(|flags| {
+                    let flags = u32::from_ne_bytes(flags.try_into().unwrap()) as i32;
+                    let up = libc::IFF_UP | libc::IFF_RUNNING;
+                    flags & up == up
+                }),
+                _ => false,
+            };
+            // messages are aligned to 4 bytes
+            let aligned = (len + 3) & !3;
+            buffer = buffer.get(aligned..).unwrap_or_default();
+        }
+        found
+    }
+}
+
+/// Routing sockets of macOS, whose parsing is also tested on other Unix
+/// platforms
+#[cfg(any(target_os = "macos", all(test, unix)))]
+mod route {
+    #[cfg(target_os = "macos")]
+    use std::{
+        io,
+        os::fd::{AsRawFd, FromRawFd, OwnedFd},
+    };
+
+    /// Types of routing messages, as in `<net/route.h>` of macOS
+    const RTM_NEWADDR: u8 = 0xc;
+    const RTM_IFINFO: u8 = 0xe;
+
+    /// Opens a routing socket, receiving every change of routes, addresses
+    /// and interfaces.
+    #[cfg(target_os = "macos")]
+    pub(super) fn subscribe() -> io::Result<OwnedFd> {
+        // SAFETY: plain syscall, the returned descriptor is owned below
+        let fd = unsafe { libc::socket(libc::PF_ROUTE, libc::SOCK_RAW, libc::AF_UNSPEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: fd is a valid descriptor not owned elsewhere
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+        // SAFETY: plain syscalls on a valid descriptor
+        let ret = unsafe {
+            let flags = libc::fcntl(fd.as_raw_fd(), libc::F_GETFL);
+            libc::fcntl(fd.as_raw_fd(), libc::F_SETFL, flags | libc::O_NONBLOCK)
+        };
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(fd)
+    }
+
+    /// Returns whether any message reports an interface coming up or a new
+    /// address.
+    pub(super) fn has_new_network(mut buffer: &[u8]) -> bool {
+        let mut found = false;
+        // struct if_msghdr { msglen: u16, version: u8, type: u8, addrs: i32, flags: i32, .. }
+        while buffer.len() >= 4 {
+            // NOPANIC: buffer holds at least the common header
+            let len = u16::from_ne_bytes(buffer[0..2].try_into().unwrap()) as usize;
+            let kind = buffer[3];
+            if len < 4 || len > buffer.len() {
+                break;
+            }
+            found |= match kind {
+                RTM_NEWADDR => true,
+                RTM_IFINFO => buffer[..len].get(8..12).is_some_and(|flags| {
+                    let flags = i32::from_ne_bytes(flags.try_into().unwrap());
+                    let up = libc::IFF_UP | libc::IFF_RUNNING;
+                    flags & up == up
+                }),
+                _ => false,
+            };
+            buffer = &buffer[len..];
+        }
+        found
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::netlink::has_new_network;
+
+    fn message(kind: u16, payload: &[u8]) -> Vec<u8> {
+        let len = (16 + payload.len()) as u32;
+        let mut message = len.to_ne_bytes().to_vec();
+        message.extend_from_slice(&kind.to_ne_bytes());
+        message.extend_from_slice(&[0; 10]);
+        message.extend_from_slice(payload);
+        message
+    }
+
+    #[test]
+    fn detect_new_network() {
+        let mut link = [0; 16];
+        link[8..12].copy_from_slice(&((libc::IFF_UP | libc::IFF_RUNNING) as u32).to_ne_bytes());
+        assert!(has_new_network(&message(libc::RTM_NEWLINK, &link)));
+        assert!(!has_new_network(&message(libc::RTM_NEWLINK, &[0; 16])));
+        assert!(has_new_network(
+            &[
+                message(libc::RTM_DELADDR, &[0; 8]),
+                message(libc::RTM_NEWADDR, &[0; 8])
+            ]
+            .concat()
+        ));
+    }
+}
+
+#[cfg(all(test, unix))]
+mod route_tests {
+    use super::route::has_new_network;
+
+    fn message(kind: u8, flags: i32) -> Vec<u8> {
+        let mut message = 16u16.to_ne_bytes().to_vec();
+        message.extend_from_slice(&[5, kind, 0, 0, 0, 0]);
+        message.extend_from_slice(&flags.to_ne_bytes());
+        message.extend_from_slice(&[0; 4]);
+        message
+    }
+
+    #[test]
+    fn detect_new_route_network() {
+        assert!(has_new_network(&message(
+            0xe,
+            libc::IFF_UP | libc::IFF_RUNNING
+        )));
+        assert!(!has_new_network(&message(0xe, 0)));
+        assert!(has_new_network(
+            &[message(0xd, 0), message(0xc, 0)].concat()
+        ));
+    }
+}
@@ -0,0 +1,57 @@
+//! Posts a desktop notification for each scan job, to the Notification Center
+//! on macOS and through `notify-send` elsewhere, for listeners running in the
+//! background without a terminal to notify in.
+
+use std::process::Command;
+
+use tokio::{sync::mpsc::UnboundedReceiver, task};
+use tracing::debug;
+
+use crate::poll::ListenerEvent;
+
+/// Posts the scan jobs reported by listeners, until all listeners are gone.
+pub async fn show(mut events: UnboundedReceiver<ListenerEvent>) {
+    while let Some(event) = events.recv().await {
+        let ListenerEvent::Interrupt {
+            scanner,
+            host,
+            interrupt,
+        } = event
+        else {
+            continue;
+        };
+        let mut command = command(
+            &format!("Scan to {host}"),
+            &format!("{interrupt} on {scanner}"),
+        );
+        let posted = task::spawn_blocking(move || command.status()).await;
+        match posted {
+            Ok(Ok(status)) if status.success() => {}
+            Ok(Ok(status)) => debug!("couldn't post notification: {status}"),
+            Ok(Err(e)) => debug!("couldn't post notification: {e}"),
+            Err(e) => debug!("couldn't post notification: {e}"),
+        }
+    }
+}
+
+#[cfg(target_os = "macos")]
+fn command(title: &str, body: &str) -> Command {
+    // passed as arguments rather than interpolated, which would need escaping
+    let mut command = Command::new("osascript");
+    command
+        .arg("-e")
+        .arg("on run argv\ndisplay notification (item 2 of argv) with title (item 1 of argv)\nend run")
+        .arg(title)
+        .arg(body);
+    command
+}
+
+#[cfg(not(target_os = "macos"))]
+fn command(title: &str, body: &str) -> Command {
+    let mut command = Command::new("notify-send");
+    command
+        .arg("--app-name=scanner-button")
+        .arg(title)
+        .arg(body);
+    command
+}
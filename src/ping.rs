@@ -0,0 +1,165 @@
+use std::{
+    fmt::Display,
+    io::{self, Write},
+    net::SocketAddr,
+};
+
+use anyhow::Context;
+use bjnp::{discover, poll};
+use tokio::{
+    signal,
+    task::JoinSet,
+    time::{interval, timeout, Duration, Instant},
+};
+use tracing::{debug, warn};
+
+use crate::{
+    channel::{Channel, InFlight},
+    utils::SocketOptions,
+};
+
+#[derive(Debug)]
+pub struct PingConfig {
+    pub scanner_addr: SocketAddr,
+    pub socket_options: SocketOptions,
+    /// Number of commands to send, or `None` to send until interrupted
+    pub count: Option<u64>,
+    pub interval: Duration,
+    pub max_waiting: Duration,
+    /// Send empty polls instead of discover commands
+    pub poll: bool,
+}
+
+/// Round trip times of the replies received so far
+#[derive(Debug, Default)]
+struct PingStats {
+    sent: u64,
+    rtts: Vec<Duration>,
+}
+
+impl Display for PingStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let received = self.rtts.len() as u64;
+        let loss = if self.sent > 0 {
+            (self.sent - received) as f64 * 100.0 / self.sent as f64
+        } else {
+            0.0
+        };
+        f.write_fmt(format_args!(
+            "{} commands sent, {received} replies received, {loss:.1}% loss",
+            self.sent
+        ))?;
+        if let (Some(min), Some(max)) = (self.rtts.iter().min(), self.rtts.iter().max()) {
+            let avg = self.rtts.iter().sum::<Duration>() / self.rtts.len() as u32;
+            f.write_fmt(format_args!(
+                "\nrtt min/avg/max = {:.2}/{:.2}/{:.2} ms",
+                millis(*min),
+                millis(avg),
+                millis(*max)
+            ))?;
+        }
+        Ok(())
+    }
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+/// Sends a command to the scanner every `interval` and reports the round trip
+/// time of each reply, until `count` commands are answered or timed out, or
+/// until interrupted by Ctrl-C.
+pub async fn ping(config: PingConfig) -> anyhow::Result<()> {
+    debug!("loaded ping config {config:?}");
+
+    let scanner_addr = config.scanner_addr;
+    let channel = Channel::new(scanner_addr, &config.socket_options).await?;
+    let mut stdout = io::stdout();
+    let kind = if config.poll {
+        "empty poll"
+    } else {
+        "discover"
+    };
+    writeln!(stdout, "PING {scanner_addr} with {kind} commands")
+        .context("failed to write to stdout")?;
+
+    let mut stats = PingStats::default();
+    let mut replies = JoinSet::new();
+    let mut ticks = interval(config.interval);
+    let ctrl_c = signal::ctrl_c();
+    tokio::pin!(ctrl_c);
+    loop {
+        let done = config.count.is_some_and(|count| stats.sent >= count);
+        if done && replies.is_empty() {
+            break;
+        }
+
+        tokio::select! {
+            _ = ticks.tick(), if !done => {
+                let seq = stats.sent;
+                stats.sent += 1;
+                let started = Instant::now();
+                match submit(&channel, config.poll).await {
+                    Ok(in_flight) => {
+                        let max_waiting = config.max_waiting;
+                        let poll = config.poll;
+                        replies.spawn(async move {
+                            let reply = timeout(max_waiting, recv(in_flight, poll)).await;
+                            (seq, reply.map(|reply| reply.map(|_| started.elapsed())))
+                        });
+                    }
+                    Err(e) => warn!("seq={seq}: {e:?}"),
+                }
+            },
+            Some(joined) = replies.join_next() => {
+                let (seq, reply) = joined.context("failed to join task")?;
+                match reply {
+                    Ok(Ok(rtt)) => {
+                        stats.rtts.push(rtt);
+                        writeln!(
+                            stdout,
+                            "reply from {scanner_addr}: seq={seq} time={:.2} ms",
+                            millis(rtt)
+                        )
+                        .context("failed to write to stdout")?;
+                    }
+                    Ok(Err(e)) => warn!("seq={seq}: {e:?}"),
+                    Err(_) => {
+                        writeln!(stdout, "timeout for seq={seq}")
+                            .context("failed to write to stdout")?;
+                    }
+                }
+            },
+            result = &mut ctrl_c => {
+                result.context("failed to listen for Ctrl-C")?;
+                // commands still awaiting replies are not counted
+                stats.sent -= replies.len() as u64;
+                break;
+            }
+        }
+    }
+
+    writeln!(stdout, "--- {scanner_addr} ping statistics ---\n{stats}")
+        .context("failed to write to stdout")?;
+    Ok(())
+}
+
+async fn submit(channel: &Channel, poll: bool) -> anyhow::Result<InFlight> {
+    if poll {
+        let command = poll::CommandBuilder::new(poll::PollType::Empty)
+            .build()
+            .unwrap();
+        channel.submit(command).await
+    } else {
+        channel.submit(discover::Request::new()).await
+    }
+}
+
+async fn recv(in_flight: InFlight, poll: bool) -> anyhow::Result<()> {
+    if poll {
+        in_flight.recv::<poll::Response>().await?;
+    } else {
+        in_flight.recv::<discover::Response>().await?;
+    }
+    Ok(())
+}
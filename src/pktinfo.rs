@@ -0,0 +1,164 @@
+//! Sends and receives IPv4 datagrams with `IP_PKTINFO`, which picks the
+//! interface a datagram is sent on and tells the interface a datagram is
+//! received on, so that a single socket serves every interface.
+
+use std::{
+    io, mem,
+    net::{Ipv4Addr, SocketAddr, SocketAddrV4},
+    os::fd::AsRawFd,
+    ptr,
+};
+
+use tokio::{io::Interest, net::UdpSocket};
+
+/// Control message buffer, aligned for `cmsghdr` and large enough for a single
+/// `in_pktinfo`
+type Control = [u64; 8];
+
+/// Asks for the interface each datagram is received on.
+pub fn enable(socket: &UdpSocket) -> io::Result<()> {
+    let on: libc::c_int = 1;
+    // SAFETY: the option value is a `c_int` of the given length
+    let ret = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::IPPROTO_IP,
+            libc::IP_PKTINFO,
+            &on as *const libc::c_int as *const libc::c_void,
+            mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if ret < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+/// Sends `buffer` to `target` out of the interface at `index`, which also
+/// applies to the limited broadcast address.
+pub async fn send_on(
+    socket: &UdpSocket,
+    buffer: &[u8],
+    target: SocketAddrV4,
+    index: u32,
+) -> io::Result<usize> {
+    loop {
+        socket.writable().await?;
+        match socket.try_io(Interest::WRITABLE, || {
+            sendmsg(socket, buffer, target, index)
+        }) {
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => continue,
+            result => return result,
+        }
+    }
+}
+
+/// Receives a datagram into `buffer`, returning its size, its source and the
+/// index of the interface it was received on if known.
+pub async fn recv(
+    socket: &UdpSocket,
+    buffer: &mut [u8],
+) -> io::Result<(usize, SocketAddr, Option<u32>)> {
+    loop {
+        socket.readable().await?;
+        match socket.try_io(Interest::READABLE, || recvmsg(socket, buffer)) {
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => continue,
+            result => return result,
+        }
+    }
+}
+
+fn sendmsg(
+    socket: &UdpSocket,
+    buffer: &[u8],
+    target: SocketAddrV4,
+    index: u32,
+) -> io::Result<usize> {
+    let mut addr = libc::sockaddr_in {
+        sin_family: libc::AF_INET as libc::sa_family_t,
+        sin_port: target.port().to_be(),
+        sin_addr: libc::in_addr {
+            s_addr: u32::from(*target.ip()).to_be(),
+        },
+        sin_zero: [0; 8],
+    };
+    let mut iov = libc::iovec {
+        iov_base: buffer.as_ptr() as *mut libc::c_void,
+        iov_len: buffer.len(),
+    };
+    let mut control: Control = [0; 8];
+    // SAFETY: all-zero is a valid `msghdr`
+    let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+    msg.msg_name = &mut addr as *mut libc::sockaddr_in as *mut libc::c_void;
+    msg.msg_namelen = mem::size_of::<libc::sockaddr_in>() as libc::socklen_t;
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+    // SAFETY: `CMSG_*` only compute sizes and offsets within `control`, which
+    // is large enough for a single `in_pktinfo`
+    unsafe {
+        msg.msg_controllen = libc::CMSG_SPACE(mem::size_of::<libc::in_pktinfo>() as u32) as _;
+        let cmsg = libc::CMSG_FIRSTHDR(&msg);
+        (*cmsg).cmsg_level = libc::IPPROTO_IP;
+        (*cmsg).cmsg_type = libc::IP_PKTINFO;
+        (*cmsg).cmsg_len = libc::CMSG_LEN(mem::size_of::<libc::in_pktinfo>() as u32) as _;
+        ptr::write_unaligned(
+            libc::CMSG_DATA(cmsg) as *mut libc::in_pktinfo,
+            libc::in_pktinfo {
+                ipi_ifindex: index as libc::c_int,
+                ipi_spec_dst: libc::in_addr { s_addr: 0 },
+                ipi_addr: libc::in_addr { s_addr: 0 },
+            },
+        );
+    }
+    // SAFETY: `msg` points to buffers valid for the lengths it gives
+    let size = unsafe { libc::sendmsg(socket.as_raw_fd(), &msg, 0) };
+    if size < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(size as usize)
+    }
+}
+
+fn recvmsg(socket: &UdpSocket, buffer: &mut [u8]) -> io::Result<(usize, SocketAddr, Option<u32>)> {
+    // SAFETY: all-zero is a valid `sockaddr_in`
+    let mut addr: libc::sockaddr_in = unsafe { mem::zeroed() };
+    let mut iov = libc::iovec {
+        iov_base: buffer.as_mut_ptr() as *mut libc::c_void,
+        iov_len: buffer.len(),
+    };
+    let mut control: Control = [0; 8];
+    // SAFETY: all-zero is a valid `msghdr`
+    let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+    msg.msg_name = &mut addr as *mut libc::sockaddr_in as *mut libc::c_void;
+    msg.msg_namelen = mem::size_of::<libc::sockaddr_in>() as libc::socklen_t;
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+    msg.msg_controllen = mem::size_of::<Control>() as _;
+    // SAFETY: `msg` points to buffers valid for the lengths it gives
+    let size = unsafe { libc::recvmsg(socket.as_raw_fd(), &mut msg, 0) };
+    if size < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let mut index = None;
+    // SAFETY: the control messages were written by `recvmsg` within `control`
+    unsafe {
+        let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+        while !cmsg.is_null() {
+            if (*cmsg).cmsg_level == libc::IPPROTO_IP && (*cmsg).cmsg_type == libc::IP_PKTINFO {
+                let info: libc::in_pktinfo =
+                    ptr::read_unaligned(libc::CMSG_DATA(cmsg) as *const libc::in_pktinfo);
+                index = Some(info.ipi_ifindex as u32);
+            }
+            cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+        }
+    }
+    let source = SocketAddrV4::new(
+        Ipv4Addr::from(u32::from_be(addr.sin_addr.s_addr)),
+        u16::from_be(addr.sin_port),
+    );
+    Ok((size as usize, source.into(), index))
+}
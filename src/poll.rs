@@ -1,204 +1,852 @@
-use std::{cmp, ffi::OsString, net::SocketAddr, process::Command};
+use std::{
+    borrow::Cow,
+    ffi::OsString,
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+    process::Command,
+};
 
 use anyhow::{anyhow, Context};
 use bjnp::{
-    discover,
-    poll::{self, Interrupt},
-    serdes::Empty,
-    Host, PayloadType,
+    discover::{self, MacAddr},
+    identity,
+    poll::{self, Interrupt, Lenient, StatusFlags},
+    Host,
+};
+use thiserror::Error;
+use time::{OffsetDateTime, PrimitiveDateTime, UtcOffset};
+use tokio::{
+    sync::{mpsc::UnboundedSender, watch},
+    task::JoinSet,
+    time::{error::Elapsed, sleep, Duration, Instant},
+};
+use tracing::{debug, debug_span, info, info_span, trace, warn, Instrument};
+
+use crate::{
+    channel::{Channel, Dispatcher},
+    crash,
+    lock::{AlreadyLocked, ScannerLocks},
+    machine::{self, Action, Backoff, Input, State},
+    network,
+    privilege::Credentials,
+    quirks::{Quirks, QuirksDb},
+    resolve::Resolver,
+    schedule::ActiveHours,
+    state::{self, History, ScannerState, StateStore},
+    status::StatusRules,
+    suspend::{self, Power},
+    utils::{glob_match, ignore_err, now_at, unspecified_for, SocketOptions},
+    wol,
 };
-use log::{debug, info, trace, warn};
-use time::{OffsetDateTime, PrimitiveDateTime};
-use tokio::time::{sleep, timeout, Duration};
 
-use crate::{channel::Channel, utils::ignore_err};
+/// Attempts at resetting a scan job before giving up on it
+const RESET_ATTEMPTS: u32 = 3;
+/// Delay before the second attempt at resetting a scan job, doubled for each
+/// further attempt
+const RESET_BACKOFF: Duration = Duration::from_millis(250);
+/// Interval between attempts at polling a scanner in deep sleep
+const ASLEEP_PROBE_INTERVAL: Duration = Duration::from_secs(30);
+/// Delay for the network interface of a scanner to come up after waking it
+const WOL_DELAY: Duration = Duration::from_secs(3);
 
-#[derive(Debug)]
-enum State {
-    Init,
-    Poll,
-    Backoff(Duration),
+/// A scan job, which spans several interrupts when the feeder reports each
+/// batch of pages separately
+#[derive(Debug, Clone, Copy)]
+struct Job {
+    id: u32,
+    batch: u32,
+    session_id: u32,
+    last_batch_at: Instant,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct ListenConfig {
     pub scanner_addr: SocketAddr,
+    /// Host name with port the scanner was given by, resolved again on each
+    /// initialization
+    pub scanner_name: Option<String>,
+    pub bind_addr: Option<SocketAddr>,
+    pub socket_options: SocketOptions,
     pub hostname: Host,
     pub initial_max_waiting: u64,
+    pub backoff_initial: Duration,
     pub backoff_factor: f32,
-    pub backoff_maximum: u64,
-    pub command: (OsString, Vec<OsString>),
+    pub backoff_maximum: Duration,
+    /// Interval between renewals of the registration, overriding the quirks
+    /// of the model
+    pub keepalive_interval: Option<Duration>,
+    pub reset: bool,
+    /// Minimum time between launched commands, ignoring scan jobs in between
+    pub cooldown: Option<Duration>,
+    /// Maximum time between batches of pages of the same feeder job
+    pub adf_window: Duration,
+    pub status_rules: StatusRules,
+    /// Command to launch for each scan job, or `None` to only report them
+    pub command: Option<(OsString, Vec<OsString>)>,
+    /// Receive the progress of the listener
+    pub events: Vec<UnboundedSender<ListenerEvent>>,
+    /// Pattern the model of the scanner must match, see [`glob_match`]
+    pub expect_model: Option<String>,
+    /// Times of the day when the scanner is polled, always if `None`
+    pub active_hours: Option<ActiveHours>,
+    /// Whether to send a Wake-on-LAN packet when the scanner doesn't answer
+    /// discover commands
+    pub wol: bool,
+    /// MAC address to wake until the scanner is discovered
+    pub wol_mac: Option<MacAddr>,
+    /// Offset of the local time zone captured at startup, or `None` if it
+    /// couldn't be determined, see [`now_at`]
+    pub local_offset: Option<UtcOffset>,
+}
+
+/// A device other than the expected scanner answering at the address of the
+/// scanner, which is never polled
+#[derive(Error, Debug)]
+pub enum UnexpectedDevice {
+    #[error("device at {addr} is made by `{manufacturer}` rather than Canon")]
+    Manufacturer {
+        addr: SocketAddr,
+        manufacturer: String,
+    },
+    #[error("scanner at {addr} is `{model}`, which doesn't match `--expect-model {pattern}`")]
+    Model {
+        addr: SocketAddr,
+        model: String,
+        pattern: String,
+    },
+}
+
+/// Progress of a listener, for showing it other than in logs
+#[derive(Debug, Clone)]
+pub enum ListenerEvent {
+    State {
+        scanner: SocketAddr,
+        host: Host,
+        state: String,
+    },
+    Interrupt {
+        scanner: SocketAddr,
+        host: Host,
+        interrupt: String,
+    },
+    Transition {
+        scanner: SocketAddr,
+        host: Host,
+        action: Action,
+    },
+}
+
+/// Shared sockets by address family
+#[derive(Debug, Clone, Default)]
+struct Dispatchers {
+    v4: Option<Dispatcher>,
+    v6: Option<Dispatcher>,
+}
+
+impl Dispatchers {
+    /// Returns the socket reaching `addr`, if one of its family is bound.
+    fn for_addr(&self, addr: &SocketAddr) -> Option<&Dispatcher> {
+        match addr {
+            SocketAddr::V4(_) => self.v4.as_ref(),
+            SocketAddr::V6(_) => self.v6.as_ref(),
+        }
+    }
+
+    /// Returns where the socket reaching `addr` is kept.
+    fn slot(&mut self, addr: &SocketAddr) -> &mut Option<Dispatcher> {
+        match addr {
+            SocketAddr::V4(_) => &mut self.v4,
+            SocketAddr::V6(_) => &mut self.v6,
+        }
+    }
 }
 
 struct Listener {
     channel: Channel,
     state: State,
     session_id: u32,
+    registered_at: Instant,
+    last_action_id: Option<u32>,
+    /// Scan job whose reset the scanner didn't acknowledge, reported again
+    /// until it is
+    unreset_action_id: Option<u32>,
+    launched_at: Option<Instant>,
+    /// Number of scan jobs so far, numbering them
+    jobs: u32,
+    /// Last feeder job, which the next interrupt may continue
+    feeder_job: Option<Job>,
+    last_status: u32,
     config: ListenConfig,
+    store: Option<StateStore>,
+    saved: ScannerState,
+    history: History,
+    /// Unix time of the last successful poll
+    last_success: Option<i64>,
+    locks: ScannerLocks,
+    /// Whether the scanner was locked for this process
+    locked: bool,
+    quirks_db: QuirksDb,
+    resolver: Resolver,
+    /// Shared sockets by address family, for switching to another address
+    dispatchers: Dispatchers,
+    /// Quirks of the model, known once identified
+    quirks: Quirks,
+    identified: bool,
+    /// Whether the scanner answered the discover command of the last
+    /// initialization
+    discovered: bool,
+    /// MAC address of the scanner, once discovered
+    mac_addr: Option<MacAddr>,
+    /// Backoff of the previous run, continued if the first initialization fails
+    restored_backoff: Option<Duration>,
+    /// State last sent to `config.events`
+    reported: String,
 }
 
 impl Listener {
-    async fn new(config: ListenConfig) -> anyhow::Result<Self> {
-        let channel = Channel::new(config.scanner_addr).await?;
-
-        Ok(Self {
+    fn new(
+        channel: Channel,
+        config: ListenConfig,
+        store: Option<StateStore>,
+        locks: ScannerLocks,
+        quirks_db: QuirksDb,
+        resolver: Resolver,
+        dispatchers: Dispatchers,
+    ) -> Self {
+        let saved = store
+            .as_ref()
+            .map(|store| store.get(config.scanner_addr, &config.hostname.to_string()))
+            .unwrap_or_default();
+        let mac_addr = config
+            .wol_mac
+            .or_else(|| saved.mac_addr.as_deref()?.parse().ok());
+        let mut listener = Self {
             channel,
             state: State::Init,
             session_id: 0,
+            registered_at: Instant::now(),
+            last_action_id: None,
+            unreset_action_id: None,
+            launched_at: None,
+            jobs: 0,
+            feeder_job: None,
+            last_status: 0,
+            restored_backoff: saved.backoff.map(Duration::from_secs),
             config,
-        })
+            store,
+            history: saved.history.clone(),
+            last_success: saved.last_success,
+            saved,
+            locks,
+            locked: false,
+            quirks_db,
+            resolver,
+            dispatchers,
+            quirks: Quirks::default(),
+            identified: false,
+            discovered: false,
+            mac_addr,
+            reported: String::new(),
+        };
+        if let Some(session_id) = listener.saved.session_id {
+            // polling with the previous session avoids registering another one,
+            // a stale session fails the poll and falls back to initialization
+            info!(
+                "{host}: resuming session {session_id}",
+                host = listener.config.hostname
+            );
+            listener.session_id = session_id;
+            listener.state = State::Poll;
+        }
+        listener
+    }
+
+    /// Records the outcome of a poll in the history of the listener.
+    fn record(&mut self, status: Option<u32>, error: Option<String>) {
+        let now = OffsetDateTime::now_utc().replace_nanosecond(0).unwrap();
+        self.history.record(now.to_string(), status, error);
     }
 
-    async fn next(&mut self) -> anyhow::Result<State> {
+    /// Saves the state of the listener if it changed since last saved, or if
+    /// the last successful poll saved is older than [`state::stale_after`].
+    fn persist(&mut self) {
+        let Some(store) = &self.store else {
+            return;
+        };
+        let mut state = ScannerState {
+            session_id: matches!(self.state, State::Poll).then_some(self.session_id),
+            backoff: match self.state {
+                State::Backoff(dur) => Some(dur.as_secs()),
+                _ => None,
+            },
+            mac_addr: self.mac_addr.map(|mac| mac.to_string()),
+            history: self.history.clone(),
+            last_success: self.saved.last_success,
+            poll_interval_ms: Some(self.quirks.poll_interval.as_millis() as u64),
+        };
+        let save_interval = state::stale_after(self.quirks.poll_interval).as_secs() as i64;
+        let success_due = match (self.last_success, self.saved.last_success) {
+            (Some(last), Some(saved)) => last - saved >= save_interval,
+            (last, saved) => last != saved,
+        };
+        if state != self.saved || success_due {
+            state.last_success = self.last_success;
+            store.set(
+                self.config.scanner_addr,
+                &self.config.hostname.to_string(),
+                state.clone(),
+            );
+            self.saved = state;
+        }
+    }
+
+    /// Does the work of the current state, talking to the scanner.
+    async fn work(&mut self) -> anyhow::Result<()> {
         match &self.state {
             State::Init => {
                 trace!("initialize listener");
 
                 self.try_init(Duration::from_secs(self.config.initial_max_waiting))
-                    .await?;
-
-                Ok(State::Poll)
+                    .await
             }
             State::Poll => {
                 trace!("polling listener");
 
-                let max_waiting = Duration::from_secs(self.config.initial_max_waiting);
-                let now = OffsetDateTime::now_local().unwrap_or_else(|_| OffsetDateTime::now_utc());
-                let now = PrimitiveDateTime::new(now.date(), now.time());
-
-                let command = poll::CommandBuilder::new(poll::PollType::Full)
-                    .host(self.config.hostname)
-                    .session_id(self.session_id)
-                    .datetime(now)
-                    .build()
-                    .unwrap();
-                timeout(max_waiting, self.channel.send(PayloadType::Poll, command))
-                    .await?
-                    .context("timeout when sending poll command")?;
-                let resp: poll::Response = timeout(max_waiting, self.channel.recv())
-                    .await?
-                    .context("timeout awaiting poll response")?;
+                // initialization waits the longest, in case the scanner is
+                // slow after restarting
+                let max_waiting = self
+                    .channel
+                    .timeout(Duration::from_secs(self.config.initial_max_waiting));
+                if !self.locked {
+                    // a resumed session skipped initialization
+                    self.discover(max_waiting).await?;
+                }
+                if self.renewal_due() {
+                    trace!("renewing host registration");
+                    self.register(max_waiting).await?;
+                }
+
+                let resp = self.send_poll(max_waiting).await?;
 
+                self.record(Some(resp.status()), None);
+                self.last_success = Some(OffsetDateTime::now_utc().unix_timestamp());
+                let session_invalid = self.quirks.session_invalid;
+                if session_invalid.is_some_and(|flags| resp.status_flags().contains(flags)) {
+                    // e.g. the scanner restarted
+                    info!(
+                        "scanner dropped session {session_id}, registering again",
+                        session_id = self.session_id
+                    );
+                    return self.register(max_waiting).await;
+                }
                 if let Some(session_id) = resp.session_id() {
                     self.session_id = session_id;
                 }
 
-                if resp.status() == 0x8000 {
-                    if let Some(interrupt) = resp.interrupt() {
-                        info!("received scanner job: {interrupt}");
-                        ignore_err(self.launch(interrupt));
+                let status = resp.status_flags().condition();
+                if status != self.last_status {
+                    self.last_status = status;
+                    if status != 0 {
+                        self.config.status_rules.apply(status)?;
                     }
-
-                    // cancel job
-                    let command = poll::CommandBuilder::new(poll::PollType::Reset)
-                        .host(self.config.hostname)
-                        .session_id(self.session_id)
-                        .action_id(resp.action_id().unwrap_or(0))
-                        .build()
-                        .unwrap();
-
-                    timeout(max_waiting, self.channel.send(PayloadType::Poll, command))
-                        .await?
-                        .context("timeout when sending poll command")?;
-
-                    let _: poll::Response = timeout(max_waiting, self.channel.recv())
-                        .await?
-                        .context("timeout awaiting poll response")?;
                 }
 
-                // 1 seconds between polling
-                sleep(Duration::from_secs(1)).await;
-                Ok(State::Poll)
+                self.service(&resp, max_waiting).await?;
+
+                sleep(self.quirks.poll_interval).await;
+                Ok(())
             }
             State::Backoff(dur) => {
                 trace!("backing off listener");
 
+                sleep(*dur).await;
                 // try again
-                self.try_init(*dur).await?;
+                self.try_init(Duration::from_secs(self.config.initial_max_waiting))
+                    .await
+            }
+            State::Quiet(dur) => {
+                trace!("pausing listener");
 
-                Ok(State::Poll)
+                sleep(*dur).await;
+                Ok(())
+            }
+            State::Asleep => {
+                trace!("probing sleeping scanner");
+
+                sleep(ASLEEP_PROBE_INTERVAL).await;
+                let max_waiting = Duration::from_secs(self.config.initial_max_waiting);
+                self.wake(max_waiting).await;
+                self.try_init(max_waiting).await
             }
         }
     }
 
     async fn try_init(&mut self, max_waiting: Duration) -> anyhow::Result<()> {
-        self.channel.reset_sequence();
-
-        // Detect scanner online
-        timeout(max_waiting, self.channel.send(PayloadType::Discover, Empty))
-            .await?
-            .context("timeout when sending discover command")?;
-        let _: discover::Response = timeout(max_waiting, self.channel.recv())
-            .await?
-            .context("timeout awaiting disover response")?;
+        self.discovered = false;
+        match self.discover_any(max_waiting).await {
+            Err(e) if e.chain().any(|cause| cause.is::<Elapsed>()) && self.config.wol => {
+                let Some(mac) = self.mac_addr else {
+                    debug!("MAC address of the scanner unknown, can't wake it up");
+                    return Err(e);
+                };
+                info!(
+                    "{host}: scanner not answering, sending Wake-on-LAN packet to {mac}",
+                    host = self.config.hostname
+                );
+                wol::wake(mac, self.channel.peer()).await?;
+                sleep(WOL_DELAY).await;
+                self.discover(max_waiting).await?;
+            }
+            result => result?,
+        }
+        self.discovered = true;
 
         // Send initial poll
+        self.register(max_waiting).await
+    }
+
+    /// Addresses to discover the scanner at, the current one first and then
+    /// one of each other address family its host name resolves to.
+    async fn addresses(&self) -> Vec<SocketAddr> {
+        let current = self.channel.peer();
+        let Some(name) = &self.config.scanner_name else {
+            return vec![current];
+        };
+        let resolved = match self.resolver.resolve(name).await {
+            Ok(resolved) => resolved,
+            Err(e) => {
+                // the last address may still be the right one
+                debug!("couldn't resolve {name}: {e}");
+                return vec![current];
+            }
+        };
+        let mut addrs = vec![];
+        if resolved.contains(&current) {
+            addrs.push(current);
+        }
+        for &addr in &resolved {
+            if self.dispatchers.for_addr(&addr).is_some()
+                && !addrs.iter().any(|other| other.is_ipv4() == addr.is_ipv4())
+            {
+                addrs.push(addr);
+            }
+        }
+        if addrs.is_empty() {
+            addrs.push(current);
+        }
+        addrs
+    }
+
+    /// Discovers the scanner at each of its addresses in turn, until one
+    /// answers, and keeps polling it there.
+    async fn discover_any(&mut self, max_waiting: Duration) -> anyhow::Result<()> {
+        let previous = self.channel.peer();
+        let addrs = self.addresses().await;
+        for (i, &addr) in addrs.iter().enumerate() {
+            if addr != self.channel.peer() {
+                let dispatcher = self
+                    .dispatchers
+                    .for_addr(&addr)
+                    .with_context(|| format!("no socket to reach {addr}"))?;
+                self.channel = dispatcher.channel(addr);
+            }
+            self.channel.reset_sequence();
+            match self.discover(max_waiting).await {
+                Err(e) if e.chain().any(|cause| cause.is::<Elapsed>()) && i + 1 < addrs.len() => {
+                    debug!("scanner not answering at {addr}, trying {}", addrs[i + 1]);
+                }
+                result => {
+                    if result.is_ok() && addr != previous {
+                        info!("{host}: scanner now at {addr}", host = self.config.hostname);
+                    }
+                    return result;
+                }
+            }
+        }
+        unreachable!("there is at least one address to discover at")
+    }
+
+    /// Detects the scanner online, locks it for this process and identifies
+    /// its model the first time.
+    async fn discover(&mut self, max_waiting: Duration) -> anyhow::Result<()> {
+        let resp = self
+            .channel
+            .roundtrip_with_timeout(discover::Request::new(), max_waiting, 0)
+            .await?;
+
+        self.locks.acquire(resp.mac_addr())?;
+        self.mac_addr = Some(*resp.mac_addr());
+        self.locked = true;
+        if !self.identified {
+            self.identify(max_waiting).await?;
+        }
+        Ok(())
+    }
+
+    /// Sends the discover commands waking the model from deep sleep, if any.
+    async fn wake(&mut self, max_waiting: Duration) {
+        for _ in 0..self.quirks.wake_probes {
+            let probe =
+                self.channel
+                    .roundtrip_with_timeout(discover::Request::new(), max_waiting, 0);
+            // the scanner may take several probes to answer
+            if let Err(e) = probe.await {
+                trace!("no answer to wake probe: {e}");
+            }
+        }
+    }
+
+    /// Looks up the quirks of the scanner by its model.
+    async fn identify(&mut self, max_waiting: Duration) -> anyhow::Result<()> {
+        let identity = self
+            .channel
+            .roundtrip_with_timeout(identity::Request::new(), max_waiting, 0)
+            .await?;
+
+        let host = self.config.hostname;
+        let addr = self.config.scanner_addr;
+        let manufacturer = identity.get("MFG").unwrap_or_default();
+        if !manufacturer.starts_with("Canon") {
+            return Err(UnexpectedDevice::Manufacturer {
+                addr,
+                manufacturer: manufacturer.to_owned(),
+            }
+            .into());
+        }
+        let model = identity.get("MDL").unwrap_or_default();
+        if let Some(pattern) = &self.config.expect_model {
+            if !glob_match(pattern, model) {
+                return Err(UnexpectedDevice::Model {
+                    addr,
+                    model: model.to_owned(),
+                    pattern: pattern.clone(),
+                }
+                .into());
+            }
+        }
+        info!("{host}: scanner model is `{model}`");
+        self.quirks = self.quirks_db.resolve(model);
+        debug!("{host}: applying quirks {quirks:?}", quirks = self.quirks);
+        if self.quirks.reset == Some(true) && !self.config.reset {
+            warn!(
+                "{host}: `{model}` reports no further scan jobs until reset, `--no-reset` may \
+                leave it stuck"
+            );
+        }
+        self.identified = true;
+        Ok(())
+    }
+
+    /// Whether scan jobs are reset, unless the model must not be.
+    fn reset(&self) -> bool {
+        self.config.reset && self.quirks.reset != Some(false)
+    }
+
+    /// Whether the registration is to be renewed, every `--keepalive-interval`
+    /// or as often as the model needs.
+    fn renewal_due(&self) -> bool {
+        let keepalive_interval = self
+            .config
+            .keepalive_interval
+            .unwrap_or(self.quirks.keepalive_interval);
+        self.registered_at.elapsed() >= keepalive_interval
+    }
+
+    /// Registers the host on the scanner with a host-only poll.
+    async fn register(&mut self, max_waiting: Duration) -> anyhow::Result<()> {
+        let resp = self.send_registration(max_waiting).await?;
+        let resp = match resp.session_id() {
+            Some(_) => resp,
+            None => {
+                // a button pressed before startup is reported in place of a
+                // session, and can't be reset without one. It is reported
+                // again by the first poll once registered
+                info!("scanner job pending since before registration, serviced once registered");
+                self.send_registration(max_waiting).await?
+            }
+        };
+        self.session_id = resp
+            .session_id()
+            .ok_or_else(|| anyhow!("scanner keeps reporting a job during host registration"))?;
+        self.registered_at = Instant::now();
+        debug!(
+            "registered as {host} with session {session_id}",
+            host = self.config.hostname,
+            session_id = self.session_id
+        );
+
+        Ok(())
+    }
+
+    async fn send_registration(&mut self, max_waiting: Duration) -> anyhow::Result<poll::Response> {
         let command = poll::CommandBuilder::new(poll::PollType::HostOnly)
             .host(self.config.hostname)
             .build()
             .unwrap();
-        timeout(max_waiting, self.channel.send(PayloadType::Poll, command))
-            .await?
-            .context("timeout when sending poll command")?;
-        let resp: poll::Response = timeout(max_waiting, self.channel.recv())
-            .await?
-            .context("timeout awaiting poll response")?;
+        self.channel
+            .roundtrip_with_timeout(command, max_waiting, 0)
+            .await
+    }
 
-        self.session_id = resp
-            .session_id()
-            .ok_or_else(|| anyhow!("unexpected interrupt during first poll"))?;
+    async fn send_poll(&mut self, max_waiting: Duration) -> anyhow::Result<poll::Response> {
+        let command = poll::CommandBuilder::new(poll::PollType::Full)
+            .host(self.config.hostname)
+            .session_id(self.session_id)
+            .datetime(
+                local_or_utc_now(self.config.local_offset)
+                    .try_into()
+                    .expect("current year fits in 4 digits"),
+            )
+            .build()
+            .unwrap();
+        let layout = &self.quirks.interrupt_layout;
+        self.channel
+            .roundtrip_parsed_with_timeout(
+                command,
+                |buffer| poll::Response::deserialize_with_layout(buffer, layout),
+                max_waiting,
+                0,
+            )
+            .await
+    }
+
+    /// Launches the scan job reported in `resp` if any, then resets it.
+    async fn service(
+        &mut self,
+        resp: &poll::Response,
+        max_waiting: Duration,
+    ) -> anyhow::Result<()> {
+        if !resp.status_flags().contains(StatusFlags::INTERRUPT) {
+            return Ok(());
+        }
+
+        // without resetting, the same job is reported until serviced elsewhere
+        let seen = (!self.reset() && self.last_action_id == resp.action_id())
+            || (self.unreset_action_id.is_some() && self.unreset_action_id == resp.action_id());
+        let cooling_down = self.config.cooldown.is_some_and(|cooldown| {
+            self.launched_at
+                .is_some_and(|launched_at| launched_at.elapsed() < cooldown)
+        });
+        match resp.interrupt().filter(|_| !seen) {
+            Some(interrupt) if cooling_down => {
+                info!("ignored scanner job during cooldown: {interrupt}");
+            }
+            Some(interrupt) => {
+                info!("received scanner job: {interrupt}");
+                self.report(|scanner, host| ListenerEvent::Interrupt {
+                    scanner,
+                    host,
+                    interrupt: interrupt.to_string(),
+                });
+                self.launched_at = Some(Instant::now());
+                let job = self.next_job(interrupt);
+                ignore_err(self.launch(interrupt, job));
+            }
+            None => {}
+        }
+        self.last_action_id = resp.action_id();
 
+        if self.reset() {
+            // action id 0 is rejected by some models, which then keep the job
+            match resp.action_id().filter(|action_id| *action_id != 0) {
+                Some(action_id) => self.reset_job(action_id, max_waiting).await,
+                None => warn!("scanner job without an action id, it can't be reset"),
+            }
+        }
         Ok(())
     }
 
-    fn transit_err(&mut self) {
-        match &self.state {
-            State::Init => {
-                trace!("transit to Backoff");
-                self.state = State::Backoff(Duration::from_secs(self.config.initial_max_waiting));
+    /// Resets scan job `action_id`, retrying with backoff until the scanner no
+    /// longer reports it.
+    async fn reset_job(&mut self, action_id: u32, max_waiting: Duration) {
+        let mut backoff = RESET_BACKOFF;
+        for attempt in 1..=RESET_ATTEMPTS {
+            match self.send_reset(action_id, max_waiting).await {
+                Ok(resp) if resp.action_id() != Some(action_id) => {
+                    trace!("reset scanner job {action_id}");
+                    self.unreset_action_id = None;
+                    return;
+                }
+                Ok(_) => debug!("scanner job {action_id} still reported after reset #{attempt}"),
+                Err(e) => debug!("reset #{attempt} of scanner job {action_id} failed: {e:#}"),
             }
-            State::Poll => {
-                trace!("transit to Init");
-                self.state = State::Init;
+            if attempt < RESET_ATTEMPTS {
+                sleep(backoff).await;
+                backoff *= 2;
             }
-            State::Backoff(dur) => {
-                trace!("transit to longer Backoff");
+        }
 
-                let new_dur = cmp::min(
-                    self.config.backoff_maximum,
-                    (dur.as_secs() as f32 * self.config.backoff_factor) as u64,
-                );
-                self.state = State::Backoff(Duration::from_secs(new_dur));
+        if self.unreset_action_id != Some(action_id) {
+            warn!(
+                "scanner never acknowledged reset of job {action_id}, it may report no further \
+                scan jobs until the job is cancelled on the scanner"
+            );
+            self.unreset_action_id = Some(action_id);
+        }
+    }
+
+    async fn send_reset(
+        &mut self,
+        action_id: u32,
+        max_waiting: Duration,
+    ) -> anyhow::Result<poll::Response> {
+        let command = poll::CommandBuilder::new(poll::PollType::Reset)
+            .host(self.config.hostname)
+            .session_id(self.session_id)
+            .action_id(action_id)
+            .build()
+            .unwrap();
+        self.channel
+            .roundtrip_with_timeout(command, max_waiting, 0)
+            .await
+    }
+
+    /// Moves to the state following `input`, carrying out the action of the
+    /// transition.
+    fn transit(&mut self, input: Input) {
+        // the backoff of the previous run only continues the first failure
+        let restored = match self.state {
+            State::Init => self.restored_backoff.take(),
+            _ => None,
+        };
+        let backoff = Backoff {
+            initial: restored.unwrap_or(self.config.backoff_initial),
+            factor: self.config.backoff_factor,
+            maximum: self.config.backoff_maximum,
+        };
+        let (state, action) = machine::next(self.state, input, &backoff);
+        if state != self.state {
+            trace!("transit from {:?} to {state:?} on {input:?}", self.state);
+        }
+        self.state = state;
+        let Some(action) = action else {
+            return;
+        };
+
+        let host = self.config.hostname;
+        match action {
+            Action::Connected | Action::Disconnected => {}
+            // the next failure starts the backoff over
+            Action::Reconnected => info!("{host}: reconnected to scanner"),
+            Action::Asleep => info!(
+                "{host}: scanner seems asleep, probing every {}s",
+                ASLEEP_PROBE_INTERVAL.as_secs()
+            ),
+            Action::WokeUp => info!("{host}: scanner woke up"),
+            Action::Paused(dur) => {
+                if let Some(hours) = &self.config.active_hours {
+                    info!(
+                        "{host}: outside of active hours {hours}, pausing polling for {}s",
+                        dur.as_secs()
+                    );
+                }
             }
+            Action::Unpaused => info!("{host}: active hours started, resuming polling"),
+        }
+        self.report(|scanner, host| ListenerEvent::Transition {
+            scanner,
+            host,
+            action,
+        });
+    }
+
+    /// Sends an event to each of `config.events`.
+    fn report(&self, event: impl Fn(SocketAddr, Host) -> ListenerEvent) {
+        for events in self.config.events.iter() {
+            // the receiver going away only stops reporting
+            let _ = events.send(event(self.config.scanner_addr, self.config.hostname));
         }
     }
 
-    fn launch(&self, interrupt: &Interrupt) -> anyhow::Result<()> {
+    /// Reports the state of the listener if it changed since last reported.
+    fn report_state(&mut self) {
+        let state = match self.state {
+            State::Init => "initializing".to_owned(),
+            State::Poll => format!("polling (session {})", self.session_id),
+            State::Backoff(dur) => format!("retrying in {}s", dur.as_secs()),
+            State::Quiet(dur) => format!("quiet hours, resuming in {}s", dur.as_secs()),
+            State::Asleep => "scanner asleep".to_owned(),
+        };
+        if state != self.reported {
+            debug!(
+                "{host}: {state}; {peer}: {stats}",
+                host = self.config.hostname,
+                stats = self.channel.stats(),
+                peer = self.channel.peer()
+            );
+            crash::state(
+                self.config.scanner_addr,
+                &self.config.hostname.to_string(),
+                format!("{state}; {}: {}", self.channel.peer(), self.channel.stats()),
+            );
+            self.reported = state;
+            self.report(|scanner, host| ListenerEvent::State {
+                scanner,
+                host,
+                state: self.reported.clone(),
+            });
+        }
+    }
+
+    /// Returns the job an interrupt belongs to. Feeder interrupts of the same
+    /// session within `adf_window` of each other are batches of the same job.
+    fn next_job(&mut self, interrupt: &Interrupt) -> Job {
+        let now = Instant::now();
+        let feeder = interrupt.source() == poll::Source::AutoDocumentFeeder;
+        let continued = self.feeder_job.filter(|job| {
+            feeder
+                && job.session_id == self.session_id
+                && now - job.last_batch_at < self.config.adf_window
+        });
+        let job = match continued {
+            Some(job) => Job {
+                batch: job.batch + 1,
+                last_batch_at: now,
+                ..job
+            },
+            None => {
+                self.jobs = self.jobs.wrapping_add(1);
+                Job {
+                    id: self.jobs,
+                    batch: 1,
+                    session_id: self.session_id,
+                    last_batch_at: now,
+                }
+            }
+        };
+        self.feeder_job = feeder.then_some(job);
+        job
+    }
+
+    fn launch(&self, interrupt: &Interrupt, job: Job) -> anyhow::Result<()> {
+        let Some((cmd, args)) = &self.config.command else {
+            return Ok(());
+        };
+        let _span = info_span!("job", id = job.id, batch = job.batch).entered();
         trace!("launch external program");
 
         let color_mode = match interrupt.color_mode() {
             poll::ColorMode::Color => "COLOR",
             poll::ColorMode::Mono => "MONO",
         };
-        let size = match interrupt.size() {
+        let size = lenient_env(interrupt.size(), |size| match size {
             poll::Size::A4 => "A4",
             poll::Size::Letter => "LETTER",
             poll::Size::_10x15 => "10x15",
             poll::Size::_13x18 => "13x18",
             poll::Size::Auto => "AUTO",
-        };
-        let format = match interrupt.format() {
+        });
+        let format = lenient_env(interrupt.format(), |format| match format {
             poll::Format::Jpeg => "JPEG",
             poll::Format::Tiff => "TIFF",
             poll::Format::Pdf => "PDF",
             poll::Format::KompaktPdf => "KOMPAKT_PDF",
-        };
-        let dpi = match interrupt.dpi() {
+        });
+        let dpi = lenient_env(interrupt.dpi(), |dpi| match dpi {
             poll::DPI::_75 => "75",
             poll::DPI::_150 => "150",
             poll::DPI::_300 => "300",
             poll::DPI::_600 => "600",
-        };
+        });
         let source = match interrupt.source() {
             poll::Source::Flatbed => "FLATBED",
             poll::Source::AutoDocumentFeeder => "FEEDER",
@@ -214,17 +862,18 @@ impl Listener {
             None => "",
         };
 
-        let (cmd, args) = &self.config.command;
-
         Command::new(cmd)
             .args(args)
             .env("SCANNER_COLOR_MODE", color_mode)
-            .env("SCANNER_PAGE", size)
-            .env("SCANNER_FORMAT", format)
-            .env("SCANNER_DPI", dpi)
+            .env("SCANNER_PAGE", size.as_ref())
+            .env("SCANNER_FORMAT", format.as_ref())
+            .env("SCANNER_DPI", dpi.as_ref())
             .env("SCANNER_SOURCE", source)
             .env("SCANNER_ADF_TYPE", feeder_type)
             .env("SCANNER_ADF_ORIENT", feeder_orientation)
+            .env("SCANNER_TARGET", self.config.hostname.to_string())
+            .env("SCANNER_JOB_ID", job.id.to_string())
+            .env("SCANNER_PAGE_BATCH", job.batch.to_string())
             .spawn()
             .with_context(|| format!("failed to launch executable `{}`", cmd.to_string_lossy()))?;
 
@@ -232,18 +881,482 @@ impl Listener {
     }
 }
 
-pub async fn listen(config: ListenConfig) -> anyhow::Result<()> {
-    debug!("loaded listening config {config:?}");
+/// Converts a field of an interrupt into the value of an environment variable,
+/// falling back to the raw byte in hex (e.g. `0x0c`) if it is unknown.
+fn lenient_env<T>(value: Lenient<T>, name: impl FnOnce(T) -> &'static str) -> Cow<'static, str> {
+    match value {
+        Lenient::Known(value) => name(value).into(),
+        Lenient::Unknown(byte) => format!("{byte:#04x}").into(),
+    }
+}
 
-    let mut listener = Listener::new(config).await?;
+/// Initializes as the listener of `config` would and polls once, without
+/// launching the command of the host entry nor resetting the scan job
+/// reported if any, which is left to the listener. Returns what the scanner
+/// answered.
+pub async fn cycle(
+    config: ListenConfig,
+    dispatcher: Dispatcher,
+    quirks_db: QuirksDb,
+) -> anyhow::Result<String> {
+    let channel = dispatcher.channel(config.scanner_addr);
+    let mut dispatchers = Dispatchers::default();
+    *dispatchers.slot(&config.scanner_addr) = Some(dispatcher);
+    // the lock keeps two processes from fighting over the sessions and scan
+    // jobs of the scanner, which a single poll leaving jobs alone doesn't, and
+    // a listener running meanwhile holds it
+    let locks = ScannerLocks::new(true);
+    let mut listener = Listener::new(
+        channel,
+        config,
+        None,
+        locks,
+        quirks_db,
+        Resolver::default(),
+        dispatchers,
+    );
+    let max_waiting = Duration::from_secs(listener.config.initial_max_waiting);
+    listener.try_init(max_waiting).await?;
+    let resp = listener.send_poll(max_waiting).await?;
+
+    let mut summary = format!(
+        "registered with session {session_id}, polled status {status:#x}",
+        session_id = listener.session_id,
+        status = resp.status()
+    );
+    let pending = resp
+        .action_id()
+        .filter(|_| resp.status_flags().contains(StatusFlags::INTERRUPT));
+    match pending {
+        Some(action_id) => summary.push_str(&format!(", left scan job {action_id} pending")),
+        None => summary.push_str(", no scan job pending"),
+    }
+    Ok(summary)
+}
+
+async fn run(
+    mut listener: Listener,
+    mut power: watch::Receiver<Power>,
+    mut network: watch::Receiver<()>,
+) -> anyhow::Result<()> {
+    debug!(
+        "loaded listening config {config:?}",
+        config = listener.config
+    );
 
     loop {
-        match listener.next().await {
-            Ok(new_state) => listener.state = new_state,
+        let until_active = listener.config.active_hours.as_ref().and_then(|hours| {
+            hours.until_active(local_or_utc_now(listener.config.local_offset).time())
+        });
+        if let Some(dur) = until_active {
+            listener.transit(Input::OutsideActiveHours(dur));
+        }
+        let backing_off = matches!(listener.state, State::Backoff(_));
+        if !backing_off {
+            // only a backoff is cut short, changes meanwhile are stale by then
+            network.borrow_and_update();
+        }
+        listener.report_state();
+        let span = debug_span!("iteration", state = ?listener.state);
+        tokio::select! {
+            result = listener.work().instrument(span) => match result {
+                Ok(()) => listener.transit(Input::Succeeded),
+                Err(e) if e.is::<AlreadyLocked>() || e.is::<UnexpectedDevice>() => return Err(e),
+                Err(e) => {
+                    warn!("{host}: {e:#}", host = listener.config.hostname);
+                    listener.record(None, Some(e.to_string()));
+                    let asleep =
+                        listener.discovered && e.chain().any(|cause| cause.is::<Elapsed>());
+                    listener.transit(Input::Failed { asleep });
+                }
+            },
+            Ok(()) = power.changed() => {
+                let host = listener.config.hostname;
+                if *power.borrow_and_update() == Power::Suspended {
+                    info!("{host}: paused until the system resumes");
+                    while *power.borrow_and_update() == Power::Suspended {
+                        power.changed().await?;
+                    }
+                }
+                debug!("{host}: re-initializing after resume");
+                listener.transit(Input::Resumed);
+            },
+            // dropping other work could cancel a launched scan job before its reset
+            Ok(()) = network.changed(), if backing_off => {
+                let host = listener.config.hostname;
+                debug!("{host}: re-initializing after network change");
+                listener.transit(Input::NetworkChanged);
+            },
+        }
+        listener.persist();
+    }
+}
+
+/// Returns the local date and time sent to scanners in full polls, or the UTC
+/// one if the time zone of the host couldn't be determined at startup.
+fn local_or_utc_now(offset: Option<UtcOffset>) -> PrimitiveDateTime {
+    let now = now_at(offset);
+    PrimitiveDateTime::new(now.date(), now.time())
+}
+
+/// Warns if the `offset` of the local time zone couldn't be captured at
+/// startup, so that UTC is used in its place.
+pub fn check_local_offset(offset: Option<UtcOffset>) {
+    if offset.is_none() {
+        warn!(
+            "couldn't determine the local time zone at startup, UTC time is sent to scanners and \
+            used for --active-hours instead"
+        );
+    }
+}
+
+/// Listens with one or more host entries registered on scanners, each
+/// launching its own command. All listeners of the same address family share a
+/// single socket.
+pub async fn listen(
+    configs: Vec<ListenConfig>,
+    store: Option<StateStore>,
+    locks: ScannerLocks,
+    credentials: Option<Credentials>,
+) -> anyhow::Result<()> {
+    let quirks_db = QuirksDb::load()?;
+    let resolver = Resolver::default();
+
+    let mut dispatchers = Dispatchers::default();
+    let power = suspend::watch();
+    let network = network::watch();
+    let mut listeners = Vec::with_capacity(configs.len());
+    for config in &configs {
+        let slot = dispatchers.slot(&config.scanner_addr);
+        if slot.is_none() {
+            let local = config
+                .bind_addr
+                .unwrap_or_else(|| unspecified_for(&config.scanner_addr));
+            *slot = Some(Dispatcher::bind(local, &config.socket_options).await?);
+        }
+        // a host name may also resolve to the other family
+        if config.scanner_name.is_some() && config.bind_addr.is_none() {
+            let local = if config.scanner_addr.is_ipv4() {
+                SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0))
+            } else {
+                SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0))
+            };
+            let slot = dispatchers.slot(&local);
+            if slot.is_none() {
+                match Dispatcher::bind(local, &config.socket_options).await {
+                    Ok(dispatcher) => *slot = Some(dispatcher),
+                    Err(e) => debug!("{e:#}, only trying addresses of the same family"),
+                }
+            }
+        }
+    }
+    for config in configs {
+        let channel = dispatchers
+            .for_addr(&config.scanner_addr)
+            .with_context(|| format!("no socket to reach {}", config.scanner_addr))?
+            .channel(config.scanner_addr);
+        listeners.push(Listener::new(
+            channel,
+            config,
+            store.clone(),
+            locks.clone(),
+            quirks_db.clone(),
+            resolver.clone(),
+            dispatchers.clone(),
+        ));
+    }
+    // sockets are bound, nothing below needs more than the given user
+    if let Some(credentials) = credentials {
+        credentials.apply()?;
+    }
+
+    let mut set = JoinSet::new();
+    for listener in listeners {
+        let span = info_span!(
+            "listener",
+            scanner = %listener.config.scanner_addr,
+            host = %listener.config.hostname
+        );
+        set.spawn(run(listener, power.clone(), network.clone()).instrument(span));
+    }
+    let terminated = terminated();
+    tokio::pin!(terminated);
+    loop {
+        tokio::select! {
+            result = set.join_next() => match result {
+                Some(result) => result.context("failed to join task")??,
+                None => break,
+            },
+            signal = &mut terminated => {
+                // state is saved after every iteration, nothing is lost
+                info!("stopping on {signal}");
+                break;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Waits for a request to terminate, returning the name of the signal.
+async fn terminated() -> &'static str {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        match signal(SignalKind::terminate()) {
+            Ok(mut sigterm) => tokio::select! {
+                _ = sigterm.recv() => "SIGTERM",
+                _ = tokio::signal::ctrl_c() => "SIGINT",
+            },
             Err(e) => {
-                warn!("{e}");
-                listener.transit_err();
+                warn!("couldn't listen for SIGTERM: {e}");
+                let _ = tokio::signal::ctrl_c().await;
+                "SIGINT"
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+        "Ctrl-C"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::{
+        net::UdpSocket,
+        sync::mpsc::{self, UnboundedReceiver},
+        time::{advance, timeout},
+    };
+
+    /// Answers as a scanner reporting scan job 1 in full polls until reset,
+    /// leaving the first reset unacknowledged. Sends each reset received to
+    /// `resets`.
+    async fn stuck_job(scanner: UdpSocket, resets: UnboundedSender<()>) {
+        let mut buffer = [0; 1024];
+        let mut resets_received = 0;
+        loop {
+            let (size, peer) = scanner.recv_from(&mut buffer).await.unwrap();
+            let mut response = buffer[..16].to_vec();
+            response[4] = 0x82;
+            let poll_type = buffer.get(17).filter(|_| size > 17);
+            match (buffer[5], poll_type) {
+                // discover
+                (0x01, _) => {
+                    response.extend([0, 1, 8, 0, 6, 4, 0x00, 0x1e, 0x8f, 1, 2, 3, 127, 0, 0, 1]);
+                }
+                (0x32, Some(0x02 | 0x05)) => {
+                    if poll_type == Some(&0x05) {
+                        resets_received += 1;
+                        let _ = resets.send(());
+                    }
+                    if resets_received < 2 {
+                        // interrupt flag, action id 1 and a flatbed interrupt
+                        response.extend([0, 0, 0x80, 0, 0, 0, 0, 0, 0, 0, 0, 0x14, 0, 0, 0, 1]);
+                        response
+                            .extend([0, 0, 0, 0, 0, 0, 0, 1, 1, 0, 1, 1, 2, 0, 0, 0, 0, 0, 0, 0]);
+                    } else {
+                        response.extend([0, 0, 0, 0, 0, 0, 0, 7]);
+                        response.extend([0; 28]);
+                    }
+                }
+                (0x32, _) => {
+                    response.extend([0, 0, 0, 0, 0, 0, 0, 7]);
+                    response.extend([0; 28]);
+                }
+                _ => {}
+            }
+            let size = (response.len() - 16) as u32;
+            response[12..16].copy_from_slice(&size.to_be_bytes());
+            scanner.send_to(&response, peer).await.unwrap();
+        }
+    }
+
+    /// Answers as a scanner with scan job 1 pending before the host registers,
+    /// reporting it in place of a session to the first registration and in
+    /// full polls until reset. Sends the session of each reset to `resets`.
+    async fn job_before_registration(scanner: UdpSocket, resets: UnboundedSender<u32>) {
+        let mut buffer = [0; 1024];
+        let mut registrations = 0;
+        let mut reset = false;
+        loop {
+            let (size, peer) = scanner.recv_from(&mut buffer).await.unwrap();
+            let mut response = buffer[..16].to_vec();
+            response[4] = 0x82;
+            let poll_type = buffer.get(17).filter(|_| size > 17);
+            let job = match (buffer[5], poll_type) {
+                // discover
+                (0x01, _) => {
+                    response.extend([0, 1, 8, 0, 6, 4, 0x00, 0x1e, 0x8f, 1, 2, 3, 127, 0, 0, 1]);
+                    None
+                }
+                (0x32, Some(0x01)) => {
+                    registrations += 1;
+                    Some(registrations == 1)
+                }
+                (0x32, Some(0x02)) => Some(!reset),
+                (0x32, Some(0x05)) => {
+                    reset = true;
+                    let _ = resets.send(u32::from_be_bytes(buffer[20..24].try_into().unwrap()));
+                    Some(false)
+                }
+                _ => None,
+            };
+            match job {
+                Some(true) => {
+                    // interrupt flag, action id 1 and a flatbed interrupt
+                    response.extend([0, 0, 0x80, 0, 0, 0, 0, 0, 0, 0, 0, 0x14, 0, 0, 0, 1]);
+                    response.extend([0, 0, 0, 0, 0, 0, 0, 1, 1, 0, 1, 1, 2, 0, 0, 0, 0, 0, 0, 0]);
+                }
+                Some(false) => {
+                    response.extend([0, 0, 0, 0, 0, 0, 0, 7]);
+                    response.extend([0; 28]);
+                }
+                None => {}
             }
+            let size = (response.len() - 16) as u32;
+            response[12..16].copy_from_slice(&size.to_be_bytes());
+            scanner.send_to(&response, peer).await.unwrap();
         }
     }
+
+    /// Returns a listener along with the socket of its scanner, which answers
+    /// nothing unless a test answers through it.
+    async fn listener() -> (Listener, UdpSocket) {
+        let scanner = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let scanner_addr = scanner.local_addr().unwrap();
+        let socket_options = SocketOptions::default();
+        let dispatcher = Dispatcher::bind("127.0.0.1:0".parse().unwrap(), &socket_options)
+            .await
+            .unwrap();
+        let config = ListenConfig {
+            scanner_addr,
+            scanner_name: None,
+            bind_addr: None,
+            socket_options,
+            hostname: Host::new("test"),
+            initial_max_waiting: 1,
+            backoff_initial: Duration::from_secs(1),
+            backoff_factor: 2.0,
+            backoff_maximum: Duration::from_secs(5),
+            keepalive_interval: None,
+            reset: true,
+            cooldown: None,
+            adf_window: Duration::from_secs(10),
+            status_rules: StatusRules::default(),
+            command: None,
+            events: vec![],
+            expect_model: None,
+            active_hours: None,
+            wol: false,
+            wol_mac: None,
+            local_offset: None,
+        };
+        let listener = Listener::new(
+            dispatcher.channel(scanner_addr),
+            config,
+            None,
+            ScannerLocks::new(true),
+            QuirksDb::default(),
+            Resolver::default(),
+            Dispatchers {
+                v4: Some(dispatcher),
+                v6: None,
+            },
+        );
+        (listener, scanner)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn backoff_sleeps_before_retrying() {
+        let (mut listener, _scanner) = listener().await;
+        listener.state = State::Backoff(Duration::from_secs(4));
+        let started = Instant::now();
+        let e = listener.work().await.unwrap_err();
+        assert!(e.chain().any(|cause| cause.is::<Elapsed>()));
+        // the backoff, then the discover command waited out
+        assert_eq!(started.elapsed(), Duration::from_secs(4 + 1));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn registration_renewed_after_keepalive_interval() {
+        let (mut listener, _scanner) = listener().await;
+        listener.registered_at = Instant::now();
+        let keepalive_interval = listener.quirks.keepalive_interval;
+        advance(keepalive_interval - Duration::from_secs(1)).await;
+        assert!(!listener.renewal_due());
+        advance(Duration::from_secs(1)).await;
+        assert!(listener.renewal_due());
+
+        listener.config.keepalive_interval = Some(keepalive_interval * 2);
+        assert!(!listener.renewal_due());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn quiet_hours_resume_with_initialization() {
+        let (mut listener, _scanner) = listener().await;
+        let started = Instant::now();
+        listener.state = State::Quiet(Duration::from_secs(3600));
+        listener.work().await.unwrap();
+        assert_eq!(started.elapsed(), Duration::from_secs(3600));
+        listener.transit(Input::Succeeded);
+        assert_eq!(listener.state, State::Init);
+    }
+
+    /// Counts the scan jobs reported so far.
+    fn interrupts(events: &mut UnboundedReceiver<ListenerEvent>) -> usize {
+        let mut interrupts = 0;
+        while let Ok(event) = events.try_recv() {
+            if let ListenerEvent::Interrupt { .. } = event {
+                interrupts += 1;
+            }
+        }
+        interrupts
+    }
+
+    #[tokio::test]
+    async fn network_change_while_polling_keeps_job() {
+        let (mut listener, scanner) = listener().await;
+        let (events, mut received) = mpsc::unbounded_channel();
+        listener.config.events.push(events);
+        listener.identified = true;
+        listener.quirks.poll_interval = Duration::from_millis(50);
+        let (resets, mut reset) = mpsc::unbounded_channel();
+        tokio::spawn(stuck_job(scanner, resets));
+        let (_power, power) = watch::channel(Power::Awake);
+        let (network_changed, network) = watch::channel(());
+        let running = tokio::spawn(run(listener, power, network));
+
+        let wait = Duration::from_secs(5);
+        timeout(wait, reset.recv()).await.unwrap();
+        // while waiting to reset again
+        network_changed.send(()).unwrap();
+        timeout(wait, reset.recv()).await.unwrap();
+        // polled again after the reset
+        sleep(Duration::from_millis(200)).await;
+        running.abort();
+        assert_eq!(interrupts(&mut received), 1);
+    }
+
+    #[tokio::test]
+    async fn job_pending_at_registration_reset_in_session() {
+        let (mut listener, scanner) = listener().await;
+        let (events, mut received) = mpsc::unbounded_channel();
+        listener.config.events.push(events);
+        listener.identified = true;
+        listener.quirks.poll_interval = Duration::from_millis(50);
+        let (resets, mut reset) = mpsc::unbounded_channel();
+        tokio::spawn(job_before_registration(scanner, resets));
+        let (_power, power) = watch::channel(Power::Awake);
+        let (_network_changed, network) = watch::channel(());
+        let running = tokio::spawn(run(listener, power, network));
+
+        // reset by the first poll, in the session issued by the scanner
+        let session_id = timeout(Duration::from_secs(5), reset.recv()).await.unwrap();
+        running.abort();
+        assert_eq!(session_id, Some(7));
+        assert_eq!(interrupts(&mut received), 1);
+    }
 }
@@ -0,0 +1,103 @@
+//! Drops the privileges of the process once its sockets are bound, so that it
+//! can be started as root without polling scanners and launching commands as
+//! root.
+
+use anyhow::bail;
+
+/// User and group to switch to, resolved before any privilege is dropped
+#[derive(Debug, Clone, Copy)]
+pub struct Credentials {
+    #[cfg(unix)]
+    uid: Option<libc::uid_t>,
+    #[cfg(unix)]
+    gid: Option<libc::gid_t>,
+}
+
+#[cfg(unix)]
+impl Credentials {
+    /// Resolves `user` and `group` given by names or numeric ids. Without a
+    /// group, the primary group of the user is used.
+    pub fn resolve(user: Option<&str>, group: Option<&str>) -> anyhow::Result<Self> {
+        use std::ffi::CString;
+
+        let mut uid = None;
+        let mut gid = None;
+        if let Some(user) = user {
+            let name = CString::new(user)?;
+            // SAFETY: name is a valid C string, and the returned entry is read
+            // before any other call into the password database
+            let entry = unsafe { libc::getpwnam(name.as_ptr()).as_ref() };
+            match (entry, user.parse()) {
+                (Some(entry), _) => {
+                    uid = Some(entry.pw_uid);
+                    gid = Some(entry.pw_gid);
+                }
+                (None, Ok(id)) => uid = Some(id),
+                (None, Err(_)) => bail!("unknown user `{user}`"),
+            }
+        }
+        if let Some(group) = group {
+            let name = CString::new(group)?;
+            // SAFETY: as above, with the group database
+            let entry = unsafe { libc::getgrnam(name.as_ptr()).as_ref() };
+            match (entry, group.parse()) {
+                (Some(entry), _) => gid = Some(entry.gr_gid),
+                (None, Ok(id)) => gid = Some(id),
+                (None, Err(_)) => bail!("unknown group `{group}`"),
+            }
+        }
+        Ok(Self { uid, gid })
+    }
+
+    /// Switches to the group and then the user, leaving supplementary groups
+    /// behind.
+    pub fn apply(self) -> anyhow::Result<()> {
+        use std::io;
+
+        use anyhow::Context;
+        use tracing::info;
+
+        fn check(ret: libc::c_int) -> io::Result<()> {
+            if ret < 0 {
+                Err(io::Error::last_os_error())
+            } else {
+                Ok(())
+            }
+        }
+
+        if let Some(gid) = self.gid {
+            // SAFETY: plain syscalls
+            check(unsafe { libc::setgroups(1, &gid) })
+                .context("failed to drop supplementary groups")?;
+            check(unsafe { libc::setgid(gid) })
+                .with_context(|| format!("failed to switch to group {gid}"))?;
+        }
+        if let Some(uid) = self.uid {
+            // SAFETY: plain syscall
+            check(unsafe { libc::setuid(uid) })
+                .with_context(|| format!("failed to switch to user {uid}"))?;
+            // SAFETY: plain syscall
+            if uid != 0 && unsafe { libc::setuid(0) } == 0 {
+                bail!("privileges were not dropped, root could be regained");
+            }
+        }
+        info!(
+            "dropped privileges to uid {} gid {}",
+            // SAFETY: plain syscalls
+            unsafe { libc::getuid() },
+            unsafe { libc::getgid() }
+        );
+        Ok(())
+    }
+}
+
+#[cfg(not(unix))]
+impl Credentials {
+    pub fn resolve(_user: Option<&str>, _group: Option<&str>) -> anyhow::Result<Self> {
+        bail!("switching user or group is not supported on this platform")
+    }
+
+    pub fn apply(self) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
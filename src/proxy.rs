@@ -0,0 +1,222 @@
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::Context;
+use bjnp::{
+    discover::{self, MacAddr, Responder},
+    serdes::Serialize,
+    Packet, PacketHeaderOnly, PacketType, PayloadType,
+};
+use tokio::{net::UdpSocket, task::JoinHandle, time::timeout};
+use tracing::{debug, info, trace, warn};
+
+use crate::{
+    dump::{self, Direction},
+    utils::{hex_dump, ignore_err, unspecified_for, SocketOptions},
+};
+
+/// Time after which a client without any traffic is forgotten
+const SESSION_IDLE: Duration = Duration::from_secs(300);
+
+#[derive(Debug)]
+pub struct ProxyConfig {
+    pub scanner_addr: SocketAddr,
+    pub bind_addr: SocketAddr,
+    pub socket_options: SocketOptions,
+    pub advertise_ip: Option<IpAddr>,
+    pub mac_addr: Option<MacAddr>,
+}
+
+struct Session {
+    upstream: Arc<UdpSocket>,
+    task: JoinHandle<()>,
+}
+
+pub async fn proxy(config: ProxyConfig) -> anyhow::Result<()> {
+    debug!("loaded proxy config {config:?}");
+
+    let local = Arc::new(
+        UdpSocket::bind(config.bind_addr)
+            .await
+            .with_context(|| format!("couldn't bind to {}", config.bind_addr))?,
+    );
+    local
+        .set_broadcast(true)
+        .with_context(|| format!("couldn't set socket at {} to broadcast", config.bind_addr))?;
+    info!(
+        "proxying {bind} to scanner at {scanner}",
+        bind = config.bind_addr,
+        scanner = config.scanner_addr
+    );
+
+    let mut sessions: HashMap<SocketAddr, Session> = HashMap::new();
+    let mut buffer = [0; 65536];
+    loop {
+        let (size, client) = local
+            .recv_from(&mut buffer)
+            .await
+            .with_context(|| format!("error receiving packet at {}", config.bind_addr))?;
+        let buffer = &buffer[..size];
+        trace!(
+            "inbound packet from {client}: {buffer:?}",
+            buffer = hex_dump(buffer)
+        );
+        dump::packet(Direction::Inbound, client, buffer);
+
+        if let Some(mac_addr) = config.mac_addr {
+            match answer_discover(&config, mac_addr, buffer, client).await {
+                Ok(Some(response)) => {
+                    dump::packet(Direction::Outbound, client, &response);
+                    if let Err(e) = local.send_to(&response, client).await {
+                        warn!("couldn't answer discover command from {client}: {e}");
+                    }
+                    continue;
+                }
+                Ok(None) => {}
+                Err(e) => {
+                    warn!("couldn't answer discover command from {client}: {e:?}");
+                    continue;
+                }
+            }
+        }
+
+        sessions.retain(|_, session| !session.task.is_finished());
+        let session = match sessions.get(&client) {
+            Some(session) => session,
+            None => {
+                let Some(session) = ignore_err(open_session(&config, local.clone(), client).await)
+                else {
+                    continue;
+                };
+                info!("new client {client}");
+                sessions.entry(client).or_insert(session)
+            }
+        };
+
+        dump::packet(Direction::Outbound, config.scanner_addr, buffer);
+        if let Err(e) = session.upstream.send(buffer).await {
+            warn!(
+                "couldn't forward packet from {client} to {scanner}: {e}",
+                scanner = config.scanner_addr
+            );
+        }
+    }
+}
+
+async fn open_session(
+    config: &ProxyConfig,
+    local: Arc<UdpSocket>,
+    client: SocketAddr,
+) -> anyhow::Result<Session> {
+    let scanner = config.scanner_addr;
+    let advertise_ip = match config.advertise_ip {
+        Some(ip) => ip,
+        None => local_ip_towards(client).await?,
+    };
+
+    let upstream_local = unspecified_for(&scanner);
+    let upstream = UdpSocket::bind(upstream_local)
+        .await
+        .with_context(|| format!("couldn't bind to {upstream_local}"))?;
+    config.socket_options.apply(&upstream).with_context(|| {
+        format!(
+            "couldn't set socket options {options:?} on {upstream_local}",
+            options = config.socket_options
+        )
+    })?;
+    upstream
+        .connect(scanner)
+        .await
+        .with_context(|| format!("couldn't connect to remote socket {scanner}"))?;
+    let upstream = Arc::new(upstream);
+
+    let task = tokio::spawn({
+        let upstream = upstream.clone();
+        async move {
+            let mut buffer = [0; 65536];
+            loop {
+                let size = match timeout(SESSION_IDLE, upstream.recv(&mut buffer)).await {
+                    Ok(Ok(size)) => size,
+                    Ok(Err(e)) => {
+                        warn!("error receiving packet from {scanner} for {client}: {e}");
+                        break;
+                    }
+                    Err(_) => {
+                        debug!("client {client} idle, closing session");
+                        break;
+                    }
+                };
+                let buffer = &buffer[..size];
+                trace!(
+                    "inbound packet from {scanner}: {buffer:?}",
+                    buffer = hex_dump(buffer)
+                );
+                dump::packet(Direction::Inbound, scanner, buffer);
+
+                let rewritten = rewrite_discover(buffer, advertise_ip);
+                let buffer = rewritten.as_deref().unwrap_or(buffer);
+                dump::packet(Direction::Outbound, client, buffer);
+                if let Err(e) = local.send_to(buffer, client).await {
+                    warn!("couldn't forward packet from {scanner} to {client}: {e}");
+                }
+            }
+        }
+    });
+
+    Ok(Session { upstream, task })
+}
+
+/// Answers a discover command locally without asking the scanner.
+async fn answer_discover(
+    config: &ProxyConfig,
+    mac_addr: MacAddr,
+    buffer: &[u8],
+    client: SocketAddr,
+) -> anyhow::Result<Option<Vec<u8>>> {
+    let advertise_ip = match config.advertise_ip {
+        Some(ip) => ip,
+        None => local_ip_towards(client).await?,
+    };
+    let responder = Responder::new(discover::Response::new(mac_addr, advertise_ip));
+    Ok(responder.respond(buffer)?.map(|packet| {
+        debug!("answering discover command from {client}: {packet:-}");
+        packet.serialize_to_vec()
+    }))
+}
+
+/// Rewrites the IP address of a discover response so that the client talks to
+/// the proxy instead of the scanner.
+fn rewrite_discover(buffer: &[u8], advertise_ip: IpAddr) -> Option<Vec<u8>> {
+    let packet = PacketHeaderOnly::parse(buffer).ok()?;
+    if packet.packet_type() != PacketType::ScannerResponse
+        || packet.payload_type() != PayloadType::Discover
+        || packet.payload_size() == 0
+    {
+        return None;
+    }
+    let packet: Packet<discover::Response> = ignore_err(packet.try_into())?;
+    let packet =
+        packet.map_payload(|response| discover::Response::new(*response.mac_addr(), advertise_ip));
+    debug!("advertising scanner as {advertise_ip}: {packet:-}");
+    Some(packet.serialize_to_vec())
+}
+
+/// Finds the local IP address used to reach `remote`.
+async fn local_ip_towards(remote: SocketAddr) -> anyhow::Result<IpAddr> {
+    let local = unspecified_for(&remote);
+    let socket = UdpSocket::bind(local)
+        .await
+        .with_context(|| format!("couldn't bind to {local}"))?;
+    socket
+        .connect(remote)
+        .await
+        .with_context(|| format!("couldn't find a route to {remote}"))?;
+    Ok(socket
+        .local_addr()
+        .with_context(|| format!("couldn't obtain local address towards {remote}"))?
+        .ip())
+}
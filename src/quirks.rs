@@ -0,0 +1,358 @@
+//! Deviations of scanner models from the MX920, which the protocol was worked
+//! out on, looked up by the `MDL` reported in the identity of the scanner.
+//!
+//! This is a mechanism only: the MX920 is the only built-in model, no other
+//! model having been captured yet, so quirks of other models come from the
+//! user. Built-in quirks are overridden by `quirks.toml` in the configuration
+//! directory, in the same format as [`BUILTIN`]. Models laying out interrupts
+//! differently are described by files in the `layouts` directory next to it,
+//! each named after the layout, e.g. `layouts/ts5100.toml`.
+
+use std::{collections::HashMap, fs, io, path::Path, sync::Arc};
+
+use anyhow::{anyhow, bail, Context};
+use bjnp::poll::{InterruptField, InterruptLayout, StatusFlags, ValueMapping};
+use serde::Deserialize;
+use tokio::time::Duration;
+use tracing::debug;
+
+use crate::utils::config_dir;
+
+/// Quirks of the models captured so far, where an entry applies to every
+/// model whose `MDL` starts with `mdl`
+const BUILTIN: &str = r#"
+[[model]]
+mdl = "MX920"
+interrupt_layout = "mx920"
+reset = true
+min_poll_interval_ms = 1000
+"#;
+
+/// Interval between polls of models without a limit
+const DEFAULT_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Interval between renewals of the registration of models not known to drop
+/// idle hosts sooner
+const DEFAULT_KEEPALIVE_INTERVAL: Duration = Duration::from_secs(600);
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Entry {
+    mdl: String,
+    interrupt_layout: Option<String>,
+    reset: Option<bool>,
+    min_poll_interval_ms: Option<u64>,
+    keepalive_interval_secs: Option<u64>,
+    wake_probes: Option<u32>,
+    session_invalid_status: Option<u32>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct QuirksFile {
+    #[serde(default)]
+    model: Vec<Entry>,
+}
+
+/// An interrupt layout like
+///
+/// ```toml
+/// size = 13                 # offsets default to those of the MX920
+/// dpi = 14
+///
+/// [[map]]
+/// field = "size"
+/// from = 0x0c               # byte sent by the model
+/// to = 0x0b                 # byte of the same value sent by the MX920
+/// ```
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct LayoutFile {
+    color_mode: Option<usize>,
+    source: Option<usize>,
+    feeder_type: Option<usize>,
+    size: Option<usize>,
+    format: Option<usize>,
+    dpi: Option<usize>,
+    feeder_orientation: Option<usize>,
+    #[serde(default)]
+    map: Vec<MappingEntry>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MappingEntry {
+    field: String,
+    from: u8,
+    to: u8,
+}
+
+/// Quirks of a model
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Quirks {
+    pub interrupt_layout: InterruptLayout,
+    /// Whether scan jobs must or must not be reset for the scanner to report
+    /// the next one, or `None` to follow `--no-reset`
+    pub reset: Option<bool>,
+    /// Interval between polls
+    pub poll_interval: Duration,
+    /// Interval between renewals of the registration, unless overridden by
+    /// `--keepalive-interval`
+    pub keepalive_interval: Duration,
+    /// Discover commands sent back to back to wake the model from deep sleep
+    pub wake_probes: u32,
+    /// Status bits the model reports when the session polled is unknown to
+    /// it, e.g. after it restarted, so that the host registers again
+    pub session_invalid: Option<StatusFlags>,
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        Self {
+            interrupt_layout: InterruptLayout::MX920,
+            reset: None,
+            poll_interval: DEFAULT_POLL_INTERVAL,
+            keepalive_interval: DEFAULT_KEEPALIVE_INTERVAL,
+            wake_probes: 0,
+            session_invalid: None,
+        }
+    }
+}
+
+/// Built-in quirks followed by the quirks of the user, and the interrupt
+/// layouts they refer to, shared by listeners. The default has no entries, all
+/// models getting the default quirks.
+#[derive(Debug, Clone, Default)]
+pub struct QuirksDb {
+    entries: Arc<Vec<Entry>>,
+    layouts: Arc<HashMap<String, InterruptLayout>>,
+}
+
+impl QuirksDb {
+    /// Loads the built-in quirks, and `quirks.toml` and the layouts in the
+    /// configuration directory if they exist.
+    pub fn load() -> anyhow::Result<Self> {
+        let mut entries = parse(BUILTIN).context("invalid built-in quirks")?;
+        let mut layouts = builtin_layouts();
+        if let Some(dir) = config_dir() {
+            layouts.extend(load_layouts(&dir.join("layouts"))?);
+            entries.extend(load_file(&dir.join("quirks.toml"))?);
+        }
+        Self::new(entries, layouts)
+    }
+
+    fn new(entries: Vec<Entry>, layouts: HashMap<String, InterruptLayout>) -> anyhow::Result<Self> {
+        for entry in entries.iter() {
+            if let Some(name) = &entry.interrupt_layout {
+                if !layouts.contains_key(name) {
+                    bail!("unknown interrupt layout `{name}` for `{}`", entry.mdl);
+                }
+            }
+        }
+        Ok(Self {
+            entries: Arc::new(entries),
+            layouts: Arc::new(layouts),
+        })
+    }
+
+    /// Looks up the quirks of `model`, later entries overriding earlier ones.
+    pub fn resolve(&self, model: &str) -> Quirks {
+        let mut quirks = Quirks::default();
+        for entry in self
+            .entries
+            .iter()
+            .filter(|entry| model.starts_with(&entry.mdl))
+        {
+            let layout = entry
+                .interrupt_layout
+                .as_ref()
+                .and_then(|name| self.layouts.get(name));
+            if let Some(layout) = layout {
+                quirks.interrupt_layout = layout.clone();
+            }
+            if let Some(reset) = entry.reset {
+                quirks.reset = Some(reset);
+            }
+            if let Some(interval) = entry.min_poll_interval_ms {
+                quirks.poll_interval = Duration::from_millis(interval).max(DEFAULT_POLL_INTERVAL);
+            }
+            if let Some(interval) = entry.keepalive_interval_secs {
+                quirks.keepalive_interval = Duration::from_secs(interval.max(1));
+            }
+            if let Some(probes) = entry.wake_probes {
+                quirks.wake_probes = probes;
+            }
+            if let Some(bits) = entry.session_invalid_status.filter(|bits| *bits != 0) {
+                quirks.session_invalid = Some(StatusFlags::from_bits(bits));
+            }
+        }
+        quirks
+    }
+}
+
+fn load_file(path: &Path) -> anyhow::Result<Vec<Entry>> {
+    match fs::read_to_string(path) {
+        Ok(content) => {
+            parse(&content).with_context(|| format!("invalid quirks in {}", path.display()))
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(vec![]),
+        Err(e) => Err(e).with_context(|| format!("couldn't read quirks from {}", path.display())),
+    }
+}
+
+fn builtin_layouts() -> HashMap<String, InterruptLayout> {
+    HashMap::from([("mx920".to_owned(), InterruptLayout::MX920)])
+}
+
+fn parse(content: &str) -> anyhow::Result<Vec<Entry>> {
+    let file: QuirksFile = toml::from_str(content)?;
+    Ok(file.model)
+}
+
+/// Loads the layouts in `dir`, named after their files.
+fn load_layouts(dir: &Path) -> anyhow::Result<HashMap<String, InterruptLayout>> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(HashMap::new()),
+        Err(e) => {
+            return Err(e).with_context(|| format!("couldn't read layouts in {}", dir.display()))
+        }
+    };
+
+    let mut layouts = HashMap::new();
+    for entry in entries {
+        let path = entry
+            .with_context(|| format!("couldn't read layouts in {}", dir.display()))?
+            .path();
+        let Some(name) = path
+            .file_stem()
+            .filter(|_| path.extension().is_some_and(|ext| ext == "toml"))
+            .and_then(|name| name.to_str())
+        else {
+            continue;
+        };
+        let layout = fs::read_to_string(&path)
+            .map_err(anyhow::Error::from)
+            .and_then(|content| parse_layout(&content))
+            .with_context(|| format!("invalid interrupt layout in {}", path.display()))?;
+        debug!("loaded interrupt layout `{name}` from {}", path.display());
+        layouts.insert(name.to_owned(), layout);
+    }
+    Ok(layouts)
+}
+
+fn parse_layout(content: &str) -> anyhow::Result<InterruptLayout> {
+    let file: LayoutFile = toml::from_str(content)?;
+    let default = InterruptLayout::MX920;
+    let layout = InterruptLayout {
+        color_mode: file.color_mode.unwrap_or(default.color_mode),
+        source: file.source.unwrap_or(default.source),
+        feeder_type: file.feeder_type.unwrap_or(default.feeder_type),
+        size: file.size.unwrap_or(default.size),
+        format: file.format.unwrap_or(default.format),
+        dpi: file.dpi.unwrap_or(default.dpi),
+        feeder_orientation: file
+            .feeder_orientation
+            .unwrap_or(default.feeder_orientation),
+        mappings: file
+            .map
+            .into_iter()
+            .map(|entry| {
+                Ok(ValueMapping {
+                    field: entry.field.parse().map_err(|e: String| anyhow!(e))?,
+                    from: entry.from,
+                    to: entry.to,
+                })
+            })
+            .collect::<anyhow::Result<_>>()?,
+    };
+    for field in InterruptField::ALL {
+        if layout.offset(field) >= InterruptLayout::LEN {
+            bail!(
+                "offset {} of `{field}` is past the end of the interrupt ({} bytes)",
+                layout.offset(field),
+                InterruptLayout::LEN
+            );
+        }
+    }
+    Ok(layout)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn user_quirks_override_builtin() {
+        let mut entries = parse(BUILTIN).unwrap();
+        entries.extend(
+            parse(
+                r#"
+                [[model]]
+                mdl = "MX920"
+                reset = false
+
+                [[model]]
+                mdl = "TS"
+                min_poll_interval_ms = 2500
+                keepalive_interval_secs = 120
+                wake_probes = 3
+                session_invalid_status = 0x4000
+                "#,
+            )
+            .unwrap(),
+        );
+        let db = QuirksDb::new(entries, builtin_layouts()).unwrap();
+
+        let quirks = db.resolve("MX920 series");
+        assert_eq!(quirks.reset, Some(false));
+        assert_eq!(quirks.poll_interval, Duration::from_secs(1));
+        assert_eq!(
+            db.resolve("TS5100 series").poll_interval,
+            Duration::from_millis(2500)
+        );
+        assert_eq!(
+            db.resolve("TS5100 series").keepalive_interval,
+            Duration::from_secs(120)
+        );
+        assert_eq!(db.resolve("TS5100 series").wake_probes, 3);
+        assert_eq!(
+            db.resolve("TS5100 series").session_invalid,
+            Some(StatusFlags::from_bits(0x4000))
+        );
+        assert_eq!(db.resolve("Dummy"), Quirks::default());
+    }
+
+    #[test]
+    fn user_layout() {
+        let layout = parse_layout(
+            r#"
+            size = 13
+
+            [[map]]
+            field = "size"
+            from = 0x0c
+            to = 0x0b
+            "#,
+        )
+        .unwrap();
+        assert_eq!(layout.size, 13);
+        assert_eq!(layout.dpi, InterruptLayout::MX920.dpi);
+        assert_eq!(
+            layout.mappings,
+            [ValueMapping {
+                field: InterruptField::Size,
+                from: 0x0c,
+                to: 0x0b,
+            }]
+        );
+        assert!(parse_layout("dpi = 20").is_err());
+        assert!(parse_layout("[[map]]\nfield = \"sise\"\nfrom = 1\nto = 2").is_err());
+
+        let entries = parse("[[model]]\nmdl = \"TS\"\ninterrupt_layout = \"ts\"").unwrap();
+        assert!(QuirksDb::new(entries.clone(), HashMap::new()).is_err());
+        let db =
+            QuirksDb::new(entries, HashMap::from([("ts".to_owned(), layout.clone())])).unwrap();
+        assert_eq!(db.resolve("TS5100 series").interrupt_layout, layout);
+    }
+}
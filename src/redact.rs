@@ -0,0 +1,276 @@
+//! Masks data identifying the user or the scanner, so that reports and logs
+//! can be shared publicly in bug reports: MAC addresses except their vendor
+//! part, serial numbers in identities, and, with `--redact`, the names of
+//! hosts in logs.
+
+use std::{
+    borrow::Cow,
+    io::{self, Write},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        RwLock,
+    },
+};
+
+use bjnp::{Host, PacketHeaderOnly, PayloadType, HEADER_SIZE};
+use tracing_subscriber::fmt::MakeWriter;
+
+/// Keys of identities which may hold serial numbers
+const SERIAL_KEYS: &[&str] = &["SN", "SER", "SERN", "SERIAL", "SERIALNUMBER"];
+
+/// Whether logs are redacted
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Names masked in logs, as displayed and as sent to scanners
+static HOSTNAMES: RwLock<Vec<(String, Vec<u8>)>> = RwLock::new(Vec::new());
+
+/// Redacts logs from now on.
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+/// Masks `name` in logs, if they are redacted.
+pub fn add_hostname(name: &str) {
+    // names are truncated to fit in poll commands
+    let name = Host::new(name).to_string();
+    if name.is_empty() {
+        return;
+    }
+    let encoded = name.encode_utf16().flat_map(u16::to_be_bytes).collect();
+    HOSTNAMES.write().unwrap().push((name, encoded));
+}
+
+/// Masks the MAC address in discover responses, keeping the vendor part,
+/// and serial numbers in identities.
+pub fn packet(buffer: &[u8]) -> Vec<u8> {
+    let mut bytes = buffer.to_vec();
+    let Ok(packet) = PacketHeaderOnly::parse(buffer) else {
+        return bytes;
+    };
+    // bytes after the payload aren't part of the packet
+    let start = HEADER_SIZE.min(buffer.len());
+    let end = start
+        .saturating_add(packet.payload_size() as usize)
+        .min(buffer.len());
+    let payload = &mut bytes[start..end];
+    match packet.payload_type() {
+        PayloadType::Discover if payload.len() > 6 => {
+            let mac_len = payload[4] as usize;
+            let end = (6 + mac_len).min(payload.len());
+            payload[(6 + 3).min(end)..end].fill(0);
+        }
+        PayloadType::GetId if payload.len() > 2 => {
+            let mut offset = 2;
+            for item in buffer[start + 2..end].split(|b| *b == b';') {
+                if let Some(colon) = item.iter().position(|b| *b == b':') {
+                    let key = String::from_utf8_lossy(&item[..colon]);
+                    if SERIAL_KEYS.contains(&key.as_ref()) {
+                        payload[offset + colon + 1..offset + item.len()].fill(b'X');
+                    }
+                }
+                offset += item.len() + 1;
+            }
+        }
+        _ => {}
+    }
+    bytes
+}
+
+/// Masks a packet logged, if logs are redacted: as [`packet`], and the
+/// names of hosts in poll commands.
+pub fn logged_packet(buffer: &[u8]) -> Cow<'_, [u8]> {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return Cow::Borrowed(buffer);
+    }
+    let mut bytes = packet(buffer);
+    for (_, encoded) in HOSTNAMES.read().unwrap().iter() {
+        let mut i = 0;
+        while i + encoded.len() <= bytes.len() {
+            if bytes[i..].starts_with(encoded) {
+                for c in bytes[i..i + encoded.len()].chunks_mut(2) {
+                    c.copy_from_slice(&[0, b'X']);
+                }
+                i += encoded.len();
+            } else {
+                i += 1;
+            }
+        }
+    }
+    Cow::Owned(bytes)
+}
+
+/// Masks a line of log, if logs are redacted.
+pub fn line(line: &str) -> Cow<'_, str> {
+    if ENABLED.load(Ordering::Relaxed) {
+        Cow::Owned(text(line))
+    } else {
+        Cow::Borrowed(line)
+    }
+}
+
+/// Masks MAC addresses, serial numbers and the names of hosts in `line`.
+fn text(line: &str) -> String {
+    let mut chars: Vec<char> = line.chars().collect();
+    mask_macs(&mut chars);
+    mask_serials(&mut chars);
+    for (name, _) in HOSTNAMES.read().unwrap().iter() {
+        let name: Vec<char> = name.chars().collect();
+        mask_words(&mut chars, &name);
+    }
+    chars.into_iter().collect()
+}
+
+/// Zeroes MAC addresses separated by `:` or `-`, keeping the vendor part.
+fn mask_macs(chars: &mut [char]) {
+    const LEN: usize = 17;
+    let mut i = 0;
+    while i + LEN <= chars.len() {
+        let candidate = &chars[i..i + LEN];
+        let separator = candidate[2];
+        let is_mac = matches!(separator, ':' | '-')
+            && candidate.iter().enumerate().all(|(j, c)| match j % 3 {
+                2 => *c == separator,
+                _ => c.is_ascii_hexdigit(),
+            })
+            && (i == 0 || !chars[i - 1].is_ascii_hexdigit())
+            && chars.get(i + LEN).is_none_or(|c| !c.is_ascii_hexdigit());
+        if is_mac {
+            for j in (9..LEN).filter(|j| j % 3 != 2) {
+                chars[i + j] = '0';
+            }
+            i += LEN;
+        } else {
+            i += 1;
+        }
+    }
+}
+
+/// Masks the values of serial numbers in identities like `SN:1234;`.
+fn mask_serials(chars: &mut [char]) {
+    let mut i = 0;
+    while i < chars.len() {
+        let at_key = i == 0 || matches!(chars[i - 1], ';' | ' ');
+        let key_len = chars[i..]
+            .iter()
+            .position(|c| !c.is_ascii_uppercase())
+            .unwrap_or(chars.len() - i);
+        let key: String = chars[i..i + key_len].iter().collect();
+        if at_key && chars.get(i + key_len) == Some(&':') && SERIAL_KEYS.contains(&key.as_str()) {
+            let start = i + key_len + 1;
+            let end = chars[start..]
+                .iter()
+                .position(|c| *c == ';')
+                .map_or(chars.len(), |len| start + len);
+            chars[start..end].fill('X');
+            i = end;
+        } else {
+            i += key_len.max(1);
+        }
+    }
+}
+
+/// Masks `word` where it isn't part of a longer word.
+fn mask_words(chars: &mut [char], word: &[char]) {
+    let is_word_char = |c: &char| c.is_alphanumeric() || matches!(c, '-' | '_');
+    let mut i = 0;
+    while i + word.len() <= chars.len() {
+        if chars[i..].starts_with(word)
+            && (i == 0 || !is_word_char(&chars[i - 1]))
+            && chars.get(i + word.len()).is_none_or(|c| !is_word_char(c))
+        {
+            chars[i..i + word.len()].fill('X');
+            i += word.len();
+        } else {
+            i += 1;
+        }
+    }
+}
+
+/// Makes writers masking what is written, if logs are redacted.
+pub struct Redacting<M>(pub M);
+
+impl<'a, M: MakeWriter<'a>> MakeWriter<'a> for Redacting<M> {
+    type Writer = RedactingWriter<M::Writer>;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        RedactingWriter(self.0.make_writer())
+    }
+}
+
+/// Writer masking each write, which holds a whole line of log
+pub struct RedactingWriter<W>(W);
+
+impl<W: Write> Write for RedactingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if !ENABLED.load(Ordering::Relaxed) {
+            return self.0.write(buf);
+        }
+        let text = String::from_utf8_lossy(buf);
+        self.0.write_all(line(&text).as_bytes())?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bjnp::{
+        discover,
+        serdes::{Empty, Serialize},
+        Packet, PacketBuilder, PacketType,
+    };
+
+    use super::*;
+
+    #[test]
+    fn redact_mac_and_serial() {
+        let discover = PacketBuilder::new(PacketType::ScannerResponse, PayloadType::Discover)
+            .build(discover::Response::new(
+                "00:1e:8f:01:02:03".parse().unwrap(),
+                "192.168.1.20".parse().unwrap(),
+            ))
+            .serialize_to_vec();
+        let redacted = packet(&discover);
+        let packet = PacketHeaderOnly::parse(&redacted).unwrap();
+        let resp: Packet<discover::Response> = packet.try_into().unwrap();
+        assert_eq!(
+            resp.payload_ref().mac_addr().to_string(),
+            "00:1e:8f:00:00:00"
+        );
+
+        let mut identity = b"\x00\x1cMFG:Canon;SN:ABC123;MDL:X;".to_vec();
+        let mut buffer = PacketBuilder::new(PacketType::ScannerResponse, PayloadType::GetId)
+            .build(Empty)
+            .serialize_to_vec();
+        buffer[15] = identity.len() as u8; // payload size
+        buffer.append(&mut identity);
+        let redacted = super::packet(&buffer);
+        let packet = PacketHeaderOnly::parse(&redacted).unwrap();
+        let resp: Packet<bjnp::identity::Response> = packet.try_into().unwrap();
+        assert_eq!(resp.payload_ref().get("SN"), Some("XXXXXX"));
+        assert_eq!(resp.payload_ref().get("MDL"), Some("X"));
+
+        // trailing bytes are not taken for the payload
+        buffer.extend_from_slice(b"SN:1;");
+        let redacted = super::packet(&buffer);
+        assert!(redacted.ends_with(b"SN:XXXXXX;MDL:X;SN:1;"));
+    }
+
+    #[test]
+    fn redact_text() {
+        add_hostname("Youmu-Desktop");
+        assert_eq!(
+            text(
+                "Youmu-Desktop: locked 00:1e:8f:01:02:03 in /tmp/scanner-button-00-1e-8f-01-02-03"
+            ),
+            "XXXXXXXXXXXXX: locked 00:1e:8f:00:00:00 in /tmp/scanner-button-00-1e-8f-00-00-00"
+        );
+        assert_eq!(
+            text("MDL:MX920 series;SN:ABC123;MFG:Canon; Youmu-Desktop2"),
+            "MDL:MX920 series;SN:XXXXXX;MFG:Canon; Youmu-Desktop2"
+        );
+    }
+}
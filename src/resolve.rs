@@ -0,0 +1,156 @@
+//! Resolves the host names scanners are given by again on each initialization,
+//! for scanners whose address changes, with a cache so that listeners
+//! initializing at the same time don't each query the resolver. Also looks up
+//! the names of devices found by scans.
+
+use std::{
+    collections::HashMap,
+    io,
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+};
+
+use tokio::{
+    net::lookup_host,
+    sync::Mutex,
+    task,
+    time::{Duration, Instant},
+};
+use tracing::debug;
+
+/// Time a resolved address is used before resolving the name again
+const TTL: Duration = Duration::from_secs(60);
+/// Time a failed resolution is reported before resolving the name again
+const NEGATIVE_TTL: Duration = Duration::from_secs(10);
+
+/// Address of a scanner, along with the host name it was given by if any.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScannerAddr {
+    pub addr: SocketAddr,
+    /// Host name with port, `None` for an IP address
+    pub name: Option<String>,
+}
+
+#[derive(Debug)]
+struct Entry {
+    result: Result<Vec<SocketAddr>, String>,
+    expires_at: Instant,
+}
+
+/// Cache entry of a name, locked on its own while the name is resolved
+type SharedEntry = Arc<Mutex<Option<Entry>>>;
+
+/// Cache of resolved host names shared by listeners.
+#[derive(Debug, Clone, Default)]
+pub struct Resolver(Arc<std::sync::Mutex<HashMap<String, SharedEntry>>>);
+
+impl Resolver {
+    /// Resolves `name` to all its addresses, in the order of the resolver.
+    ///
+    /// The entry of `name` stays locked while resolving, so that listeners
+    /// resolving the same name at the same time wait for a single query,
+    /// while other names are resolved meanwhile.
+    pub async fn resolve(&self, name: &str) -> io::Result<Vec<SocketAddr>> {
+        let entry = self.entry(name);
+        let mut entry = entry.lock().await;
+        let now = Instant::now();
+        let entry = match &mut *entry {
+            Some(entry) if entry.expires_at > now => entry,
+            _ => {
+                let result = match lookup_host(name).await {
+                    Ok(addrs) => Ok(addrs.collect()),
+                    Err(e) => Err(e.to_string()),
+                };
+                debug!("resolved {name}: {result:?}");
+                let ttl = if result.is_ok() { TTL } else { NEGATIVE_TTL };
+                entry.insert(Entry {
+                    result,
+                    expires_at: now + ttl,
+                })
+            }
+        };
+        entry
+            .result
+            .clone()
+            .map_err(|e| io::Error::other(e.as_str()))
+    }
+
+    /// Returns the cache entry of `name`, empty until first resolved.
+    fn entry(&self, name: &str) -> SharedEntry {
+        let mut entries = self.0.lock().unwrap();
+        entries.entry(name.to_owned()).or_default().clone()
+    }
+}
+
+/// Looks up the name `ip` resolves back to, without blocking the runtime.
+pub async fn reverse(ip: IpAddr) -> Option<String> {
+    match task::spawn_blocking(move || reverse_blocking(ip)).await {
+        Ok(Ok(name)) => Some(name),
+        Ok(Err(e)) => {
+            debug!("couldn't resolve the name of {ip}: {e}");
+            None
+        }
+        Err(e) => {
+            debug!("couldn't resolve the name of {ip}: {e}");
+            None
+        }
+    }
+}
+
+#[cfg(unix)]
+fn reverse_blocking(ip: IpAddr) -> io::Result<String> {
+    use std::ffi::CStr;
+
+    let addr = socket2::SockAddr::from(SocketAddr::new(ip, 0));
+    // NI_MAXHOST
+    let mut host = [0 as libc::c_char; 1025];
+    // SAFETY: `addr` and `host` are valid for the lengths given
+    let ret = unsafe {
+        libc::getnameinfo(
+            addr.as_ptr(),
+            addr.len(),
+            host.as_mut_ptr(),
+            host.len() as libc::socklen_t,
+            std::ptr::null_mut(),
+            0,
+            libc::NI_NAMEREQD,
+        )
+    };
+    if ret != 0 {
+        // SAFETY: `gai_strerror` returns a static NUL-terminated string
+        let message = unsafe { CStr::from_ptr(libc::gai_strerror(ret)) };
+        return Err(io::Error::other(message.to_string_lossy()));
+    }
+    // SAFETY: `getnameinfo` wrote a NUL-terminated name into `host`
+    let name = unsafe { CStr::from_ptr(host.as_ptr()) };
+    Ok(name.to_string_lossy().into_owned())
+}
+
+#[cfg(not(unix))]
+fn reverse_blocking(_ip: IpAddr) -> io::Result<String> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "reverse lookups aren't supported on this system",
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn resolver_caches_names() {
+        let resolver = Resolver::default();
+        let name = "127.0.0.1:8612";
+        let addr: SocketAddr = name.parse().unwrap();
+        assert_eq!(resolver.resolve(name).await.unwrap(), [addr]);
+
+        // served from the cache until expired
+        let cached = vec!["[::1]:8612".parse().unwrap(), addr];
+        let entry = resolver.entry(name);
+        entry.lock().await.as_mut().unwrap().result = Ok(cached.clone());
+        assert_eq!(resolver.resolve(name).await.unwrap(), cached);
+        entry.lock().await.as_mut().unwrap().expires_at = Instant::now();
+        assert_eq!(resolver.resolve(name).await.unwrap(), [addr]);
+    }
+}
@@ -1,46 +1,360 @@
 use std::{
+    collections::{HashMap, HashSet},
+    fmt::Display,
     io::{self, Write},
-    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::Arc,
     time::Duration,
 };
 
 use anyhow::{ensure, Context};
 use bjnp::{
-    discover, identity,
-    serdes::{Empty, Serialize},
+    discover::{self, DiscoveredDevice, MacAddr},
+    identity,
+    serdes::{FixedSize, Serialize},
     Packet, PacketBuilder, PacketHeaderOnly, PacketType, PayloadType,
 };
-use log::{debug, error, info, trace};
+use clap::ValueEnum;
+#[cfg(feature = "interfaces")]
 use network_interface::{NetworkInterface, NetworkInterfaceConfig};
+#[cfg(feature = "color")]
 use owo_colors::{OwoColorize, Style};
-use pretty_hex::PrettyHex;
 use stream::wrappers::UnboundedReceiverStream;
 use tokio::{
     net::UdpSocket,
-    sync::mpsc::unbounded_channel,
-    task::JoinSet,
-    time::{sleep_until, Instant},
+    sync::{mpsc::unbounded_channel, Semaphore},
+    task::{JoinError, JoinSet},
+    time::{interval, sleep_until, timeout, Instant},
 };
 use tokio_stream::{self as stream, StreamExt, StreamMap};
+use tracing::{debug, enabled, error, info, trace, Level};
 
-use crate::{channel::Channel, utils::BJNP_PORT};
+#[cfg(target_os = "linux")]
+use crate::pktinfo;
+use crate::{
+    channel::Channel,
+    crash,
+    dump::{self, Direction},
+    resolve,
+    utils::{hex_dump, parse_error, SocketOptions, BJNP_PORT},
+};
+
+/// Responses to discover commands, with their round trip times
+pub type ResponseStream =
+    UnboundedReceiverStream<anyhow::Result<(discover::Response, Option<Duration>)>>;
+/// Responses received on an interface, by interface name and local address
+pub type InterfaceResponses = ((String, IpAddr), ResponseStream);
+
+const IPV4_BROADCAST: Ipv4Addr = Ipv4Addr::new(255, 255, 255, 255);
+const IPV6_LINKLOCAL_MULTICAST: Ipv6Addr = Ipv6Addr::new(0xFF02, 0, 0, 0, 0, 0, 0, 1);
+
+/// Interface name, local address and broadcast address to broadcast discover
+/// commands with
+pub type BroadcastTarget = (String, IpAddr, IpAddr);
+
+/// Prefixes of the names of virtual interfaces, i.e. container bridges and
+/// VPN tunnels, which scanners are hardly ever on
+#[cfg(any(feature = "interfaces", test))]
+const VIRTUAL_PREFIXES: [&str; 5] = ["docker", "veth", "tun", "tap", "utun"];
 
-pub async fn scan(max_waiting: u64) -> anyhow::Result<()> {
+/// Local addresses to broadcast discover commands from, with the interface
+/// name and the broadcast address of each. Virtual interfaces and those down
+/// are left out unless `all_interfaces` is set.
+#[cfg(feature = "interfaces")]
+pub fn broadcast_targets(all_interfaces: bool) -> anyhow::Result<Vec<BroadcastTarget>> {
     // binding to 0.0.0.0 relies on system routing table, so it is
     // more robust to get all the local IP and bind to them.
     let interfaces =
         NetworkInterface::show().context("couldn't obtain the list of network interfaces")?;
+    let running = if all_interfaces {
+        None
+    } else {
+        running_interfaces()
+    };
+    Ok(interfaces
+        .into_iter()
+        .filter(|interface| {
+            if all_interfaces {
+                return true;
+            }
+            let name = &interface.name;
+            if is_virtual(name) {
+                debug!("skipping virtual interface {name}");
+                return false;
+            }
+            if running
+                .as_ref()
+                .is_some_and(|running| !running.contains(name))
+            {
+                debug!("skipping interface {name}, which is down");
+                return false;
+            }
+            true
+        })
+        .filter_map(|interface| match interface.addr? {
+            network_interface::Addr::V4(addr) => Some((
+                interface.name,
+                addr.ip.into(),
+                directed_broadcast(addr.ip, addr.broadcast, addr.netmask)?.into(),
+            )),
+            network_interface::Addr::V6(addr) if !addr.ip.is_loopback() => Some((
+                interface.name,
+                addr.ip.into(),
+                IPV6_LINKLOCAL_MULTICAST.into(),
+            )),
+            network_interface::Addr::V6(_) => None,
+        })
+        .collect())
+}
+
+#[cfg(any(feature = "interfaces", test))]
+fn is_virtual(name: &str) -> bool {
+    VIRTUAL_PREFIXES
+        .iter()
+        .any(|prefix| name.starts_with(prefix))
+}
+
+/// Returns the names of the interfaces both up and running, or `None` if
+/// unknown.
+#[cfg(all(feature = "interfaces", unix))]
+fn running_interfaces() -> Option<HashSet<String>> {
+    use std::{ffi::CStr, ptr};
+
+    let mut addrs = ptr::null_mut();
+    // SAFETY: `addrs` is freed below
+    if unsafe { libc::getifaddrs(&mut addrs) } < 0 {
+        debug!(
+            "couldn't obtain the flags of network interfaces: {}",
+            io::Error::last_os_error()
+        );
+        return None;
+    }
+    let mut running = HashSet::new();
+    let mut cursor = addrs;
+    while !cursor.is_null() {
+        // SAFETY: `cursor` points to an entry of the list from `getifaddrs`
+        let entry = unsafe { &*cursor };
+        let flags = entry.ifa_flags as libc::c_int;
+        if flags & libc::IFF_UP != 0 && flags & libc::IFF_RUNNING != 0 {
+            // SAFETY: interface names are NUL-terminated
+            let name = unsafe { CStr::from_ptr(entry.ifa_name) };
+            running.insert(name.to_string_lossy().into_owned());
+        }
+        cursor = entry.ifa_next;
+    }
+    // SAFETY: `addrs` was returned by `getifaddrs` and isn't used afterwards
+    unsafe { libc::freeifaddrs(addrs) };
+    Some(running)
+}
+
+#[cfg(all(feature = "interfaces", not(unix)))]
+fn running_interfaces() -> Option<HashSet<String>> {
+    None
+}
+
+/// Returns the broadcast address of the subnet of `ip`, computed from its
+/// netmask if the interface doesn't report it, or `None` for loopback and
+/// point-to-point links, which have no other device to discover.
+#[cfg(any(feature = "interfaces", test))]
+fn directed_broadcast(
+    ip: Ipv4Addr,
+    broadcast: Option<Ipv4Addr>,
+    netmask: Option<Ipv4Addr>,
+) -> Option<Ipv4Addr> {
+    if ip.is_loopback() {
+        return None;
+    }
+    match (broadcast, netmask) {
+        // a /31 or /32 subnet has no broadcast address
+        (_, Some(netmask)) if u32::from(netmask) >= 0xffff_fffe => None,
+        (Some(broadcast), _) => Some(broadcast),
+        (None, Some(netmask)) => Some((u32::from(ip) | !u32::from(netmask)).into()),
+        (None, None) => Some(IPV4_BROADCAST),
+    }
+}
+
+/// Local addresses to broadcast discover commands from, with the interface
+/// name and the broadcast address of each.
+#[cfg(not(feature = "interfaces"))]
+pub fn broadcast_targets(_all_interfaces: bool) -> anyhow::Result<Vec<BroadcastTarget>> {
+    // without the list of interfaces, rely on the system routing table
+    Ok(vec![
+        (
+            "default".to_string(),
+            Ipv4Addr::UNSPECIFIED.into(),
+            IPV4_BROADCAST.into(),
+        ),
+        (
+            "default".to_string(),
+            Ipv6Addr::UNSPECIFIED.into(),
+            IPV6_LINKLOCAL_MULTICAST.into(),
+        ),
+    ])
+}
+
+#[derive(Debug)]
+pub struct ScanConfig {
+    pub max_waiting: Duration,
+    pub socket_options: SocketOptions,
+    /// Stop waiting once this many devices have answered
+    pub expect: Option<usize>,
+    /// Number of discover commands broadcast on each interface
+    pub probes: u16,
+    pub probe_interval: Duration,
+    /// Number of devices inquired for their identity at once
+    pub max_inquiries: usize,
+    pub inquiry_timeout: Duration,
+    /// Broadcast on every IPv4 interface from a single socket
+    pub shared_socket: bool,
+    /// Also broadcast on virtual interfaces and those down
+    pub all_interfaces: bool,
+    /// Look up the host name of each device by its IP address
+    pub resolve_names: bool,
+}
+
+/// A device found by [`scan`]
+#[derive(Debug)]
+pub struct ScannedDevice {
+    pub device: DiscoveredDevice,
+    /// Name the IP address of the device resolves back to, if looked up
+    pub hostname: Option<String>,
+}
+
+/// How `scan` prints the devices found
+#[derive(Debug, Clone, Copy)]
+pub struct ScanOutput {
+    /// Print each device as soon as it answers instead of sorted at the end
+    pub stream: bool,
+    /// Print only this value of each device, for use in scripts
+    pub quiet_output: Option<QuietOutput>,
+}
+
+/// Value printed for each device by `scan --quiet-output`
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum QuietOutput {
+    Ip,
+    Addr,
+    Mac,
+}
+
+/// Responses to the discover commands broadcast on an interface
+#[derive(Debug, Default)]
+struct ProbeStats {
+    responses: usize,
+    devices: HashSet<IpAddr>,
+    /// Round trip time of each response matched to a probe
+    rtts: Vec<Duration>,
+}
+
+impl ProbeStats {
+    fn record(&mut self, device: IpAddr, rtt: Option<Duration>) {
+        self.responses += 1;
+        self.devices.insert(device);
+        self.rtts.extend(rtt);
+    }
+}
+
+impl Display for ProbeStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_fmt(format_args!(
+            "{} response(s) from {} device(s)",
+            self.responses,
+            self.devices.len()
+        ))?;
+        if let (Some(min), Some(max)) = (self.rtts.iter().min(), self.rtts.iter().max()) {
+            let avg = self.rtts.iter().sum::<Duration>() / self.rtts.len() as u32;
+            let ms = |rtt: &Duration| rtt.as_secs_f64() * 1000.0;
+            f.write_fmt(format_args!(
+                ", rtt min/avg/max = {:.1}/{:.1}/{:.1} ms",
+                ms(min),
+                ms(&avg),
+                ms(max)
+            ))?;
+        }
+        Ok(())
+    }
+}
+
+/// Scans and prints the devices found.
+pub async fn print_scan(config: ScanConfig, output: ScanOutput) -> anyhow::Result<()> {
+    let devices = scan(config, |device| {
+        if output.stream {
+            print_device(device, output.quiet_output)?;
+        }
+        Ok(())
+    })
+    .await?;
+    if !output.stream {
+        for device in devices.iter() {
+            print_device(device, output.quiet_output)?;
+        }
+    }
+    Ok(())
+}
+
+/// Broadcasts discover commands and inquires the identity of each device
+/// answering, passing each device to `found` as soon as it is inquired.
+/// Returns the devices sorted by IP and MAC address.
+pub async fn scan(
+    config: ScanConfig,
+    mut found: impl FnMut(&ScannedDevice) -> anyhow::Result<()>,
+) -> anyhow::Result<Vec<ScannedDevice>> {
+    debug!("loaded scan config {config:?}");
+
     let mut task_set = JoinSet::new();
-    let mut map = interfaces
+    let targets = broadcast_targets(config.all_interfaces)?;
+    #[cfg(target_os = "linux")]
+    let (shared, targets) = if config.shared_socket {
+        shared_broadcast_scan(
+            &mut task_set,
+            targets,
+            config.socket_options,
+            (Some(config.probes), config.probe_interval),
+        )
+    } else {
+        (vec![], targets)
+    };
+    #[cfg(not(target_os = "linux"))]
+    let shared: Vec<InterfaceResponses> = {
+        if config.shared_socket {
+            tracing::warn!(
+                "shared sockets aren't supported on this system, binding one per interface"
+            );
+        }
+        vec![]
+    };
+    let mut map = targets
         .into_iter()
-        .filter(|interface| interface.addr.is_some())
-        .map(|interface| {
-            let receiver = broadcast_scan(&mut task_set, &interface);
-            ((interface.name, interface.addr.unwrap().ip()), receiver)
+        .map(|(name, ip, broadcast)| {
+            let receiver = broadcast_scan(
+                &mut task_set,
+                name.clone(),
+                ip,
+                broadcast,
+                config.socket_options,
+                (Some(config.probes), config.probe_interval),
+            );
+            ((name, ip), receiver)
         })
         .collect::<StreamMap<_, _>>();
+    map.extend(shared);
+    let mut stats: HashMap<_, _> = map
+        .keys()
+        .map(|key| (key.clone(), ProbeStats::default()))
+        .collect();
+    let mut inquiries = JoinSet::new();
+    // each inquiry opens a socket, which is limited on large subnets
+    let permits = Arc::new(Semaphore::new(config.max_inquiries));
+    // each probe is answered by every device
+    let mut detected = HashSet::new();
+    let mut devices = vec![];
+    // the same device may answer on several interfaces
+    let mut seen = HashSet::new();
 
-    let deadline = Instant::now() + Duration::from_secs(max_waiting);
+    // whether `expect` devices were found
+    let mut enough = false;
+
+    let deadline = Instant::now() + config.max_waiting;
     let sleep = sleep_until(deadline);
     tokio::pin!(sleep);
     loop {
@@ -48,9 +362,37 @@ pub async fn scan(max_waiting: u64) -> anyhow::Result<()> {
             Some(((name, addr), maybe_resp)) = map.next() => {
                 // received response
                 match maybe_resp {
-                    Ok(resp) => {
-                        info!("detected device at {addr}");
-                        task_set.spawn(inquire_device(resp));
+                    Ok((resp, rtt)) => {
+                        let device = *resp.ip_addr();
+                        if let Some(stats) = stats.get_mut(&(name, addr)) {
+                            stats.record(device, rtt);
+                        }
+                        if detected.insert(device) {
+                            info!("detected device at {device}");
+                            let permits = permits.clone();
+                            let socket_options = config.socket_options;
+                            let inquiry_timeout = config.inquiry_timeout;
+                            let resolve_names = config.resolve_names;
+                            inquiries.spawn(async move {
+                                // NOPANIC: the semaphore is never closed
+                                let _permit = permits.acquire_owned().await.unwrap();
+                                let inquiry = inquire_device(resp, socket_options);
+                                let device = timeout(inquiry_timeout, inquiry)
+                                    .await
+                                    .with_context(|| {
+                                        format!("timeout inquiring identity of {device}")
+                                    })??;
+                                let hostname = if resolve_names {
+                                    timeout(inquiry_timeout, resolve::reverse(*device.ip_addr()))
+                                        .await
+                                        .ok()
+                                        .flatten()
+                                } else {
+                                    None
+                                };
+                                Ok(ScannedDevice { device, hostname })
+                            });
+                        }
                     },
                     Err(e) => {
                         error!("socket at {addr} on {name}: {e:?}");
@@ -63,107 +405,123 @@ pub async fn scan(max_waiting: u64) -> anyhow::Result<()> {
                     .context("failed to join task")
                     .and_then(std::convert::identity)
                 {
+                    if crash::is_panic(&e) {
+                        return Err(e);
+                    }
                     error!("socket error: {e:?}");
                 }
             },
+            Some(join_result) = inquiries.join_next() => {
+                if collect(join_result, &config, &mut seen, &mut devices, &mut found)? {
+                    debug!("found {} device(s), stop waiting", seen.len());
+                    enough = true;
+                    break;
+                }
+            },
             _ = &mut sleep => {
                 break;
             }
         }
     }
-    // Clear tasks
+    // stop receiving responses at once, but give the devices detected in
+    // time a grace period to report their identity
     task_set.shutdown().await;
-    Ok(())
+    if !enough && !inquiries.is_empty() {
+        debug!("awaiting {} pending inquiries", inquiries.len());
+        let grace = sleep_until(Instant::now() + config.inquiry_timeout);
+        tokio::pin!(grace);
+        while !inquiries.is_empty() {
+            tokio::select! {
+                Some(join_result) = inquiries.join_next() => {
+                    if collect(join_result, &config, &mut seen, &mut devices, &mut found)? {
+                        break;
+                    }
+                },
+                _ = &mut grace => {
+                    break;
+                }
+            }
+        }
+    }
+    inquiries.shutdown().await;
+
+    if enabled!(Level::INFO) {
+        let mut stats: Vec<_> = stats.into_iter().collect();
+        stats.sort_by(|(a, _), (b, _)| a.cmp(b));
+        for ((name, ip), stats) in stats {
+            info!("probes from {ip} on {name}: {stats}");
+        }
+    }
+
+    devices.sort_by_key(|scanned| (*scanned.device.ip_addr(), *scanned.device.mac_addr()));
+    Ok(devices)
 }
 
-fn broadcast_scan(
+/// Broadcasts `probes` discover commands from `ip` on the interface `name`,
+/// one every `probe_interval`, or until the returned stream is dropped if
+/// `None`, and yields the responses with their round trip time.
+pub fn broadcast_scan(
     set: &mut JoinSet<anyhow::Result<()>>,
-    interface: &NetworkInterface,
-) -> UnboundedReceiverStream<anyhow::Result<discover::Response>> {
-    const IPV4_BROADCAST: Ipv4Addr = Ipv4Addr::new(255, 255, 255, 255);
-    const IPV6_LINKLOCAL_MULTICAST: Ipv6Addr = Ipv6Addr::new(0xFF02, 0, 0, 0, 0, 0, 0, 1);
-
+    name: String,
+    ip: IpAddr,
+    broadcast: IpAddr,
+    socket_options: SocketOptions,
+    (probes, probe_interval): (Option<u16>, Duration),
+) -> ResponseStream {
     let (sender, receiver) = unbounded_channel();
 
     set.spawn({
-        let name = interface.name.clone();
-        let ifaddr = interface.addr.unwrap();
-
         async move {
             // create socket
-            let (socket, broadcast) = match ifaddr {
-                network_interface::Addr::V4(addr) => {
-                    let socket = UdpSocket::bind(SocketAddr::new(addr.ip.into(), 0))
-                        .await
-                        .with_context(|| {
-                            format!("couldn't bind to {ip} on {name}", ip = addr.ip)
-                        })?;
-                    socket.set_broadcast(true).with_context(|| {
-                        format!(
-                            "couldn't set socket for {ip} on {name} to broadcast",
-                            ip = addr.ip
-                        )
-                    })?;
-                    let broadcast = addr.broadcast.unwrap_or(IPV4_BROADCAST);
-                    let broadcast = SocketAddr::new(broadcast.into(), BJNP_PORT);
-                    (socket, broadcast)
-                }
-                network_interface::Addr::V6(addr) => {
-                    let socket = UdpSocket::bind(SocketAddr::new(addr.ip.into(), 0))
-                        .await
-                        .with_context(|| {
-                            format!("couldn't bind to {ip} on {name}", ip = addr.ip)
-                        })?;
-                    let broadcast = SocketAddr::new(IPV6_LINKLOCAL_MULTICAST.into(), BJNP_PORT);
-                    (socket, broadcast)
-                }
-            };
+            let socket = UdpSocket::bind(SocketAddr::new(ip, 0))
+                .await
+                .with_context(|| format!("couldn't bind to {ip} on {name}"))?;
+            if ip.is_ipv4() {
+                socket.set_broadcast(true).with_context(|| {
+                    format!("couldn't set socket for {ip} on {name} to broadcast")
+                })?;
+            }
+            let broadcast = SocketAddr::new(broadcast, BJNP_PORT);
             let local = socket
                 .local_addr()
                 .with_context(|| format!("couldn't obtain socket local address on {name}"))?;
             debug!("binded socket to {local} on {name}");
+            socket_options.apply(&socket).with_context(|| {
+                format!("couldn't set socket options {socket_options:?} on {local} on {name}")
+            })?;
 
-            // create command
-            let command =
-                PacketBuilder::new(PacketType::ScannerCommand, PayloadType::Discover).build(Empty);
-
-            debug!("broadcast discover command to {broadcast} on {name}: {command:-}",);
-
-            // broadcast command
-            let buffer = command.serialize_to_vec();
-            socket
-                .send_to(buffer.as_slice(), broadcast)
-                .await
-                .with_context(|| {
-                    format!("failed to broadcast to {broadcast} from {local} on {name}")
-                })?;
-
-            // receiving command
+            // broadcast a command every `probe_interval` while receiving, each
+            // with its own sequence number to match responses to
+            let mut sent = Probes::new(probes);
+            let mut ticks = interval(probe_interval);
             let mut buffer = [0; 65536];
             loop {
-                let resp = socket
-                    .recv_from(&mut buffer)
-                    .await
+                let received = tokio::select! {
+                    _ = ticks.tick(), if sent.pending() => {
+                        let command =
+                            PacketBuilder::new(PacketType::ScannerCommand, PayloadType::Discover)
+                                .sequence(sent.send())
+                                .build(discover::Request::new());
+                        debug!("broadcast discover command to {broadcast} on {name}: {command:-}",);
+
+                        // NOPANIC: the array fits a discover command exactly
+                        let command = command
+                            .serialize_to_array::<{ Packet::<discover::Request>::SIZE }>()
+                            .unwrap();
+                        dump::packet(Direction::Outbound, broadcast, &command);
+                        socket
+                            .send_to(command.as_slice(), broadcast)
+                            .await
+                            .with_context(|| {
+                                format!("failed to broadcast to {broadcast} from {local} on {name}")
+                            })?;
+                        continue;
+                    },
+                    received = socket.recv_from(&mut buffer) => received,
+                };
+                let resp = received
                     .with_context(|| format!("error receiving packet at {local} on {name}",))
-                    .and_then(|(size, remote)| {
-                        // parsing
-
-                        let buffer = &buffer[..size];
-                        trace!(
-                            "inbound packet from {remote}: {buffer:?}",
-                            buffer = buffer.hex_dump()
-                        );
-
-                        let packet = PacketHeaderOnly::parse(buffer)?;
-                        ensure!(
-                            packet.error() == 0 || packet.payload_size() > 0,
-                            "remote peer {remote} returns error code `{err:#02x}`",
-                            err = packet.error()
-                        );
-
-                        let packet: Packet<discover::Response> = packet.try_into()?;
-                        Ok(packet.payload())
-                    });
+                    .and_then(|(size, remote)| parse_response(&buffer[..size], remote, &sent));
 
                 if sender.send(resp).is_err() {
                     trace!("receiving end of {local} on {name} closed");
@@ -178,40 +536,371 @@ fn broadcast_scan(
     receiver.into()
 }
 
-async fn inquire_device(device: discover::Response) -> anyhow::Result<()> {
-    let mut channel = Channel::new(SocketAddr::new(*device.ip_addr(), BJNP_PORT)).await?;
-    channel.send(PayloadType::GetId, Empty).await?;
-    let id: identity::Response = channel.recv().await?;
-    let mut id: Vec<_> = id.iter().collect();
-    id.sort();
+/// Broadcasts discover commands on every IPv4 interface of `targets` from a
+/// single socket, attributing responses to interfaces by `IP_PKTINFO`. Returns
+/// the responses received on each interface as [`broadcast_scan`] does,
+/// along with the targets left to scan with a socket each, i.e. IPv6 ones and
+/// those whose interface is unknown.
+#[cfg(target_os = "linux")]
+pub fn shared_broadcast_scan(
+    set: &mut JoinSet<anyhow::Result<()>>,
+    targets: Vec<BroadcastTarget>,
+    socket_options: SocketOptions,
+    (probes, probe_interval): (Option<u16>, Duration),
+) -> (Vec<InterfaceResponses>, Vec<BroadcastTarget>) {
+    let mut receivers = vec![];
+    let mut rest = vec![];
+    // interface index, broadcast address and where its responses go
+    let mut interfaces = vec![];
+    for (name, ip, broadcast) in targets {
+        let IpAddr::V4(broadcast_v4) = broadcast else {
+            rest.push((name, ip, broadcast));
+            continue;
+        };
+        match crate::utils::scope_id(&name) {
+            Ok(index) => {
+                let (sender, receiver) = unbounded_channel();
+                interfaces.push((index, broadcast_v4, sender));
+                receivers.push(((name, ip), receiver.into()));
+            }
+            Err(e) => {
+                debug!("{e}, binding a socket to {ip} on {name}");
+                rest.push((name, ip, broadcast));
+            }
+        }
+    }
+    if interfaces.is_empty() {
+        return (receivers, rest);
+    }
+
+    set.spawn(async move {
+        let local = SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0));
+        let socket = UdpSocket::bind(local)
+            .await
+            .with_context(|| format!("couldn't bind to {local}"))?;
+        socket
+            .set_broadcast(true)
+            .with_context(|| format!("couldn't set socket at {local} to broadcast"))?;
+        socket_options.apply(&socket).with_context(|| {
+            format!("couldn't set socket options {socket_options:?} on {local}")
+        })?;
+        pktinfo::enable(&socket)
+            .with_context(|| format!("couldn't enable IP_PKTINFO on {local}"))?;
+        debug!("binded shared socket to {local}");
+
+        let mut sent = Probes::new(probes);
+        let mut ticks = interval(probe_interval);
+        let mut buffer = [0; 65536];
+        loop {
+            let received = tokio::select! {
+                _ = ticks.tick(), if sent.pending() => {
+                    let command =
+                        PacketBuilder::new(PacketType::ScannerCommand, PayloadType::Discover)
+                            .sequence(sent.send())
+                            .build(discover::Request::new());
+                    // NOPANIC: the array fits a discover command exactly
+                    let command = command
+                        .serialize_to_array::<{ Packet::<discover::Request>::SIZE }>()
+                        .unwrap();
+                    for (index, broadcast, _) in interfaces.iter() {
+                        let broadcast = std::net::SocketAddrV4::new(*broadcast, BJNP_PORT);
+                        debug!("broadcast discover command to {broadcast} on interface {index}");
+                        dump::packet(Direction::Outbound, broadcast.into(), &command);
+                        pktinfo::send_on(&socket, command.as_slice(), broadcast, *index)
+                            .await
+                            .with_context(|| {
+                                format!("failed to broadcast to {broadcast} on interface {index}")
+                            })?;
+                    }
+                    continue;
+                },
+                received = pktinfo::recv(&socket, &mut buffer) => received,
+            };
+            let (size, remote, index) =
+                received.with_context(|| format!("error receiving packet at {local}"))?;
+            let Some((_, _, sender)) = interfaces
+                .iter()
+                .find(|(interface, _, _)| Some(*interface) == index)
+            else {
+                debug!("discarded packet from {remote} on unknown interface {index:?}");
+                continue;
+            };
+            let resp = parse_response(&buffer[..size], remote, &sent);
+            if sender.send(resp).is_err() {
+                trace!("receiving end of interface {index:?} closed");
+                interfaces.retain(|(interface, _, _)| Some(*interface) != index);
+                if interfaces.is_empty() {
+                    break;
+                }
+            }
+        }
+
+        Ok::<(), anyhow::Error>(())
+    });
+
+    (receivers, rest)
+}
+
+/// Number of discover commands whose send time is kept to measure the round
+/// trip time of responses
+const KEPT_PROBES: usize = 64;
+
+/// Discover commands broadcast so far, numbered by sequence number, which
+/// wraps around when probing until dropped.
+#[derive(Debug)]
+struct Probes {
+    /// Number of commands to send, or `None` for no limit
+    limit: Option<u16>,
+    count: u32,
+    /// Sequence number and send time of the last commands, by sequence
+    /// number modulo [`KEPT_PROBES`]
+    sent: [Option<(u16, Instant)>; KEPT_PROBES],
+}
+
+impl Probes {
+    fn new(limit: Option<u16>) -> Self {
+        Probes {
+            limit,
+            count: 0,
+            sent: [None; KEPT_PROBES],
+        }
+    }
+
+    /// Returns whether more commands are to be sent.
+    fn pending(&self) -> bool {
+        self.limit.is_none_or(|limit| self.count < u32::from(limit))
+    }
+
+    /// Records a command sent now, returning its sequence number.
+    fn send(&mut self) -> u16 {
+        let sequence = self.count as u16;
+        self.sent[sequence as usize % KEPT_PROBES] = Some((sequence, Instant::now()));
+        self.count = self.count.wrapping_add(1);
+        sequence
+    }
+
+    /// Returns when the command `sequence` was sent, unless too long ago.
+    fn sent_at(&self, sequence: u16) -> Option<Instant> {
+        self.sent[sequence as usize % KEPT_PROBES]
+            .filter(|(sent, _)| *sent == sequence)
+            .map(|(_, at)| at)
+    }
+}
+
+/// Parses a response to the discover commands of `sent`, returning the
+/// response with its round trip time.
+fn parse_response(
+    buffer: &[u8],
+    remote: SocketAddr,
+    sent: &Probes,
+) -> anyhow::Result<(discover::Response, Option<Duration>)> {
+    let rtt_end = Instant::now();
+    trace!(
+        "inbound packet from {remote}: {buffer:?}",
+        buffer = hex_dump(buffer)
+    );
+    dump::packet(Direction::Inbound, remote, buffer);
+
+    let packet = PacketHeaderOnly::parse(buffer).map_err(|e| parse_error(e, buffer))?;
+    ensure!(
+        packet.error() == 0 || packet.payload_size() > 0,
+        "remote peer {remote} returns error code `{err:#02x}`",
+        err = packet.error()
+    );
 
-    let key_style = Style::new().bright_blue();
-    let value_style = Style::new().bright_yellow();
+    let packet: Packet<discover::Response> =
+        packet.try_into().map_err(|e| parse_error(e, buffer))?;
+    trace!("decoded packet {packet:#}");
+    // responses carry the sequence number of the command
+    let rtt = sent.sent_at(packet.sequence()).map(|sent| rtt_end - sent);
+    Ok((packet.payload(), rtt))
+}
 
+/// Adds the device of a finished inquiry, passing it to `found`, and
+/// returns whether `expect` devices have been found.
+fn collect(
+    join_result: Result<anyhow::Result<ScannedDevice>, JoinError>,
+    config: &ScanConfig,
+    seen: &mut HashSet<(IpAddr, MacAddr)>,
+    devices: &mut Vec<ScannedDevice>,
+    found: &mut impl FnMut(&ScannedDevice) -> anyhow::Result<()>,
+) -> anyhow::Result<bool> {
+    match join_result
+        .context("failed to join task")
+        .and_then(std::convert::identity)
+    {
+        Ok(scanned) if !seen.insert((*scanned.device.ip_addr(), *scanned.device.mac_addr())) => {}
+        Ok(device) => {
+            found(&device)?;
+            devices.push(device);
+        }
+        Err(e) if crash::is_panic(&e) => return Err(e),
+        Err(e) => error!("inquiry failed: {e:?}"),
+    }
+    Ok(config.expect.is_some_and(|expect| seen.len() >= expect))
+}
+
+async fn inquire_device(
+    device: discover::Response,
+    socket_options: SocketOptions,
+) -> anyhow::Result<DiscoveredDevice> {
+    let mut channel = Channel::new(
+        SocketAddr::new(*device.ip_addr(), BJNP_PORT),
+        &socket_options,
+    )
+    .await?;
+    let id = channel.roundtrip(identity::Request::new()).await?;
+
+    Ok(DiscoveredDevice::new(&device, id))
+}
+
+fn print_device(
+    ScannedDevice { device, hostname }: &ScannedDevice,
+    quiet_output: Option<QuietOutput>,
+) -> anyhow::Result<()> {
     let stdout = io::stdout();
     let mut handle = stdout.lock();
 
+    if let Some(quiet_output) = quiet_output {
+        let line = match quiet_output {
+            QuietOutput::Ip => device.ip_addr().to_string(),
+            QuietOutput::Addr => SocketAddr::new(*device.ip_addr(), BJNP_PORT).to_string(),
+            QuietOutput::Mac => device.mac_addr().to_string(),
+        };
+        writeln!(handle, "{line}").context("failed to write to stdout")?;
+        return Ok(());
+    }
+
     writeln!(
         handle,
         "Scanner {IP}={ip} {MAC}={mac}",
-        IP = "IP".if_supports_color(owo_colors::Stream::Stdout, |v| v.style(key_style)),
-        MAC = "MAC".if_supports_color(owo_colors::Stream::Stdout, |v| v.style(key_style)),
-        ip = format!("{addr}:{BJNP_PORT}", addr = device.ip_addr())
-            .if_supports_color(owo_colors::Stream::Stdout, |v| v.style(value_style)),
-        mac = device
-            .mac_addr()
-            .if_supports_color(owo_colors::Stream::Stdout, |v| v.style(value_style)),
+        IP = key("IP"),
+        MAC = key("MAC"),
+        ip = value(format!("{addr}:{BJNP_PORT}", addr = device.ip_addr())),
+        mac = value(device.mac_addr()),
     )
     .context("failed to write to stdout")?;
-    for (key, value) in id.iter() {
+    if let Some(hostname) = hostname {
+        writeln!(
+            handle,
+            "  {HOSTNAME}: {hostname}",
+            HOSTNAME = key("Hostname"),
+            hostname = value(hostname)
+        )
+        .context("failed to write to stdout")?;
+    }
+    #[cfg(feature = "oui")]
+    {
+        let vendor = device.mac_addr().vendor();
+        // the embedded table is far from covering every Canon OUI, so only
+        // OUIs known to belong to another vendor are suspicious
+        if let Some(vendor) = vendor.filter(|vendor| !vendor.starts_with("Canon")) {
+            tracing::warn!(
+                "device at {addr} ({mac}) may not be a Canon device, its MAC belongs to {vendor}",
+                addr = device.ip_addr(),
+                mac = device.mac_addr()
+            );
+        }
         writeln!(
             handle,
-            "  {key}: {value}",
-            key = key.if_supports_color(owo_colors::Stream::Stdout, |v| v.style(key_style)),
-            value = value.if_supports_color(owo_colors::Stream::Stdout, |v| v.style(value_style))
+            "  {VENDOR}: {vendor}",
+            VENDOR = key("Vendor"),
+            vendor = value(vendor.unwrap_or("<unknown>"))
         )
         .context("failed to write to stdout")?;
     }
+    let mut identity: Vec<_> = device.identity().iter().collect();
+    identity.sort();
+    for (k, v) in identity {
+        writeln!(handle, "  {key}: {value}", key = key(k), value = value(v))
+            .context("failed to write to stdout")?;
+    }
+    writeln!(
+        handle,
+        "  {CAPABILITIES}: {capabilities}",
+        CAPABILITIES = key("Capabilities"),
+        capabilities = value(device.capabilities())
+    )
+    .context("failed to write to stdout")?;
 
     Ok(())
 }
+
+/// Highlights a key in the scan results if stdout supports color.
+#[cfg(feature = "color")]
+fn key(key: impl Display) -> String {
+    highlight(key, Style::new().bright_blue())
+}
+
+/// Highlights a value in the scan results if stdout supports color.
+#[cfg(feature = "color")]
+fn value(value: impl Display) -> String {
+    highlight(value, Style::new().bright_yellow())
+}
+
+#[cfg(feature = "color")]
+fn highlight(text: impl Display, style: Style) -> String {
+    text.if_supports_color(owo_colors::Stream::Stdout, |v| v.style(style))
+        .to_string()
+}
+
+#[cfg(not(feature = "color"))]
+fn key(key: impl Display) -> String {
+    key.to_string()
+}
+
+#[cfg(not(feature = "color"))]
+fn value(value: impl Display) -> String {
+    value.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn directed_broadcast_from_netmask() {
+        let ip = Ipv4Addr::new(10, 1, 2, 3);
+        let netmask = Some(Ipv4Addr::new(255, 255, 252, 0));
+        assert_eq!(
+            directed_broadcast(ip, None, netmask),
+            Some(Ipv4Addr::new(10, 1, 3, 255))
+        );
+        let broadcast = Some(Ipv4Addr::new(10, 1, 2, 255));
+        assert_eq!(directed_broadcast(ip, broadcast, netmask), broadcast);
+        assert_eq!(directed_broadcast(ip, None, None), Some(IPV4_BROADCAST));
+        let point_to_point = Some(Ipv4Addr::BROADCAST);
+        assert_eq!(directed_broadcast(ip, None, point_to_point), None);
+        let netmask = Some(Ipv4Addr::new(255, 0, 0, 0));
+        assert_eq!(directed_broadcast(Ipv4Addr::LOCALHOST, None, netmask), None);
+    }
+
+    #[test]
+    fn virtual_interfaces() {
+        assert!(is_virtual("docker0"));
+        assert!(is_virtual("veth1a2b3c"));
+        assert!(is_virtual("tun0"));
+        assert!(is_virtual("utun3"));
+        assert!(!is_virtual("eth0"));
+        assert!(!is_virtual("wlp2s0"));
+        assert!(!is_virtual("en0"));
+    }
+
+    #[test]
+    fn probes_until_dropped() {
+        let mut limited = Probes::new(Some(2));
+        assert_eq!((limited.send(), limited.send()), (0, 1));
+        assert!(!limited.pending());
+
+        let mut probes = Probes::new(None);
+        for _ in 0..u32::from(u16::MAX) + 3 {
+            assert!(probes.pending());
+            probes.send();
+        }
+        // sequence numbers wrapped around, only the last ones are kept
+        assert!(probes.sent_at(2).is_none());
+        assert!(probes.sent_at(1).is_some());
+        assert!(probes.sent_at(u16::MAX - KEPT_PROBES as u16 + 3).is_some());
+        assert!(probes.sent_at(u16::MAX - KEPT_PROBES as u16 + 2).is_none());
+    }
+}
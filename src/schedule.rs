@@ -0,0 +1,128 @@
+use std::{fmt, str::FromStr};
+
+use time::Time;
+use tokio::time::Duration;
+
+const SECS_PER_DAY: u32 = 24 * 3600;
+
+/// Times of the day, in local time, when listeners poll scanners, like
+/// `08:00-22:00` or `07:00-09:00,18:00-23:30`. A range ending before it starts
+/// spans midnight, and one ending when it starts spans the whole day.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ActiveHours {
+    /// Start and end of each range, in seconds since midnight
+    ranges: Vec<(u32, u32)>,
+}
+
+impl ActiveHours {
+    fn contains(&self, secs: u32) -> bool {
+        self.ranges
+            .iter()
+            .any(|&(start, end)| match start.cmp(&end) {
+                std::cmp::Ordering::Less => start <= secs && secs < end,
+                std::cmp::Ordering::Greater => secs >= start || secs < end,
+                std::cmp::Ordering::Equal => true,
+            })
+    }
+
+    /// Returns the delay from `now` until the next range starts, or `None`
+    /// if `now` is within a range.
+    pub fn until_active(&self, now: Time) -> Option<Duration> {
+        let (hour, minute, second) = now.as_hms();
+        let secs = hour as u32 * 3600 + minute as u32 * 60 + second as u32;
+        if self.contains(secs) {
+            return None;
+        }
+        self.ranges
+            .iter()
+            .map(|&(start, _)| (start + SECS_PER_DAY - secs) % SECS_PER_DAY)
+            .min()
+            .map(|secs| Duration::from_secs(secs.into()))
+    }
+}
+
+impl FromStr for ActiveHours {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let ranges = s
+            .split(',')
+            .map(|range| {
+                let (start, end) = range
+                    .trim()
+                    .split_once('-')
+                    .ok_or_else(|| format!("`{range}` is not in the form of `HH:MM-HH:MM`"))?;
+                Ok((parse_time(start)?, parse_time(end)?))
+            })
+            .collect::<Result<_, String>>()?;
+        Ok(Self { ranges })
+    }
+}
+
+impl fmt::Display for ActiveHours {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, (start, end)) in self.ranges.iter().enumerate() {
+            if i > 0 {
+                f.write_str(",")?;
+            }
+            write!(
+                f,
+                "{:02}:{:02}-{:02}:{:02}",
+                start / 3600,
+                start % 3600 / 60,
+                end / 3600,
+                end % 3600 / 60
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Parses `HH:MM` into seconds since midnight, where `24:00` is midnight.
+fn parse_time(s: &str) -> Result<u32, String> {
+    let invalid = || format!("`{s}` is not a time of the day like `22:00`");
+    let (hour, minute) = s.trim().split_once(':').ok_or_else(invalid)?;
+    let hour: u32 = hour.parse().map_err(|_| invalid())?;
+    let minute: u32 = minute.parse().map_err(|_| invalid())?;
+    match (hour, minute) {
+        (24, 0) => Ok(0),
+        (0..24, 0..60) => Ok(hour * 3600 + minute * 60),
+        _ => Err(invalid()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn active_hours() {
+        let at = |hour, minute| Time::from_hms(hour, minute, 0).unwrap();
+        let hours: ActiveHours = "08:00-22:00".parse().unwrap();
+        assert_eq!(hours.until_active(at(12, 0)), None);
+        assert_eq!(hours.until_active(at(8, 0)), None);
+        assert_eq!(
+            hours.until_active(at(22, 0)),
+            Some(Duration::from_secs(10 * 3600))
+        );
+        assert_eq!(hours.until_active(at(7, 59)), Some(Duration::from_secs(60)));
+
+        let hours: ActiveHours = "07:00-09:00, 18:00-00:30".parse().unwrap();
+        assert_eq!(hours.to_string(), "07:00-09:00,18:00-00:30");
+        assert_eq!(hours.until_active(at(0, 15)), None);
+        assert_eq!(
+            hours.until_active(at(12, 0)),
+            Some(Duration::from_secs(6 * 3600))
+        );
+        assert_eq!(
+            "00:00-24:00"
+                .parse::<ActiveHours>()
+                .unwrap()
+                .until_active(at(3, 0)),
+            None
+        );
+
+        assert!("8-22".parse::<ActiveHours>().is_err());
+        assert!("08:00-24:30".parse::<ActiveHours>().is_err());
+    }
+}
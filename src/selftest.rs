@@ -0,0 +1,185 @@
+//! Checks a listening configuration once and exits, for package installation
+//! scripts and CI of user configurations: binds the socket, discovers the
+//! scanner, runs a cycle of initialization and poll for each host entry and
+//! looks up the commands they launch without running them.
+
+use std::{
+    env,
+    ffi::OsStr,
+    path::{Path, PathBuf},
+};
+
+use anyhow::anyhow;
+use bjnp::discover;
+use serde::Serialize;
+use tokio::time::Duration;
+
+use crate::{
+    channel::Dispatcher,
+    poll::{self, ListenConfig},
+    quirks::QuirksDb,
+    utils::unspecified_for,
+};
+
+#[derive(Debug, Serialize)]
+struct Check {
+    check: &'static str,
+    /// Host entry checked, unless common to all of them
+    #[serde(skip_serializing_if = "Option::is_none")]
+    host: Option<String>,
+    passed: bool,
+    detail: String,
+}
+
+impl Check {
+    fn new(check: &'static str, host: Option<String>, result: anyhow::Result<String>) -> Self {
+        let (passed, detail) = match result {
+            Ok(detail) => (true, detail),
+            Err(e) => (false, format!("{e:#}")),
+        };
+        Self {
+            check,
+            host,
+            passed,
+            detail,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct Report {
+    passed: bool,
+    checks: Vec<Check>,
+}
+
+/// Checks the host entries of `configs`, all on the same scanner, prints the
+/// report as TOML and returns whether every check passed.
+pub async fn run(configs: Vec<ListenConfig>) -> anyhow::Result<bool> {
+    let quirks_db = QuirksDb::load()?;
+    let commands: Vec<_> = configs
+        .iter()
+        .map(|config| {
+            let host = Some(config.hostname.to_string());
+            Check::new("command", host, command(config))
+        })
+        .collect();
+    let mut checks = scanner_checks(configs, quirks_db).await;
+    checks.extend(commands);
+    let report = Report {
+        passed: checks.iter().all(|check| check.passed),
+        checks,
+    };
+    print!("{}", toml::to_string(&report)?);
+    Ok(report.passed)
+}
+
+/// Checks the socket, the scanner and a cycle for each host entry, stopping
+/// at the first check the others depend on failing.
+async fn scanner_checks(configs: Vec<ListenConfig>, quirks_db: QuirksDb) -> Vec<Check> {
+    let mut checks = vec![];
+    // NOPANIC: there is at least one host entry to register
+    let config = &configs[0];
+    let local = config
+        .bind_addr
+        .unwrap_or_else(|| unspecified_for(&config.scanner_addr));
+    let dispatcher = match Dispatcher::bind(local, &config.socket_options).await {
+        Ok(dispatcher) => {
+            checks.push(Check::new("socket", None, Ok(format!("bound to {local}"))));
+            dispatcher
+        }
+        Err(e) => {
+            // nothing else can be sent
+            checks.push(Check::new("socket", None, Err(e)));
+            return checks;
+        }
+    };
+
+    let mut channel = dispatcher.channel(config.scanner_addr);
+    let max_waiting = Duration::from_secs(config.initial_max_waiting);
+    let discovered = channel
+        .roundtrip_with_timeout(discover::Request::new(), max_waiting, 0)
+        .await
+        .map(|resp| {
+            format!(
+                "{mac_addr} answered at {addr}",
+                mac_addr = resp.mac_addr(),
+                addr = config.scanner_addr
+            )
+        });
+    checks.push(Check::new("scanner", None, discovered));
+
+    for config in configs {
+        let host = Some(config.hostname.to_string());
+        let cycle = poll::cycle(config, dispatcher.clone(), quirks_db.clone()).await;
+        checks.push(Check::new("cycle", host, cycle));
+    }
+    checks
+}
+
+/// Looks up the program launched on scan jobs as spawning it would, without
+/// running it.
+fn command(config: &ListenConfig) -> anyhow::Result<String> {
+    let Some((program, _)) = &config.command else {
+        return Ok("no command, scan jobs are only reported".to_owned());
+    };
+    match find_executable(program) {
+        Some(path) => Ok(format!(
+            "`{}` is {}",
+            program.to_string_lossy(),
+            path.display()
+        )),
+        None => Err(anyhow!(
+            "`{}` isn't an executable file nor found in PATH",
+            program.to_string_lossy()
+        )),
+    }
+}
+
+/// Returns the executable file `program` refers to, a path if it has several
+/// components and otherwise a file name searched in `PATH`.
+fn find_executable(program: &OsStr) -> Option<PathBuf> {
+    let path = Path::new(program);
+    if path.components().count() > 1 {
+        return is_executable(path).then(|| path.to_owned());
+    }
+    env::split_paths(&env::var_os("PATH")?)
+        .map(|dir| dir.join(program))
+        .find(|path| is_executable(path))
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+
+    path.metadata()
+        .is_ok_and(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    // programs are found without their extension
+    path.is_file() || path.with_extension("exe").is_file()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn finds_executables() {
+        assert_eq!(
+            find_executable(OsStr::new("/bin/sh")),
+            Some(PathBuf::from("/bin/sh"))
+        );
+        assert!(find_executable(OsStr::new("sh")).is_some());
+        assert!(find_executable(OsStr::new("scanner-button-missing")).is_none());
+
+        let path = env::temp_dir().join(format!("scanner-button-{}.sh", std::process::id()));
+        std::fs::write(&path, "").unwrap();
+        let found = find_executable(path.as_os_str());
+        std::fs::remove_file(&path).unwrap();
+        // not executable
+        assert!(found.is_none());
+    }
+}
@@ -0,0 +1,231 @@
+//! Renders and installs systemd units running a listener, so that it starts
+//! with the machine or the session of the user and restarts after failing.
+
+use std::{
+    env, fs, iter,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use anyhow::{anyhow, ensure, Context};
+
+/// Options taking a path, resolved against the current directory since
+/// services run from `/`
+const PATH_OPTIONS: [&str; 5] = [
+    "--targets",
+    "--status-rules",
+    "--state-file",
+    "--log-file",
+    "--dump-packets",
+];
+
+#[derive(Debug)]
+pub struct InstallConfig {
+    /// Name of the unit without `.service`
+    pub name: String,
+    /// Whether to install a user unit instead of a system unit
+    pub user: bool,
+    /// Whether to enable and start the unit once installed
+    pub enable: bool,
+    /// Whether to replace an installed unit of the same name
+    pub force: bool,
+    /// Arguments of this program running the listener, from `listen`
+    pub listen: Vec<String>,
+}
+
+/// Renders a unit running `command`, the program with its arguments, for the
+/// system or for the manager of the user. A unit of the system runs as
+/// `run_as` if given, or as root.
+pub fn unit(description: &str, command: &[String], user: bool, run_as: Option<&str>) -> String {
+    let exec_start: Vec<_> = command.iter().map(|arg| quote(arg)).collect();
+    let run_as = match run_as {
+        Some(name) if !user => format!("User={name}\n"),
+        _ => String::new(),
+    };
+    // user managers have no network targets to wait for
+    let (after, wanted_by) = if user {
+        ("", "default.target")
+    } else {
+        (
+            "Wants=network-online.target\nAfter=network-online.target\n",
+            "multi-user.target",
+        )
+    };
+    format!(
+        "\
+[Unit]
+Description={description}
+{after}
+[Service]
+{run_as}ExecStart={exec_start}
+Restart=on-failure
+RestartSec=5
+
+[Install]
+WantedBy={wanted_by}
+",
+        exec_start = exec_start.join(" ")
+    )
+}
+
+/// Installs a unit running the listener into the directory of the system or
+/// the user, then enables and starts it if asked.
+pub fn install(config: InstallConfig) -> anyhow::Result<()> {
+    let dir = unit_dir(config.user)?;
+    let name = format!("{}.service", config.name);
+    let path = dir.join(&name);
+    ensure!(
+        config.force || !path.exists(),
+        "{} already exists, pass `--force` to replace it",
+        path.display()
+    );
+
+    let program = env::current_exe().context("couldn't locate this program")?;
+    let current = env::current_dir().context("couldn't get the current directory")?;
+    let command: Vec<_> = iter::once(program.display().to_string())
+        .chain(absolutize(config.listen, &current))
+        .collect();
+    let unit = unit("Scan button listener", &command, config.user, None);
+    fs::create_dir_all(&dir).with_context(|| format!("couldn't create {}", dir.display()))?;
+    fs::write(&path, unit).with_context(|| format!("couldn't write unit to {}", path.display()))?;
+    println!("Installed {}", path.display());
+
+    let user = if config.user { " --user" } else { "" };
+    if config.enable {
+        systemctl(config.user, &["daemon-reload"])?;
+        systemctl(config.user, &["enable", "--now", &name])?;
+        println!("Enabled and started {name}, see its logs with `journalctl{user} -u {name}`");
+    } else {
+        println!(
+            "Start it with `systemctl{user} daemon-reload && systemctl{user} enable --now {name}`"
+        );
+    }
+    Ok(())
+}
+
+/// Directory units are installed into, `/etc/systemd/system` for the system
+/// and `~/.config/systemd/user` for the user.
+fn unit_dir(user: bool) -> anyhow::Result<PathBuf> {
+    if !user {
+        return Ok(PathBuf::from("/etc/systemd/system"));
+    }
+    let config = env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))
+        .ok_or_else(|| anyhow!("couldn't locate the configuration directory of the user"))?;
+    Ok(config.join("systemd").join("user"))
+}
+
+/// Resolves the values of [`PATH_OPTIONS`] in `args` against `dir`, leaving
+/// the command after `--` as is.
+fn absolutize(args: Vec<String>, dir: &Path) -> Vec<String> {
+    let mut args = args.into_iter();
+    let mut absolute = vec![];
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            absolute.push(arg);
+            absolute.extend(args);
+            break;
+        }
+        match arg.split_once('=') {
+            Some((option, path)) if PATH_OPTIONS.contains(&option) => {
+                absolute.push(format!("{option}={}", dir.join(path).display()));
+            }
+            _ if PATH_OPTIONS.contains(&arg.as_str()) => {
+                absolute.push(arg);
+                absolute.extend(args.next().map(|path| dir.join(path).display().to_string()));
+            }
+            _ => absolute.push(arg),
+        }
+    }
+    absolute
+}
+
+fn systemctl(user: bool, args: &[&str]) -> anyhow::Result<()> {
+    let mut command = Command::new("systemctl");
+    if user {
+        command.arg("--user");
+    }
+    let status = command
+        .args(args)
+        .status()
+        .context("couldn't run systemctl")?;
+    ensure!(
+        status.success(),
+        "`systemctl {}` failed: {status}",
+        args.join(" ")
+    );
+    Ok(())
+}
+
+/// Quotes an argument of `ExecStart`, escaping the specifiers and variables
+/// systemd would otherwise expand.
+fn quote(arg: &str) -> String {
+    let arg = arg.replace('%', "%%").replace('$', "$$");
+    let plain = !arg.is_empty()
+        && !arg.contains(|c: char| c.is_whitespace() || matches!(c, '"' | '\'' | '\\' | ';'));
+    if plain {
+        arg
+    } else {
+        format!("\"{}\"", arg.replace('\\', "\\\\").replace('"', "\\\""))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unit_quotes_arguments() {
+        let command = [
+            "/usr/bin/scanner-button",
+            "listen",
+            "--targets",
+            "/home/me/My Scans/targets.toml",
+            "50%",
+            "$HOME",
+            "say \"hi\"",
+        ]
+        .map(String::from);
+        let system = unit("Scan button listener", &command, false, Some("me"));
+        assert!(system.contains(
+            "User=me\nExecStart=/usr/bin/scanner-button listen --targets \
+             \"/home/me/My Scans/targets.toml\" 50%% $$HOME \"say \\\"hi\\\"\"\n"
+        ));
+        assert!(system.contains("WantedBy=multi-user.target\n"));
+        let user = unit("", &command, true, Some("me"));
+        assert!(user.contains("WantedBy=default.target\n"));
+        assert!(!user.contains("User="));
+    }
+
+    #[test]
+    fn absolutize_paths() {
+        let args = [
+            "listen",
+            "--targets",
+            "targets.toml",
+            "--state-file=/var/lib/state.toml",
+            "--status-rules=rules.toml",
+            "--",
+            "scan.sh",
+            "--targets",
+            "x",
+        ]
+        .map(String::from);
+        assert_eq!(
+            absolutize(args.to_vec(), Path::new("/home/me")),
+            [
+                "listen",
+                "--targets",
+                "/home/me/targets.toml",
+                "--state-file=/var/lib/state.toml",
+                "--status-rules=/home/me/rules.toml",
+                "--",
+                "scan.sh",
+                "--targets",
+                "x",
+            ]
+        );
+    }
+}
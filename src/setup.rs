@@ -0,0 +1,278 @@
+//! Interactive setup for first time users: discovers scanners, asks for the
+//! one to listen on and the command to execute on scan jobs, and writes a
+//! targets file for `listen --targets` along with an optional systemd unit.
+
+use std::{
+    env, fs,
+    io::{self, BufRead, Write},
+    net::{SocketAddr, ToSocketAddrs},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context};
+
+use crate::{
+    scan::{self, ScanConfig, ScannedDevice},
+    service,
+    targets::{Target, Targets},
+    utils::BJNP_PORT,
+};
+
+#[derive(Debug)]
+pub struct SetupConfig {
+    pub scan: ScanConfig,
+    /// File to write the host entry to
+    pub output: PathBuf,
+    /// File to write the systemd unit to if asked for
+    pub unit: PathBuf,
+}
+
+/// Commands offered to be executed on scan jobs
+const TEMPLATES: [&str; 3] = [
+    "Save scans as PNG into a folder with `scanimage`",
+    "Log scan jobs to the system log with `logger`",
+    "Enter a command",
+];
+
+/// Asks questions on a terminal, or any other pair of input and output.
+struct Prompt<R, W> {
+    input: R,
+    output: W,
+}
+
+impl<R: BufRead, W: Write> Prompt<R, W> {
+    /// Writes `text` and reads a line in answer.
+    fn read_line(&mut self, text: &str) -> anyhow::Result<String> {
+        write!(self.output, "{text}: ")?;
+        self.output.flush()?;
+        let mut line = String::new();
+        if self.input.read_line(&mut line)? == 0 {
+            bail!("setup cancelled");
+        }
+        Ok(line.trim().to_owned())
+    }
+
+    /// Asks for a line of text, returning `default` for an empty line.
+    fn ask(&mut self, question: &str, default: Option<&str>) -> anyhow::Result<String> {
+        loop {
+            let answer = match default {
+                Some(default) => self.read_line(&format!("{question} [{default}]"))?,
+                None => self.read_line(question)?,
+            };
+            match (answer.as_str(), default) {
+                ("", Some(default)) => return Ok(default.to_owned()),
+                ("", None) => continue,
+                _ => return Ok(answer),
+            }
+        }
+    }
+
+    /// Asks to pick one of `options` by its number, returning its index.
+    fn choose(&mut self, question: &str, options: &[String]) -> anyhow::Result<usize> {
+        writeln!(self.output, "{question}")?;
+        for (i, option) in options.iter().enumerate() {
+            writeln!(self.output, "  {}) {option}", i + 1)?;
+        }
+        loop {
+            let answer = self.ask("Number", Some("1"))?;
+            match answer.parse::<usize>() {
+                Ok(n) if (1..=options.len()).contains(&n) => return Ok(n - 1),
+                _ => writeln!(self.output, "`{answer}` is not one of the numbers above")?,
+            }
+        }
+    }
+
+    /// Asks a yes or no question.
+    fn confirm(&mut self, question: &str, default: bool) -> anyhow::Result<bool> {
+        loop {
+            let choices = if default { "Y/n" } else { "y/N" };
+            let answer = self.read_line(&format!("{question} [{choices}]"))?;
+            match answer.to_lowercase().as_str() {
+                "" => return Ok(default),
+                "y" | "yes" => return Ok(true),
+                "n" | "no" => return Ok(false),
+                _ => writeln!(self.output, "please answer yes or no")?,
+            }
+        }
+    }
+}
+
+/// Runs the setup on the terminal.
+pub async fn setup(config: SetupConfig) -> anyhow::Result<()> {
+    println!("Looking for scanners in the LAN...");
+    let devices = scan::scan(config.scan, |_| Ok(())).await?;
+    let stdin = io::stdin();
+    let mut prompt = Prompt {
+        input: stdin.lock(),
+        output: io::stdout(),
+    };
+
+    let scanner = choose_scanner(&mut prompt, &devices)?;
+    let name = prompt.ask(
+        "Name of the host entry shown on the scanner, where `{user}` and `{host}` are \
+         replaced by the user name and the name of this machine",
+        Some("{host}"),
+    )?;
+    let command = choose_command(&mut prompt)?;
+    let targets: Targets = [Target { name, command }].into_iter().collect();
+    if confirm_overwrite(&mut prompt, &config.output)? {
+        targets.save(&config.output)?;
+        println!("Wrote {}", config.output.display());
+    }
+
+    let targets_path = config
+        .output
+        .canonicalize()
+        .with_context(|| format!("couldn't locate {}", config.output.display()))?;
+    let program = env::current_exe().context("couldn't locate this program")?;
+    let listen = [
+        program.display().to_string(),
+        "listen".to_owned(),
+        "--scanner".to_owned(),
+        scanner,
+        "--targets".to_owned(),
+        targets_path.display().to_string(),
+    ];
+    if prompt.confirm("Write a systemd unit running the listener?", false)?
+        && confirm_overwrite(&mut prompt, &config.unit)?
+    {
+        // run as the invoking user, whose home scans are saved into
+        let user = env::var("USER")
+            .or_else(|_| env::var("USERNAME"))
+            .ok()
+            .filter(|user| !user.is_empty() && user != "root");
+        let unit = service::unit("Scan button listener", &listen, false, user.as_deref());
+        fs::write(&config.unit, unit)
+            .with_context(|| format!("couldn't write unit to {}", config.unit.display()))?;
+        println!(
+            "Wrote {unit}, install it with\n  \
+             sudo cp {unit} /etc/systemd/system/\n  \
+             sudo systemctl enable --now {name}",
+            unit = config.unit.display(),
+            name = config
+                .unit
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy(),
+        );
+    } else {
+        println!("Start listening with\n  {}", listen.join(" "));
+    }
+    Ok(())
+}
+
+/// Asks for the scanner among the discovered devices, or for its address.
+fn choose_scanner(
+    prompt: &mut Prompt<impl BufRead, impl Write>,
+    devices: &[ScannedDevice],
+) -> anyhow::Result<String> {
+    let mut options: Vec<_> = devices
+        .iter()
+        .map(|ScannedDevice { device, hostname }| {
+            let addr = SocketAddr::new(*device.ip_addr(), BJNP_PORT);
+            let model = device.identity().get("MDL").unwrap_or("<unknown model>");
+            match hostname {
+                Some(hostname) => format!("{model} at {addr} ({hostname})"),
+                None => format!("{model} at {addr} ({})", device.mac_addr()),
+            }
+        })
+        .collect();
+    options.push("Enter the address of a scanner not found".to_owned());
+    let choice = prompt.choose("Scanner to listen on:", &options)?;
+    if let Some(ScannedDevice { device, .. }) = devices.get(choice) {
+        return Ok(SocketAddr::new(*device.ip_addr(), BJNP_PORT).to_string());
+    }
+    loop {
+        let addr = prompt.ask(
+            "Address of the scanner with port, e.g. 192.168.1.20:8612",
+            None,
+        )?;
+        match addr.to_socket_addrs() {
+            Ok(_) => return Ok(addr),
+            Err(e) => writeln!(prompt.output, "`{addr}` is not a valid address: {e}")?,
+        }
+    }
+}
+
+/// Asks for the command to execute on scan jobs, with its arguments.
+fn choose_command(prompt: &mut Prompt<impl BufRead, impl Write>) -> anyhow::Result<Vec<String>> {
+    let options = TEMPLATES.map(String::from);
+    match prompt.choose(
+        "Command to execute when the scan button is pressed:",
+        &options,
+    )? {
+        0 => {
+            let folder = prompt.ask("Folder to save scans into", Some("~/Scans"))?;
+            // the listener may run as another user, or without a shell
+            let folder = match (folder.strip_prefix("~/"), env::var("HOME")) {
+                (Some(rest), Ok(home)) => format!("{home}/{rest}"),
+                _ => folder,
+            };
+            Ok(vec![
+                "sh".to_owned(),
+                "-c".to_owned(),
+                "mkdir -p \"$0\" && scanimage --format=png --resolution \"$SCANNER_DPI\" \
+                 --output-file \"$0/scan-$(date +%Y%m%d-%H%M%S).png\""
+                    .to_owned(),
+                folder,
+            ])
+        }
+        1 => Ok(vec![
+            "logger".to_owned(),
+            "--tag".to_owned(),
+            "scanner-button".to_owned(),
+            "scan button pressed".to_owned(),
+        ]),
+        _ => loop {
+            let command = prompt.ask(
+                "Command with arguments, separated by spaces, which is given the scan settings \
+                 in SCANNER_* variables",
+                None,
+            )?;
+            let command: Vec<_> = command.split_whitespace().map(str::to_owned).collect();
+            if !command.is_empty() {
+                return Ok(command);
+            }
+        },
+    }
+}
+
+/// Asks whether to overwrite `path` if it exists.
+fn confirm_overwrite(
+    prompt: &mut Prompt<impl BufRead, impl Write>,
+    path: &Path,
+) -> anyhow::Result<bool> {
+    if !path.exists() {
+        return Ok(true);
+    }
+    prompt.confirm(&format!("Overwrite {}?", path.display()), false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prompt_retries_invalid_answers() {
+        let mut output = vec![];
+        let mut prompt = Prompt {
+            input: "7\n\nyes\n3\nscan-to.sh  /srv/scans\n".as_bytes(),
+            output: &mut output,
+        };
+        assert_eq!(
+            prompt
+                .choose("Pick", &["a".to_owned(), "b".to_owned()])
+                .unwrap(),
+            0
+        );
+        assert!(prompt.confirm("Sure?", false).unwrap());
+        assert_eq!(
+            choose_command(&mut prompt).unwrap(),
+            ["scan-to.sh", "/srv/scans"]
+        );
+        assert!(prompt.ask("More", None).is_err());
+        assert!(String::from_utf8(output)
+            .unwrap()
+            .contains("`7` is not one of the numbers above"));
+    }
+}
@@ -0,0 +1,286 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    fs, io,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+/// Number of changes kept in the history of a listener
+const HISTORY_LEN: usize = 32;
+
+/// Number of poll intervals without a successful poll after which a polling
+/// listener is stalled
+const STALE_POLLS: u32 = 5;
+
+/// Shortest time without a successful poll after which a polling listener is
+/// stalled, since a single poll may wait this long for a slow scanner
+const MIN_STALE: Duration = Duration::from_secs(30);
+
+/// State of a listener worth keeping across restarts
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScannerState {
+    /// Session of the registered host, if the listener was polling
+    pub session_id: Option<u32>,
+    /// Delay in seconds of the backoff, if the listener was backing off
+    pub backoff: Option<u64>,
+    /// MAC address of the scanner, to wake it up once it no longer answers
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mac_addr: Option<String>,
+    #[serde(default, skip_serializing_if = "History::is_empty")]
+    pub history: History,
+    /// Unix time of the last successful poll
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_success: Option<i64>,
+    /// Interval between polls in milliseconds
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub poll_interval_ms: Option<u64>,
+}
+
+/// Returns the time without a successful poll after which a listener polling
+/// every `interval` is stalled. The last successful poll is saved at most this
+/// often, so that the state file isn't rewritten on every poll.
+pub fn stale_after(interval: Duration) -> Duration {
+    (interval * STALE_POLLS).max(MIN_STALE)
+}
+
+impl ScannerState {
+    /// Returns for how many seconds the listener has missed its polls, if it
+    /// is polling and hasn't polled successfully for [`stale_after`] its poll
+    /// interval at the Unix time `now`, e.g. because it hangs or was killed.
+    pub fn overdue(&self, now: i64) -> Option<i64> {
+        self.session_id?;
+        let elapsed = now - self.last_success?;
+        let stale = stale_after(Duration::from_millis(self.poll_interval_ms?)).as_secs() as i64;
+        // the saved time lags behind by up to `stale` itself
+        (elapsed > 2 * stale).then_some(elapsed)
+    }
+}
+
+/// A change of the outcome of polling a scanner, which holds until the next
+/// change
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    /// Time of the change in UTC
+    pub at: String,
+    /// Status reported by the scanner, if it responded
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<u32>,
+    /// Why polling failed, if it did
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Last changes of the outcome of polls, oldest first, so that a listener
+/// which stopped working can be diagnosed without verbose logs
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct History(VecDeque<HistoryEntry>);
+
+impl History {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Returns the current outcome of polls, if any poll was made.
+    pub fn last(&self) -> Option<&HistoryEntry> {
+        self.0.back()
+    }
+
+    /// Records the outcome of a poll if it differs from the last one, keeping
+    /// the last [`HISTORY_LEN`] changes.
+    pub fn record(&mut self, at: String, status: Option<u32>, error: Option<String>) {
+        if self
+            .0
+            .back()
+            .is_some_and(|last| last.status == status && last.error == error)
+        {
+            return;
+        }
+        if self.0.len() == HISTORY_LEN {
+            self.0.pop_front();
+        }
+        self.0.push_back(HistoryEntry { at, status, error });
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Entry {
+    scanner: SocketAddr,
+    host: String,
+    #[serde(flatten)]
+    state: ScannerState,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct StateFile {
+    #[serde(default)]
+    listener: Vec<Entry>,
+}
+
+/// States of listeners persisted in a TOML file like
+///
+/// ```toml
+/// [[listener]]
+/// scanner = "192.168.1.20:8612"
+/// host = "Youmu-Desktop"
+/// session_id = 7
+///
+/// [[listener.history]]
+/// at = "2023-01-21 10:00:00.0 +00:00:00"
+/// status = 0
+/// ```
+///
+/// Listeners are keyed by the address of the scanner and the host name, so
+/// that the file can be shared by all listeners of a process. The file is
+/// rewritten whenever a state changes.
+#[derive(Debug, Clone)]
+pub struct StateStore {
+    path: PathBuf,
+    states: Arc<Mutex<HashMap<(SocketAddr, String), ScannerState>>>,
+}
+
+impl StateStore {
+    /// Loads the states from `path`, starting afresh if the file doesn't exist.
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let file: StateFile = match fs::read_to_string(path) {
+            Ok(content) => toml::from_str(&content)
+                .with_context(|| format!("invalid state file {}", path.display()))?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => StateFile::default(),
+            Err(e) => {
+                return Err(e)
+                    .with_context(|| format!("couldn't read state from {}", path.display()))
+            }
+        };
+        let states = file
+            .listener
+            .into_iter()
+            .map(|entry| ((entry.scanner, entry.host), entry.state))
+            .collect();
+        Ok(Self {
+            path: path.to_owned(),
+            states: Arc::new(Mutex::new(states)),
+        })
+    }
+
+    pub fn get(&self, scanner: SocketAddr, host: &str) -> ScannerState {
+        let states = self.states.lock().unwrap();
+        states
+            .get(&(scanner, host.to_owned()))
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Returns the states of all listeners, sorted by scanner and host.
+    pub fn all(&self) -> Vec<(SocketAddr, String, ScannerState)> {
+        let states = self.states.lock().unwrap();
+        let mut all: Vec<_> = states
+            .iter()
+            .map(|((scanner, host), state)| (*scanner, host.clone(), state.clone()))
+            .collect();
+        all.sort_by(|a, b| (a.0, &a.1).cmp(&(b.0, &b.1)));
+        all
+    }
+
+    /// Records the state of a listener, logging rather than failing when the
+    /// file can't be written.
+    pub fn set(&self, scanner: SocketAddr, host: &str, state: ScannerState) {
+        let mut states = self.states.lock().unwrap();
+        states.insert((scanner, host.to_owned()), state);
+        if let Err(e) = self.save(&states) {
+            warn!("{e:?}");
+        }
+    }
+
+    fn save(&self, states: &HashMap<(SocketAddr, String), ScannerState>) -> anyhow::Result<()> {
+        let mut listener: Vec<_> = states
+            .iter()
+            .map(|((scanner, host), state)| Entry {
+                scanner: *scanner,
+                host: host.clone(),
+                state: state.clone(),
+            })
+            .collect();
+        listener.sort_by(|a, b| (a.scanner, &a.host).cmp(&(b.scanner, &b.host)));
+        let content = toml::to_string(&StateFile { listener })?;
+
+        // replace the file at once, so that a crash never leaves it truncated
+        let temp = self.path.with_extension("tmp");
+        fs::write(&temp, content)
+            .and_then(|_| fs::rename(&temp, &self.path))
+            .with_context(|| format!("couldn't write state to {}", self.path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn state_round_trip() {
+        let path = std::env::temp_dir().join(format!("scanner-button-{}.toml", std::process::id()));
+        let scanner = "192.168.1.20:8612".parse().unwrap();
+        let mut polling = ScannerState {
+            session_id: Some(7),
+            ..Default::default()
+        };
+        polling
+            .history
+            .record("2023-01-21 10:00:00.0 +00:00:00".into(), Some(0), None);
+        let backing_off = ScannerState {
+            backoff: Some(20),
+            mac_addr: Some("00:1e:8f:01:02:03".into()),
+            ..Default::default()
+        };
+
+        let store = StateStore::load(&path).unwrap();
+        assert_eq!(store.get(scanner, "Youmu-Desktop"), ScannerState::default());
+        store.set(scanner, "Youmu-Desktop", polling.clone());
+        store.set(scanner, "Youmu-Laptop", backing_off.clone());
+
+        let store = StateStore::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(store.get(scanner, "Youmu-Desktop"), polling);
+        assert_eq!(store.get(scanner, "Youmu-Laptop"), backing_off);
+    }
+
+    #[test]
+    fn overdue_after_stale_polls() {
+        let mut state = ScannerState {
+            session_id: Some(7),
+            last_success: Some(1000),
+            poll_interval_ms: Some(10_000),
+            ..Default::default()
+        };
+        assert_eq!(state.overdue(1100), None);
+        assert_eq!(state.overdue(1101), Some(101));
+
+        // short intervals leave time for a slow poll
+        state.poll_interval_ms = Some(1000);
+        assert_eq!(state.overdue(1060), None);
+        assert_eq!(state.overdue(1061), Some(61));
+
+        // not polling, e.g. in quiet hours
+        state.session_id = None;
+        assert_eq!(state.overdue(2000), None);
+    }
+
+    #[test]
+    fn history_keeps_changes() {
+        let mut history = History::default();
+        for i in 0..HISTORY_LEN / 2 + 1 {
+            history.record(format!("{i}"), Some(0), None);
+            history.record(format!("{i}"), None, Some("timeout".into()));
+            history.record(format!("{i}"), None, Some("timeout".into()));
+        }
+        assert_eq!(history.0.len(), HISTORY_LEN);
+        assert_eq!(history.0.back().unwrap().error.as_deref(), Some("timeout"));
+        assert_eq!(history.0.front().unwrap().at, "1");
+    }
+}
@@ -0,0 +1,122 @@
+use std::{fs, path::Path, process::Command};
+
+use anyhow::{bail, Context};
+use serde::Deserialize;
+use tracing::{trace, warn};
+
+/// What to do when the scanner reports a matching status
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    Ignore,
+    Log,
+    Hook,
+    Error,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Rule {
+    code: u32,
+    #[serde(default = "Rule::default_mask")]
+    mask: u32,
+    action: Action,
+    #[serde(default)]
+    command: Vec<String>,
+}
+
+impl Rule {
+    fn default_mask() -> u32 {
+        u32::MAX
+    }
+
+    fn matches(&self, status: u32) -> bool {
+        status & self.mask == self.code
+    }
+}
+
+/// Rules mapping poll status values to actions, loaded from a TOML file like
+///
+/// ```toml
+/// [[status]]
+/// code = 0x0040
+/// action = "hook"
+/// command = ["notify-send", "ADF jam"]
+/// ```
+///
+/// The first matching rule wins; statuses without a matching rule are ignored.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct StatusRules {
+    #[serde(default)]
+    status: Vec<Rule>,
+}
+
+impl StatusRules {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("couldn't read status rules from {}", path.display()))?;
+        let rules: Self = toml::from_str(&content)
+            .with_context(|| format!("invalid status rules in {}", path.display()))?;
+        for rule in rules.status.iter() {
+            if rule.action == Action::Hook && rule.command.is_empty() {
+                bail!(
+                    "status rule for {code:#06x} has action `hook` but no command",
+                    code = rule.code
+                );
+            }
+        }
+        Ok(rules)
+    }
+
+    /// Applies the first rule matching `status`. Returns an error if the rule
+    /// treats the status as an error.
+    pub fn apply(&self, status: u32) -> anyhow::Result<()> {
+        let Some(rule) = self.status.iter().find(|rule| rule.matches(status)) else {
+            return Ok(());
+        };
+        trace!("status {status:#06x} matched rule {rule:?}");
+
+        match rule.action {
+            Action::Ignore => {}
+            Action::Log => warn!("scanner reported status {status:#06x}"),
+            Action::Hook => {
+                let (cmd, args) = rule.command.split_first().unwrap();
+                if let Err(e) = Command::new(cmd)
+                    .args(args)
+                    .env("SCANNER_STATUS", format!("{status:#06x}"))
+                    .spawn()
+                {
+                    warn!("failed to launch executable `{cmd}`: {e}");
+                }
+            }
+            Action::Error => bail!("scanner reported error status {status:#06x}"),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn first_matching_rule_wins() {
+        let rules: StatusRules = toml::from_str(
+            r#"
+            [[status]]
+            code = 0x0040
+            mask = 0x00f0
+            action = "error"
+
+            [[status]]
+            code = 0x0001
+            action = "log"
+            "#,
+        )
+        .unwrap();
+        assert!(rules.apply(0x0041).is_err());
+        assert!(rules.apply(0x0001).is_ok());
+        assert!(rules.apply(0x0002).is_ok());
+    }
+}
@@ -0,0 +1,86 @@
+//! Detects suspend and resume of the system, so that listeners stop polling
+//! while the system sleeps and re-register as soon as it wakes up, instead of
+//! timing out into a long backoff.
+//!
+//! Resume is detected by the wall clock jumping ahead of the monotonic clock,
+//! which doesn't advance during suspend. On Unix, a sleep hook can also send
+//! `SIGUSR1` before suspend and `SIGUSR2` after resume.
+
+use std::{sync::Arc, time::SystemTime};
+
+use tokio::{
+    sync::watch,
+    time::{sleep, Duration, Instant},
+};
+use tracing::{debug, info};
+
+/// Interval between checks of the clocks
+const CLOCK_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Difference between the clocks tolerated without assuming a suspend, to
+/// allow for scheduling delays and clock adjustments
+const CLOCK_SLACK: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Power {
+    Awake,
+    Suspended,
+}
+
+/// Spawns the detection of suspend and resume. Every resume is notified
+/// through the receiver as [`Power::Awake`], even if no suspend was reported.
+pub fn watch() -> watch::Receiver<Power> {
+    let (sender, receiver) = watch::channel(Power::Awake);
+    let sender = Arc::new(sender);
+    #[cfg(unix)]
+    tokio::spawn(sleep_hook(sender.clone()));
+    tokio::spawn(detect_resume(sender));
+    receiver
+}
+
+async fn detect_resume(sender: Arc<watch::Sender<Power>>) {
+    loop {
+        let wall = SystemTime::now();
+        let monotonic = Instant::now();
+        sleep(CLOCK_CHECK_INTERVAL).await;
+
+        let suspended = wall
+            .elapsed()
+            .unwrap_or_default()
+            .saturating_sub(monotonic.elapsed());
+        if suspended > CLOCK_SLACK {
+            info!("system resumed after about {}s", suspended.as_secs());
+            if sender.send(Power::Awake).is_err() {
+                break;
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+async fn sleep_hook(sender: Arc<watch::Sender<Power>>) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let (mut suspend, mut resume) = match (
+        signal(SignalKind::user_defined1()),
+        signal(SignalKind::user_defined2()),
+    ) {
+        (Ok(suspend), Ok(resume)) => (suspend, resume),
+        (Err(e), _) | (_, Err(e)) => {
+            tracing::warn!("couldn't listen for suspend signals: {e}");
+            return;
+        }
+    };
+
+    loop {
+        let power = tokio::select! {
+            Some(()) = suspend.recv() => Power::Suspended,
+            Some(()) = resume.recv() => Power::Awake,
+            else => break,
+        };
+        debug!("received {power:?} from sleep hook");
+        if sender.send(power).is_err() {
+            break;
+        }
+    }
+}
@@ -0,0 +1,123 @@
+use std::{collections::HashSet, fs, path::Path};
+
+use anyhow::{bail, Context};
+use serde::{Deserialize, Serialize};
+
+/// A host entry registered on the scanner besides the main one, executing
+/// its own command when selected on the scanner
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Target {
+    /// Name of the entry, accepting the same placeholders as `--hostname`
+    pub name: String,
+    /// Command and its arguments
+    pub command: Vec<String>,
+}
+
+/// Host entries loaded from a TOML file like
+///
+/// ```toml
+/// [[target]]
+/// name = "{user}-photos"
+/// command = ["scan-to.sh", "/srv/photos"]
+/// ```
+///
+/// so that one listener routes scan jobs for a whole household.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Targets {
+    #[serde(default)]
+    target: Vec<Target>,
+}
+
+impl Targets {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("couldn't read targets from {}", path.display()))?;
+        Self::parse(&content).with_context(|| format!("invalid targets in {}", path.display()))
+    }
+
+    fn parse(content: &str) -> anyhow::Result<Self> {
+        let targets: Self = toml::from_str(content)?;
+        let mut names = HashSet::new();
+        for target in targets.target.iter() {
+            if target.name.is_empty() {
+                bail!("target without a name");
+            }
+            if target.command.is_empty() {
+                bail!("target `{}` has no command", target.name);
+            }
+            if !names.insert(target.name.as_str()) {
+                bail!("duplicate target `{}`", target.name);
+            }
+        }
+        Ok(targets)
+    }
+
+    /// Writes the host entries to `path` in the format of [`Self::load`].
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        let content = toml::to_string(self)?;
+        fs::write(path, content)
+            .with_context(|| format!("couldn't write targets to {}", path.display()))
+    }
+}
+
+impl FromIterator<Target> for Targets {
+    fn from_iter<I: IntoIterator<Item = Target>>(iter: I) -> Self {
+        Self {
+            target: iter.into_iter().collect(),
+        }
+    }
+}
+
+impl IntoIterator for Targets {
+    type Item = Target;
+    type IntoIter = std::vec::IntoIter<Target>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.target.into_iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_targets() {
+        let targets = Targets::parse(
+            r#"
+            [[target]]
+            name = "{user}-photos"
+            command = ["scan-to.sh", "/srv/photos"]
+
+            [[target]]
+            name = "office"
+            command = ["scan-to.sh", "/srv/office"]
+            "#,
+        )
+        .unwrap();
+        let names: Vec<_> = targets.into_iter().map(|target| target.name).collect();
+        assert_eq!(names, ["{user}-photos", "office"]);
+
+        assert!(Targets::parse("[[target]]\nname = \"a\"\ncommand = []").is_err());
+        assert!(Targets::parse(
+            "[[target]]\nname = \"a\"\ncommand = [\"x\"]\n[[target]]\nname = \"a\"\ncommand = [\"y\"]"
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn serialize_targets() {
+        let targets: Targets = [Target {
+            name: "{host}".into(),
+            command: vec!["sh".into(), "-c".into(), "echo \"$SCANNER_DPI\"".into()],
+        }]
+        .into_iter()
+        .collect();
+        let parsed = Targets::parse(&toml::to_string(&targets).unwrap()).unwrap();
+        let parsed: Vec<_> = parsed.into_iter().collect();
+        assert_eq!(parsed[0].name, "{host}");
+        assert_eq!(parsed[0].command, ["sh", "-c", "echo \"$SCANNER_DPI\""]);
+    }
+}
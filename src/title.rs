@@ -0,0 +1,104 @@
+//! Shows the state of listeners in the title of the terminal, and notifies of
+//! scan jobs and lost scanners with OSC 9 where the terminal supports it, so
+//! that a listener running in a spare terminal or tmux pane can be checked at a
+//! glance.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    env,
+    io::{self, IsTerminal, Write},
+    net::SocketAddr,
+};
+
+use time::UtcOffset;
+use tokio::sync::mpsc::UnboundedReceiver;
+use tracing::{debug, warn};
+
+use crate::{machine::Action, poll::ListenerEvent, utils::clock};
+
+/// Writes an OSC sequence, passed through tmux to the outer terminal if
+/// needed.
+fn osc(out: &mut impl Write, tmux: bool, command: &str) -> io::Result<()> {
+    if tmux {
+        // tmux swallows OSC 9 unless wrapped, with escapes doubled
+        write!(out, "\x1bPtmux;\x1b\x1b]{command}\x07\x1b\\")?;
+    } else {
+        write!(out, "\x1b]{command}\x07")?;
+    }
+    out.flush()
+}
+
+/// Removes control characters which would end the sequence early.
+fn sanitize(text: &str) -> String {
+    text.chars().filter(|c| !c.is_control()).collect()
+}
+
+/// Updates the title of the terminal on stderr with the events of listeners,
+/// until all listeners are gone.
+pub async fn show(mut events: UnboundedReceiver<ListenerEvent>, local_offset: Option<UtcOffset>) {
+    let mut stderr = io::stderr();
+    if !stderr.is_terminal() {
+        warn!("stderr is not a terminal, its title is not updated");
+        return;
+    }
+    let tmux = env::var_os("TMUX").is_some();
+
+    let mut states: BTreeMap<(SocketAddr, String), String> = BTreeMap::new();
+    let mut last_job = None;
+    let mut disconnected = BTreeSet::new();
+    while let Some(event) = events.recv().await {
+        let result = match event {
+            ListenerEvent::State {
+                scanner,
+                host,
+                state,
+            } => {
+                states.insert((scanner, host.to_string()), state);
+                Ok(())
+            }
+            ListenerEvent::Interrupt {
+                scanner,
+                host,
+                interrupt,
+            } => {
+                last_job = Some(clock(local_offset));
+                let message = format!("9;{host} on {}: {interrupt}", scanner.ip());
+                osc(&mut stderr, tmux, &sanitize(&message))
+            }
+            ListenerEvent::Transition {
+                scanner,
+                host,
+                action,
+            } => {
+                // connecting is only notified after disconnecting
+                let key = (scanner, host.to_string());
+                let notified = match action {
+                    Action::Disconnected => disconnected.insert(key),
+                    Action::Connected | Action::Reconnected | Action::WokeUp => {
+                        disconnected.remove(&key)
+                    }
+                    _ => false,
+                };
+                if !notified {
+                    continue;
+                }
+                let message = format!("9;{host} on {}: {action}", scanner.ip());
+                osc(&mut stderr, tmux, &sanitize(&message))
+            }
+        };
+
+        let mut title = states
+            .iter()
+            .map(|((_, host), state)| format!("{host}: {state}"))
+            .collect::<Vec<_>>()
+            .join(", ");
+        if let Some(time) = &last_job {
+            title.push_str(&format!(" | last job at {time}"));
+        }
+        // the title is shown by tmux itself, no passthrough needed
+        let result = result.and_then(|_| osc(&mut stderr, false, &sanitize(&format!("2;{title}"))));
+        if let Err(e) = result {
+            debug!("couldn't update the terminal title: {e}");
+        }
+    }
+}
@@ -1,9 +1,72 @@
-use std::fmt::Display;
+use std::{
+    borrow::Cow,
+    cmp, env,
+    ffi::OsStr,
+    fmt::{Debug, Display},
+    io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    ops::Range,
+    path::PathBuf,
+};
 
-use log::error;
+use bjnp::serdes::{FormatError, ParseError};
+use encoding_rs::Encoding;
+#[cfg(feature = "interfaces")]
+use network_interface::{NetworkInterface, NetworkInterfaceConfig};
+use socket2::SockRef;
+use time::{OffsetDateTime, UtcOffset};
+use tokio::net::UdpSocket;
+use tracing::{debug, enabled, error, warn, Level};
+
+use crate::redact;
 
 pub const BJNP_PORT: u16 = 8612;
 
+/// IP-level options applied to sockets talking to scanners
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SocketOptions {
+    /// TTL (IPv4) or hop limit (IPv6) of outgoing packets, for both unicast
+    /// and broadcast/multicast
+    pub ttl: Option<u32>,
+    /// Differentiated services code point of outgoing packets (IPv4 only)
+    pub dscp: Option<u8>,
+}
+
+impl SocketOptions {
+    pub fn apply(&self, socket: &UdpSocket) -> Result<(), io::Error> {
+        let is_ipv4 = socket.local_addr()?.is_ipv4();
+        let socket = SockRef::from(socket);
+        if let Some(ttl) = self.ttl {
+            if is_ipv4 {
+                socket.set_ttl(ttl)?;
+                socket.set_multicast_ttl_v4(ttl)?;
+            } else {
+                socket.set_unicast_hops_v6(ttl)?;
+                socket.set_multicast_hops_v6(ttl)?;
+            }
+        }
+        if let Some(dscp) = self.dscp {
+            if is_ipv4 {
+                // DSCP takes the upper 6 bits of the TOS byte
+                socket.set_tos((dscp as u32) << 2)?;
+            } else {
+                debug!("DSCP is not supported on IPv6 sockets, ignored");
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Returns the unspecified address with an ephemeral port in the same family
+/// as `remote`, suitable for binding a socket that talks to `remote`.
+pub fn unspecified_for(remote: &SocketAddr) -> SocketAddr {
+    const IPV4_ANY: IpAddr = IpAddr::V4(Ipv4Addr::UNSPECIFIED);
+    const IPV6_ANY: IpAddr = IpAddr::V6(Ipv6Addr::UNSPECIFIED);
+
+    let local_ip = if remote.is_ipv4() { IPV4_ANY } else { IPV6_ANY };
+    SocketAddr::new(local_ip, 0)
+}
+
 pub fn ignore_err<T, E: Display>(x: Result<T, E>) -> Option<T> {
     match x {
         Ok(t) => Some(t),
@@ -13,3 +76,299 @@ pub fn ignore_err<T, E: Display>(x: Result<T, E>) -> Option<T> {
         }
     }
 }
+
+/// Formats a packet for trace logging.
+pub fn hex_dump(buffer: &[u8]) -> impl Debug + '_ {
+    struct Bytes<'a>(Cow<'a, [u8]>);
+
+    impl Debug for Bytes<'_> {
+        #[cfg(feature = "hex-dump")]
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            use pretty_hex::PrettyHex;
+
+            Debug::fmt(&self.0.as_ref().hex_dump(), f)
+        }
+
+        #[cfg(not(feature = "hex-dump"))]
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            f.write_fmt(format_args!("{:02x?}", self.0.as_ref()))
+        }
+    }
+
+    Bytes(redact::logged_packet(buffer))
+}
+
+/// Resolves an IPv6 zone, either an interface index or an interface name, to a
+/// scope id.
+pub fn scope_id(zone: &str) -> Result<u32, io::Error> {
+    match zone.parse() {
+        Ok(index) => Ok(index),
+        Err(_) => interface_index(zone),
+    }
+}
+
+#[cfg(feature = "interfaces")]
+fn interface_index(name: &str) -> Result<u32, io::Error> {
+    let interfaces = NetworkInterface::show().map_err(|e| io::Error::other(e.to_string()))?;
+    interfaces
+        .iter()
+        .find(|interface| interface.name == name)
+        .map(|interface| interface.index)
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no network interface named `{name}`"),
+            )
+        })
+}
+
+#[cfg(not(feature = "interfaces"))]
+fn interface_index(name: &str) -> Result<u32, io::Error> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!("zone `{name}` is not an interface index"),
+    ))
+}
+
+/// Decodes a host name from the OS. On Unix, a name which isn't valid UTF-8 is
+/// decoded as `encoding`, or the encoding of the current locale if not given.
+#[cfg(unix)]
+pub fn decode_hostname(name: &OsStr, encoding: Option<&'static Encoding>) -> String {
+    use std::os::unix::ffi::OsStrExt;
+
+    let bytes = name.as_bytes();
+    let encoding = encoding.unwrap_or_else(|| match std::str::from_utf8(bytes) {
+        Ok(_) => encoding_rs::UTF_8,
+        Err(_) => locale_encoding(),
+    });
+    let (decoded, had_errors) = encoding.decode_without_bom_handling(bytes);
+    if had_errors {
+        warn!(
+            "host name {bytes:02x?} is not valid {encoding}, try `--hostname-encoding`",
+            encoding = encoding.name()
+        );
+    }
+    decoded.into_owned()
+}
+
+/// Decodes a host name from the OS, which is always Unicode on this platform.
+#[cfg(not(unix))]
+pub fn decode_hostname(name: &OsStr, encoding: Option<&'static Encoding>) -> String {
+    if encoding.is_some() {
+        warn!("`--hostname-encoding` is ignored on this platform");
+    }
+    // only unpaired surrogates are lossy
+    name.to_string_lossy().into_owned()
+}
+
+/// Returns the encoding of the current locale, e.g. EUC-JP for `ja_JP.eucJP`,
+/// falling back to UTF-8.
+#[cfg(unix)]
+fn locale_encoding() -> &'static Encoding {
+    let locale = ["LC_ALL", "LC_CTYPE", "LANG"]
+        .iter()
+        .filter_map(|var| std::env::var(var).ok())
+        .find(|locale| !locale.is_empty());
+    let codeset = locale.as_deref().and_then(|locale| {
+        let (_, codeset) = locale.split_once('.')?;
+        codeset.split('@').next()
+    });
+    codeset
+        .and_then(|codeset| {
+            // glibc spells e.g. `euc-jp` as `eucJP`
+            Encoding::for_label(codeset.as_bytes()).or_else(|| {
+                let rest = codeset.strip_prefix("euc")?;
+                Encoding::for_label(format!("euc-{rest}").as_bytes())
+            })
+        })
+        .unwrap_or(encoding_rs::UTF_8)
+}
+
+/// Replaces `{key}` in `template` by the value of `key` in `vars`, where `{{`
+/// and `}}` stand for literal braces. Unknown keys are left as is.
+pub fn expand_template(template: &str, vars: &[(&str, &str)]) -> String {
+    let mut expanded = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(pos) = rest.find(['{', '}']) {
+        expanded.push_str(&rest[..pos]);
+        rest = &rest[pos..];
+        if rest.starts_with("{{") || rest.starts_with("}}") {
+            expanded.push_str(&rest[..1]);
+            rest = &rest[2..];
+            continue;
+        }
+
+        let placeholder = rest
+            .strip_prefix('{')
+            .and_then(|s| s.split_once('}'))
+            .and_then(|(key, after)| {
+                let (_, value) = vars.iter().find(|(k, _)| *k == key)?;
+                Some((*value, after))
+            });
+        match placeholder {
+            Some((value, after)) => {
+                expanded.push_str(value);
+                rest = after;
+            }
+            None => {
+                warn!("unknown placeholder or unmatched brace in `{template}`");
+                expanded.push_str(&rest[..1]);
+                rest = &rest[1..];
+            }
+        }
+    }
+    expanded.push_str(rest);
+    expanded
+}
+
+/// Matches `text` against `pattern` ignoring case, where `*` stands for any
+/// number of characters, e.g. `MX9*` matches `MX920 series`.
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern = pattern.to_lowercase();
+    let text = text.to_lowercase();
+    let mut parts = pattern.split('*');
+    // NOPANIC: split yields at least one part
+    let first = parts.next().unwrap();
+    let Some(mut rest) = text.strip_prefix(first) else {
+        return false;
+    };
+    let mut parts: Vec<_> = parts.collect();
+    let Some(last) = parts.pop() else {
+        // no `*` at all
+        return rest.is_empty();
+    };
+    for part in parts {
+        match rest.find(part) {
+            Some(pos) => rest = &rest[pos + part.len()..],
+            None => return false,
+        }
+    }
+    rest.ends_with(last)
+}
+
+/// Directory of the configuration of this program, e.g.
+/// `~/.config/scanner-button`, or `None` if the home directory is unknown.
+pub fn config_dir() -> Option<PathBuf> {
+    #[cfg(windows)]
+    let base = env::var_os("APPDATA").map(PathBuf::from);
+    #[cfg(not(windows))]
+    let base = env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")));
+    base.map(|base| base.join(env!("CARGO_PKG_NAME")))
+}
+
+/// Current time at the local `offset` captured at startup, or UTC if it
+/// couldn't be determined. The offset is never looked up again since that
+/// fails once the process has threads.
+pub fn now_at(offset: Option<UtcOffset>) -> OffsetDateTime {
+    OffsetDateTime::now_utc().to_offset(offset.unwrap_or(UtcOffset::UTC))
+}
+
+/// Time of day at `offset` as `HH:MM:SS`, see [`now_at`].
+pub fn clock(offset: Option<UtcOffset>) -> String {
+    let now = now_at(offset);
+    format!("{:02}:{:02}:{:02}", now.hour(), now.minute(), now.second())
+}
+
+/// Converts an error parsing `buffer` into an [`anyhow::Error`]. With `-v`, a
+/// hex excerpt around the offending bytes is included in the message.
+pub fn parse_error(error: ParseError, buffer: &[u8]) -> anyhow::Error {
+    let ParseError::InvalidFormat(format_error) = &error else {
+        return error.into();
+    };
+    if !enabled!(Level::INFO) {
+        return error.into();
+    }
+
+    let span = match format_error {
+        FormatError::InvalidByte { offset, .. } => *offset..*offset + 1,
+        FormatError::InvalidSlice { span, .. } => span.clone(),
+    };
+    let message = format!(
+        "packet bytes around the error:\n{}",
+        hex_excerpt(buffer, span)
+    );
+    anyhow::Error::new(error).context(message)
+}
+
+/// Formats the bytes in `span` with a few bytes around, marking the bytes in
+/// `span` on the line below.
+fn hex_excerpt(buffer: &[u8], span: Range<usize>) -> String {
+    const CONTEXT: usize = 8;
+
+    let end = cmp::min(buffer.len(), span.end + CONTEXT);
+    let start = cmp::min(span.start.saturating_sub(CONTEXT), end);
+    let bytes: Vec<_> = buffer[start..end]
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect();
+    let markers: Vec<_> = (start..end)
+        .map(|i| if span.contains(&i) { "^^" } else { "  " })
+        .collect();
+    format!(
+        "  {start:04x}: {bytes}\n        {markers}",
+        bytes = bytes.join(" "),
+        markers = markers.join(" ").trim_end()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expand_hostname_template() {
+        let vars = [("user", "alice"), ("host", "desktop")];
+        assert_eq!(
+            expand_template("Scan to {user}@{host}", &vars),
+            "Scan to alice@desktop"
+        );
+        assert_eq!(
+            expand_template("{{user}} {unknown} {user", &vars),
+            "{user} {unknown} {user"
+        );
+    }
+
+    #[test]
+    fn match_glob() {
+        assert!(glob_match("MX920 series", "MX920 series"));
+        assert!(glob_match("mx9*", "MX920 series"));
+        assert!(glob_match("*920*", "MX920 series"));
+        assert!(glob_match("MX*series", "MX920 series"));
+        assert!(!glob_match("MX920", "MX920 series"));
+        assert!(!glob_match("TS*", "MX920 series"));
+        assert!(!glob_match("MX*20*20", "MX920 series"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn decode_non_utf8_hostname() {
+        use std::os::unix::ffi::OsStrExt;
+
+        // "ホスト" in Shift_JIS
+        let name = OsStr::from_bytes(&[0x83, 0x7a, 0x83, 0x58, 0x83, 0x67]);
+        assert_eq!(
+            decode_hostname(name, Some(encoding_rs::SHIFT_JIS)),
+            "ホスト"
+        );
+        assert_eq!(decode_hostname(OsStr::new("ホスト"), None), "ホスト");
+    }
+
+    #[test]
+    fn hex_excerpt_marks_span() {
+        let buffer: Vec<u8> = (0..32).collect();
+        assert_eq!(
+            hex_excerpt(&buffer, 12..14),
+            format!(
+                "  0004: 04 05 06 07 08 09 0a 0b 0c 0d 0e 0f 10 11 12 13 14 15\n{:32}^^ ^^",
+                ""
+            )
+        );
+        assert_eq!(
+            hex_excerpt(&buffer, 0..1),
+            "  0000: 00 01 02 03 04 05 06 07 08\n        ^^"
+        );
+    }
+}
@@ -0,0 +1,68 @@
+//! Wake-on-LAN, for multifunction printers powering their network interface
+//! down while idle, which then don't answer until woken up.
+
+use std::net::{Ipv4Addr, SocketAddr};
+
+use anyhow::{bail, Context};
+use bjnp::discover::MacAddr;
+use tokio::net::UdpSocket;
+use tracing::debug;
+
+use crate::utils::unspecified_for;
+
+/// Port magic packets are conventionally sent to
+const WOL_PORT: u16 = 9;
+
+/// Builds a magic packet: 6 bytes of `0xff` followed by the MAC address
+/// repeated 16 times.
+fn magic_packet(mac: [u8; 6]) -> [u8; 102] {
+    let mut packet = [0xff; 102];
+    for chunk in packet[6..].chunks_exact_mut(6) {
+        chunk.copy_from_slice(&mac);
+    }
+    packet
+}
+
+/// Sends a magic packet waking `mac` to the scanner at `scanner`, and to the
+/// broadcast address for IPv4 since a sleeping scanner may be out of the ARP
+/// cache.
+pub async fn wake(mac: MacAddr, scanner: SocketAddr) -> anyhow::Result<()> {
+    let MacAddr::Eui48(addr) = mac else {
+        bail!("Wake-on-LAN needs a 48-bit MAC address, not {mac}");
+    };
+    let packet = magic_packet(addr.into());
+
+    let local = unspecified_for(&scanner);
+    let socket = UdpSocket::bind(local)
+        .await
+        .with_context(|| format!("couldn't bind to {local}"))?;
+    let mut targets = vec![SocketAddr::new(scanner.ip(), WOL_PORT)];
+    if scanner.is_ipv4() {
+        socket
+            .set_broadcast(true)
+            .with_context(|| format!("couldn't set socket at {local} to broadcast"))?;
+        targets.push(SocketAddr::new(Ipv4Addr::BROADCAST.into(), WOL_PORT));
+    }
+    for target in targets {
+        socket
+            .send_to(&packet, target)
+            .await
+            .with_context(|| format!("couldn't send magic packet to {target}"))?;
+    }
+    debug!("sent magic packet for {mac} to {scanner}");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn magic_packet_repeats_mac() {
+        let packet = magic_packet([0x00, 0x1e, 0x8f, 0x01, 0x02, 0x03]);
+        assert_eq!(packet[..6], [0xff; 6]);
+        assert!(packet[6..]
+            .chunks(6)
+            .all(|chunk| chunk == [0x00, 0x1e, 0x8f, 0x01, 0x02, 0x03]));
+    }
+}